@@ -1,11 +1,14 @@
 package app
 
 import (
+	"encoding/json"
 	"fmt"
 	"io"
 	"os"
+	"sort"
 	"strconv"
 	"strings"
+	"time"
 
 	"github.com/tsiemens/acb/fx"
 	"github.com/tsiemens/acb/log"
@@ -49,10 +52,40 @@ type DescribedReader struct {
 	Reader io.Reader
 }
 
+const (
+	BrokerQuestrade = "questrade"
+)
+
+// Converts csvs exported by broker into the format read by ptf.ParseTxCsv.
+// If broker is empty, the readers are returned as is.
+func ConvertBrokerCsvs(
+	broker string, csvFileReaders []DescribedReader, options AcbRunOptions,
+	errPrinter log.ErrorPrinter) ([]DescribedReader, error) {
+
+	switch strings.ToLower(broker) {
+	case "":
+		return csvFileReaders, nil
+	case BrokerQuestrade:
+		converted := make([]DescribedReader, 0, len(csvFileReaders))
+		for _, csvReader := range csvFileReaders {
+			reader, err := ptf.ConvertQuestradeCsv(
+				csvReader.Reader, csvReader.Desc, options.csvOptions(), errPrinter)
+			if err != nil {
+				return nil, newAcbError(ERR_PARSE, err)
+			}
+			converted = append(converted, DescribedReader{csvReader.Desc, reader})
+		}
+		return converted, nil
+	default:
+		return nil, newAcbError(ERR_VALIDATION, fmt.Errorf(
+			"Unsupported broker '%s' (supported: %s)", broker, BrokerQuestrade))
+	}
+}
+
 type LegacyOptions struct {
-	NoSuperficialLosses        bool
-	NoPartialSuperficialLosses bool
-	SortBuysBeforeSells        bool
+	NoSuperficialLosses        bool `json:"no_superficial_losses"`
+	NoPartialSuperficialLosses bool `json:"no_partial_superficial_losses"`
+	SortBuysBeforeSells        bool `json:"sort_buys_before_sells"`
 }
 
 func NewLegacyOptions() LegacyOptions {
@@ -63,65 +96,345 @@ func NewLegacyOptions() LegacyOptions {
 	}
 }
 
-func RunAcbAppToModel(
+const (
+	OutputFormatText     = "text"
+	OutputFormatMarkdown = "markdown"
+	OutputFormatHtml     = "html"
+	OutputFormatJson     = "json"
+)
+
+const (
+	OrderAscending  = "asc"
+	OrderDescending = "desc"
+)
+
+type ReportOptions struct {
+	// OutputFormatText (the default if empty), OutputFormatMarkdown,
+	// OutputFormatHtml or OutputFormatJson. With json, only the deltas are
+	// written (see ptf.JsonReport), and the options for tables are ignored.
+	// With html, a single page is written, with the capital gains by year at the
+	// top (regardless of YearTotals).
+	OutputFormat string `json:"output_format"`
+	// OrderAscending (the default if empty) or OrderDescending, which shows the
+	// newest rows of the tables first. This only affects the display, so
+	// running totals still accumulate chronologically. Ignored for json.
+	Order string `json:"order"`
+	// Print the total commissions paid per year, after the security tables.
+	CommissionTotals bool `json:"commission_totals"`
+	// The columns (and their order) to show in the security tables.
+	// All columns are shown if empty.
+	Columns []string `json:"columns"`
+	// Add a column with the capital gain of each sale in its trade currency.
+	TradeCurrencyGains bool `json:"trade_currency_gains"`
+	// Add a column with the total ACB in the transaction currency. This is
+	// omitted (with a warning) for securities with transactions in multiple
+	// currencies.
+	TradeCurrencyAcb bool `json:"trade_currency_acb"`
+	// Add columns with the running totals of capital gains and superficial
+	// losses in each tax year.
+	RunningTotals bool `json:"running_totals"`
+	// Print the capital gains totals per year, after the security tables.
+	YearTotals bool `json:"year_totals"`
+	// If set, only this security is reported. Other securities are only
+	// computed if they move shares into it (eg. with a Spinoff).
+	Security string `json:"security"`
+	// If non-zero, only deltas settled in this year are reported. All years
+	// are still computed, so ACB and superficial losses are unaffected.
+	TaxYear int `json:"tax_year"`
+	// If non-zero, only deltas of txs on or after this date are reported.
+	// Earlier txs are still computed, so ACB and superficial losses are
+	// unaffected.
+	Since time.Time `json:"since"`
+	// If non-zero, print the net capital gains of each year split into those
+	// settled before and on/after this date (eg. ptf.DefaultInclusionSplitDate),
+	// after the security tables.
+	InclusionSplitDate time.Time `json:"inclusion_split_date"`
+	// Add a note to the security tables with the calculation of each
+	// automatically computed superficial loss.
+	ExplainSuperficialLosses bool `json:"explain_sfl"`
+	// The read indexes (as in "tx #N") of txs for which to print a derivation
+	// of the computed delta, after the security tables.
+	ExplainTxs []int `json:"explain_txs"`
+	// If set, called as each security's deltas are computed.
+	Progress ptf.ProgressFunc `json:"-"`
+}
+
+func NewReportOptions() ReportOptions {
+	return ReportOptions{
+		OutputFormat:             OutputFormatText,
+		Order:                    OrderAscending,
+		CommissionTotals:         false,
+		Columns:                  []string{},
+		TradeCurrencyGains:       false,
+		TradeCurrencyAcb:         false,
+		RunningTotals:            false,
+		YearTotals:               false,
+		Security:                 "",
+		TaxYear:                  0,
+		Since:                    time.Time{},
+		InclusionSplitDate:       time.Time{},
+		ExplainSuperficialLosses: false,
+		ExplainTxs:               []int{},
+		Progress:                 nil,
+	}
+}
+
+func (o *ReportOptions) txTableHeader() []string {
+	header := append([]string{}, ptf.TxTableHeader...)
+	if o.TradeCurrencyGains {
+		header = append(header, ptf.TradeCurrencyGainHeader)
+	}
+	if o.TradeCurrencyAcb {
+		header = append(header, ptf.TradeCurrencyAcbHeader)
+	}
+	if o.RunningTotals {
+		header = append(header, ptf.RunningTotalsHeader...)
+	}
+	return header
+}
+
+// Reverses the rows of table if the report is in descending order.
+// Returns table.
+func (o *ReportOptions) orderRows(table *ptf.RenderTable) *ptf.RenderTable {
+	if o.Order == OrderDescending {
+		ptf.ReverseRenderTableRows(table)
+	}
+	return table
+}
+
+// The report options are ignored.
+func RunAcbAppToDeltaModels(
 	csvFileReaders []DescribedReader,
 	allInitStatus map[string]*ptf.PortfolioSecurityStatus,
-	forceDownload bool,
-	renderFullDollarValues bool,
-	legacyOptions LegacyOptions,
+	options AcbRunOptions,
 	ratesCache fx.RatesCache,
-	errPrinter log.ErrorPrinter) (map[string]*ptf.RenderTable, error) {
+	errPrinter log.ErrorPrinter) (map[string]*ptf.SecurityDeltas, error) {
 
-	rateLoader := fx.NewRateLoader(forceDownload, ratesCache, errPrinter)
+	return runAcbAppToDeltaModels(
+		csvFileReaders, allInitStatus, options, "", nil, ratesCache, errPrinter)
+}
+
+// If security is non-empty, only the deltas of security are returned.
+// progress may be nil.
+func runAcbAppToDeltaModels(
+	csvFileReaders []DescribedReader,
+	allInitStatus map[string]*ptf.PortfolioSecurityStatus,
+	options AcbRunOptions,
+	security string,
+	progress ptf.ProgressFunc,
+	ratesCache fx.RatesCache,
+	errPrinter log.ErrorPrinter) (map[string]*ptf.SecurityDeltas, error) {
+
+	rateLoader := fx.NewRateLoader(options.ForceDownload, ratesCache, errPrinter)
+	rateLoader.RefreshStale = options.RefreshStaleRates
+	if options.CsvRateTable != nil {
+		rateLoader.Overrides = options.CsvRateTable
+	}
 
 	allTxs := make([]*ptf.Tx, 0, 20)
+	sources := make([]ptf.SourceTxs, 0, len(csvFileReaders))
 	var globalReadIndex uint32 = 0
 	for _, csvReader := range csvFileReaders {
-		txs, err := ptf.ParseTxCsv(csvReader.Reader, globalReadIndex, csvReader.Desc, rateLoader)
+		txs, err := ptf.ParseTxCsv(
+			csvReader.Reader, globalReadIndex, csvReader.Desc, rateLoader, options.csvOptions())
 		if err != nil {
-			return nil, err
+			return nil, newAcbError(ERR_PARSE, err)
 		}
 		globalReadIndex += uint32(len(txs))
 		for _, tx := range txs {
 			allTxs = append(allTxs, tx)
 		}
+		sources = append(sources, ptf.SourceTxs{Desc: csvReader.Desc, Txs: txs})
 	}
 
-	allTxs = ptf.SortTxs(allTxs, legacyOptions.SortBuysBeforeSells)
-	txsBySec := ptf.SplitTxsBySecurity(allTxs)
+	for _, overlap := range ptf.FindSourceDateOverlaps(sources) {
+		errPrinter.F("Warning: %s\n", overlap)
+	}
+	for _, dup := range ptf.FindDuplicateTxs(allTxs) {
+		errPrinter.F("Warning: %s\n", dup)
+	}
+	for _, outOfSeq := range ptf.FindOutOfSequenceTxs(allTxs) {
+		errPrinter.F("Warning: %s\n", outOfSeq)
+	}
+	for _, rate := range ptf.FindImplausibleExchangeRates(
+		allTxs, options.LocalCurrency, options.PlausibleExchangeRates) {
+		errPrinter.F("Warning: %s\n", rate)
+	}
+	if options.CheckCurrencyCodes {
+		for _, curr := range ptf.FindUnknownCurrencies(allTxs) {
+			errPrinter.F("Warning: %s\n", curr)
+		}
+	}
+	for _, future := range ptf.FindFutureTxs(allTxs) {
+		if options.FutureTxDatesAreErrors {
+			return nil, newAcbError(ERR_VALIDATION, fmt.Errorf("%s", future))
+		}
+		errPrinter.F("Warning: %s\n", future)
+	}
 
-	portfolioLegacyOptions := ptf.LegacyOptions{
-		NoSuperficialLosses:        legacyOptions.NoSuperficialLosses,
-		NoPartialSuperficialLosses: legacyOptions.NoPartialSuperficialLosses,
+	allTxs = ptf.SortTxs(allTxs, options.Legacy.SortBuysBeforeSells)
+	if security != "" {
+		allTxs = ptf.FilterTxsForSecurity(allTxs, security)
+	}
+	if options.SameDayTradeNetting {
+		allTxs = ptf.NetSameDayTrades(allTxs)
 	}
-	models := make(map[string]*ptf.RenderTable)
 
-	nSecs := len(txsBySec)
-	i := 0
-	for sec, secTxs := range txsBySec {
-		secInitStatus, ok := allInitStatus[sec]
+	deltaModels := ptf.TxsToDeltaModelsWithProgress(
+		allTxs, allInitStatus, options.deltaOptions(), progress)
+	if security != "" {
+		deltaModel, ok := deltaModels[security]
 		if !ok {
-			secInitStatus = nil
+			return nil, newAcbError(ERR_VALIDATION,
+				fmt.Errorf("No transactions found for %s", security))
 		}
-		deltas, err := ptf.TxsToDeltaList(secTxs, secInitStatus, portfolioLegacyOptions)
+		deltaModels = map[string]*ptf.SecurityDeltas{security: deltaModel}
+	}
 
-		tableModel := ptf.RenderTxTableModel(deltas, renderFullDollarValues)
-		if err != nil {
-			tableModel.Errors = append(tableModel.Errors, err)
+	secs := make([]string, 0, len(deltaModels))
+	for sec, _ := range deltaModels {
+		secs = append(secs, sec)
+	}
+	sort.Strings(secs)
+	for _, sec := range secs {
+		for _, d := range deltaModels[sec].Deltas {
+			for _, warning := range d.Warnings {
+				errPrinter.F("Warning: %s\n", warning)
+			}
 		}
+	}
+	return deltaModels, nil
+}
+
+// The tables are rendered as specified by options.Report.
+func renderDeltaModels(
+	deltaModels map[string]*ptf.SecurityDeltas,
+	options AcbRunOptions,
+	errPrinter log.ErrorPrinter) map[string]*ptf.RenderTable {
+
+	models := make(map[string]*ptf.RenderTable)
+
+	renderFullDollarValues := options.RenderFullDollarValues
+	reportOptions := options.Report
+	deltaOptions := options.deltaOptions()
+	for sec, deltaModel := range deltaModels {
+		tableModel := ptf.RenderTxTableModel(
+			deltaModel.Deltas, options.Rounding, renderFullDollarValues)
+		tableModel.Errors = append(tableModel.Errors, deltaModel.Errors...)
+		if reportOptions.TradeCurrencyGains {
+			ptf.AddTradeCurrencyGainColumn(
+				tableModel, deltaModel.Deltas, deltaOptions, renderFullDollarValues)
+		}
+		if reportOptions.TradeCurrencyAcb {
+			err := ptf.AddTradeCurrencyAcbColumn(
+				tableModel, deltaModel.Deltas, deltaOptions, renderFullDollarValues)
+			if err != nil {
+				errPrinter.F("Warning: Trade currency ACB of %s is not shown: %v\n", sec, err)
+			}
+		}
+		if reportOptions.RunningTotals {
+			ptf.AddRunningTotalsColumns(
+				tableModel, deltaModel.Deltas, renderFullDollarValues)
+		}
+		if reportOptions.ExplainSuperficialLosses {
+			ptf.AddSuperficialLossCalcNotes(tableModel, deltaModel.Deltas)
+		}
+		if len(reportOptions.Columns) > 0 {
+			// Columns must be validated by the caller
+			ptf.SelectRenderTableColumns(tableModel, reportOptions.Columns)
+		}
+		reportOptions.orderRows(tableModel)
 		models[sec] = tableModel
+	}
+	return models
+}
 
-		if i < (nSecs - 1) {
-			fmt.Println("")
+// Returns deltaModels with only the deltas of txs settled in year.
+// Securities without any such deltas (or errors) are omitted.
+func filterDeltaModelsByYear(
+	deltaModels map[string]*ptf.SecurityDeltas, year int) map[string]*ptf.SecurityDeltas {
+
+	return filterDeltaModels(deltaModels, func(d *ptf.TxDelta) bool {
+		return d.Tx.Date.Year() == year
+	})
+}
+
+// Returns deltaModels with only the deltas of txs on or after since.
+// Securities without any such deltas (or errors) are omitted.
+func filterDeltaModelsSince(
+	deltaModels map[string]*ptf.SecurityDeltas, since time.Time) map[string]*ptf.SecurityDeltas {
+
+	return filterDeltaModels(deltaModels, func(d *ptf.TxDelta) bool {
+		return !d.Tx.Date.Before(since)
+	})
+}
+
+func filterDeltaModels(
+	deltaModels map[string]*ptf.SecurityDeltas,
+	keep func(d *ptf.TxDelta) bool) map[string]*ptf.SecurityDeltas {
+
+	filtered := make(map[string]*ptf.SecurityDeltas)
+	for sec, deltaModel := range deltaModels {
+		deltas := make([]*ptf.TxDelta, 0, len(deltaModel.Deltas))
+		for _, d := range deltaModel.Deltas {
+			if keep(d) {
+				deltas = append(deltas, d)
+			}
 		}
-		i++
+		if len(deltas) > 0 || len(deltaModel.Errors) > 0 {
+			filtered[sec] = &ptf.SecurityDeltas{Deltas: deltas, Errors: deltaModel.Errors}
+		}
+	}
+	return filtered
+}
+
+func allDeltas(deltaModels map[string]*ptf.SecurityDeltas) []*ptf.TxDelta {
+	deltas := make([]*ptf.TxDelta, 0, len(deltaModels))
+	for _, deltaModel := range deltaModels {
+		deltas = append(deltas, deltaModel.Deltas...)
 	}
-	return models, nil
+	return deltas
+}
+
+func RunAcbAppToModel(
+	csvFileReaders []DescribedReader,
+	allInitStatus map[string]*ptf.PortfolioSecurityStatus,
+	forceDownload bool,
+	renderFullDollarValues bool,
+	legacyOptions LegacyOptions,
+	ratesCache fx.RatesCache,
+	errPrinter log.ErrorPrinter) (map[string]*ptf.RenderTable, error) {
+
+	options := NewAcbRunOptions()
+	options.ForceDownload = forceDownload
+	options.RenderFullDollarValues = renderFullDollarValues
+	options.Legacy = legacyOptions
+	deltaModels, err := RunAcbAppToDeltaModels(
+		csvFileReaders, allInitStatus, options, ratesCache, errPrinter)
+	if err != nil {
+		return nil, err
+	}
+	return renderDeltaModels(deltaModels, options, errPrinter), nil
 }
 
 func WriteRenderTables(
 	renderTables map[string]*ptf.RenderTable,
 	writer io.Writer) {
+	writeRenderTables(renderTables, writer, ptf.PrintRenderTable)
+}
+
+// Returns the function used to print tables in outputFormat.
+func tablePrinter(outputFormat string) func(*ptf.RenderTable, io.Writer) {
+	if outputFormat == OutputFormatMarkdown {
+		return ptf.PrintRenderTableMarkdown
+	}
+	return ptf.PrintRenderTable
+}
+
+func writeRenderTables(
+	renderTables map[string]*ptf.RenderTable,
+	writer io.Writer,
+	printTable func(*ptf.RenderTable, io.Writer)) {
 
 	nSecs := len(renderTables)
 	i := 0
@@ -130,7 +443,7 @@ func WriteRenderTables(
 			fmt.Fprintf(writer, "[!] %v. Printing parsed information state:\n", err)
 		}
 		fmt.Fprintf(writer, "Transactions for %s\n", sec)
-		ptf.PrintRenderTable(renderTable, writer)
+		printTable(renderTable, writer)
 		if i < (nSecs - 1) {
 			fmt.Fprintln(writer, "")
 		}
@@ -138,8 +451,21 @@ func WriteRenderTables(
 	}
 }
 
+// Writes the deltas as an indented ptf.JsonReport.
+func WriteJsonReport(deltaModels map[string]*ptf.SecurityDeltas, writer io.Writer) error {
+	out, err := json.MarshalIndent(ptf.MakeJsonReport(deltaModels), "", "  ")
+	if err != nil {
+		return err
+	}
+	_, err = fmt.Fprintln(writer, string(out))
+	return err
+}
+
 // Returns an OK flag. Used to signal what exit code to use.
 // All errors get printed to the errPrinter or to the writer (as appropriate).
+//
+// Deprecated: Use RunAcbAppWithOptions. The other options are the defaults (see
+// NewAcbRunOptions).
 func RunAcbAppToWriter(
 	writer io.Writer,
 	csvFileReaders []DescribedReader,
@@ -147,21 +473,431 @@ func RunAcbAppToWriter(
 	forceDownload bool,
 	renderFullDollarValues bool,
 	legacyOptions LegacyOptions,
+	reportOptions ReportOptions,
 	ratesCache fx.RatesCache,
 	errPrinter log.ErrorPrinter) (bool, map[string]*ptf.RenderTable) {
 
-	renderTables, err := RunAcbAppToModel(
-		csvFileReaders, allInitStatus, forceDownload, renderFullDollarValues,
-		legacyOptions, ratesCache, errPrinter,
+	options := NewAcbRunOptions()
+	options.ForceDownload = forceDownload
+	options.RenderFullDollarValues = renderFullDollarValues
+	options.Legacy = legacyOptions
+	options.Report = reportOptions
+	result, err := RunAcbAppWithOptions(
+		writer, csvFileReaders, allInitStatus, options, ratesCache, errPrinter)
+	// Errors computing the deltas of securities are written with their tables.
+	category, _ := ErrorCategoryOf(err)
+	ok := err == nil || category == ERR_COMPUTATION
+	if result == nil {
+		return ok, nil
+	}
+	return ok, result.RenderTables
+}
+
+// The models produced by RunAcbAppWithOptions.
+type AcbRunResult struct {
+	// nil if the output format is json.
+	RenderTables map[string]*ptf.RenderTable
+	// The totals over the reported deltas of all securities.
+	Summary ptf.DeltaSummaryStats
+}
+
+// Returns an *AcbError if the run failed (see ExitCode). All errors get
+// printed to the errPrinter or to the writer (as appropriate).
+// The result is nil if the deltas could not be computed. If the deltas of some
+// securities could not be computed, the result (and output) is still complete
+// for the others, and the error is an ERR_COMPUTATION.
+func RunAcbAppWithOptions(
+	writer io.Writer,
+	csvFileReaders []DescribedReader,
+	allInitStatus map[string]*ptf.PortfolioSecurityStatus,
+	options AcbRunOptions,
+	ratesCache fx.RatesCache,
+	errPrinter log.ErrorPrinter) (*AcbRunResult, error) {
+
+	renderFullDollarValues := options.RenderFullDollarValues
+	reportOptions := options.Report
+
+	if reportOptions.OutputFormat != "" &&
+		reportOptions.OutputFormat != OutputFormatText &&
+		reportOptions.OutputFormat != OutputFormatMarkdown &&
+		reportOptions.OutputFormat != OutputFormatHtml &&
+		reportOptions.OutputFormat != OutputFormatJson {
+		err := fmt.Errorf("Invalid output format '%s' (must be %s, %s, %s or %s)",
+			reportOptions.OutputFormat, OutputFormatText, OutputFormatMarkdown,
+			OutputFormatHtml, OutputFormatJson)
+		errPrinter.Ln("Error:", err)
+		return nil, newAcbError(ERR_VALIDATION, err)
+	}
+	if reportOptions.Order != "" &&
+		reportOptions.Order != OrderAscending &&
+		reportOptions.Order != OrderDescending {
+		err := fmt.Errorf("Invalid order '%s' (must be %s or %s)",
+			reportOptions.Order, OrderAscending, OrderDescending)
+		errPrinter.Ln("Error:", err)
+		return nil, newAcbError(ERR_VALIDATION, err)
+	}
+	err := ptf.ValidateColumns(reportOptions.txTableHeader(), reportOptions.Columns)
+	if err != nil {
+		errPrinter.Ln("Error:", err)
+		return nil, newAcbError(ERR_VALIDATION, err)
+	}
+
+	deltaModels, err := runAcbAppToDeltaModels(
+		csvFileReaders, allInitStatus, options,
+		reportOptions.Security, reportOptions.Progress, ratesCache, errPrinter,
 	)
 
 	if err != nil {
 		errPrinter.Ln("Error:", err)
-		return false, nil
+		return nil, err
+	}
+	computationErr := deltaModelsError(deltaModels)
+	if reportOptions.TaxYear != 0 {
+		deltaModels = filterDeltaModelsByYear(deltaModels, reportOptions.TaxYear)
 	}
+	if !reportOptions.Since.IsZero() {
+		deltaModels = filterDeltaModelsSince(deltaModels, reportOptions.Since)
+	}
+
+	result := &AcbRunResult{Summary: ptf.SummarizeDeltas(allDeltas(deltaModels))}
 
-	WriteRenderTables(renderTables, writer)
-	return true, renderTables
+	if reportOptions.OutputFormat == OutputFormatJson {
+		err = WriteJsonReport(deltaModels, writer)
+		if err != nil {
+			errPrinter.Ln("Error:", err)
+			return nil, newAcbError(ERR_IO, err)
+		}
+		return result, computationErr
+	}
+
+	renderTables := renderDeltaModels(deltaModels, options, errPrinter)
+	result.RenderTables = renderTables
+
+	if reportOptions.OutputFormat == OutputFormatHtml {
+		writeHtmlReport(deltaModels, renderTables, renderFullDollarValues, reportOptions, writer)
+		return result, computationErr
+	}
+
+	printTable := tablePrinter(reportOptions.OutputFormat)
+	writeRenderTables(renderTables, writer, printTable)
+	writeTxExplanations(
+		deltaModels, reportOptions.ExplainTxs, options.deltaOptions(), writer, errPrinter)
+
+	if reportOptions.CommissionTotals {
+		commissionTotals := ptf.CommissionTotalsByYear(allDeltas(deltaModels))
+		fmt.Fprintln(writer, "\nCommission totals")
+		printTable(reportOptions.orderRows(
+			ptf.RenderCommissionTotalsTable(commissionTotals, renderFullDollarValues)),
+			writer)
+	}
+	if reportOptions.YearTotals {
+		yearGains := ptf.AnnualGainsReport(allDeltas(deltaModels))
+		fmt.Fprintln(writer, "\nCapital gains by year")
+		printTable(reportOptions.orderRows(
+			ptf.RenderYearGainsTable(yearGains, renderFullDollarValues)), writer)
+	}
+	if !reportOptions.InclusionSplitDate.IsZero() {
+		periodGains := ptf.InclusionPeriodGainsReport(
+			allDeltas(deltaModels), reportOptions.InclusionSplitDate)
+		fmt.Fprintln(writer, "\nCapital gains by inclusion period")
+		printTable(reportOptions.orderRows(ptf.RenderInclusionPeriodGainsTable(
+			periodGains, reportOptions.InclusionSplitDate, renderFullDollarValues)), writer)
+	}
+	return result, computationErr
+}
+
+// Returns an ERR_COMPUTATION *AcbError for the first error (by security) in
+// deltaModels, or nil if there are none.
+func deltaModelsError(deltaModels map[string]*ptf.SecurityDeltas) error {
+	secs := make([]string, 0, len(deltaModels))
+	for sec, _ := range deltaModels {
+		secs = append(secs, sec)
+	}
+	sort.Strings(secs)
+	for _, sec := range secs {
+		if errs := deltaModels[sec].Errors; len(errs) > 0 {
+			return newAcbError(ERR_COMPUTATION, fmt.Errorf("Error in %s: %v", sec, errs[0]))
+		}
+	}
+	return nil
+}
+
+// Writes ptf.ExplainTxDelta for each delta of a tx with one of readIndexes.
+// There may be several for a single tx (eg. the Acquire derived from a Spinoff).
+func writeTxExplanations(
+	deltaModels map[string]*ptf.SecurityDeltas,
+	readIndexes []int,
+	deltaOptions ptf.DeltaOptions,
+	writer io.Writer,
+	errPrinter log.ErrorPrinter) {
+
+	if len(readIndexes) == 0 {
+		return
+	}
+	secs := make([]string, 0, len(deltaModels))
+	for sec, _ := range deltaModels {
+		secs = append(secs, sec)
+	}
+	sort.Strings(secs)
+
+	for _, readIndex := range readIndexes {
+		found := false
+		for _, sec := range secs {
+			for _, d := range deltaModels[sec].Deltas {
+				if int(d.Tx.ReadIndex) != readIndex {
+					continue
+				}
+				fmt.Fprintf(writer, "\n%s\n", ptf.ExplainTxDelta(d, deltaOptions))
+				found = true
+			}
+		}
+		if !found {
+			errPrinter.F("Warning: No computed transaction #%d to explain\n", readIndex)
+		}
+	}
+}
+
+func writeHtmlReport(
+	deltaModels map[string]*ptf.SecurityDeltas,
+	renderTables map[string]*ptf.RenderTable,
+	renderFullDollarValues bool,
+	reportOptions ReportOptions,
+	writer io.Writer) {
+
+	deltas := allDeltas(deltaModels)
+	summaries := []*ptf.TitledRenderTable{
+		&ptf.TitledRenderTable{
+			Title: "Capital gains by year",
+			Table: reportOptions.orderRows(ptf.RenderYearGainsTable(
+				ptf.AnnualGainsReport(deltas), renderFullDollarValues)),
+		},
+	}
+	if reportOptions.CommissionTotals {
+		summaries = append(summaries, &ptf.TitledRenderTable{
+			Title: "Commission totals",
+			Table: reportOptions.orderRows(ptf.RenderCommissionTotalsTable(
+				ptf.CommissionTotalsByYear(deltas), renderFullDollarValues)),
+		})
+	}
+	if !reportOptions.InclusionSplitDate.IsZero() {
+		summaries = append(summaries, &ptf.TitledRenderTable{
+			Title: "Capital gains by inclusion period",
+			Table: reportOptions.orderRows(ptf.RenderInclusionPeriodGainsTable(
+				ptf.InclusionPeriodGainsReport(deltas, reportOptions.InclusionSplitDate),
+				reportOptions.InclusionSplitDate, renderFullDollarValues)),
+		})
+	}
+	ptf.WriteHtmlReport(summaries, renderTables, writer)
+}
+
+// Writes a table of every disposition (for T5008 reconciliation). If year is
+// non-zero, only dispositions in that year are included.
+// Returns an *AcbError if the run failed (see ExitCode). All errors are
+// printed to errPrinter.
+func RunT5008ToWriter(
+	writer io.Writer,
+	csvFileReaders []DescribedReader,
+	allInitStatus map[string]*ptf.PortfolioSecurityStatus,
+	options AcbRunOptions,
+	year int,
+	ratesCache fx.RatesCache,
+	errPrinter log.ErrorPrinter) error {
+
+	deltaModels, err := RunAcbAppToDeltaModels(
+		csvFileReaders, allInitStatus, options, ratesCache, errPrinter)
+	if err != nil {
+		errPrinter.Ln("Error:", err)
+		return err
+	}
+
+	// Rows would be missing for any security with errors.
+	for sec, deltaModel := range deltaModels {
+		for _, err := range deltaModel.Errors {
+			errPrinter.F("Error in %s: %v\n", sec, err)
+		}
+	}
+	if err = deltaModelsError(deltaModels); err != nil {
+		return err
+	}
+
+	rows := ptf.T5008Rows(allDeltas(deltaModels), year)
+	ptf.PrintRenderTable(ptf.RenderT5008Table(rows, options.RenderFullDollarValues), writer)
+	return nil
+}
+
+// Writes a table of the position of each security after its last tx settled on
+// or before asOf (or its last tx, if asOf is zero). See ptf.CurrentHoldings.
+// Returns an *AcbError if the run failed (see ExitCode). All errors are
+// printed to errPrinter.
+func RunHoldingsToWriter(
+	writer io.Writer,
+	csvFileReaders []DescribedReader,
+	allInitStatus map[string]*ptf.PortfolioSecurityStatus,
+	options AcbRunOptions,
+	asOf time.Time,
+	ratesCache fx.RatesCache,
+	errPrinter log.ErrorPrinter) error {
+
+	deltaModels, err := RunAcbAppToDeltaModels(
+		csvFileReaders, allInitStatus, options, ratesCache, errPrinter)
+	if err != nil {
+		errPrinter.Ln("Error:", err)
+		return err
+	}
+
+	// The positions of securities with errors would be wrong.
+	for sec, deltaModel := range deltaModels {
+		for _, err := range deltaModel.Errors {
+			errPrinter.F("Error in %s: %v\n", sec, err)
+		}
+	}
+	if err = deltaModelsError(deltaModels); err != nil {
+		return err
+	}
+
+	rows := ptf.CurrentHoldings(allDeltas(deltaModels), asOf)
+	ptf.PrintRenderTable(ptf.RenderHoldingsTable(
+		rows, options.Rounding, options.RenderFullDollarValues), writer)
+	return nil
+}
+
+// Writes a csv of every disposition settled in year, with the columns of
+// Schedule 3 for publicly traded shares (see ptf.Schedule3Rows).
+// Returns an *AcbError if the run failed (see ExitCode). All errors are
+// printed to errPrinter.
+func RunSchedule3ToWriter(
+	writer io.Writer,
+	csvFileReaders []DescribedReader,
+	allInitStatus map[string]*ptf.PortfolioSecurityStatus,
+	options AcbRunOptions,
+	year int,
+	ratesCache fx.RatesCache,
+	errPrinter log.ErrorPrinter) error {
+
+	deltaModels, err := RunAcbAppToDeltaModels(
+		csvFileReaders, allInitStatus, options, ratesCache, errPrinter)
+	if err != nil {
+		errPrinter.Ln("Error:", err)
+		return err
+	}
+
+	// Rows would be missing for any security with errors.
+	for sec, deltaModel := range deltaModels {
+		for _, err := range deltaModel.Errors {
+			errPrinter.F("Error in %s: %v\n", sec, err)
+		}
+	}
+	if err = deltaModelsError(deltaModels); err != nil {
+		return err
+	}
+
+	rows := ptf.Schedule3Rows(allDeltas(deltaModels), year)
+	err = ptf.WriteSchedule3Csv(rows, writer)
+	if err != nil {
+		errPrinter.Ln("Error:", err)
+		return newAcbError(ERR_IO, err)
+	}
+	return nil
+}
+
+// Writes the txs of all securities as a single csv in the canonical format
+// (see ptf.MakeNormalizedTxs and ptf.WriteTxCsv). Computing it again (with the
+// same initial statuses) reproduces the same deltas.
+// Returns an *AcbError if the run failed (see ExitCode). All errors are
+// printed to errPrinter.
+func RunNormalizeToWriter(
+	writer io.Writer,
+	csvFileReaders []DescribedReader,
+	allInitStatus map[string]*ptf.PortfolioSecurityStatus,
+	options AcbRunOptions,
+	ratesCache fx.RatesCache,
+	errPrinter log.ErrorPrinter) error {
+
+	deltaModels, err := RunAcbAppToDeltaModels(
+		csvFileReaders, allInitStatus, options, ratesCache, errPrinter)
+	if err != nil {
+		errPrinter.Ln("Error:", err)
+		return err
+	}
+
+	// The superficial losses of a security with errors are unknown.
+	secs := make([]string, 0, len(deltaModels))
+	for sec, deltaModel := range deltaModels {
+		for _, err := range deltaModel.Errors {
+			errPrinter.F("Error in %s: %v\n", sec, err)
+		}
+		secs = append(secs, sec)
+	}
+	if err = deltaModelsError(deltaModels); err != nil {
+		return err
+	}
+
+	sort.Strings(secs)
+	deltas := make([]*ptf.TxDelta, 0)
+	for _, sec := range secs {
+		deltas = append(deltas, deltaModels[sec].Deltas...)
+	}
+	err = ptf.WriteTxCsv(ptf.MakeNormalizedTxs(deltas), options.csvOptions(), writer)
+	if err != nil {
+		errPrinter.Ln("Error:", err)
+		return newAcbError(ERR_IO, err)
+	}
+	return nil
+}
+
+// Writes a table of the deltas which were added, removed or changed from the
+// txs of oldCsvReaders to those of newCsvReaders (see ptf.DiffDeltas).
+// Returns an *AcbError if the run failed (see ExitCode). All errors are
+// printed to errPrinter.
+func RunDiffToWriter(
+	writer io.Writer,
+	oldCsvReaders []DescribedReader,
+	newCsvReaders []DescribedReader,
+	allInitStatus map[string]*ptf.PortfolioSecurityStatus,
+	options AcbRunOptions,
+	ratesCache fx.RatesCache,
+	errPrinter log.ErrorPrinter) error {
+
+	runDeltas := func(csvReaders []DescribedReader) ([]*ptf.TxDelta, error) {
+		deltaModels, err := RunAcbAppToDeltaModels(
+			csvReaders, allInitStatus, options, ratesCache, errPrinter)
+		if err != nil {
+			errPrinter.Ln("Error:", err)
+			return nil, err
+		}
+		// Deltas would be missing for any security with errors.
+		for sec, deltaModel := range deltaModels {
+			for _, err := range deltaModel.Errors {
+				errPrinter.F("Error in %s: %v\n", sec, err)
+			}
+		}
+		if err = deltaModelsError(deltaModels); err != nil {
+			return nil, err
+		}
+		return allDeltas(deltaModels), nil
+	}
+
+	oldDeltas, err := runDeltas(oldCsvReaders)
+	if err != nil {
+		return err
+	}
+	newDeltas, err := runDeltas(newCsvReaders)
+	if err != nil {
+		return err
+	}
+
+	diffs := ptf.DiffDeltas(oldDeltas, newDeltas)
+	if len(diffs) == 0 {
+		_, err = fmt.Fprintln(writer, "No changes")
+	} else {
+		ptf.PrintRenderTable(
+			ptf.RenderDeltaDiffTable(diffs, options.RenderFullDollarValues), writer)
+	}
+	if err != nil {
+		errPrinter.Ln("Error:", err)
+		return newAcbError(ERR_IO, err)
+	}
+	return nil
 }
 
 // Returns an OK flag. Used to signal what exit code to use.
@@ -171,13 +907,14 @@ func RunAcbAppToConsole(
 	forceDownload bool,
 	renderFullDollarValues bool,
 	legacyOptions LegacyOptions,
+	reportOptions ReportOptions,
 	ratesCache fx.RatesCache,
 	errPrinter log.ErrorPrinter) bool {
 
 	ok, _ := RunAcbAppToWriter(
 		os.Stdout,
 		csvFileReaders, allInitStatus, forceDownload, renderFullDollarValues,
-		legacyOptions, ratesCache, errPrinter,
+		legacyOptions, reportOptions, ratesCache, errPrinter,
 	)
 	return ok
 }
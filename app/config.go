@@ -0,0 +1,108 @@
+package app
+
+import (
+	"encoding/json"
+	"fmt"
+	"io"
+	"os"
+	"strings"
+
+	ptf "github.com/tsiemens/acb/portfolio"
+)
+
+// Settings which can be provided in a config file, rather than through
+// individual flags. Eg.
+//
+//	{
+//	  "date_format": "2006/01/02",
+//	  "default_currency": "USD",
+//	  "action_aliases": {"reinvest": "Buy", "distribution": "RoC"},
+//	  "infer_settlement_dates": true,
+//	  "settlement_days": 1,
+//	  "use_trade_date_as_settlement": false
+//	}
+type Config struct {
+	// Format of dates in the csv files (see ptf.ParseDateFormat).
+	DateFormat string `json:"date_format"`
+	// Currency of rows which do not specify one.
+	DefaultCurrency string `json:"default_currency"`
+	// Maps additional action names to the built-in actions.
+	ActionAliases map[string]string `json:"action_aliases"`
+	// If set, rows with only a trade date settle SettlementDays business days
+	// after it (see ptf.CsvOptions.InferSettlementDates).
+	InferSettlementDates bool `json:"infer_settlement_dates"`
+	// Overrides the number of business days used by InferSettlementDates (or
+	// --infer-settlement-dates).
+	SettlementDays *uint32 `json:"settlement_days"`
+	// If set, rows with only a trade date settle on it (see
+	// ptf.CsvOptions.TradeDateAsSettlement).
+	UseTradeDateAsSettlement bool `json:"use_trade_date_as_settlement"`
+}
+
+func LoadConfig(reader io.Reader) (*Config, error) {
+	dec := json.NewDecoder(reader)
+	dec.DisallowUnknownFields()
+	config := &Config{}
+	err := dec.Decode(config)
+	if err != nil {
+		return nil, fmt.Errorf("Invalid config: %v", err)
+	}
+	return config, nil
+}
+
+func LoadConfigFile(path string) (*Config, error) {
+	fp, err := os.Open(path)
+	if err != nil {
+		return nil, err
+	}
+	defer fp.Close()
+	config, err := LoadConfig(fp)
+	if err != nil {
+		return nil, fmt.Errorf("%s: %v", path, err)
+	}
+	return config, nil
+}
+
+// Parses a map of additional action names to built-in action names into
+// (lower-case) aliases for ptf.CsvOptions.ActionAliases.
+func parseActionAliases(actionNames map[string]string) (map[string]ptf.TxAction, error) {
+	aliases := make(map[string]ptf.TxAction)
+	for alias, actionName := range actionNames {
+		action, err := ptf.ParseTxAction(actionName, nil)
+		if err != nil {
+			return nil, fmt.Errorf("Invalid action for alias '%s': %v", alias, err)
+		}
+		aliases[strings.TrimSpace(strings.ToLower(alias))] = action
+	}
+	return aliases, nil
+}
+
+// Applies the config to the csv options of options.
+// If applyDateFormat is false, the date format in the config is ignored
+// (eg. because it was overridden on the command line).
+func (c *Config) Apply(options *AcbRunOptions, applyDateFormat bool) error {
+	aliases, err := parseActionAliases(c.ActionAliases)
+	if err != nil {
+		return err
+	}
+
+	if applyDateFormat && c.DateFormat != "" {
+		dateFormat, err := ptf.ParseDateFormat(c.DateFormat)
+		if err != nil {
+			return err
+		}
+		options.CsvDateFormat = dateFormat
+	}
+	options.CsvDefaultCurrency = ptf.Currency(strings.ToUpper(c.DefaultCurrency))
+	options.CsvActionAliases = aliases
+	if c.InferSettlementDates {
+		options.CsvInferSettlementDates = true
+	}
+	if c.SettlementDays != nil {
+		options.CsvSettlementDays = *c.SettlementDays
+	}
+	if c.UseTradeDateAsSettlement {
+		options.CsvTradeDateAsSettlement = true
+	}
+	return nil
+}
@@ -0,0 +1,93 @@
+package app
+
+import (
+	"errors"
+)
+
+// The class of failure of a run, which determines the exit code of the cli.
+type ErrorCategory int
+
+const (
+	// A csv could not be parsed (including invalid values in it).
+	ERR_PARSE ErrorCategory = iota
+	// The input was parsed, but is not valid for the run (eg. an invalid
+	// option, or txs dated in the future with --error-on-future-dates).
+	ERR_VALIDATION
+	// The deltas of some security could not be computed (eg. a sale of more
+	// shares than are held, or a RoC which exceeds the ACB).
+	ERR_COMPUTATION
+	// Output could not be written.
+	ERR_IO
+)
+
+// The exit code for errors which are not an *AcbError (eg. invalid flags).
+const ExitCodeGeneralError = 1
+
+func (c ErrorCategory) String() string {
+	switch c {
+	case ERR_PARSE:
+		return "parse error"
+	case ERR_VALIDATION:
+		return "validation error"
+	case ERR_COMPUTATION:
+		return "computation error"
+	case ERR_IO:
+		return "io error"
+	default:
+		return "error"
+	}
+}
+
+// Returns the process exit code for the category.
+func (c ErrorCategory) ExitCode() int {
+	switch c {
+	case ERR_PARSE:
+		return 2
+	case ERR_VALIDATION:
+		return 3
+	case ERR_COMPUTATION:
+		return 4
+	case ERR_IO:
+		return 5
+	default:
+		return ExitCodeGeneralError
+	}
+}
+
+// An error of a run, with its category.
+type AcbError struct {
+	Category ErrorCategory
+	Err      error
+}
+
+func newAcbError(category ErrorCategory, err error) *AcbError {
+	return &AcbError{Category: category, Err: err}
+}
+
+func (e *AcbError) Error() string {
+	return e.Err.Error()
+}
+
+func (e *AcbError) Unwrap() error {
+	return e.Err
+}
+
+// Returns the category of err, if it is (or wraps) an *AcbError.
+func ErrorCategoryOf(err error) (ErrorCategory, bool) {
+	var acbErr *AcbError
+	if errors.As(err, &acbErr) {
+		return acbErr.Category, true
+	}
+	return 0, false
+}
+
+// Returns the process exit code for err (0 if it is nil).
+func ExitCode(err error) int {
+	if err == nil {
+		return 0
+	}
+	if category, ok := ErrorCategoryOf(err); ok {
+		return category.ExitCode()
+	}
+	return ExitCodeGeneralError
+}
@@ -0,0 +1,306 @@
+package app
+
+import (
+	"bytes"
+	"encoding/json"
+	"fmt"
+	"strings"
+
+	"github.com/tsiemens/acb/fx"
+	ptf "github.com/tsiemens/acb/portfolio"
+)
+
+// All of the options for a run of the app (see RunAcbAppWithOptions).
+// The options are only read by a run, so the same options may be used by
+// concurrent runs.
+type AcbRunOptions struct {
+	ForceDownload          bool
+	RenderFullDollarValues bool
+	// If set, the exchange rates published since the cached ones are
+	// downloaded (see fx.RateLoader.RefreshStale).
+	RefreshStaleRates bool
+	// The currency in which ACB and gains are computed.
+	LocalCurrency ptf.Currency
+	Legacy        LegacyOptions
+	Report        ReportOptions
+
+	// The options of the same names in ptf.DeltaOptions.
+	Rounding               ptf.RoundingPolicy
+	CostBasis              ptf.CostBasisMethod
+	SuperficialLosses      ptf.SuperficialLossMode
+	AllowShortSales        bool
+	AutoOddLotSales        bool
+	PerShareAcbTracking    bool
+	DistributionFeesNetted bool
+	// If set, the buys (and sells) of a security on the same day are combined
+	// into a single Buy (or Sell) before computing deltas. See
+	// ptf.NetSameDayTrades.
+	SameDayTradeNetting bool
+
+	// Exchange rates outside of this range are warned about (see
+	// ptf.FindImplausibleExchangeRates). If it is the zero range, no rates are
+	// checked.
+	PlausibleExchangeRates ptf.ExchangeRateRange
+	// If set, currencies which are not ISO 4217 codes are warned about (see
+	// ptf.FindUnknownCurrencies).
+	CheckCurrencyCodes bool
+	// If set, txs dated after today are an error, rather than a warning.
+	FutureTxDatesAreErrors bool
+
+	// The options of ptf.CsvOptions, without the Csv prefix.
+	CsvDateFormat              string
+	CsvDelimiter               rune
+	CsvDecimalComma            bool
+	CsvInferSettlementDates    bool
+	CsvSettlementDays          uint32
+	CsvTradeDateAsSettlement   bool
+	CsvExchangeRateConvention  ptf.ExchangeRateConvention
+	CsvActionAliases           map[string]ptf.TxAction
+	CsvDefaultCurrency         ptf.Currency
+	CsvAmountPerSharePrecision int
+	CsvFillDownSecurity        bool
+	CsvAllowNegativeCommission bool
+	CsvRateTable               *fx.CsvRateTable
+}
+
+func NewAcbRunOptions() AcbRunOptions {
+	return AcbRunOptions{
+		ForceDownload:          false,
+		RenderFullDollarValues: false,
+		RefreshStaleRates:      false,
+		LocalCurrency:          ptf.CAD,
+		Legacy:                 NewLegacyOptions(),
+		Report:                 NewReportOptions(),
+
+		Rounding:          ptf.ROUND_NONE,
+		CostBasis:         ptf.ACB_COST_BASIS,
+		SuperficialLosses: ptf.SFL_AUTO,
+
+		PlausibleExchangeRates: ptf.PlausibleExchangeRatesDefault,
+		CheckCurrencyCodes:     true,
+		FutureTxDatesAreErrors: false,
+
+		CsvDateFormat:              ptf.CsvDateFormatDefault,
+		CsvDelimiter:               ',',
+		CsvSettlementDays:          ptf.CsvSettlementDaysDefault,
+		CsvExchangeRateConvention:  ptf.FX_LOCAL_PER_FOREIGN,
+		CsvActionAliases:           map[string]ptf.TxAction{},
+		CsvDefaultCurrency:         ptf.DEFAULT_CURRENCY,
+		CsvAmountPerSharePrecision: -1,
+	}
+}
+
+// The json representation of AcbRunOptions. Eg.
+//
+//	{
+//	  "render_full_values": true,
+//	  "local_currency": "CAD",
+//	  "rounding": "cents",
+//	  "cost_basis": "acb",
+//	  "sfl_mode": "auto",
+//	  "net_same_day_trades": true,
+//	  "fx_sanity_range": "0.3:3.0",
+//	  "legacy": {"no_superficial_losses": false},
+//	  "report": {"output_format": "json", "year_totals": true},
+//	  "csv": {"date_format": "dmy", "delimiter": ";", "decimal_comma": true}
+//	}
+type jsonAcbRunOptions struct {
+	ForceDownload          bool           `json:"force_download"`
+	RenderFullDollarValues bool           `json:"render_full_values"`
+	RefreshStaleRates      bool           `json:"refresh_fx"`
+	LocalCurrency          string         `json:"local_currency"`
+	Rounding               string         `json:"rounding"`
+	CostBasis              string         `json:"cost_basis"`
+	SuperficialLosses      string         `json:"sfl_mode"`
+	AllowShortSales        bool           `json:"allow_short"`
+	AutoOddLotSales        bool           `json:"auto_odd_lot_sales"`
+	PerShareAcbTracking    bool           `json:"per_share_acb"`
+	DistributionFeesNetted bool           `json:"net_distribution_fees"`
+	SameDayTradeNetting    bool           `json:"net_same_day_trades"`
+	PlausibleExchangeRates string         `json:"fx_sanity_range"`
+	CheckCurrencyCodes     bool           `json:"check_currency_codes"`
+	FutureTxDatesAreErrors bool           `json:"error_on_future_dates"`
+	Legacy                 LegacyOptions  `json:"legacy"`
+	Report                 ReportOptions  `json:"report"`
+	Csv                    jsonCsvOptions `json:"csv"`
+}
+
+// The json representation of the Csv options of AcbRunOptions.
+type jsonCsvOptions struct {
+	// See ptf.ParseDateFormat.
+	DateFormat string `json:"date_format"`
+	// See ptf.ParseCsvDelimiter.
+	Delimiter             string `json:"delimiter"`
+	DecimalComma          bool   `json:"decimal_comma"`
+	InferSettlementDates  bool   `json:"infer_settlement_dates"`
+	SettlementDays        uint32 `json:"settlement_days"`
+	TradeDateAsSettlement bool   `json:"trade_date_as_settlement"`
+	// See ptf.ParseExchangeRateConvention.
+	ExchangeRateConvention string `json:"fx_convention"`
+	// Maps additional action names to the built-in actions (as in Config).
+	ActionAliases           map[string]string `json:"action_aliases"`
+	DefaultCurrency         string            `json:"default_currency"`
+	AmountPerSharePrecision int               `json:"amount_per_share_precision"`
+	FillDownSecurity        bool              `json:"fill_down_security"`
+	AllowNegativeCommission bool              `json:"allow_commission_rebates"`
+	// The contents of a rates csv (see fx.ReadCsvRateTable), rather than a path,
+	// since there may be no file system to read from (eg. in wasm).
+	RatesCsv string `json:"rates_csv"`
+}
+
+// Parses options from json (eg. a JSON.stringify'd js object). Any options
+// which are not present keep their NewAcbRunOptions defaults.
+func ParseAcbRunOptionsJson(data []byte) (AcbRunOptions, error) {
+	defaults := NewAcbRunOptions()
+	defaultRange := fmt.Sprintf("%v:%v",
+		defaults.PlausibleExchangeRates.Min, defaults.PlausibleExchangeRates.Max)
+	jsonOptions := jsonAcbRunOptions{
+		LocalCurrency:          string(defaults.LocalCurrency),
+		PlausibleExchangeRates: defaultRange,
+		CheckCurrencyCodes:     defaults.CheckCurrencyCodes,
+		Legacy:                 defaults.Legacy,
+		Report:                 defaults.Report,
+		Csv: jsonCsvOptions{
+			DateFormat:              defaults.CsvDateFormat,
+			Delimiter:               string(defaults.CsvDelimiter),
+			SettlementDays:          defaults.CsvSettlementDays,
+			AmountPerSharePrecision: defaults.CsvAmountPerSharePrecision,
+		},
+	}
+	dec := json.NewDecoder(bytes.NewReader(data))
+	dec.DisallowUnknownFields()
+	err := dec.Decode(&jsonOptions)
+	if err != nil {
+		return defaults, fmt.Errorf("Invalid options: %v", err)
+	}
+
+	options := defaults
+	options.ForceDownload = jsonOptions.ForceDownload
+	options.RenderFullDollarValues = jsonOptions.RenderFullDollarValues
+	options.RefreshStaleRates = jsonOptions.RefreshStaleRates
+	options.LocalCurrency, err = ParseLocalCurrency(jsonOptions.LocalCurrency)
+	if err != nil {
+		return defaults, fmt.Errorf("Invalid options: local_currency %v", err)
+	}
+	options.Rounding, err = ptf.ParseRoundingPolicy(jsonOptions.Rounding)
+	if err != nil {
+		return defaults, fmt.Errorf("Invalid options: %v", err)
+	}
+	options.CostBasis, err = ptf.ParseCostBasisMethod(jsonOptions.CostBasis)
+	if err != nil {
+		return defaults, fmt.Errorf("Invalid options: %v", err)
+	}
+	options.SuperficialLosses, err = ptf.ParseSuperficialLossMode(jsonOptions.SuperficialLosses)
+	if err != nil {
+		return defaults, fmt.Errorf("Invalid options: %v", err)
+	}
+	options.AllowShortSales = jsonOptions.AllowShortSales
+	options.AutoOddLotSales = jsonOptions.AutoOddLotSales
+	options.PerShareAcbTracking = jsonOptions.PerShareAcbTracking
+	options.DistributionFeesNetted = jsonOptions.DistributionFeesNetted
+	options.SameDayTradeNetting = jsonOptions.SameDayTradeNetting
+	options.PlausibleExchangeRates, err = ptf.ParseExchangeRateRange(
+		jsonOptions.PlausibleExchangeRates)
+	if err != nil {
+		return defaults, fmt.Errorf("Invalid options: %v", err)
+	}
+	options.CheckCurrencyCodes = jsonOptions.CheckCurrencyCodes
+	options.FutureTxDatesAreErrors = jsonOptions.FutureTxDatesAreErrors
+	options.Legacy = jsonOptions.Legacy
+	options.Report = jsonOptions.Report
+
+	err = jsonOptions.Csv.applyTo(&options)
+	if err != nil {
+		return defaults, fmt.Errorf("Invalid options: csv: %v", err)
+	}
+	return options, nil
+}
+
+// Sets the Csv options of options (whose LocalCurrency must already be set).
+func (c *jsonCsvOptions) applyTo(options *AcbRunOptions) error {
+	var err error
+	options.CsvDateFormat, err = ptf.ParseDateFormat(c.DateFormat)
+	if err != nil {
+		return err
+	}
+	options.CsvDelimiter, err = ptf.ParseCsvDelimiter(c.Delimiter)
+	if err != nil {
+		return err
+	}
+	err = ptf.CheckCsvDecimalComma(options.CsvDelimiter, c.DecimalComma)
+	if err != nil {
+		return err
+	}
+	options.CsvDecimalComma = c.DecimalComma
+	options.CsvInferSettlementDates = c.InferSettlementDates
+	options.CsvSettlementDays = c.SettlementDays
+	options.CsvTradeDateAsSettlement = c.TradeDateAsSettlement
+	options.CsvExchangeRateConvention, err = ptf.ParseExchangeRateConvention(
+		c.ExchangeRateConvention)
+	if err != nil {
+		return err
+	}
+	options.CsvActionAliases, err = parseActionAliases(c.ActionAliases)
+	if err != nil {
+		return err
+	}
+	options.CsvDefaultCurrency = ptf.Currency(strings.ToUpper(c.DefaultCurrency))
+	options.CsvAmountPerSharePrecision = c.AmountPerSharePrecision
+	options.CsvFillDownSecurity = c.FillDownSecurity
+	options.CsvAllowNegativeCommission = c.AllowNegativeCommission
+	if c.RatesCsv != "" {
+		options.CsvRateTable, err = fx.ReadCsvRateTable(
+			strings.NewReader(c.RatesCsv), "rates_csv", string(options.LocalCurrency))
+		if err != nil {
+			return err
+		}
+	}
+	return nil
+}
+
+// Returns the options for computing the deltas.
+func (o *AcbRunOptions) deltaOptions() ptf.DeltaOptions {
+	return ptf.DeltaOptions{
+		Legacy: ptf.LegacyOptions{
+			NoSuperficialLosses:        o.Legacy.NoSuperficialLosses,
+			NoPartialSuperficialLosses: o.Legacy.NoPartialSuperficialLosses,
+		},
+		LocalCurrency:          o.LocalCurrency,
+		CostBasis:              o.CostBasis,
+		SuperficialLosses:      o.SuperficialLosses,
+		Rounding:               o.Rounding,
+		AllowShortSales:        o.AllowShortSales,
+		PerShareAcbTracking:    o.PerShareAcbTracking,
+		AutoOddLotSales:        o.AutoOddLotSales,
+		DistributionFeesNetted: o.DistributionFeesNetted,
+	}
+}
+
+// Returns the options for reading (and writing) the csvs.
+func (o *AcbRunOptions) csvOptions() ptf.CsvOptions {
+	return ptf.CsvOptions{
+		LocalCurrency:           o.LocalCurrency,
+		DateFormat:              o.CsvDateFormat,
+		Delimiter:               o.CsvDelimiter,
+		DecimalComma:            o.CsvDecimalComma,
+		InferSettlementDates:    o.CsvInferSettlementDates,
+		SettlementDays:          o.CsvSettlementDays,
+		TradeDateAsSettlement:   o.CsvTradeDateAsSettlement,
+		ExchangeRateConvention:  o.CsvExchangeRateConvention,
+		ActionAliases:           o.CsvActionAliases,
+		DefaultCurrency:         o.CsvDefaultCurrency,
+		AmountPerSharePrecision: o.CsvAmountPerSharePrecision,
+		FillDownSecurity:        o.CsvFillDownSecurity,
+		AllowNegativeCommission: o.CsvAllowNegativeCommission,
+		RateTable:               o.CsvRateTable,
+	}
+}
+
+// Parses a currency code (case-insensitive) for AcbRunOptions.LocalCurrency.
+func ParseLocalCurrency(code string) (ptf.Currency, error) {
+	currency := ptf.Currency(strings.ToUpper(strings.TrimSpace(code)))
+	if currency == ptf.DEFAULT_CURRENCY {
+		return currency, fmt.Errorf("cannot be empty")
+	}
+	return currency, nil
+}
@@ -0,0 +1,59 @@
+package cmd
+
+import (
+	"os"
+
+	"github.com/spf13/cobra"
+
+	"github.com/tsiemens/acb/app"
+	"github.com/tsiemens/acb/fx"
+	"github.com/tsiemens/acb/log"
+)
+
+func runDiffCmd(cmd *cobra.Command, args []string) {
+	errPrinter := log.NewWarningRecordingErrorPrinter(&log.StderrErrorPrinter{})
+
+	nOld := cmd.ArgsLenAtDash()
+	if nOld == -1 {
+		if len(args) != 2 {
+			errPrinter.F("Error: separate the old and new csvs with --\n")
+			os.Exit(1)
+		}
+		nOld = 1
+	}
+	if nOld == 0 || nOld == len(args) {
+		errPrinter.F("Error: at least one old and one new csv are required\n")
+		os.Exit(1)
+	}
+
+	allInitStatus, csvReaders, options := prepareCsvRun(cmd, args, errPrinter)
+	defer closeCsvReaders(csvReaders)
+
+	err := app.RunDiffToWriter(
+		os.Stdout,
+		csvReaders[:nOld], csvReaders[nOld:], allInitStatus, options,
+		&fx.CsvRatesCache{ErrPrinter: errPrinter}, errPrinter)
+	if err != nil {
+		os.Exit(app.ExitCode(err))
+	}
+	exitOnStrictWarnings(errPrinter)
+}
+
+var diffCmd = &cobra.Command{
+	Use:   "diff OLD_CSV NEW_CSV | diff OLD_CSV ... -- NEW_CSV ...",
+	Short: "Print the deltas which changed between two sets of csvs",
+	Long: `Compute the deltas of the old csvs and of the new csvs, and print those
+which were added, removed or changed, with their old and new ACB and capital
+gain.
+
+Deltas are matched by security, settlement date and action. Where several
+match, those with the same shares and amounts are matched first, so a row added
+on the same day as another is shown as added, rather than as a change to the
+other.`,
+	Run:  runDiffCmd,
+	Args: cobra.MinimumNArgs(2),
+}
+
+func init() {
+	RootCmd.AddCommand(diffCmd)
+}
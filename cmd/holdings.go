@@ -0,0 +1,58 @@
+package cmd
+
+import (
+	"os"
+	"strings"
+	"time"
+
+	"github.com/spf13/cobra"
+
+	"github.com/tsiemens/acb/app"
+	"github.com/tsiemens/acb/fx"
+	"github.com/tsiemens/acb/log"
+	ptf "github.com/tsiemens/acb/portfolio"
+)
+
+var HoldingsAsOfOpt string
+
+func runHoldingsCmd(cmd *cobra.Command, args []string) {
+	errPrinter := log.NewWarningRecordingErrorPrinter(&log.StderrErrorPrinter{})
+
+	var asOf time.Time
+	if HoldingsAsOfOpt != "" {
+		var err error
+		asOf, err = time.Parse(ptf.CsvDateFormatDefault, strings.TrimSpace(HoldingsAsOfOpt))
+		if err != nil {
+			errPrinter.F("Error parsing --as-of: %v\n", err)
+			os.Exit(1)
+		}
+	}
+
+	allInitStatus, csvReaders, options := prepareCsvRun(cmd, args, errPrinter)
+	defer closeCsvReaders(csvReaders)
+
+	err := app.RunHoldingsToWriter(
+		os.Stdout,
+		csvReaders, allInitStatus, options, asOf, &fx.CsvRatesCache{ErrPrinter: errPrinter}, errPrinter)
+	if err != nil {
+		os.Exit(app.ExitCode(err))
+	}
+	exitOnStrictWarnings(errPrinter)
+}
+
+var holdingsCmd = &cobra.Command{
+	Use:   "holdings [CSV_FILE ...]",
+	Short: "Print the share balance and ACB of each security currently held",
+	Long: `Print the share balance, total ACB and ACB per share of each security held
+after its last transaction, or its last transaction settled on or before
+--as-of. Securities with no shares held are omitted.`,
+	Run:  runHoldingsCmd,
+	Args: cobra.MinimumNArgs(1),
+}
+
+func init() {
+	holdingsCmd.Flags().StringVar(&HoldingsAsOfOpt, "as-of", "",
+		"Show the holdings as of this date (YYYY-MM-DD), rather than after the last "+
+			"transaction")
+	RootCmd.AddCommand(holdingsCmd)
+}
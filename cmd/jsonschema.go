@@ -0,0 +1,31 @@
+package cmd
+
+import (
+	"encoding/json"
+	"fmt"
+	"os"
+
+	"github.com/spf13/cobra"
+
+	ptf "github.com/tsiemens/acb/portfolio"
+)
+
+func runJsonSchemaCmd(cmd *cobra.Command, args []string) {
+	out, err := json.MarshalIndent(ptf.JsonReportSchema(), "", "  ")
+	if err != nil {
+		fmt.Fprintln(os.Stderr, "Error:", err)
+		os.Exit(1)
+	}
+	fmt.Println(string(out))
+}
+
+var jsonSchemaCmd = &cobra.Command{
+	Use:   "json-schema",
+	Short: "Print the JSON Schema of the JSON report format",
+	Run:   runJsonSchemaCmd,
+	Args:  cobra.NoArgs,
+}
+
+func init() {
+	RootCmd.AddCommand(jsonSchemaCmd)
+}
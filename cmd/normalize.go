@@ -0,0 +1,45 @@
+package cmd
+
+import (
+	"os"
+
+	"github.com/spf13/cobra"
+
+	"github.com/tsiemens/acb/app"
+	"github.com/tsiemens/acb/fx"
+	"github.com/tsiemens/acb/log"
+)
+
+func runNormalizeCmd(cmd *cobra.Command, args []string) {
+	errPrinter := log.NewWarningRecordingErrorPrinter(&log.StderrErrorPrinter{})
+
+	allInitStatus, csvReaders, options := prepareCsvRun(cmd, args, errPrinter)
+	defer closeCsvReaders(csvReaders)
+
+	err := app.RunNormalizeToWriter(
+		os.Stdout,
+		csvReaders, allInitStatus, options,
+		&fx.CsvRatesCache{ErrPrinter: errPrinter}, errPrinter)
+	if err != nil {
+		os.Exit(app.ExitCode(err))
+	}
+	exitOnStrictWarnings(errPrinter)
+}
+
+var normalizeCmd = &cobra.Command{
+	Use:   "normalize [CSV_FILE ...]",
+	Short: "Print all transactions as a single csv in the canonical format",
+	Long: `Read the csvs (in any supported format) and print all of their transactions
+as a single csv, with every column acb understands and resolved exchange rates.
+
+Sells specify the superficial loss which was applied to them, so the csv
+produces the same results regardless of superficial loss settings. Transactions
+derived from others (eg. the Acquire of a Spinoff) are omitted, as they are
+derived again. Any --symbol-base must be provided again when it is read.`,
+	Run:  runNormalizeCmd,
+	Args: cobra.MinimumNArgs(1),
+}
+
+func init() {
+	RootCmd.AddCommand(normalizeCmd)
+}
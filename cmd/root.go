@@ -2,9 +2,11 @@ package cmd
 
 import (
 	"fmt"
+	"io"
 	"os"
 	"path/filepath"
 	"strings"
+	"time"
 
 	"github.com/spf13/cobra"
 	// "github.com/spf13/viper"
@@ -13,16 +15,140 @@ import (
 	"github.com/tsiemens/acb/fx"
 	"github.com/tsiemens/acb/log"
 	ptf "github.com/tsiemens/acb/portfolio"
+	"github.com/tsiemens/acb/util"
 )
 
 var ForceDownload = false
 var PrintFullDollarValues = false
 var InitialSymStatusOpt []string
+var ConfigFile string
+var LocalCurrencyOpt string
+var BrokerOpt string
+var FxConventionOpt string
+var CostBasisOpt string
+var RoundingOpt string
+var SflModeOpt string
+var DelimiterOpt string
+var FxSanityRangeOpt string
+var RatesCsvOpt string
+var SinceOpt string
+var InclusionSplitDateOpt string
+var Strict = false
 
 var legacyOptions = app.NewLegacyOptions()
+var reportOptions = app.NewReportOptions()
 
-func runRootCmd(cmd *cobra.Command, args []string) {
-	errPrinter := &log.StderrErrorPrinter{}
+// The flags bound directly to run options. The rest are set by prepareCsvRun.
+var runOptions = app.NewAcbRunOptions()
+
+// Applies the options common to all commands which read transaction csvs,
+// and opens the csvs. Exits on any error.
+// The returned readers should be closed with closeCsvReaders.
+func prepareCsvRun(cmd *cobra.Command, args []string, errPrinter log.ErrorPrinter) (
+	map[string]*ptf.PortfolioSecurityStatus, []app.DescribedReader, app.AcbRunOptions) {
+
+	options := runOptions
+	if ConfigFile != "" {
+		config, err := app.LoadConfigFile(ConfigFile)
+		if err == nil {
+			err = config.Apply(&options, !cmd.Flags().Changed("date-fmt"))
+		}
+		if err != nil {
+			errPrinter.F("Error loading --config: %v\n", err)
+			os.Exit(1)
+		}
+		if cmd.Flags().Changed("settlement-days") {
+			options.CsvSettlementDays = runOptions.CsvSettlementDays
+		}
+	}
+
+	dateFormat, err := ptf.ParseDateFormat(options.CsvDateFormat)
+	if err != nil {
+		errPrinter.F("Error parsing --date-fmt: %v\n", err)
+		os.Exit(1)
+	}
+	options.CsvDateFormat = dateFormat
+
+	options.CsvDelimiter, err = ptf.ParseCsvDelimiter(DelimiterOpt)
+	if err != nil {
+		errPrinter.F("Error parsing --delimiter: %v\n", err)
+		os.Exit(1)
+	}
+	err = ptf.CheckCsvDecimalComma(options.CsvDelimiter, options.CsvDecimalComma)
+	if err != nil {
+		errPrinter.F("Error: --decimal-comma: %v\n", err)
+		os.Exit(1)
+	}
+
+	options.LocalCurrency, err = app.ParseLocalCurrency(LocalCurrencyOpt)
+	if err != nil {
+		errPrinter.F("Error: --local-currency %v\n", err)
+		os.Exit(1)
+	}
+
+	options.CsvExchangeRateConvention, err = ptf.ParseExchangeRateConvention(FxConventionOpt)
+	if err != nil {
+		errPrinter.F("Error parsing --fx-convention: %v\n", err)
+		os.Exit(1)
+	}
+
+	options.PlausibleExchangeRates, err = ptf.ParseExchangeRateRange(FxSanityRangeOpt)
+	if err != nil {
+		errPrinter.F("Error parsing --fx-sanity-range: %v\n", err)
+		os.Exit(1)
+	}
+
+	if RatesCsvOpt != "" {
+		fp, err := os.Open(RatesCsvOpt)
+		if err != nil {
+			errPrinter.F("Error opening --rates-csv: %v\n", err)
+			os.Exit(app.ERR_IO.ExitCode())
+		}
+		options.CsvRateTable, err = fx.ReadCsvRateTable(
+			fp, RatesCsvOpt, string(options.LocalCurrency))
+		fp.Close()
+		if err != nil {
+			errPrinter.F("Error: %v\n", err)
+			os.Exit(1)
+		}
+	}
+
+	options.ForceDownload = ForceDownload
+	options.RenderFullDollarValues = PrintFullDollarValues
+
+	options.CostBasis, err = ptf.ParseCostBasisMethod(CostBasisOpt)
+	if err != nil {
+		errPrinter.F("Error parsing --cost-basis: %v\n", err)
+		os.Exit(1)
+	}
+
+	options.Rounding, err = ptf.ParseRoundingPolicy(RoundingOpt)
+	if err != nil {
+		errPrinter.F("Error parsing --rounding: %v\n", err)
+		os.Exit(1)
+	}
+
+	options.SuperficialLosses, err = ptf.ParseSuperficialLossMode(SflModeOpt)
+	if err != nil {
+		errPrinter.F("Error parsing --sfl-mode: %v\n", err)
+		os.Exit(1)
+	}
+
+	if SinceOpt != "" {
+		reportOptions.Since, err = time.Parse(ptf.CsvDateFormatDefault, strings.TrimSpace(SinceOpt))
+		if err != nil {
+			errPrinter.F("Error parsing --since: %v\n", err)
+			os.Exit(1)
+		}
+	}
+	if InclusionSplitDateOpt != "" {
+		reportOptions.InclusionSplitDate, err = time.Parse(
+			ptf.CsvDateFormatDefault, strings.TrimSpace(InclusionSplitDateOpt))
+		if err != nil {
+			errPrinter.F("Error parsing --inclusion-split-date: %v\n", err)
+			os.Exit(1)
+		}
+	}
 
 	allInitStatus, err := app.ParseInitialStatus(InitialSymStatusOpt)
 	if err != nil {
@@ -34,22 +160,60 @@ func runRootCmd(cmd *cobra.Command, args []string) {
 	for _, csvName := range args {
 		fp, err := os.Open(csvName)
 		if err != nil {
+			closeCsvReaders(csvReaders)
 			errPrinter.F("Error: %v\n", err)
-			os.Exit(1)
+			os.Exit(app.ERR_IO.ExitCode())
 		}
-		defer fp.Close()
 		csvReaders = append(csvReaders, app.DescribedReader{csvName, fp})
 	}
 
-	ok := app.RunAcbAppToConsole(
-		csvReaders, allInitStatus, ForceDownload, PrintFullDollarValues,
-		legacyOptions,
-		&fx.CsvRatesCache{ErrPrinter: errPrinter}, errPrinter)
-	if !ok {
+	if BrokerOpt != "" {
+		converted, err := app.ConvertBrokerCsvs(BrokerOpt, csvReaders, options, errPrinter)
+		// The converted readers do not depend on the files.
+		closeCsvReaders(csvReaders)
+		if err != nil {
+			errPrinter.F("Error: %v\n", err)
+			os.Exit(app.ExitCode(err))
+		}
+		csvReaders = converted
+	}
+
+	options.Legacy = legacyOptions
+	options.Report = reportOptions
+	return allInitStatus, csvReaders, options
+}
+
+// Exits with an error if --strict was given, and any warnings were printed.
+func exitOnStrictWarnings(errPrinter *log.WarningRecordingErrorPrinter) {
+	if Strict && errPrinter.SawWarning {
+		errPrinter.F("Error: Warnings are treated as errors with --strict\n")
 		os.Exit(1)
 	}
 }
 
+func closeCsvReaders(csvReaders []app.DescribedReader) {
+	for _, csvReader := range csvReaders {
+		if closer, ok := csvReader.Reader.(io.Closer); ok {
+			closer.Close()
+		}
+	}
+}
+
+func runRootCmd(cmd *cobra.Command, args []string) {
+	errPrinter := log.NewWarningRecordingErrorPrinter(&log.StderrErrorPrinter{})
+
+	allInitStatus, csvReaders, options := prepareCsvRun(cmd, args, errPrinter)
+	defer closeCsvReaders(csvReaders)
+
+	_, err := app.RunAcbAppWithOptions(
+		os.Stdout, csvReaders, allInitStatus, options,
+		&fx.CsvRatesCache{ErrPrinter: errPrinter}, errPrinter)
+	if err != nil {
+		os.Exit(app.ExitCode(err))
+	}
+	exitOnStrictWarnings(errPrinter)
+}
+
 func cmdName() string {
 	binName := os.Args[0]
 	return filepath.Base(binName)
@@ -73,8 +237,13 @@ Each CSV provided should contain a header with these column names:
 %s
 Non-essential columns like exchange rates and currency columns are optional.
 
-Exchange rates are always provided to be multiplied with the given amount to produce
-the equivalent value in the default (local) currency.
+Exchange rates are provided to be multiplied with the given amount to produce
+the equivalent value in the default (local) currency (unless --fx-convention is
+inverted, in which case the given amount is divided by them).
+
+Exit codes: 1 for invalid options, 2 if a csv could not be parsed, 3 if the
+transactions are not valid for the run, 4 if the ACB of some security could not
+be computed, and 5 if a file could not be read or the output written.
  `, strings.Join(ptf.ColNames, ", ")),
 	// Uncomment the following line if your bare application
 	// has an action associated with it:
@@ -100,13 +269,160 @@ func init() {
 		"Print verbose output")
 	RootCmd.PersistentFlags().BoolVarP(&ForceDownload, "force-download", "f", false,
 		"Download exchange rates, even if they are cached")
-	RootCmd.PersistentFlags().StringVar(&ptf.CsvDateFormat, "date-fmt", ptf.CsvDateFormatDefault,
-		"Format of how dates appear in the csv file. Must represent Jan 2, 2006")
-	RootCmd.Flags().StringSliceVarP(&InitialSymStatusOpt, "symbol-base", "b", []string{},
+	RootCmd.PersistentFlags().BoolVar(&runOptions.RefreshStaleRates, "refresh-fx", false,
+		"Download only the exchange rates published since those cached. "+
+			"(By default, this is only done for dates which have no cached rate.)")
+	RootCmd.PersistentFlags().StringVar(&runOptions.CsvDateFormat, "date-fmt",
+		ptf.CsvDateFormatDefault,
+		"Format of how dates appear in the csv file. Either iso (2006-01-02), ymd (2006/01/02), "+
+			"mdy (01/02/2006), dmy (02/01/2006), or a format representing Jan 2, 2006")
+	RootCmd.PersistentFlags().StringVar(&DelimiterOpt, "delimiter", ",",
+		"Field delimiter of the csv files (eg. ';'), or 'tab'")
+	RootCmd.PersistentFlags().BoolVar(&runOptions.CsvDecimalComma, "decimal-comma", false,
+		"Numbers in the csv files use a decimal comma, optionally with periods "+
+			"separating thousands (eg. 1.234,56). Requires a --delimiter other than ','")
+	RootCmd.PersistentFlags().BoolVar(&runOptions.CsvInferSettlementDates,
+		"infer-settlement-dates", false,
+		"For rows with a trade date but no date, use a settlement date --settlement-days "+
+			"business days after the trade date (weekends are skipped, holidays are not).")
+	RootCmd.PersistentFlags().Uint32Var(&runOptions.CsvSettlementDays,
+		"settlement-days", ptf.CsvSettlementDaysDefault,
+		"Business days between trade and settlement, for --infer-settlement-dates")
+	RootCmd.PersistentFlags().BoolVar(&runOptions.CsvFillDownSecurity,
+		"fill-down-security", false,
+		"For rows with an empty security, use the security of the previous row "+
+			"in the same csv.")
+	RootCmd.PersistentFlags().BoolVar(&runOptions.CsvAllowNegativeCommission,
+		"allow-commission-rebates", false,
+		"Allow negative commissions, for rebates paid by the broker. A rebate reduces "+
+			"the ACB of a buy, and increases the proceeds of a sell.")
+	RootCmd.PersistentFlags().BoolVar(&runOptions.CsvTradeDateAsSettlement,
+		"trade-date-as-settlement", false,
+		"For rows with a trade date but no date, use the trade date as the settlement date. "+
+			"This shifts superficial loss periods slightly from the true ones.")
+	RootCmd.PersistentFlags().IntVar(&runOptions.CsvAmountPerSharePrecision,
+		"amount-per-share-precision", -1,
+		"Round amounts per share in the csv files to this many decimal places "+
+			"(eg. 4). By default (-1), they are not rounded.")
+	RootCmd.PersistentFlags().StringVar(&LocalCurrencyOpt, "local-currency",
+		string(app.NewAcbRunOptions().LocalCurrency),
+		"The currency in which ACB and capital gains are computed. Rows in this currency "+
+			"always have an exchange rate of 1.")
+	RootCmd.PersistentFlags().StringVar(&RatesCsvOpt, "rates-csv", "",
+		"A csv of exchange rates (with the columns date, currency and rate) to use for "+
+			"rows without an exchange rate, before the automatic USD/CAD rates. "+
+			"Rates are local currency per unit of the foreign currency.")
+	RootCmd.PersistentFlags().StringVar(&FxConventionOpt, "fx-convention", "standard",
+		"How exchange rates in the csvs are quoted. 'standard' rates are local currency "+
+			"per unit of foreign currency (eg. CAD per USD), and 'inverted' rates are foreign "+
+			"currency per unit of local currency (eg. USD per CAD).")
+	RootCmd.PersistentFlags().StringVar(&FxSanityRangeOpt, "fx-sanity-range", "0.3:3.0",
+		"Warn about CAD/USD exchange rates (local per foreign) outside of this range, "+
+			"formatted as MIN:MAX. These are likely mistyped. 'none' disables the warnings.")
+	RootCmd.PersistentFlags().BoolVar(&runOptions.CheckCurrencyCodes, "check-currency-codes", true,
+		"Warn about currencies which are not ISO 4217 codes (eg. USE instead of USD). "+
+			"These are likely mistyped.")
+	RootCmd.PersistentFlags().BoolVar(&runOptions.FutureTxDatesAreErrors,
+		"error-on-future-dates", false,
+		"Fail if any transaction is dated after today, rather than only warning. "+
+			"Such dates are likely typos.")
+	RootCmd.PersistentFlags().StringVar(&CostBasisOpt, "cost-basis", "acb",
+		"How the cost of sold shares is determined: acb (average cost), or fifo (oldest "+
+			"shares first). fifo is not valid for Canadian taxes, and does not support "+
+			"superficial losses.")
+	RootCmd.PersistentFlags().StringVar(&RoundingOpt, "rounding", "none",
+		"How computed ACBs and capital gains are rounded: none, cents (halves away from "+
+			"zero), or bankers-cents (halves to the even cent). Rounding is applied to each "+
+			"transaction, so it carries through to later ones.")
+	RootCmd.PersistentFlags().StringVar(&SflModeOpt, "sfl-mode", "auto",
+		"auto: detect superficial losses automatically. manual: only apply superficial "+
+			"losses specified in the csvs (eg. as computed by an accountant), and treat "+
+			"all other losses as capital losses.")
+	RootCmd.PersistentFlags().BoolVar(&runOptions.PerShareAcbTracking, "per-share-acb", false,
+		"Keep the ACB per share at full precision, and derive the total ACB from it, "+
+			"rather than the reverse. This avoids drift in the ACB per share over many "+
+			"sells.")
+	RootCmd.PersistentFlags().BoolVar(&runOptions.AllowShortSales, "allow-short", false,
+		"Allow sells of more shares than are held, which open a short position. "+
+			"Buys then cover the short position, and realize a capital gain or loss.")
+	RootCmd.PersistentFlags().BoolVar(&runOptions.AutoOddLotSales, "auto-odd-lot-sales", false,
+		"When a Split would leave a fractional number of shares, sell the shares which "+
+			"would become the fraction just before the split, at the amount/share of the Split "+
+			"(which must be given).")
+	RootCmd.PersistentFlags().BoolVar(&runOptions.DistributionFeesNetted, "net-distribution-fees", false,
+		"Treat the commission of RoC and Div transactions as a fee deducted from the "+
+			"distribution, so that only the net amount reduces the ACB (or is income). "+
+			"By default, distributions have no fees.")
+	RootCmd.PersistentFlags().BoolVar(&runOptions.SameDayTradeNetting, "net-same-day-trades", false,
+		"Combine the buys of a security on the same day (with the same exchange rate) "+
+			"into a single buy, and likewise for sells. This shortens the output for "+
+			"frequent trading, but loses the detail of each trade.")
+	RootCmd.PersistentFlags().BoolVar(&Strict, "strict", false,
+		"Exit with an error if any warnings were printed (eg. possible duplicate "+
+			"transactions), after printing the output.")
+	RootCmd.PersistentFlags().StringVar(&BrokerOpt, "broker", "",
+		"Read the csvs as activity exports from this broker, rather than in the "+
+			"standard format. Supported brokers: "+app.BrokerQuestrade)
+	RootCmd.PersistentFlags().StringVar(&ConfigFile, "config", "",
+		"JSON file with settings (date_format, default_currency, action_aliases, "+
+			"infer_settlement_dates, settlement_days, use_trade_date_as_settlement). "+
+			"Flags given on the command line take precedence.")
+	RootCmd.PersistentFlags().StringSliceVarP(&InitialSymStatusOpt, "symbol-base", "b", []string{},
 		"Base share count and ACBs for symbols, assumed at the beginning of time. "+
 			"Formatted as SYM:nShares:totalAcb. Eg. GOOG:20:1000.00 . May be provided multiple times.")
 	RootCmd.PersistentFlags().BoolVar(&PrintFullDollarValues,
 		"print-full-values", false, "Print all digits in output values")
+	RootCmd.PersistentFlags().StringVar(&reportOptions.OutputFormat,
+		"format", app.OutputFormatText,
+		"Output format: text, markdown, html, or json (see the json-schema command)")
+	RootCmd.PersistentFlags().StringVar(&reportOptions.Order,
+		"order", app.OrderAscending,
+		"Order of the rows of the tables: asc (oldest first) or desc (newest first). "+
+			"This only affects the display, not how values are computed.")
+	RootCmd.PersistentFlags().IntVar(&reportOptions.TaxYear,
+		"tax-year", 0,
+		"Only report transactions settled in this year. Earlier transactions are still "+
+			"used to compute the ACB, and later ones to compute superficial losses.")
+	RootCmd.PersistentFlags().StringVar(&SinceOpt, "since", "",
+		"Only report transactions on or after this date (YYYY-MM-DD). Earlier "+
+			"transactions are still used to compute the ACB and superficial losses.")
+	RootCmd.PersistentFlags().BoolVar(&reportOptions.CommissionTotals,
+		"commission-totals", false,
+		"Print the total commissions paid per year (in local currency)")
+	RootCmd.PersistentFlags().BoolVar(&reportOptions.YearTotals,
+		"year-totals", false,
+		"Print the capital gains of all securities per year, with superficial losses "+
+			"separated from the net gain (in local currency)")
+	RootCmd.PersistentFlags().StringVar(&InclusionSplitDateOpt,
+		"inclusion-split-date", "",
+		"Print the net capital gains of each year split into those settled before and "+
+			"on/after this date (YYYY-MM-DD), for different inclusion rates. Eg. "+
+			util.DateStr(ptf.DefaultInclusionSplitDate)+" for the 2024 inclusion rate change.")
+	RootCmd.PersistentFlags().StringSliceVar(&reportOptions.Columns,
+		"columns", []string{},
+		"Columns to show in the transaction tables, in order. May be provided multiple times. "+
+			"Eg. --columns date,tx,shares,\"cap. gain\". Valid columns: "+
+			strings.Join(ptf.TxTableHeader, ", "))
+	RootCmd.PersistentFlags().BoolVar(&reportOptions.ExplainSuperficialLosses,
+		"explain-sfl", false,
+		"Print the calculation of each automatically computed superficial loss "+
+			"after its security's table")
+	RootCmd.PersistentFlags().IntSliceVar(&reportOptions.ExplainTxs,
+		"explain", []int{},
+		"Print how the delta of the transaction with this number (as in \"tx #N\" in "+
+			"messages) was computed, after the tables. May be provided multiple times.")
+	RootCmd.PersistentFlags().BoolVar(&reportOptions.TradeCurrencyGains,
+		"trade-currency-gains", false,
+		"Also show the capital gain of each sale in its trade currency (excluding exchange "+
+			"rates). This is informational only, for reconciling with foreign account statements.")
+	RootCmd.PersistentFlags().BoolVar(&reportOptions.TradeCurrencyAcb,
+		"trade-currency-acb", false,
+		"Also show the total ACB in the transaction currency (excluding exchange rates), "+
+			"for securities with transactions in a single currency.")
+	RootCmd.PersistentFlags().BoolVar(&reportOptions.RunningTotals,
+		"running-totals", false,
+		"Also show the running totals of capital gains and denied superficial losses "+
+			"in each tax year")
 
 	// Legacy Options
 	RootCmd.PersistentFlags().BoolVar(&legacyOptions.NoSuperficialLosses,
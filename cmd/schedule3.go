@@ -0,0 +1,54 @@
+package cmd
+
+import (
+	"os"
+
+	"github.com/spf13/cobra"
+
+	"github.com/tsiemens/acb/app"
+	"github.com/tsiemens/acb/fx"
+	"github.com/tsiemens/acb/log"
+)
+
+var Schedule3Year int
+
+func runSchedule3Cmd(cmd *cobra.Command, args []string) {
+	errPrinter := log.NewWarningRecordingErrorPrinter(&log.StderrErrorPrinter{})
+
+	if Schedule3Year == 0 {
+		errPrinter.F("Error: --year is required\n")
+		os.Exit(1)
+	}
+
+	allInitStatus, csvReaders, options := prepareCsvRun(cmd, args, errPrinter)
+	defer closeCsvReaders(csvReaders)
+
+	err := app.RunSchedule3ToWriter(
+		os.Stdout,
+		csvReaders, allInitStatus, options, Schedule3Year,
+		&fx.CsvRatesCache{ErrPrinter: errPrinter}, errPrinter)
+	if err != nil {
+		os.Exit(app.ExitCode(err))
+	}
+	exitOnStrictWarnings(errPrinter)
+}
+
+var schedule3Cmd = &cobra.Command{
+	Use:   "schedule3 [CSV_FILE ...]",
+	Short: "Print the dispositions of a year as a csv, for Schedule 3",
+	Long: `Print a csv row for every disposition (sale or gift) settled in --year, with
+the columns of Schedule 3 for publicly traded shares: shares, proceeds of
+disposition, adjusted cost base, outlays and expenses, and gain (or loss), in
+local currency.
+
+The portion of a loss denied as superficial is in its own column, and is
+excluded from the gain (or loss).`,
+	Run:  runSchedule3Cmd,
+	Args: cobra.MinimumNArgs(1),
+}
+
+func init() {
+	schedule3Cmd.Flags().IntVar(&Schedule3Year, "year", 0,
+		"The tax year of the dispositions (required)")
+	RootCmd.AddCommand(schedule3Cmd)
+}
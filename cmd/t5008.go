@@ -0,0 +1,46 @@
+package cmd
+
+import (
+	"os"
+
+	"github.com/spf13/cobra"
+
+	"github.com/tsiemens/acb/app"
+	"github.com/tsiemens/acb/fx"
+	"github.com/tsiemens/acb/log"
+)
+
+var T5008Year int
+
+func runT5008Cmd(cmd *cobra.Command, args []string) {
+	errPrinter := log.NewWarningRecordingErrorPrinter(&log.StderrErrorPrinter{})
+
+	allInitStatus, csvReaders, options := prepareCsvRun(cmd, args, errPrinter)
+	defer closeCsvReaders(csvReaders)
+
+	err := app.RunT5008ToWriter(
+		os.Stdout,
+		csvReaders, allInitStatus, options, T5008Year,
+		&fx.CsvRatesCache{ErrPrinter: errPrinter}, errPrinter)
+	if err != nil {
+		os.Exit(app.ExitCode(err))
+	}
+	exitOnStrictWarnings(errPrinter)
+}
+
+var t5008Cmd = &cobra.Command{
+	Use:   "t5008 [CSV_FILE ...]",
+	Short: "Print every disposition, for reconciliation with T5008 slips",
+	Long: `Print a row for every sale, with its settlement date, quantity, proceeds,
+ACB and outlays (commissions) in local currency.
+
+Superficial loss adjustments are not dispositions, and are not included.`,
+	Run:  runT5008Cmd,
+	Args: cobra.MinimumNArgs(1),
+}
+
+func init() {
+	t5008Cmd.Flags().IntVar(&T5008Year, "year", 0,
+		"Only include dispositions settled in this year")
+	RootCmd.AddCommand(t5008Cmd)
+}
@@ -11,6 +11,7 @@ import (
 	"os"
 	"os/user"
 	"path/filepath"
+	"sort"
 	"strconv"
 	"strings"
 	"time"
@@ -22,7 +23,7 @@ import (
 const (
 	cadUsdNoonObs    = "IEXE0101"
 	cadUsdIndObs     = "FXCADUSD"
-	cadUsdJsonUrlFmt = "https://www.bankofcanada.ca/valet/observations/%s/json?start_date=%d-01-01&end_date=%d-12-31"
+	cadUsdJsonUrlFmt = "https://www.bankofcanada.ca/valet/observations/%s/json?start_date=%s&end_date=%s"
 
 	lineBufSize     = 100
 	csvTimeFormat   = "2006-01-02"
@@ -50,14 +51,26 @@ type ValetJsonRoot struct {
 	Observations []ValetJsonObs `json:"observations"`
 }
 
-func getJsonUrl(year uint32) string {
+// start and end must be in the same year.
+func getJsonUrl(start time.Time, end time.Time) string {
 	var obs string
-	if year >= 2017 {
+	if start.Year() >= 2017 {
 		obs = cadUsdIndObs
 	} else {
 		obs = cadUsdNoonObs
 	}
-	return fmt.Sprintf(cadUsdJsonUrlFmt, obs, year, year)
+	return fmt.Sprintf(cadUsdJsonUrlFmt, obs, util.DateStr(start), util.DateStr(end))
+}
+
+// Retrieves rates from a remote source, for the dates from start to end
+// (inclusive), which must be in the same year.
+type RemoteRatesGetter interface {
+	GetUsdCadRates(start time.Time, end time.Time) ([]DailyRate, error)
+}
+
+// Gets rates from the Bank of Canada Valet API.
+type ValetRatesGetter struct {
+	ErrPrinter log.ErrorPrinter
 }
 
 type RatesCache interface {
@@ -104,9 +117,10 @@ func (c *CsvRatesCache) GetUsdCadRates(year uint32) ([]DailyRate, error) {
 	return c.getRatesFromCsv(file)
 }
 
-func (cr *RateLoader) GetRemoteUsdCadRatesJson(year uint32, ratesCache RatesCache) ([]DailyRate, error) {
-	fmt.Fprintf(os.Stderr, "Fetching USD/CAD exchange rates for %d\n", year)
-	url := getJsonUrl(year)
+func (g *ValetRatesGetter) GetUsdCadRates(start time.Time, end time.Time) ([]DailyRate, error) {
+	fmt.Fprintf(os.Stderr, "Fetching USD/CAD exchange rates for %s to %s\n",
+		util.DateStr(start), util.DateStr(end))
+	url := getJsonUrl(start, end)
 	log.Fverbosef(os.Stderr, "Getting %s\n", url)
 	resp, err := http.Get(url)
 	if err != nil {
@@ -126,14 +140,14 @@ func (cr *RateLoader) GetRemoteUsdCadRatesJson(year uint32, ratesCache RatesCach
 	for _, obs := range theJson.Observations {
 		date, err := time.Parse(csvTimeFormat, obs.Date)
 		if err != nil {
-			cr.ErrPrinter.Ln("Unable to parse date:", err)
+			g.ErrPrinter.Ln("Unable to parse date:", err)
 			continue
 		}
 
 		var dRate DailyRate
 		usdCadNoonVal, err := obs.UsdCadNoon.Val()
 		if err != nil {
-			cr.ErrPrinter.Ln("Failed to parse USDCAD Noon rate for", date, ":", obs.UsdCadNoon.ValStr)
+			g.ErrPrinter.Ln("Failed to parse USDCAD Noon rate for", date, ":", obs.UsdCadNoon.ValStr)
 			continue
 		}
 
@@ -142,13 +156,24 @@ func (cr *RateLoader) GetRemoteUsdCadRatesJson(year uint32, ratesCache RatesCach
 		} else {
 			usdCadVal, err := obs.UsdCad.Val()
 			if err != nil {
-				cr.ErrPrinter.Ln("Failed to parse USDCAD rate for", date, ":", obs.UsdCad.ValStr)
+				g.ErrPrinter.Ln("Failed to parse USDCAD rate for", date, ":", obs.UsdCad.ValStr)
 				continue
 			}
 			dRate = DailyRate{date, 1.0 / usdCadVal}
 		}
 		rates = append(rates, dRate)
 	}
+	return rates, nil
+}
+
+func (cr *RateLoader) GetRemoteUsdCadRatesJson(year uint32, ratesCache RatesCache) ([]DailyRate, error) {
+	start := time.Date(int(year), time.January, 1, 0, 0, 0, 0, time.UTC)
+	end := time.Date(int(year), time.December, 31, 0, 0, 0, 0, time.UTC)
+	rates, err := cr.RemoteGetter.GetUsdCadRates(start, end)
+	if err != nil {
+		return nil, err
+	}
+	cr.refreshedYears[year] = true
 
 	err = ratesCache.WriteRates(year, rates)
 	if err != nil {
@@ -157,6 +182,53 @@ func (cr *RateLoader) GetRemoteUsdCadRatesJson(year uint32, ratesCache RatesCach
 	return rates, nil
 }
 
+// Fetches the rates for the year of t which are newer than those already
+// loaded (eg. from a cache written earlier in the year), and adds them to
+// yearRates and the cache. This is done at most once per year.
+func (cr *RateLoader) refreshUsdCadYearRates(
+	t time.Time, yearRates map[time.Time]DailyRate) error {
+
+	year := uint32(t.Year())
+	if cr.refreshedYears[year] {
+		return nil
+	}
+	cr.refreshedYears[year] = true
+
+	start := time.Date(t.Year(), time.January, 1, 0, 0, 0, 0, time.UTC)
+	for date, _ := range yearRates {
+		if !date.Before(start) {
+			start = date.AddDate(0, 0, 1)
+		}
+	}
+	end := time.Date(t.Year(), time.December, 31, 0, 0, 0, 0, time.UTC)
+	if start.After(end) {
+		return nil
+	}
+	newRates, err := cr.RemoteGetter.GetUsdCadRates(start, end)
+	if err != nil {
+		return err
+	}
+	if len(newRates) == 0 {
+		return nil
+	}
+	for _, rate := range newRates {
+		yearRates[rate.Date] = rate
+	}
+
+	allRates := make([]DailyRate, 0, len(yearRates))
+	for _, rate := range yearRates {
+		allRates = append(allRates, rate)
+	}
+	sort.Slice(allRates, func(i, j int) bool {
+		return allRates[i].Date.Before(allRates[j].Date)
+	})
+	err = cr.Cache.WriteRates(year, allRates)
+	if err != nil {
+		cr.ErrPrinter.Ln("Failed to update exchange rate cache:", err)
+	}
+	return nil
+}
+
 func (c *CsvRatesCache) getRatesFromCsv(r io.Reader) ([]DailyRate, error) {
 	csvR := csv.NewReader(r)
 	csvR.FieldsPerRecord = 2
@@ -258,18 +330,57 @@ func WriteRatesToCsv(year uint32, rates []DailyRate) (err error) {
 type RateLoader struct {
 	YearRates     map[uint32]map[time.Time]DailyRate
 	ForceDownload bool
-	Cache         RatesCache
-	ErrPrinter    log.ErrorPrinter
+	// If set, the rates published since the cached ones are fetched for each
+	// year loaded, rather than only when a date has no rate. Unlike
+	// ForceDownload, the cached rates are not re-downloaded.
+	RefreshStale bool
+	Cache        RatesCache
+	// If set, rates which take precedence over those of Cache (or fetched
+	// remotely) on the same date, such as a CsvRateTable. It is only read.
+	Overrides    RatesCache
+	RemoteGetter RemoteRatesGetter
+	ErrPrinter   log.ErrorPrinter
+	// Years which have been fetched remotely during this run.
+	refreshedYears map[uint32]bool
+	// The rates of Overrides, by year.
+	overrideYearRates map[uint32]map[time.Time]DailyRate
 }
 
 func NewRateLoader(
 	forceDownload bool, ratesCache RatesCache, errPrinter log.ErrorPrinter) *RateLoader {
 	return &RateLoader{
-		YearRates:     make(map[uint32]map[time.Time]DailyRate),
-		ForceDownload: forceDownload,
-		Cache:         ratesCache,
-		ErrPrinter:    errPrinter,
+		YearRates:      make(map[uint32]map[time.Time]DailyRate),
+		ForceDownload:  forceDownload,
+		Cache:          ratesCache,
+		RemoteGetter:   &ValetRatesGetter{ErrPrinter: errPrinter},
+		ErrPrinter:     errPrinter,
+		refreshedYears: make(map[uint32]bool),
+	}
+}
+
+// Returns the rate in Overrides for exactly t, if any.
+func (cr *RateLoader) overrideRate(t time.Time) (DailyRate, bool, error) {
+	if cr.Overrides == nil {
+		return DailyRate{}, false, nil
+	}
+	if cr.overrideYearRates == nil {
+		cr.overrideYearRates = make(map[uint32]map[time.Time]DailyRate)
+	}
+	year := uint32(t.Year())
+	yearRates, ok := cr.overrideYearRates[year]
+	if !ok {
+		rates, err := cr.Overrides.GetUsdCadRates(year)
+		if err != nil {
+			return DailyRate{}, false, err
+		}
+		yearRates = make(map[time.Time]DailyRate)
+		for _, rate := range rates {
+			yearRates[rate.Date] = rate
+		}
+		cr.overrideYearRates[year] = yearRates
 	}
+	rate, ok := yearRates[t]
+	return rate, ok, nil
 }
 
 func tryGetSurroundingRates(t time.Time, yearRates map[time.Time]DailyRate) (beforeRate *DailyRate, afterRate *DailyRate) {
@@ -322,23 +433,101 @@ func getSurroundingRatesHelp(t time.Time, yearRates map[time.Time]DailyRate, pre
 	return ""
 }
 
-func (cr *RateLoader) GetUsdCadRate(t time.Time) (DailyRate, error) {
-	yearRates, ok := cr.YearRates[uint32(t.Year())]
+func (cr *RateLoader) getUsdCadYearRates(year uint32) (map[time.Time]DailyRate, error) {
+	yearRates, ok := cr.YearRates[year]
 	if !ok {
-		rates, err := cr.GetUsdCadRatesForYear(uint32(t.Year()), cr.ForceDownload, cr.Cache)
+		rates, err := cr.GetUsdCadRatesForYear(year, cr.ForceDownload, cr.Cache)
 		if err != nil {
-			return DailyRate{}, err
+			return nil, err
 		}
 		yearRates = make(map[time.Time]DailyRate)
 		for _, rate := range rates {
 			yearRates[rate.Date] = rate
 		}
-		cr.YearRates[uint32(t.Year())] = yearRates
+		cr.YearRates[year] = yearRates
+		if cr.RefreshStale {
+			err = cr.refreshUsdCadYearRates(
+				time.Date(int(year), time.January, 1, 0, 0, 0, 0, time.UTC), yearRates)
+			if err != nil {
+				return nil, err
+			}
+		}
+	}
+	return yearRates, nil
+}
+
+func hasRateAfter(t time.Time, yearRates map[time.Time]DailyRate) bool {
+	for date, _ := range yearRates {
+		if date.After(t) {
+			return true
+		}
+	}
+	return false
+}
+
+// Returns the rate for the closest business day before t, within a week
+// (from Overrides, if it has one).
+func (cr *RateLoader) priorBusinessDayRate(t time.Time) (DailyRate, bool, error) {
+	priorTime := t
+	for i := 0; i < 7; i++ {
+		priorTime = priorTime.AddDate(0, 0, -1)
+		if rate, ok, err := cr.overrideRate(priorTime); err != nil || ok {
+			return rate, ok, err
+		}
+		yearRates, err := cr.getUsdCadYearRates(uint32(priorTime.Year()))
+		if err != nil {
+			return DailyRate{}, false, err
+		}
+		if rate, ok := yearRates[priorTime]; ok {
+			return rate, true, nil
+		}
+	}
+	return DailyRate{}, false, nil
+}
+
+// Returns the USD/CAD rate for t (from Overrides, if it has one).
+//
+// If there is no rate for t because markets were closed (a weekend, or a
+// holiday before the most recent published rate), the rate of the most
+// recent prior business day is used, as the Bank of Canada does not publish
+// rates for these days.
+func (cr *RateLoader) GetUsdCadRate(t time.Time) (DailyRate, error) {
+	if rate, ok, err := cr.overrideRate(t); err != nil || ok {
+		return rate, err
+	}
+	yearRates, err := cr.getUsdCadYearRates(uint32(t.Year()))
+	if err != nil {
+		return DailyRate{}, err
 	}
 	rate, ok := yearRates[t]
-	if !ok {
-		return DailyRate{}, fmt.Errorf("Unable to retrieve exchange rate for %v%s", t,
-			getSurroundingRatesHelp(t, yearRates, "\n"))
+	if ok {
+		return rate, nil
+	}
+	if !hasRateAfter(t, yearRates) {
+		// The cached rates may predate t's publication.
+		err = cr.refreshUsdCadYearRates(t, yearRates)
+		if err != nil {
+			// Fall back to what is cached (eg. when offline).
+			cr.ErrPrinter.Ln("Warning: Failed to fetch new exchange rates:", err)
+		} else if rate, ok = yearRates[t]; ok {
+			return rate, nil
+		}
+	}
+
+	weekday := t.Weekday()
+	marketsClosed := weekday == time.Saturday || weekday == time.Sunday ||
+		hasRateAfter(t, yearRates)
+	if marketsClosed {
+		priorRate, ok, err := cr.priorBusinessDayRate(t)
+		if err != nil {
+			return DailyRate{}, err
+		}
+		if ok {
+			log.Fverbosef(os.Stderr, "Using exchange rate from %s for %s\n",
+				util.DateStr(priorRate.Date), util.DateStr(t))
+			return priorRate, nil
+		}
 	}
-	return rate, nil
+	return DailyRate{}, fmt.Errorf("Unable to retrieve exchange rate for %s%s", util.DateStr(t),
+		getSurroundingRatesHelp(t, yearRates, "\n"))
 }
@@ -0,0 +1,118 @@
+package fx
+
+import (
+	"encoding/csv"
+	"fmt"
+	"io"
+	"sort"
+	"strconv"
+	"strings"
+	"time"
+)
+
+type rateTableKey struct {
+	Currency string
+	Date     time.Time
+}
+
+// A user-maintained table of exchange rates, as an alternative to the rates
+// fetched from the Bank of Canada. Rates are units of localCurrency per unit
+// of the foreign currency, and are only looked up by exact date (there is no
+// fallback to prior days).
+//
+// As a RatesCache, it has the USD/CAD rates of the table (see
+// RateLoader.Overrides). It cannot be written to.
+type CsvRateTable struct {
+	rates         map[rateTableKey]DailyRate
+	localCurrency string
+}
+
+func NewCsvRateTable(localCurrency string) *CsvRateTable {
+	return &CsvRateTable{
+		rates:         make(map[rateTableKey]DailyRate),
+		localCurrency: strings.ToUpper(localCurrency),
+	}
+}
+
+// Reads a rate table from a csv with the columns date (yyyy-mm-dd), currency
+// and rate. The header row is required, but the columns may be in any order.
+func ReadCsvRateTable(reader io.Reader, desc string, localCurrency string) (*CsvRateTable, error) {
+	csvR := csv.NewReader(reader)
+	records, err := csvR.ReadAll()
+	if err != nil {
+		return nil, fmt.Errorf("Error reading rates csv %s: %v", desc, err)
+	}
+	if len(records) == 0 {
+		return nil, fmt.Errorf("Rates csv %s has no header", desc)
+	}
+
+	cols := map[string]int{"date": -1, "currency": -1, "rate": -1}
+	for i, name := range records[0] {
+		name = strings.ToLower(strings.TrimSpace(name))
+		if _, ok := cols[name]; ok {
+			cols[name] = i
+		}
+	}
+	for _, name := range []string{"date", "currency", "rate"} {
+		if cols[name] == -1 {
+			return nil, fmt.Errorf("Rates csv %s has no %s column", desc, name)
+		}
+	}
+
+	table := NewCsvRateTable(localCurrency)
+	for i, record := range records[1:] {
+		rowErr := func(format string, args ...interface{}) error {
+			// The line number in the file, after the header.
+			return fmt.Errorf("Error in rates csv %s line %d: %s", desc, i+2,
+				fmt.Sprintf(format, args...))
+		}
+		date, err := time.Parse(csvTimeFormat, strings.TrimSpace(record[cols["date"]]))
+		if err != nil {
+			return nil, rowErr("%v", err)
+		}
+		rate, err := strconv.ParseFloat(strings.TrimSpace(record[cols["rate"]]), 64)
+		if err != nil || rate <= 0.0 {
+			return nil, rowErr("Invalid rate '%s'", record[cols["rate"]])
+		}
+		currency := strings.ToUpper(strings.TrimSpace(record[cols["currency"]]))
+		if currency == "" {
+			return nil, rowErr("No currency")
+		}
+		table.SetRate(currency, DailyRate{date, rate})
+	}
+	return table, nil
+}
+
+func (t *CsvRateTable) SetRate(currency string, rate DailyRate) {
+	t.rates[rateTableKey{strings.ToUpper(currency), rate.Date}] = rate
+}
+
+// Returns the rate for currency on exactly date, if the table has one.
+func (t *CsvRateTable) GetRate(currency string, date time.Time) (DailyRate, bool) {
+	rate, ok := t.rates[rateTableKey{strings.ToUpper(currency), date}]
+	return rate, ok
+}
+
+// Returns the table's rates of USD in CAD for year, or nil if there are none.
+// Where the local currency is USD, these are the inverse of the CAD rates.
+func (t *CsvRateTable) GetUsdCadRates(year uint32) ([]DailyRate, error) {
+	var rates []DailyRate = nil
+	for key, rate := range t.rates {
+		if rate.Date.Year() != int(year) {
+			continue
+		}
+		if t.localCurrency == "CAD" && key.Currency == "USD" {
+			rates = append(rates, rate)
+		} else if t.localCurrency == "USD" && key.Currency == "CAD" {
+			rates = append(rates, DailyRate{rate.Date, 1.0 / rate.ForeignToLocalRate})
+		}
+	}
+	sort.Slice(rates, func(i, j int) bool {
+		return rates[i].Date.Before(rates[j].Date)
+	})
+	return rates, nil
+}
+
+func (t *CsvRateTable) WriteRates(year uint32, rates []DailyRate) error {
+	return fmt.Errorf("The rate table cannot be written to")
+}
@@ -4,6 +4,7 @@ import (
 	"fmt"
 	"io"
 	"os"
+	"strings"
 )
 
 var VerboseEnabled = false
@@ -29,3 +30,30 @@ func (p *StderrErrorPrinter) Ln(v ...interface{}) {
 func (p *StderrErrorPrinter) F(format string, v ...interface{}) {
 	fmt.Fprintf(os.Stderr, format, v...)
 }
+
+// Wraps an ErrorPrinter, and records whether any warnings (messages starting
+// with "Warning:") were printed through it.
+type WarningRecordingErrorPrinter struct {
+	ErrorPrinter
+	SawWarning bool
+}
+
+func NewWarningRecordingErrorPrinter(p ErrorPrinter) *WarningRecordingErrorPrinter {
+	return &WarningRecordingErrorPrinter{ErrorPrinter: p, SawWarning: false}
+}
+
+func (p *WarningRecordingErrorPrinter) Ln(v ...interface{}) {
+	if len(v) > 0 {
+		if str, ok := v[0].(string); ok && strings.HasPrefix(str, "Warning:") {
+			p.SawWarning = true
+		}
+	}
+	p.ErrorPrinter.Ln(v...)
+}
+
+func (p *WarningRecordingErrorPrinter) F(format string, v ...interface{}) {
+	if strings.HasPrefix(format, "Warning:") {
+		p.SawWarning = true
+	}
+	p.ErrorPrinter.F(format, v...)
+}
@@ -0,0 +1,230 @@
+package portfolio
+
+import (
+	"sort"
+	"time"
+)
+
+// Returns the total commission paid in each year (in local currency),
+// across all buys, sells and gifts in deltas.
+func CommissionTotalsByYear(deltas []*TxDelta) map[int]float64 {
+	totals := make(map[int]float64)
+	for _, d := range deltas {
+		tx := d.Tx
+		if tx.Action != BUY && tx.Action != SELL && tx.Action != GIFT {
+			continue
+		}
+		totals[tx.Date.Year()] += tx.Commission * tx.CommissionCurrToLocalExchangeRate
+	}
+	return totals
+}
+
+// Returns the total cash dividends received in each year (in local currency).
+func DividendTotalsByYear(deltas []*TxDelta) map[int]float64 {
+	totals := make(map[int]float64)
+	for _, d := range deltas {
+		if d.Tx.Action == DIV {
+			totals[d.Tx.Date.Year()] += d.DividendIncome
+		}
+	}
+	return totals
+}
+
+// Returns the total foreign tax withheld in each year (in local currency).
+func WithholdingTaxTotalsByYear(deltas []*TxDelta) map[int]float64 {
+	totals := make(map[int]float64)
+	for _, d := range deltas {
+		if d.WithholdingTax != 0.0 {
+			totals[d.Tx.Date.Year()] += d.WithholdingTax
+		}
+	}
+	return totals
+}
+
+// The totals of all dispositions in a year (in local currency).
+type YearGainsRow struct {
+	Year int
+	// Gross proceeds of disposition
+	Proceeds float64
+	// Commissions (outlays and expenses)
+	Outlays float64
+	// Outlays and expenses other than commission (eg. transfer fees)
+	OtherOutlays     float64
+	TotalAcbDisposed float64
+	// Proceeds - Outlays - OtherOutlays - TotalAcbDisposed, before superficial
+	// losses are denied.
+	CapitalGain float64
+	// The (positive) amount of capital losses denied as superficial.
+	SuperficialLossDenied float64
+	// CapitalGain + SuperficialLossDenied. This is the reportable gain.
+	NetGain float64
+}
+
+// Returns the capital gains totals for each year with a Sell (or a Buy covering
+// a short position) in deltas, sorted by year. deltas may be for any number of securities.
+func AnnualGainsReport(deltas []*TxDelta) []*YearGainsRow {
+	rowsByYear := make(map[int]*YearGainsRow)
+	for _, d := range deltas {
+		tx := d.Tx
+		if (tx.Action != SELL && tx.Action != GIFT && !d.CoversShort()) || d.OpensShort() {
+			continue
+		}
+		year := tx.Date.Year()
+		row, ok := rowsByYear[year]
+		if !ok {
+			row = &YearGainsRow{Year: year}
+			rowsByYear[year] = row
+		}
+		if d.CoversShort() {
+			// The proceeds were received by the short sales, and the cost of
+			// the covering buy (including commission) is the ACB.
+			row.Proceeds += d.CoveredShortProceeds()
+			row.TotalAcbDisposed += d.CoveredShortProceeds() - d.CapitalGain
+			row.CapitalGain += d.CapitalGain
+			row.NetGain += d.CapitalGain
+			continue
+		}
+		row.Proceeds += tx.TxCurrToLocal(float64(tx.Shares) * tx.AmountPerShare)
+		row.Outlays += tx.Commission * tx.CommissionCurrToLocalExchangeRate
+		row.OtherOutlays += tx.Outlays * tx.CommissionCurrToLocalExchangeRate
+		row.TotalAcbDisposed += d.DisposedAcb()
+		// SuperficialLoss is negative, and has already been removed from
+		// CapitalGain.
+		row.CapitalGain += d.CapitalGain + d.SuperficialLoss
+		row.SuperficialLossDenied += -d.SuperficialLoss
+		row.NetGain += d.CapitalGain
+	}
+
+	rows := make([]*YearGainsRow, 0, len(rowsByYear))
+	for _, row := range rowsByYear {
+		rows = append(rows, row)
+	}
+	sort.Slice(rows, func(i, j int) bool {
+		return rows[i].Year < rows[j].Year
+	})
+	return rows
+}
+
+// A security's position after its last tx up to some date (in local currency).
+type HoldingRow struct {
+	Security     string
+	ShareBalance uint32
+	// Non-zero if the position is short.
+	ShortBalance uint32
+	TotalAcb     float64
+	PerShareAcb  float64
+	// The settlement date of the last tx.
+	LastTxDate time.Time
+}
+
+// Returns the position of each security after its last delta settled on or
+// before asOf (or its last delta, if asOf is zero), sorted by security.
+// Securities with no shares held (or short) at that point are omitted.
+// deltas may be for any number of securities, but each security's deltas must
+// be in order.
+func CurrentHoldings(deltas []*TxDelta, asOf time.Time) []*HoldingRow {
+	lastDeltas := make(map[string]*TxDelta)
+	for _, d := range deltas {
+		if asOf.IsZero() || !d.Tx.Date.After(asOf) {
+			lastDeltas[d.Tx.Security] = d
+		}
+	}
+
+	rows := make([]*HoldingRow, 0, len(lastDeltas))
+	for sec, d := range lastDeltas {
+		status := d.PostStatus
+		if status.ShareBalance == 0 && status.ShortBalance == 0 {
+			continue
+		}
+		rows = append(rows, &HoldingRow{
+			Security:     sec,
+			ShareBalance: status.ShareBalance,
+			ShortBalance: status.ShortBalance,
+			TotalAcb:     status.TotalAcb,
+			PerShareAcb:  status.PerShareAcb(),
+			LastTxDate:   d.Tx.Date,
+		})
+	}
+	sort.Slice(rows, func(i, j int) bool {
+		return rows[i].Security < rows[j].Security
+	})
+	return rows
+}
+
+// The date of the proposed change to the capital gains inclusion rate in the
+// 2024 federal budget.
+var DefaultInclusionSplitDate = time.Date(2024, time.June, 25, 0, 0, 0, 0, time.UTC)
+
+// The net capital gains of a year (in local currency), split by whether they
+// were settled before or on/after a date (eg. a change in the inclusion rate).
+type InclusionPeriodGainsRow struct {
+	Year             int
+	NetGainBefore    float64
+	NetGainOnOrAfter float64
+}
+
+// Returns the net gains (as in YearGainsRow.NetGain) of each year with a Sell
+// (or a Buy covering a short position) in deltas, split at splitDate, sorted by
+// year. deltas may be for any number of securities.
+func InclusionPeriodGainsReport(deltas []*TxDelta, splitDate time.Time) []*InclusionPeriodGainsRow {
+	rowsByYear := make(map[int]*InclusionPeriodGainsRow)
+	for _, d := range deltas {
+		tx := d.Tx
+		if tx.Action != SELL && tx.Action != GIFT && !d.CoversShort() {
+			continue
+		}
+		year := tx.Date.Year()
+		row, ok := rowsByYear[year]
+		if !ok {
+			row = &InclusionPeriodGainsRow{Year: year}
+			rowsByYear[year] = row
+		}
+		if tx.Date.Before(splitDate) {
+			row.NetGainBefore += d.CapitalGain
+		} else {
+			row.NetGainOnOrAfter += d.CapitalGain
+		}
+	}
+
+	rows := make([]*InclusionPeriodGainsRow, 0, len(rowsByYear))
+	for _, row := range rowsByYear {
+		rows = append(rows, row)
+	}
+	sort.Slice(rows, func(i, j int) bool {
+		return rows[i].Year < rows[j].Year
+	})
+	return rows
+}
+
+// Totals over a set of deltas (in local currency).
+// Gains and losses are kept separate, rather than netted, as they are
+// reported separately.
+type DeltaSummaryStats struct {
+	// The sum of all positive capital gains.
+	TotalCapitalGains float64
+	// The (positive) sum of all allowable capital losses. Superficial losses
+	// are excluded.
+	TotalCapitalLosses float64
+	// The (positive) sum of all capital losses denied as superficial.
+	TotalSuperficialLosses float64
+	// The total cash dividends received.
+	TotalDividends float64
+}
+
+// Returns the summary totals of deltas, which may be for any number of
+// securities.
+func SummarizeDeltas(deltas []*TxDelta) DeltaSummaryStats {
+	stats := DeltaSummaryStats{}
+	for _, d := range deltas {
+		// SuperficialLoss is negative, and has already been removed from
+		// CapitalGain.
+		if d.CapitalGain > 0.0 {
+			stats.TotalCapitalGains += d.CapitalGain
+		} else {
+			stats.TotalCapitalLosses += -d.CapitalGain
+		}
+		stats.TotalSuperficialLosses += -d.SuperficialLoss
+		stats.TotalDividends += d.DividendIncome
+	}
+	return stats
+}
@@ -0,0 +1,414 @@
+package portfolio
+
+import (
+	"fmt"
+	"sort"
+	"strconv"
+	"strings"
+	"time"
+
+	"github.com/tsiemens/acb/util"
+)
+
+type TaxLossCandidate struct {
+	Security    string
+	Shares      uint32
+	TotalAcb    float64
+	MarketValue float64
+	// The (negative) capital loss realized if all shares were sold at the
+	// current price.
+	UnrealizedLoss float64
+	// Set if there was an acquisition within the 30 days prior to the
+	// evaluation date, in which case selling now would likely trigger the
+	// superficial loss rule.
+	PotentiallySuperficial bool
+}
+
+// Identifies the positions in holdings which are currently at a loss, given
+// the current price per share (in local currency) of each security.
+// Securities without a price in currentPrices are skipped.
+// txs is used only to flag candidates which may be affected by the superficial
+// loss rule, and may be nil.
+//
+// Returns the candidates (sorted by security) and the total harvestable loss.
+func FindTaxLossCandidates(
+	holdings map[string]*PortfolioSecurityStatus, currentPrices map[string]float64,
+	txs []*Tx, asOf time.Time) ([]*TaxLossCandidate, float64) {
+
+	candidates := make([]*TaxLossCandidate, 0, len(holdings))
+	var totalLoss float64 = 0.0
+	for sec, status := range holdings {
+		price, ok := currentPrices[sec]
+		if !ok || status.ShareBalance == 0 {
+			continue
+		}
+		marketValue := price * float64(status.ShareBalance)
+		loss := marketValue - status.TotalAcb
+		if loss >= 0.0 {
+			continue
+		}
+		candidates = append(candidates, &TaxLossCandidate{
+			Security:               sec,
+			Shares:                 status.ShareBalance,
+			TotalAcb:               status.TotalAcb,
+			MarketValue:            marketValue,
+			UnrealizedLoss:         loss,
+			PotentiallySuperficial: hadRecentAcquisition(sec, txs, asOf),
+		})
+		totalLoss += loss
+	}
+
+	sort.Slice(candidates, func(i, j int) bool {
+		return candidates[i].Security < candidates[j].Security
+	})
+	return candidates, totalLoss
+}
+
+func hadRecentAcquisition(security string, txs []*Tx, asOf time.Time) bool {
+	firstBadBuyDate := asOf.Add(-30 * ONE_DAY_DUR)
+	for _, tx := range txs {
+		if tx.Security != security || (tx.Action != BUY && tx.Action != ACQUIRE) {
+			continue
+		}
+		if !tx.Date.Before(firstBadBuyDate) && !tx.Date.After(asOf) {
+			return true
+		}
+	}
+	return false
+}
+
+// Returns the exchange rate (foreign to local) at which selling shares of a
+// held position at pricePerShare, less commission (both in the foreign
+// currency), would result in no capital gain or loss in local currency.
+// Any rate above this results in a gain, and any rate below it a loss.
+func BreakEvenExchangeRate(status *PortfolioSecurityStatus, shares uint32,
+	pricePerShare float64, commission float64) (float64, error) {
+
+	if shares == 0 {
+		return 0.0, fmt.Errorf("Break-even rate requires a non-zero number of shares")
+	} else if shares > status.ShareBalance {
+		return 0.0, fmt.Errorf("Cannot sell %d shares of %s: only %d are held",
+			shares, status.Security, status.ShareBalance)
+	}
+	foreignPayout := (pricePerShare * float64(shares)) - commission
+	if foreignPayout <= 0.0 {
+		return 0.0, fmt.Errorf("Sale of %s has no proceeds after commission (%f)",
+			status.Security, foreignPayout)
+	}
+	// Mirrors the Sell gain in AddTx:
+	//   gain = (payout * rate) - (perShareAcb * shares)
+	return (status.PerShareAcb() * float64(shares)) / foreignPayout, nil
+}
+
+// The transactions read from a single source (eg. a csv file).
+type SourceTxs struct {
+	Desc string
+	Txs  []*Tx
+}
+
+type SourceDateOverlap struct {
+	Security string
+	SourceA  string
+	SourceB  string
+	// The range of dates covered by both sources.
+	FirstDate time.Time
+	LastDate  time.Time
+}
+
+func (o *SourceDateOverlap) String() string {
+	return fmt.Sprintf(
+		"Transactions for %s in %s and %s have overlapping dates (%s to %s)",
+		o.Security, o.SourceA, o.SourceB,
+		util.DateStr(o.FirstDate), util.DateStr(o.LastDate))
+}
+
+type dateRange struct {
+	First time.Time
+	Last  time.Time
+}
+
+func txDateRangesBySecurity(txs []*Tx) map[string]*dateRange {
+	ranges := make(map[string]*dateRange)
+	for _, tx := range txs {
+		r, ok := ranges[tx.Security]
+		if !ok {
+			ranges[tx.Security] = &dateRange{First: tx.Date, Last: tx.Date}
+			continue
+		}
+		if tx.Date.Before(r.First) {
+			r.First = tx.Date
+		}
+		if tx.Date.After(r.Last) {
+			r.Last = tx.Date
+		}
+	}
+	return ranges
+}
+
+// Detects securities whose transactions in different sources cover overlapping
+// date ranges. This is a common sign of the same export being imported twice
+// (in whole or in part), which would double count transactions.
+// Unlike exact duplicate detection, this does not compare the transactions
+// themselves.
+func FindSourceDateOverlaps(sources []SourceTxs) []*SourceDateOverlap {
+	rangesBySource := make([]map[string]*dateRange, 0, len(sources))
+	for _, source := range sources {
+		rangesBySource = append(rangesBySource, txDateRangesBySecurity(source.Txs))
+	}
+
+	overlaps := make([]*SourceDateOverlap, 0)
+	for i := 0; i < len(sources); i++ {
+		for j := i + 1; j < len(sources); j++ {
+			for sec, rangeA := range rangesBySource[i] {
+				rangeB, ok := rangesBySource[j][sec]
+				if !ok {
+					continue
+				}
+				if rangeA.First.After(rangeB.Last) || rangeB.First.After(rangeA.Last) {
+					continue
+				}
+				overlap := &SourceDateOverlap{
+					Security:  sec,
+					SourceA:   sources[i].Desc,
+					SourceB:   sources[j].Desc,
+					FirstDate: rangeA.First,
+					LastDate:  rangeA.Last,
+				}
+				if rangeB.First.After(overlap.FirstDate) {
+					overlap.FirstDate = rangeB.First
+				}
+				if rangeB.Last.Before(overlap.LastDate) {
+					overlap.LastDate = rangeB.Last
+				}
+				overlaps = append(overlaps, overlap)
+			}
+		}
+	}
+
+	sort.SliceStable(overlaps, func(i, j int) bool {
+		return overlaps[i].Security < overlaps[j].Security
+	})
+	return overlaps
+}
+
+// A pair of txs which appear to be the same transaction entered twice.
+type DuplicateTx struct {
+	First  *Tx
+	Second *Tx
+}
+
+func (d *DuplicateTx) String() string {
+	return fmt.Sprintf(
+		"Transactions %s and %s (%s %d shares of %s on %s) may be duplicates",
+		d.First.RefStr(), d.Second.RefStr(), d.First.Action, d.First.Shares,
+		d.First.Security, util.DateStr(d.First.Date))
+}
+
+type txDuplicateKey struct {
+	Security       string
+	Date           int64
+	Action         TxAction
+	Shares         uint32
+	AmountPerShare float64
+}
+
+// Finds txs which are identical in security, date, action, shares and
+// amount/share. These are often legitimate (eg. several fills of one order),
+// so this is only meant for warnings.
+// Each later duplicate is paired with the first tx it duplicates.
+func FindDuplicateTxs(txs []*Tx) []*DuplicateTx {
+	firstTxs := make(map[txDuplicateKey]*Tx)
+	dups := make([]*DuplicateTx, 0)
+	for _, tx := range txs {
+		if tx.Action == NOTE {
+			continue
+		}
+		key := txDuplicateKey{
+			Security:       tx.Security,
+			Date:           tx.Date.Unix(),
+			Action:         tx.Action,
+			Shares:         tx.Shares,
+			AmountPerShare: tx.AmountPerShare,
+		}
+		if first, ok := firstTxs[key]; ok {
+			dups = append(dups, &DuplicateTx{First: first, Second: tx})
+		} else {
+			firstTxs[key] = tx
+		}
+	}
+	return dups
+}
+
+// A tx dated far from the txs read around it, which often means that its year
+// was mistyped.
+type OutOfSequenceTx struct {
+	Tx *Tx
+}
+
+func (o *OutOfSequenceTx) String() string {
+	return fmt.Sprintf(
+		"Transaction %s (%s %s on %s) is dated over a year apart from the %s "+
+			"transactions around it. Check that its date is correct",
+		o.Tx.RefStr(), o.Tx.Action, o.Tx.Security, util.DateStr(o.Tx.Date), o.Tx.Security)
+}
+
+// The maximum time between a tx and any of its neighbours before it is
+// considered out of sequence.
+var outOfSequenceDur = 366 * ONE_DAY_DUR
+
+// Finds txs which are dated over a year apart from all of the (up to four)
+// txs of the same security read nearest to them. txs must be in the order they
+// were read (ie. not sorted).
+func FindOutOfSequenceTxs(txs []*Tx) []*OutOfSequenceTx {
+	found := make([]*OutOfSequenceTx, 0)
+	for _, secTxs := range SplitTxsBySecurity(txs) {
+		for i, tx := range secTxs {
+			nNeighbours := 0
+			nFarNeighbours := 0
+			for j := i - 2; j <= i+2; j++ {
+				if j < 0 || j == i || j >= len(secTxs) {
+					continue
+				}
+				nNeighbours++
+				diff := tx.Date.Sub(secTxs[j].Date)
+				if diff > outOfSequenceDur || diff < -outOfSequenceDur {
+					nFarNeighbours++
+				}
+			}
+			if nNeighbours >= 2 && nFarNeighbours == nNeighbours {
+				found = append(found, &OutOfSequenceTx{Tx: tx})
+			}
+		}
+	}
+	sort.Slice(found, func(i, j int) bool {
+		return found[i].Tx.ReadIndex < found[j].Tx.ReadIndex
+	})
+	return found
+}
+
+// A range of exchange rates (local currency per unit of foreign currency).
+type ExchangeRateRange struct {
+	Min float64
+	Max float64
+}
+
+// The default range of FindImplausibleExchangeRates.
+var PlausibleExchangeRatesDefault = ExchangeRateRange{Min: 0.3, Max: 3.0}
+
+// Rates for other currencies are not checked, since they can legitimately be
+// far outside of the plausible range.
+var plausibleRateCurrencies = map[Currency]bool{CAD: true, USD: true}
+
+// Parses a range formatted as MIN:MAX (eg. 0.3:3.0), or "none" for the zero
+// range.
+func ParseExchangeRateRange(str string) (ExchangeRateRange, error) {
+	str = strings.TrimSpace(strings.ToLower(str))
+	if str == "none" {
+		return ExchangeRateRange{}, nil
+	}
+	parts := strings.Split(str, ":")
+	if len(parts) == 2 {
+		minRate, minErr := strconv.ParseFloat(strings.TrimSpace(parts[0]), 64)
+		maxRate, maxErr := strconv.ParseFloat(strings.TrimSpace(parts[1]), 64)
+		if minErr == nil && maxErr == nil && minRate > 0.0 && minRate < maxRate {
+			return ExchangeRateRange{Min: minRate, Max: maxRate}, nil
+		}
+	}
+	return ExchangeRateRange{}, fmt.Errorf(
+		"Invalid exchange rate range '%s' (must be MIN:MAX, with 0 < MIN < MAX, or none)", str)
+}
+
+// An exchange rate of a tx which is outside of the plausible range.
+type ImplausibleExchangeRate struct {
+	Tx *Tx
+	// The column of the rate ("exchange rate" or "commission exchange rate")
+	Column string
+	Rate   float64
+	// The range which Rate is outside of.
+	PlausibleRange ExchangeRateRange
+}
+
+func (r *ImplausibleExchangeRate) String() string {
+	return fmt.Sprintf(
+		"The %s of transaction %s (%s %s on %s), %s, is outside of the "+
+			"plausible range (%s to %s). Check that it is correct",
+		r.Column, r.Tx.RefStr(), r.Tx.Action, r.Tx.Security, util.DateStr(r.Tx.Date),
+		strconv.FormatFloat(r.Rate, 'f', -1, 64),
+		strconv.FormatFloat(r.PlausibleRange.Min, 'f', -1, 64),
+		strconv.FormatFloat(r.PlausibleRange.Max, 'f', -1, 64))
+}
+
+func isPlausibleExchangeRate(
+	curr Currency, rate float64, localCurrency Currency, plausibleRange ExchangeRateRange) bool {
+
+	if curr == localCurrency || !plausibleRateCurrencies[curr] ||
+		!plausibleRateCurrencies[localCurrency] {
+		return true
+	}
+	return rate >= plausibleRange.Min && rate <= plausibleRange.Max
+}
+
+// Finds exchange rates in txs (which must have their exchange rates resolved to
+// localCurrency) which are outside of plausibleRange, since they are likely
+// mistyped (eg. 13.3 rather than 1.33). Only rates between the currencies in
+// plausibleRateCurrencies are checked, and none if plausibleRange is the zero
+// range. These do not prevent computation, so this is only meant for warnings.
+func FindImplausibleExchangeRates(
+	txs []*Tx, localCurrency Currency,
+	plausibleRange ExchangeRateRange) []*ImplausibleExchangeRate {
+
+	found := make([]*ImplausibleExchangeRate, 0)
+	if plausibleRange == (ExchangeRateRange{}) {
+		return found
+	}
+	for _, tx := range txs {
+		if !isPlausibleExchangeRate(
+			tx.TxCurrency, tx.TxCurrToLocalExchangeRate, localCurrency, plausibleRange) {
+			found = append(found, &ImplausibleExchangeRate{
+				Tx: tx, Column: "exchange rate", Rate: tx.TxCurrToLocalExchangeRate,
+				PlausibleRange: plausibleRange})
+		}
+		// A commission in the tx currency has the same rate, so is only
+		// checked if it is in another currency.
+		if tx.CommissionCurrency != tx.TxCurrency &&
+			!isPlausibleExchangeRate(tx.CommissionCurrency,
+				tx.CommissionCurrToLocalExchangeRate, localCurrency, plausibleRange) {
+			found = append(found, &ImplausibleExchangeRate{
+				Tx: tx, Column: "commission exchange rate",
+				Rate: tx.CommissionCurrToLocalExchangeRate, PlausibleRange: plausibleRange})
+		}
+	}
+	return found
+}
+
+// A tx with a date after today, which is likely a typo.
+type FutureTx struct {
+	Tx *Tx
+	// The trade date of Tx if it has one, and otherwise its settlement date.
+	Date time.Time
+	// The date considered to be today.
+	Today time.Time
+}
+
+func (f *FutureTx) String() string {
+	return fmt.Sprintf("Transaction %s (%s %s) is dated %s, which is after today (%s)",
+		f.Tx.RefStr(), f.Tx.Action, f.Tx.Security, util.DateStr(f.Date),
+		util.DateStr(f.Today))
+}
+
+// Finds txs dated after today (as per util.Today). The trade date is used where
+// there is one, since a trade made today may settle after today.
+func FindFutureTxs(txs []*Tx) []*FutureTx {
+	today := util.Today()
+	found := make([]*FutureTx, 0)
+	for _, tx := range txs {
+		date := tx.Date
+		if !tx.TradeDate.IsZero() {
+			date = tx.TradeDate
+		}
+		if date.After(today) {
+			found = append(found, &FutureTx{Tx: tx, Date: date, Today: today})
+		}
+	}
+	return found
+}
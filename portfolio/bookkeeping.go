@@ -2,6 +2,9 @@ package portfolio
 
 import (
 	"fmt"
+	"math"
+	"sort"
+	"strings"
 	"time"
 
 	"github.com/tsiemens/acb/util"
@@ -15,6 +18,11 @@ func mustParseDuration(str string) time.Duration {
 
 var ONE_DAY_DUR = mustParseDuration("24h")
 
+// The most by which a RoC may exceed the ACB, where the excess is assumed to be
+// from rounding (eg. of a per-share amount), and the ACB is reduced to zero.
+// Any more is an error.
+const rocRoundingTolerance = 0.005
+
 type LegacyOptions struct {
 	NoSuperficialLosses        bool
 	NoPartialSuperficialLosses bool
@@ -27,6 +35,75 @@ func NewLegacyOptions() LegacyOptions {
 	}
 }
 
+type SuperficialLossMode int
+
+const (
+	// Superficial losses are detected automatically, unless specified.
+	SFL_AUTO SuperficialLossMode = iota
+	// Only superficial losses specified on a Sell are applied. Other losses
+	// are regular capital losses.
+	SFL_MANUAL_ONLY
+)
+
+// Options for how the deltas of txs are computed (see AddTx).
+type DeltaOptions struct {
+	Legacy LegacyOptions
+	// The currency in which ACB and capital gains are computed (that which the
+	// exchange rates of the txs convert to).
+	LocalCurrency Currency
+	// How the cost of disposed shares is determined.
+	CostBasis         CostBasisMethod
+	SuperficialLosses SuperficialLossMode
+	// How the ACB, capital gains and superficial losses of each delta are
+	// rounded. Since each delta starts from the (rounded) status of the previous
+	// one, the rounding carries through all following deltas.
+	Rounding RoundingPolicy
+	// Allows sells beyond the share balance, which open (or add to) a short
+	// position. Buys then cover the short position, realizing a gain or loss.
+	// Superficial losses are not applied to covering buys.
+	AllowShortSales bool
+	// If set, the ACB per share is kept at full precision in each status (see
+	// PortfolioSecurityStatus.TrackedPerShareAcb), and the total ACB is derived
+	// from it. Dispositions then leave the ACB per share exactly unchanged,
+	// rather than drifting from repeatedly dividing and subtracting the total
+	// ACB. Not used with FIFO_COST_BASIS or while short.
+	PerShareAcbTracking bool
+	// If a Split would leave a fractional number of shares, sells the odd lot
+	// of shares which would become the fraction just before the split (at the
+	// Split's amount/share, which must be set) instead of failing.
+	AutoOddLotSales bool
+	// If set, the commission (and outlays) of a RoC or Div tx are a fee
+	// deducted from the distribution, so only the net amount reduces the ACB
+	// (or is income). Otherwise the commission of distributions is ignored.
+	DistributionFeesNetted bool
+}
+
+func NewDeltaOptions() DeltaOptions {
+	return DeltaOptions{
+		Legacy:                 NewLegacyOptions(),
+		LocalCurrency:          CAD,
+		CostBasis:              ACB_COST_BASIS,
+		SuperficialLosses:      SFL_AUTO,
+		Rounding:               ROUND_NONE,
+		AllowShortSales:        false,
+		PerShareAcbTracking:    false,
+		AutoOddLotSales:        false,
+		DistributionFeesNetted: false,
+	}
+}
+
+func ParseSuperficialLossMode(name string) (SuperficialLossMode, error) {
+	switch strings.TrimSpace(strings.ToLower(name)) {
+	case "", "auto":
+		return SFL_AUTO, nil
+	case "manual":
+		return SFL_MANUAL_ONLY, nil
+	default:
+		return SFL_AUTO, fmt.Errorf(
+			"Invalid superficial loss mode '%s' (must be auto or manual)", name)
+	}
+}
+
 type _SuperficialLossInfo struct {
 	IsSuperficial        bool
 	FirstDateInPeriod    time.Time
@@ -35,6 +112,63 @@ type _SuperficialLossInfo struct {
 	TotalAquiredInPeriod uint32
 }
 
+// Returns the number of shares that shares becomes after a split at ratio,
+// and false if this is not a whole number.
+func splitShares(shares uint32, ratio float64) (uint32, bool) {
+	newShares := float64(shares) * ratio
+	rounded := math.Round(newShares)
+	return uint32(rounded), math.Abs(newShares-rounded) < 1e-6
+}
+
+// Returns the fee (in local currency) deducted from the distribution of a RoC
+// or Div tx, which is only non-zero with DistributionFeesNetted.
+func distributionFee(tx *Tx, options DeltaOptions) float64 {
+	if !options.DistributionFeesNetted {
+		return 0.0
+	}
+	return tx.LocalCommissionAndOutlays()
+}
+
+// Returns a Sell of the fewest shares for which split leaves a whole number of
+// shares of status, or nil if split already does so.
+// The sale is at the split's amount/share, so it fails if there is none.
+func oddLotSaleTx(split *Tx, status *PortfolioSecurityStatus) (*Tx, error) {
+	if _, whole := splitShares(status.ShareBalance, split.Ratio); whole {
+		return nil, nil
+	}
+	kept := math.Floor(float64(status.ShareBalance) * split.Ratio)
+	var keptBeforeSplit uint32 = 0
+	for ; kept > 0.0; kept-- {
+		if shares, whole := splitShares(uint32(kept), 1.0/split.Ratio); whole {
+			keptBeforeSplit = shares
+			break
+		}
+	}
+	oddShares := status.ShareBalance - keptBeforeSplit
+	if split.AmountPerShare == 0.0 {
+		return nil, fmt.Errorf("Split on %v would leave a fractional number of shares, "+
+			"but has no amount/share at which to sell the odd lot of %d shares",
+			util.DateStr(split.Date), oddShares)
+	}
+	memo := fmt.Sprintf("Odd lot of %d shares, which would be fractional after the split "+
+		"at a ratio of %g", oddShares, split.Ratio)
+	return &Tx{
+		Security:                          split.Security,
+		Date:                              split.Date,
+		TradeDate:                         split.TradeDate,
+		Action:                            SELL,
+		Shares:                            oddShares,
+		AmountPerShare:                    split.AmountPerShare,
+		TxCurrency:                        split.TxCurrency,
+		TxCurrToLocalExchangeRate:         split.TxCurrToLocalExchangeRate,
+		CommissionCurrency:                split.CommissionCurrency,
+		CommissionCurrToLocalExchangeRate: split.CommissionCurrToLocalExchangeRate,
+		Memo:                              memo,
+		ReadIndex:                         split.ReadIndex,
+		Origin:                            ORIGIN_ODD_LOT_SALE,
+	}, nil
+}
+
 // Checks if there is a Buy action within 30 days before or after the Sell
 // at idx, AND if you hold shares after the 30 day period
 // Also gathers relevant information for partial superficial loss calculation.
@@ -54,24 +188,42 @@ func getSuperficialLossInfo(idx int, txs []*Tx, shareBalanceAfterSell uint32) _S
 		TotalAquiredInPeriod: 0,
 	}
 
+	// Share counts are kept in terms of the shares of the Sell, so those after
+	// a split are scaled back by its ratio.
+	// The balance is signed, since (with AllowShortSales) sells may go short.
 	didBuyAfterInPeriod := false
+	splitRatio := 1.0
+	balance := int64(shareBalanceAfterSell)
 	for i := idx + 1; i < len(txs); i++ {
 		afterTx := txs[i]
 		if afterTx.Date.After(lastBadBuyDate) {
 			break
 		}
+		if afterTx.Origin == ORIGIN_DERIVED {
+			// The shares of a Spinoff or Rename were not bought.
+			continue
+		}
 		// Within the 30 day window after
+		shares, _ := splitShares(afterTx.Shares, 1.0/splitRatio)
 		switch afterTx.Action {
-		case BUY:
+		case BUY, ACQUIRE:
 			didBuyAfterInPeriod = true
-			sli.SharesAtEndOfPeriod += afterTx.Shares
-			sli.TotalAquiredInPeriod += afterTx.Shares
-		case SELL:
-			sli.SharesAtEndOfPeriod -= afterTx.Shares
+			balance += int64(shares)
+			sli.TotalAquiredInPeriod += shares
+		case SELL, GIFT:
+			balance -= int64(shares)
+		case SPLIT:
+			splitRatio *= afterTx.Ratio
 		default:
 			// ignored
 		}
 	}
+	// No shares are held at the end of the period if the position is short.
+	if balance > 0 {
+		sli.SharesAtEndOfPeriod = uint32(balance)
+	} else {
+		sli.SharesAtEndOfPeriod = 0
+	}
 
 	if sli.SharesAtEndOfPeriod == 0 {
 		// Not superficial
@@ -79,15 +231,21 @@ func getSuperficialLossInfo(idx int, txs []*Tx, shareBalanceAfterSell uint32) _S
 	}
 
 	didBuyBeforeInPeriod := false
+	splitRatio = 1.0
 	for i := idx - 1; i >= 0; i-- {
 		beforeTx := txs[i]
 		if beforeTx.Date.Before(firstBadBuyDate) {
 			break
 		}
 		// Within the 30 day window before
-		if beforeTx.Action == BUY {
+		if beforeTx.Origin == ORIGIN_DERIVED {
+			continue
+		} else if beforeTx.Action == BUY || beforeTx.Action == ACQUIRE {
 			didBuyBeforeInPeriod = true
-			sli.TotalAquiredInPeriod += beforeTx.Shares
+			shares, _ := splitShares(beforeTx.Shares, splitRatio)
+			sli.TotalAquiredInPeriod += shares
+		} else if beforeTx.Action == SPLIT {
+			splitRatio *= beforeTx.Ratio
 		}
 	}
 
@@ -95,6 +253,36 @@ func getSuperficialLossInfo(idx int, txs []*Tx, shareBalanceAfterSell uint32) _S
 	return sli
 }
 
+// The inputs to an automatically computed superficial loss, for auditing.
+type SuperficialLossCalc struct {
+	SharesSold uint32
+	// Shares bought or acquired from 30 days before to 30 days after the sale.
+	TotalAcquiredInPeriod uint32
+	// The share balance 30 days after the sale.
+	SharesAtEndOfPeriod uint32
+	// min(SharesSold, TotalAcquiredInPeriod, SharesAtEndOfPeriod).
+	// This portion of the loss (over SharesSold) is superficial.
+	SuperficialShares uint32
+}
+
+// Returns the inputs to the superficial loss of the Sell at idx, or nil if a
+// loss on it would not be superficial.
+func getSuperficialLossCalc(idx int, txs []*Tx, shareBalanceAfterSell uint32) *SuperficialLossCalc {
+	sli := getSuperficialLossInfo(idx, txs, shareBalanceAfterSell)
+
+	tx := txs[idx]
+	if !sli.IsSuperficial || tx.Shares == 0 {
+		return nil
+	}
+	return &SuperficialLossCalc{
+		SharesSold:            tx.Shares,
+		TotalAcquiredInPeriod: sli.TotalAquiredInPeriod,
+		SharesAtEndOfPeriod:   sli.SharesAtEndOfPeriod,
+		SuperficialShares: util.MinUint32(
+			tx.Shares, sli.TotalAquiredInPeriod, sli.SharesAtEndOfPeriod),
+	}
+}
+
 // Calculation of partial superficial losses where
 // Superficial loss = (min(#sold, totalAquired, endBalance) / #sold) x (Total Loss)
 // This function returns the left hand side of this formula, on the condition that
@@ -102,49 +290,188 @@ func getSuperficialLossInfo(idx int, txs []*Tx, shareBalanceAfterSell uint32) _S
 //
 // Reference: https://www.adjustedcostbase.ca/blog/applying-the-superficial-loss-rule-for-a-partial-disposition-of-shares/
 func SuperficialLossPercent(idx int, txs []*Tx, shareBalanceAfterSell uint32) float64 {
-	sli := getSuperficialLossInfo(idx, txs, shareBalanceAfterSell)
-
-	if sli.IsSuperficial {
-		tx := txs[idx]
-		return float64(util.MinUint32(tx.Shares, sli.TotalAquiredInPeriod, sli.SharesAtEndOfPeriod)) / float64(tx.Shares)
-	} else {
+	calc := getSuperficialLossCalc(idx, txs, shareBalanceAfterSell)
+	if calc == nil {
 		return 0.0
 	}
+	return float64(calc.SuperficialShares) / float64(calc.SharesSold)
 }
 
-func AddTx(idx int, txs []*Tx, preTxStatus *PortfolioSecurityStatus, legacyOptions LegacyOptions) (*TxDelta, error) {
-	applySuperficialLosses := !legacyOptions.NoSuperficialLosses
-	noPartialSuperficialLosses := legacyOptions.NoPartialSuperficialLosses
+// Factors by which a per-share price changing in the superficial loss period
+// suggests a stock split, rather than a price movement.
+var suspectedSplitFactors = []float64{2.0, 3.0, 1.0 / 2.0, 1.0 / 3.0}
+
+// Returns a warning if the per-share price of any buy or sell within 30 days of
+// the Sell at idx differs from that of the Sell by close to a split factor,
+// which suggests that a split was not entered, and so the superficial loss
+// (which depends on share counts) is probably wrong. Returns "" otherwise.
+func suspectedSplitWarning(idx int, txs []*Tx) string {
 	tx := txs[idx]
-	util.Assertf(tx.Security == preTxStatus.Security,
-		"AddTx: securities do not match (%s and %s)\n", tx.Security, preTxStatus.Security)
+	sellPrice := tx.TxCurrToLocal(tx.AmountPerShare)
+	if sellPrice <= 0.0 {
+		return ""
+	}
+	firstDate := tx.Date.Add(-30 * ONE_DAY_DUR)
+	lastDate := tx.Date.Add(30 * ONE_DAY_DUR)
+	for _, otherTx := range txs {
+		if otherTx.Action == SPLIT &&
+			!otherTx.Date.Before(firstDate) && !otherTx.Date.After(lastDate) {
+			// The split was entered
+			return ""
+		}
+	}
+	for i, otherTx := range txs {
+		if i == idx || otherTx.Date.Before(firstDate) || otherTx.Date.After(lastDate) ||
+			(otherTx.Action != BUY && otherTx.Action != SELL) {
+			continue
+		}
+		ratio := otherTx.TxCurrToLocal(otherTx.AmountPerShare) / sellPrice
+		for _, factor := range suspectedSplitFactors {
+			if math.Abs(ratio/factor-1.0) < 0.05 {
+				return fmt.Sprintf("Sell of %s on %s has a superficial loss, but the %s on %s "+
+					"was at %.2fx the price per share. If there was a stock split in between, "+
+					"it must be entered for the superficial loss to be correct",
+					tx.Security, util.DateStr(tx.Date), otherTx.Action,
+					util.DateStr(otherTx.Date), ratio)
+			}
+		}
+	}
+	return ""
+}
 
-	var totalLocalSharePrice float64 = float64(tx.Shares) * tx.AmountPerShare * tx.TxCurrToLocalExchangeRate
+func specifiedSflOnGainError(
+	idx int, txs []*Tx, preTxStatus *PortfolioSecurityStatus, capitalGains float64,
+	options DeltaOptions) error {
+
+	tx := txs[idx]
+	msg := fmt.Sprintf("Sell order on %v of %s specified a superficial loss, but there is "+
+		"no capital loss. The sale is a capital gain of %f in local currency",
+		tx.Date, tx.Security, capitalGains)
+
+	tcGain, tcCurr, ok := tradeCurrencyGainAt(idx, txs, preTxStatus.ShareBalance, options)
+	if ok && tcGain < 0.0 && tcCurr != options.LocalCurrency {
+		msg += fmt.Sprintf(", even though it is a loss of %f %s in its trade currency. "+
+			"This is due to the change in exchange rate since the shares were acquired",
+			tcGain, tcCurr)
+	}
+	return fmt.Errorf("%s", msg)
+}
+
+func AddTx(idx int, txs []*Tx, preTxStatus *PortfolioSecurityStatus, options DeltaOptions) (*TxDelta, error) {
+	applySuperficialLosses := !options.Legacy.NoSuperficialLosses &&
+		options.SuperficialLosses == SFL_AUTO
+	noPartialSuperficialLosses := options.Legacy.NoPartialSuperficialLosses
+	tx := txs[idx]
+	if tx.Security != preTxStatus.Security {
+		return nil, fmt.Errorf("%s tx on %v of %s does not match the security of the "+
+			"preceding status (%s)", tx.Action, tx.Date, tx.Security, preTxStatus.Security)
+	}
+
+	var totalLocalSharePrice float64 = tx.TxCurrToLocal(float64(tx.Shares) * tx.AmountPerShare)
 
 	newShareBalance := preTxStatus.ShareBalance
+	newShortBalance := preTxStatus.ShortBalance
 	var newAcbTotal float64 = preTxStatus.TotalAcb
 	var capitalGains float64 = 0.0
 	var superficialLoss float64 = 0.0
+	var dividendIncome float64 = 0.0
+	var derivedTx *Tx = nil
+	var superficialLossCalc *SuperficialLossCalc = nil
+	var warnings []string = nil
+	// Whether shares were disposed of at the ACB per share, leaving it unchanged
+	// (before any superficial loss).
+	disposedAtPerShareAcb := false
+
+	fifo := options.CostBasis == FIFO_COST_BASIS
+	var lots []Lot = nil
+	if fifo {
+		lots = fifoLots(preTxStatus)
+	}
+
+	if preTxStatus.ShortBalance > 0 &&
+		tx.Action != BUY && tx.Action != SELL && tx.Action != NOTE {
+		return nil, fmt.Errorf("%s tx on %v of %s is not supported while the position is short",
+			tx.Action, tx.Date, tx.Security)
+	}
 
 	switch tx.Action {
 	case BUY:
-		newShareBalance = preTxStatus.ShareBalance + tx.Shares
 		totalPrice := totalLocalSharePrice + (tx.Commission * tx.CommissionCurrToLocalExchangeRate)
+		if preTxStatus.ShortBalance > 0 {
+			if tx.Shares > preTxStatus.ShortBalance {
+				return nil, fmt.Errorf("Buy order on %v of %d shares of %s is more than the "+
+					"current short position (%d). It must be split into a buy which covers "+
+					"the short position, and a buy of the remaining shares",
+					tx.Date, tx.Shares, tx.Security, preTxStatus.ShortBalance)
+			}
+			newShortBalance = preTxStatus.ShortBalance - tx.Shares
+			// Negative, since TotalAcb is the negated proceeds of the short sales.
+			coveredAcb := preTxStatus.TotalAcb / float64(preTxStatus.ShortBalance) * float64(tx.Shares)
+			newAcbTotal = preTxStatus.TotalAcb - coveredAcb
+			capitalGains = -coveredAcb - totalPrice
+			break
+		}
+		newShareBalance = preTxStatus.ShareBalance + tx.Shares
 		newAcbTotal = preTxStatus.TotalAcb + (totalPrice)
+		if fifo {
+			lots = append(lots, Lot{Date: tx.Date, Shares: tx.Shares, Acb: totalPrice})
+		}
 	case SELL:
 		if tx.Shares > preTxStatus.ShareBalance {
-			return nil, fmt.Errorf("Sell order on %v of %d shares of %s is more than the current holdings (%d)",
-				tx.Date, tx.Shares, tx.Security, preTxStatus.ShareBalance)
+			if !options.AllowShortSales {
+				return nil, fmt.Errorf("Sell order on %v of %d shares of %s is more than the current holdings (%d)",
+					tx.Date, tx.Shares, tx.Security, preTxStatus.ShareBalance)
+			} else if preTxStatus.ShareBalance > 0 {
+				return nil, fmt.Errorf("Sell order on %v of %d shares of %s is more than the "+
+					"current holdings (%d). It must be split into a sell of the current holdings, "+
+					"and a short sale of the remaining shares",
+					tx.Date, tx.Shares, tx.Security, preTxStatus.ShareBalance)
+			} else if fifo {
+				return nil, fmt.Errorf("Sell order on %v of %s: short sales are not "+
+					"supported with FIFO cost basis", tx.Date, tx.Security)
+			}
+			// Open or add to a short position
+			newShortBalance = preTxStatus.ShortBalance + tx.Shares
+			totalPayout := totalLocalSharePrice - tx.LocalCommissionAndOutlays()
+			newAcbTotal = preTxStatus.TotalAcb - totalPayout
+			break
 		}
 		newShareBalance = preTxStatus.ShareBalance - tx.Shares
+		disposedAtPerShareAcb = true
 		// Note commission plays no effect on sell order ACB
-		newAcbTotal = preTxStatus.TotalAcb - (preTxStatus.PerShareAcb() * float64(tx.Shares))
-		totalPayout := totalLocalSharePrice - (tx.Commission * tx.CommissionCurrToLocalExchangeRate)
-		capitalGains = totalPayout - (preTxStatus.PerShareAcb() * float64(tx.Shares))
+		disposedAcb := preTxStatus.PerShareAcb() * float64(tx.Shares)
+		if fifo {
+			lots, disposedAcb = consumeLots(lots, tx.Shares)
+		}
+		newAcbTotal = preTxStatus.TotalAcb - disposedAcb
+		totalPayout := totalLocalSharePrice - tx.LocalCommissionAndOutlays()
+		capitalGains = totalPayout - disposedAcb
 
-		if capitalGains < 0.0 && applySuperficialLosses {
-			superficialLossPercent := SuperficialLossPercent(idx, txs, newShareBalance)
-			if superficialLossPercent != 0.0 {
+		if fifo && capitalGains < 0.0 {
+			specifiedSfl := tx.SpecifiedSuperficialLoss
+			if (specifiedSfl != nil && *specifiedSfl != 0.0) ||
+				(specifiedSfl == nil && applySuperficialLosses &&
+					SuperficialLossPercent(idx, txs, newShareBalance) != 0.0) {
+				return nil, fmt.Errorf("Sell order on %v of %s: superficial losses are not "+
+					"supported with FIFO cost basis", tx.Date, tx.Security)
+			}
+		} else if tx.SpecifiedSuperficialLoss != nil {
+			specifiedSfl := *tx.SpecifiedSuperficialLoss
+			if specifiedSfl != 0.0 && capitalGains >= 0.0 {
+				return nil, specifiedSflOnGainError(idx, txs, preTxStatus, capitalGains, options)
+			} else if capitalGains < 0.0 && specifiedSfl < capitalGains {
+				return nil, fmt.Errorf("Sell order on %v of %s: specified superficial loss (%f) "+
+					"exceeds the capital loss (%f)",
+					tx.Date, tx.Security, specifiedSfl, capitalGains)
+			}
+			superficialLoss = specifiedSfl
+			capitalGains = capitalGains - superficialLoss
+			newAcbTotal -= superficialLoss
+		} else if capitalGains < 0.0 && applySuperficialLosses {
+			calc := getSuperficialLossCalc(idx, txs, newShareBalance)
+			if calc != nil && calc.SuperficialShares > 0 {
+				superficialLossCalc = calc
+				superficialLossPercent := float64(calc.SuperficialShares) / float64(calc.SharesSold)
 				if noPartialSuperficialLosses {
 					superficialLoss = capitalGains
 					capitalGains = 0.0
@@ -155,58 +482,361 @@ func AddTx(idx int, txs []*Tx, preTxStatus *PortfolioSecurityStatus, legacyOptio
 				newAcbTotal -= superficialLoss
 			}
 		}
+		if superficialLoss != 0.0 {
+			if warning := suspectedSplitWarning(idx, txs); warning != "" {
+				warnings = append(warnings, warning)
+			}
+		}
+	case GIFT:
+		if tx.Shares > preTxStatus.ShareBalance {
+			return nil, fmt.Errorf("Gift on %v of %d shares of %s is more than the current holdings (%d)",
+				tx.Date, tx.Shares, tx.Security, preTxStatus.ShareBalance)
+		}
+		newShareBalance = preTxStatus.ShareBalance - tx.Shares
+		disposedAtPerShareAcb = true
+		// A deemed disposition at fair market value (AmountPerShare).
+		// Superficial losses are not applied.
+		disposedAcb := preTxStatus.PerShareAcb() * float64(tx.Shares)
+		if fifo {
+			lots, disposedAcb = consumeLots(lots, tx.Shares)
+		}
+		newAcbTotal = preTxStatus.TotalAcb - disposedAcb
+		proceeds := totalLocalSharePrice - tx.LocalCommissionAndOutlays()
+		capitalGains = proceeds - disposedAcb
+		if tx.ListedSecurityDonation && capitalGains > 0.0 {
+			capitalGains = 0.0
+		}
+	case OPEN:
+		if idx != 0 || preTxStatus.ShareBalance != 0 || preTxStatus.TotalAcb != 0.0 {
+			return nil, fmt.Errorf("Open tx on %v of %s must be the first transaction of "+
+				"the security, and cannot be combined with an initial status",
+				tx.Date, tx.Security)
+		}
+		// The share balance and ACB are set directly, as for an Acquire.
+		newShareBalance = tx.Shares
+		openingAcb := totalLocalSharePrice
+		if tx.TotalAmount != 0.0 {
+			openingAcb = tx.TxCurrToLocal(tx.TotalAmount)
+		}
+		if openingAcb < 0.0 {
+			return nil, fmt.Errorf("Invalid Open tx on %v: ACB (%f) is negative",
+				tx.Date, openingAcb)
+		}
+		newAcbTotal = openingAcb
+		if fifo {
+			lots = append(lots, Lot{Date: tx.Date, Shares: tx.Shares, Acb: openingAcb})
+		}
+	case ACQUIRE:
+		newShareBalance = preTxStatus.ShareBalance + tx.Shares
+		// The ACB is specified directly (eg. the FMV or the giver's ACB), so
+		// commission plays no part here.
+		acquiredAcb := totalLocalSharePrice
+		if tx.TotalAmount != 0.0 {
+			acquiredAcb = tx.TxCurrToLocal(tx.TotalAmount)
+		}
+		if acquiredAcb < 0.0 {
+			return nil, fmt.Errorf("Invalid Acquire tx on %v: ACB (%f) is negative",
+				tx.Date, acquiredAcb)
+		}
+		newAcbTotal = preTxStatus.TotalAcb + acquiredAcb
+		if fifo {
+			lots = append(lots, Lot{Date: tx.Date, Shares: tx.Shares, Acb: acquiredAcb})
+		}
 	case ROC:
 		if tx.Shares != 0 {
 			return nil, fmt.Errorf("Invalid RoC tx on %v: # of shares is non-zero (%d)",
 				tx.Date, tx.Shares)
 		}
-		acbReduction := (tx.AmountPerShare * float64(preTxStatus.ShareBalance) * tx.TxCurrToLocalExchangeRate)
+		rocAmount := tx.TxCurrToLocal(tx.AmountPerShare * float64(preTxStatus.ShareBalance))
+		fee := distributionFee(tx, options)
+		acbReduction := rocAmount - fee
+		if acbReduction < 0.0 {
+			return nil, fmt.Errorf("Invalid RoC tx on %v: the fee (%f) exceeds the RoC (%f)",
+				tx.Date, fee, rocAmount)
+		}
+		if withholding := tx.TxCurrToLocal(tx.WithholdingTax); withholding > rocAmount {
+			return nil, fmt.Errorf("Invalid RoC tx on %v: the withholding tax (%f) exceeds "+
+				"the RoC (%f)", tx.Date, withholding, rocAmount)
+		}
 		newAcbTotal = preTxStatus.TotalAcb - acbReduction
-		if newAcbTotal < 0.0 {
+		if newAcbTotal < -rocRoundingTolerance {
 			return nil, fmt.Errorf("Invalid RoC tx on %v: RoC (%f) exceeds the current ACB (%f)",
 				tx.Date, acbReduction, preTxStatus.TotalAcb)
+		} else if newAcbTotal < 0.0 {
+			warnings = append(warnings, fmt.Sprintf(
+				"RoC of %s on %s (%f) exceeds the ACB (%f) by less than %.3f, which is "+
+					"assumed to be from rounding. The ACB is reduced to 0",
+				tx.Security, util.DateStr(tx.Date), acbReduction, preTxStatus.TotalAcb,
+				rocRoundingTolerance))
+			newAcbTotal = 0.0
+		}
+		for i, _ := range lots {
+			lots[i].Acb -= tx.TxCurrToLocal(tx.AmountPerShare*float64(lots[i].Shares)) -
+				fee*float64(lots[i].Shares)/float64(preTxStatus.ShareBalance)
+			if lots[i].Acb < 0.0 {
+				lots[i].Acb = 0.0
+			}
+		}
+	case ADJUST:
+		if tx.Shares != 0 {
+			return nil, fmt.Errorf("Invalid Adjust tx on %v: # of shares is non-zero (%d)",
+				tx.Date, tx.Shares)
+		} else if preTxStatus.ShareBalance == 0 {
+			return nil, fmt.Errorf("Invalid Adjust tx on %v: no shares of %s are held",
+				tx.Date, tx.Security)
+		}
+		adjustment := tx.TxCurrToLocal(tx.TotalAmount)
+		newAcbTotal = preTxStatus.TotalAcb + adjustment
+		if newAcbTotal < 0.0 {
+			return nil, fmt.Errorf("Invalid Adjust tx on %v: the reduction (%f) exceeds the "+
+				"current ACB (%f)", tx.Date, -adjustment, preTxStatus.TotalAcb)
+		}
+		// Applied to the lots in proportion to their shares.
+		for i, _ := range lots {
+			lots[i].Acb += adjustment * float64(lots[i].Shares) /
+				float64(preTxStatus.ShareBalance)
+			if lots[i].Acb < 0.0 {
+				lots[i].Acb = 0.0
+			}
+		}
+	case DIV:
+		if tx.Shares != 0 {
+			return nil, fmt.Errorf("Invalid Div tx on %v: # of shares is non-zero (%d)",
+				tx.Date, tx.Shares)
+		}
+		dividend := tx.TxCurrToLocal(tx.DividendAmount(preTxStatus.ShareBalance))
+		fee := distributionFee(tx, options)
+		if fee > dividend {
+			return nil, fmt.Errorf("Invalid Div tx on %v: the fee (%f) exceeds the dividend (%f)",
+				tx.Date, fee, dividend)
+		}
+		if withholding := tx.TxCurrToLocal(tx.WithholdingTax); withholding > dividend {
+			return nil, fmt.Errorf("Invalid Div tx on %v: the withholding tax (%f) exceeds "+
+				"the dividend (%f)", tx.Date, withholding, dividend)
+		}
+		dividendIncome = dividend - fee
+	case NOTE:
+		if tx.Shares != 0 {
+			return nil, fmt.Errorf("Invalid Note tx on %v: # of shares is non-zero (%d)",
+				tx.Date, tx.Shares)
+		}
+	case SPINOFF, RENAME:
+		if tx.Shares != 0 {
+			return nil, fmt.Errorf("Invalid %s tx on %v: # of shares is non-zero (%d)",
+				tx.Action, tx.Date, tx.Shares)
+		}
+		ratio := tx.Ratio
+		allocatedAcb := preTxStatus.TotalAcb * tx.AcbAllocationPercent / 100.0
+		memo := fmt.Sprintf("Spinoff from %s", tx.Security)
+		if tx.Action == RENAME {
+			if ratio == 0.0 {
+				ratio = 1.0
+			}
+			allocatedAcb = preTxStatus.TotalAcb
+			memo = fmt.Sprintf("Renamed from %s", tx.Security)
+			newShareBalance = 0
+		}
+		newShares, whole := splitShares(preTxStatus.ShareBalance, ratio)
+		if !whole {
+			return nil, fmt.Errorf("Invalid %s tx on %v: %d shares at a ratio of %f "+
+				"results in a fractional number of %s shares",
+				tx.Action, tx.Date, preTxStatus.ShareBalance, ratio, tx.NewSecurity)
+		}
+		newAcbTotal = preTxStatus.TotalAcb - allocatedAcb
+		if tx.Action == RENAME {
+			// In FIFO mode, the derived Acquire becomes a single lot in the new
+			// security, dated at the rename.
+			lots = nil
+		} else {
+			for i, _ := range lots {
+				lots[i].Acb -= lots[i].Acb * tx.AcbAllocationPercent / 100.0
+			}
+		}
+		derivedTx = &Tx{
+			Security: tx.NewSecurity, Date: tx.Date, Action: ACQUIRE,
+			Shares: newShares, TotalAmount: allocatedAcb,
+			TxCurrency: options.LocalCurrency, TxCurrToLocalExchangeRate: 1.0,
+			CommissionCurrency: options.LocalCurrency, CommissionCurrToLocalExchangeRate: 1.0,
+			Memo:      memo,
+			ReadIndex: tx.ReadIndex,
+			Origin:    ORIGIN_DERIVED,
+		}
+	case SPLIT:
+		if tx.Shares != 0 {
+			return nil, fmt.Errorf("Invalid Split tx on %v: # of shares is non-zero (%d)",
+				tx.Date, tx.Shares)
+		}
+		var whole bool
+		newShareBalance, whole = splitShares(preTxStatus.ShareBalance, tx.Ratio)
+		if !whole {
+			return nil, fmt.Errorf("Invalid Split tx on %v: %d shares of %s at a ratio of %f "+
+				"results in a fractional number of shares",
+				tx.Date, preTxStatus.ShareBalance, tx.Security, tx.Ratio)
+		}
+		for i, _ := range lots {
+			lots[i].Shares, whole = splitShares(lots[i].Shares, tx.Ratio)
+			if !whole {
+				return nil, fmt.Errorf("Invalid Split tx on %v: a lot of %s at a ratio of %f "+
+					"results in a fractional number of shares", tx.Date, tx.Security, tx.Ratio)
+			}
 		}
 	default:
-		util.Assertf(false, "Invalid action: %v\n", tx.Action)
+		return nil, fmt.Errorf("Tx on %v of %s has an invalid action (%d)",
+			tx.Date, tx.Security, tx.Action)
+	}
+
+	var trackedPerShareAcb float64 = 0.0
+	if options.PerShareAcbTracking && !fifo && newShortBalance == 0 && newShareBalance > 0 {
+		if disposedAtPerShareAcb && superficialLoss == 0.0 {
+			trackedPerShareAcb = preTxStatus.PerShareAcb()
+		} else {
+			trackedPerShareAcb = newAcbTotal / float64(newShareBalance)
+		}
+		newAcbTotal = trackedPerShareAcb * float64(newShareBalance)
 	}
 
+	newAcbTotal = options.Rounding.Round(newAcbTotal)
+	capitalGains = options.Rounding.Round(capitalGains)
+	superficialLoss = options.Rounding.Round(superficialLoss)
+
 	newStatus := &PortfolioSecurityStatus{
-		Security:     preTxStatus.Security,
-		ShareBalance: newShareBalance,
-		TotalAcb:     newAcbTotal,
+		Security:           preTxStatus.Security,
+		ShareBalance:       newShareBalance,
+		TotalAcb:           newAcbTotal,
+		ShortBalance:       newShortBalance,
+		Lots:               lots,
+		TrackedPerShareAcb: trackedPerShareAcb,
 	}
 	delta := &TxDelta{
-		Tx:              tx,
-		PreStatus:       preTxStatus,
-		PostStatus:      newStatus,
-		CapitalGain:     capitalGains,
-		SuperficialLoss: superficialLoss,
+		Tx:                  tx,
+		PreStatus:           preTxStatus,
+		PostStatus:          newStatus,
+		CapitalGain:         capitalGains,
+		SuperficialLoss:     superficialLoss,
+		SuperficialLossCalc: superficialLossCalc,
+		DividendIncome:      dividendIncome,
+		WithholdingTax:      tx.TxCurrToLocal(tx.WithholdingTax),
+		DerivedTx:           derivedTx,
+		Warnings:            warnings,
 	}
 	return delta, nil
 }
 
-func TxsToDeltaList(txs []*Tx, initialStatus *PortfolioSecurityStatus, legacyOptions LegacyOptions) ([]*TxDelta, error) {
-	if initialStatus == nil {
-		if len(txs) == 0 {
-			return []*TxDelta{}, nil
+// Computes the deltas of txs (which must all be for one security, and be
+// sorted) one at a time. Txs may be injected while iterating (eg. the sale of
+// odd lots before a Split), so it keeps its own copy of txs.
+type DeltaIterator struct {
+	txs        []*Tx
+	idx        int
+	lastStatus *PortfolioSecurityStatus
+	options    DeltaOptions
+	err        error
+	// Deltas of Sells with an automatically computed superficial loss, since
+	// the last Split.
+	superficialSells []*TxDelta
+}
+
+// Returns a warning if the superficial shares of delta (a Sell), together with
+// those of the superficial Sells in the 30 days before it, exceed the shares
+// acquired in its period. The same acquired shares were then likely counted
+// for more than one Sell, so the superficial loss may be over-applied.
+// Returns "" otherwise.
+func (it *DeltaIterator) overAppliedSuperficialLossWarning(delta *TxDelta) string {
+	calc := delta.SuperficialLossCalc
+	if calc == nil || delta.SuperficialLoss == 0.0 {
+		return ""
+	}
+	firstDate := delta.Tx.Date.Add(-30 * ONE_DAY_DUR)
+	var priorShares uint32 = 0
+	for _, prior := range it.superficialSells {
+		if !prior.Tx.Date.Before(firstDate) {
+			priorShares += prior.SuperficialLossCalc.SuperficialShares
 		}
+	}
+	if priorShares == 0 || priorShares+calc.SuperficialShares <= calc.TotalAcquiredInPeriod {
+		return ""
+	}
+	return fmt.Sprintf("Sell of %s on %s has a superficial loss on %d shares, but Sells "+
+		"in the 30 days before it already had superficial losses on %d shares, and only %d "+
+		"shares were acquired in its period. The same acquired shares may have been counted "+
+		"more than once, so the superficial loss may be over-applied",
+		delta.Tx.Security, util.DateStr(delta.Tx.Date), calc.SuperficialShares,
+		priorShares, calc.TotalAcquiredInPeriod)
+}
+
+// initialStatus may be nil, in which case there are no shares before txs.
+func NewDeltaIterator(
+	txs []*Tx, initialStatus *PortfolioSecurityStatus,
+	options DeltaOptions) *DeltaIterator {
+
+	if initialStatus == nil && len(txs) > 0 {
 		initialStatus = &PortfolioSecurityStatus{
 			Security: txs[0].Security, ShareBalance: 0, TotalAcb: 0.0,
 		}
 	}
+	return &DeltaIterator{
+		txs:        append([]*Tx{}, txs...),
+		idx:        0,
+		lastStatus: initialStatus,
+		options:    options,
+		err:        nil,
+	}
+}
+
+// Returns the delta of the next tx, or nil once all txs have been computed.
+// After an error, the same error is returned by every later call.
+func (it *DeltaIterator) Next() (*TxDelta, error) {
+	if it.err != nil {
+		return nil, it.err
+	} else if it.idx >= len(it.txs) {
+		return nil, nil
+	}
+
+	i := it.idx
+	if it.txs[i].Action == SPLIT && it.options.AutoOddLotSales {
+		sellTx, err := oddLotSaleTx(it.txs[i], it.lastStatus)
+		if err != nil {
+			it.err = fmt.Errorf("%v%s", err, txSourceSuffix(it.txs[i]))
+			return nil, it.err
+		} else if sellTx != nil {
+			it.txs = append(it.txs[:i], append([]*Tx{sellTx}, it.txs[i:]...)...)
+		}
+	}
+	delta, err := AddTx(i, it.txs, it.lastStatus, it.options)
+	if err != nil {
+		it.err = fmt.Errorf("%v%s", err, txSourceSuffix(it.txs[i]))
+		return nil, it.err
+	}
+	if warning := it.overAppliedSuperficialLossWarning(delta); warning != "" {
+		delta.Warnings = append(delta.Warnings, warning)
+	}
+	if delta.Tx.Action == SPLIT {
+		// Share counts before the split are not comparable to those after it.
+		it.superficialSells = nil
+	} else if delta.SuperficialLossCalc != nil && delta.SuperficialLoss != 0.0 {
+		it.superficialSells = append(it.superficialSells, delta)
+	}
+	for j, warning := range delta.Warnings {
+		delta.Warnings[j] = warning + txSourceSuffix(it.txs[i])
+	}
+	it.idx++
+	it.lastStatus = delta.PostStatus
+	return delta, nil
+}
 
+func TxsToDeltaList(txs []*Tx, initialStatus *PortfolioSecurityStatus, options DeltaOptions) ([]*TxDelta, error) {
 	deltas := make([]*TxDelta, 0, len(txs))
-	lastStatus := initialStatus
-	for i, _ := range txs {
-		delta, err := AddTx(i, txs, lastStatus, legacyOptions)
+	it := NewDeltaIterator(txs, initialStatus, options)
+	for {
+		delta, err := it.Next()
 		if err != nil {
 			// Return what we've managed so far, for debugging
 			return deltas, err
+		} else if delta == nil {
+			return deltas, nil
 		}
-		lastStatus = delta.PostStatus
 		deltas = append(deltas, delta)
 	}
-	return deltas, nil
 }
 
 func SplitTxsBySecurity(txs []*Tx) map[string][]*Tx {
@@ -221,3 +851,144 @@ func SplitTxsBySecurity(txs []*Tx) map[string][]*Tx {
 	}
 	return txsBySec
 }
+
+// Returns the txs of security, along with the txs of any securities which
+// (directly or indirectly) derive txs into it, since those are needed to
+// compute it. Order is preserved.
+func FilterTxsForSecurity(txs []*Tx, security string) []*Tx {
+	needed := map[string]bool{security: true}
+	for changed := true; changed; {
+		changed = false
+		for _, tx := range txs {
+			derivesTx := tx.Action == SPINOFF || tx.Action == RENAME
+			if derivesTx && needed[tx.NewSecurity] && !needed[tx.Security] {
+				needed[tx.Security] = true
+				changed = true
+			}
+		}
+	}
+
+	filtered := make([]*Tx, 0, len(txs))
+	for _, tx := range txs {
+		if needed[tx.Security] {
+			filtered = append(filtered, tx)
+		}
+	}
+	return filtered
+}
+
+// Inserts tx into txs (which must be sorted), before any txs on the same day.
+func insertDerivedTx(txs []*Tx, tx *Tx) []*Tx {
+	i := sort.Search(len(txs), func(i int) bool {
+		return !txs[i].Date.Before(tx.Date)
+	})
+	txs = append(txs, nil)
+	copy(txs[i+1:], txs[i:])
+	txs[i] = tx
+	return txs
+}
+
+// Computes the deltas of every security in txs (which must be sorted).
+//
+// Txs which move shares and ACB into another security (Spinoff and Rename)
+// add a derived Acquire to that security's txs, so each security is computed
+// only after all of the securities which derive txs into it.
+func TxsToDeltaModels(
+	txs []*Tx, allInitStatus map[string]*PortfolioSecurityStatus,
+	options DeltaOptions) map[string]*SecurityDeltas {
+
+	return TxsToDeltaModelsWithProgress(txs, allInitStatus, options, nil)
+}
+
+// Called with the number of securities computed so far, and the total number
+// of securities.
+type ProgressFunc func(done int, total int)
+
+// Same as TxsToDeltaModels, but calls progress (if non-nil) after each security
+// is computed.
+func TxsToDeltaModelsWithProgress(
+	txs []*Tx, allInitStatus map[string]*PortfolioSecurityStatus,
+	options DeltaOptions, progress ProgressFunc) map[string]*SecurityDeltas {
+
+	txsBySec := SplitTxsBySecurity(txs)
+
+	// The securities which each security derives txs into, and the number of
+	// unprocessed securities which derive txs into each security.
+	targets := make(map[string]map[string]bool)
+	nPendingSources := make(map[string]int)
+	for sec, secTxs := range txsBySec {
+		targets[sec] = make(map[string]bool)
+		for _, tx := range secTxs {
+			derivesTx := tx.Action == SPINOFF || tx.Action == RENAME
+			if derivesTx && !targets[sec][tx.NewSecurity] {
+				targets[sec][tx.NewSecurity] = true
+				nPendingSources[tx.NewSecurity]++
+			}
+		}
+	}
+	for sec, _ := range nPendingSources {
+		if _, ok := txsBySec[sec]; !ok {
+			txsBySec[sec] = []*Tx{}
+		}
+	}
+
+	ready := make([]string, 0, len(txsBySec))
+	for sec, _ := range txsBySec {
+		if nPendingSources[sec] == 0 {
+			ready = append(ready, sec)
+		}
+	}
+	sort.Strings(ready)
+
+	deltaModels := make(map[string]*SecurityDeltas)
+	for len(ready) > 0 {
+		sec := ready[0]
+		ready = ready[1:]
+
+		secInitStatus, ok := allInitStatus[sec]
+		if !ok {
+			secInitStatus = nil
+		}
+		deltas, err := TxsToDeltaList(txsBySec[sec], secInitStatus, options)
+		deltaModel := &SecurityDeltas{Deltas: deltas}
+		if err != nil {
+			deltaModel.Errors = append(deltaModel.Errors, err)
+		}
+		deltaModels[sec] = deltaModel
+		if progress != nil {
+			progress(len(deltaModels), len(txsBySec))
+		}
+
+		for _, d := range deltas {
+			if d.DerivedTx != nil {
+				target := d.DerivedTx.Security
+				txsBySec[target] = insertDerivedTx(txsBySec[target], d.DerivedTx)
+			}
+		}
+		newlyReady := make([]string, 0)
+		for target, _ := range targets[sec] {
+			nPendingSources[target]--
+			if nPendingSources[target] == 0 {
+				newlyReady = append(newlyReady, target)
+			}
+		}
+		sort.Strings(newlyReady)
+		ready = append(ready, newlyReady...)
+	}
+
+	// Anything left over derives txs from itself, directly or indirectly.
+	for sec, _ := range txsBySec {
+		if _, ok := deltaModels[sec]; !ok {
+			deltaModels[sec] = &SecurityDeltas{
+				Deltas: []*TxDelta{},
+				Errors: []error{fmt.Errorf(
+					"Cannot compute %s: it receives shares from a security which "+
+						"(directly or indirectly) receives shares from it", sec)},
+			}
+			if progress != nil {
+				progress(len(deltaModels), len(txsBySec))
+			}
+		}
+	}
+	return deltaModels
+}
@@ -0,0 +1,68 @@
+package portfolio
+
+import (
+	"fmt"
+	"strings"
+
+	"github.com/tsiemens/acb/util"
+)
+
+// Active ISO 4217 currency codes.
+var iso4217Codes = makeCurrencySet(
+	"AED AFN ALL AMD ANG AOA ARS AUD AWG AZN BAM BBD BDT BGN BHD BIF BMD BND "+
+		"BOB BRL BSD BTN BWP BYN BZD CAD CDF CHF CLP CNY COP CRC CUP CVE CZK DJF "+
+		"DKK DOP DZD EGP ERN ETB EUR FJD FKP GBP GEL GHS GIP GMD GNF GTQ GYD HKD "+
+		"HNL HTG HUF IDR ILS INR IQD IRR ISK JMD JOD JPY KES KGS KHR KMF KPW KRW "+
+		"KWD KYD KZT LAK LBP LKR LRD LSL LYD MAD MDL MGA MKD MMK MNT MOP MRU MUR "+
+		"MVR MWK MXN MYR MZN NAD NGN NIO NOK NPR NZD OMR PAB PEN PGK PHP PKR PLN "+
+		"PYG QAR RON RSD RUB RWF SAR SBD SCR SDG SEK SGD SHP SLE SOS SRD SSP STN "+
+		"SVC SYP SZL THB TJS TMT TND TOP TRY TTD TWD TZS UAH UGX USD UYU UZS VES "+
+		"VND VUV WST XAF XCD XOF XPF YER ZAR ZMW ZWL")
+
+func makeCurrencySet(codes string) map[Currency]bool {
+	set := make(map[Currency]bool)
+	for _, code := range strings.Fields(codes) {
+		set[Currency(code)] = true
+	}
+	return set
+}
+
+// Returns whether curr is an ISO 4217 currency code. The unspecified currency
+// (DEFAULT_CURRENCY) is always considered valid.
+func IsKnownCurrency(curr Currency) bool {
+	return curr == DEFAULT_CURRENCY || iso4217Codes[curr]
+}
+
+// A currency of a tx which is not an ISO 4217 code (eg. a typo like "USE").
+type UnknownCurrency struct {
+	Tx *Tx
+	// The column of the currency ("currency" or "commission currency")
+	Column   string
+	Currency Currency
+}
+
+func (c *UnknownCurrency) String() string {
+	return fmt.Sprintf(
+		"The %s of transaction %s (%s %s on %s), %s, is not a known currency code. "+
+			"Check that it is correct",
+		c.Column, c.Tx.RefStr(), c.Tx.Action, c.Tx.Security, util.DateStr(c.Tx.Date),
+		c.Currency)
+}
+
+// Finds currencies in txs which are not ISO 4217 codes. These do not prevent
+// computation, so this is only meant for warnings.
+func FindUnknownCurrencies(txs []*Tx) []*UnknownCurrency {
+	found := make([]*UnknownCurrency, 0)
+	for _, tx := range txs {
+		if !IsKnownCurrency(tx.TxCurrency) {
+			found = append(found, &UnknownCurrency{
+				Tx: tx, Column: "currency", Currency: tx.TxCurrency})
+		}
+		// A commission in the tx currency is only reported once.
+		if tx.CommissionCurrency != tx.TxCurrency && !IsKnownCurrency(tx.CommissionCurrency) {
+			found = append(found, &UnknownCurrency{
+				Tx: tx, Column: "commission currency", Currency: tx.CommissionCurrency})
+		}
+	}
+	return found
+}
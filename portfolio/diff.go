@@ -0,0 +1,233 @@
+package portfolio
+
+import (
+	"fmt"
+	"math"
+	"sort"
+	"strings"
+	"time"
+
+	"github.com/tsiemens/acb/util"
+)
+
+type DeltaDiffKind int
+
+const (
+	DIFF_ADDED DeltaDiffKind = iota
+	DIFF_REMOVED
+	DIFF_CHANGED
+)
+
+func (k DeltaDiffKind) String() string {
+	switch k {
+	case DIFF_ADDED:
+		return "Added"
+	case DIFF_REMOVED:
+		return "Removed"
+	case DIFF_CHANGED:
+		return "Changed"
+	default:
+		return "invalid"
+	}
+}
+
+// A delta which differs between two runs. Old is nil for DIFF_ADDED, and New is
+// nil for DIFF_REMOVED.
+type DeltaDiff struct {
+	Kind DeltaDiffKind
+	Old  *TxDelta
+	New  *TxDelta
+	// For DIFF_CHANGED, the names of the values which differ (eg. "ACB").
+	Changes []string
+}
+
+func (d *DeltaDiff) delta() *TxDelta {
+	if d.New != nil {
+		return d.New
+	}
+	return d.Old
+}
+
+// Deltas are only matched between runs if they have the same key.
+// The read index is not part of it, since it shifts when rows are added.
+type deltaDiffKey struct {
+	Security string
+	Date     time.Time
+	Action   TxAction
+}
+
+func makeDeltaDiffKey(d *TxDelta) deltaDiffKey {
+	return deltaDiffKey{d.Tx.Security, d.Tx.Date, d.Tx.Action}
+}
+
+const deltaDiffTolerance = 0.000001
+
+func diffValuesDiffer(a float64, b float64) bool {
+	return math.Abs(a-b) > deltaDiffTolerance
+}
+
+// Whether the txs of a and b were (most likely) read from the same row.
+func sameDiffTxInputs(a *Tx, b *Tx) bool {
+	return a.Shares == b.Shares && a.TxCurrency == b.TxCurrency &&
+		!diffValuesDiffer(a.AmountPerShare, b.AmountPerShare) &&
+		!diffValuesDiffer(a.Commission, b.Commission) &&
+		!diffValuesDiffer(a.TotalAmount, b.TotalAmount) &&
+		!diffValuesDiffer(a.Ratio, b.Ratio)
+}
+
+// Returns the names of the values which differ between before and after.
+func deltaChanges(before *TxDelta, after *TxDelta) []string {
+	changes := []string{}
+	if !sameDiffTxInputs(before.Tx, after.Tx) {
+		changes = append(changes, "Tx")
+	}
+	if before.PostStatus.ShareBalance != after.PostStatus.ShareBalance ||
+		before.PostStatus.ShortBalance != after.PostStatus.ShortBalance {
+		changes = append(changes, "Share Balance")
+	}
+	if diffValuesDiffer(before.PostStatus.TotalAcb, after.PostStatus.TotalAcb) {
+		changes = append(changes, "ACB")
+	}
+	if diffValuesDiffer(before.CapitalGain, after.CapitalGain) {
+		changes = append(changes, "Cap. Gain")
+	}
+	if diffValuesDiffer(before.SuperficialLoss, after.SuperficialLoss) {
+		changes = append(changes, "SFL")
+	}
+	if diffValuesDiffer(before.DividendIncome, after.DividendIncome) {
+		changes = append(changes, "Dividend")
+	}
+	return changes
+}
+
+// Returns the deltas which were added, removed or changed from oldDeltas to
+// newDeltas (which may be for any number of securities), sorted by security and
+// then settlement date.
+//
+// Deltas are matched by their security, settlement date and action. Where
+// several share these, deltas with the same tx values (shares, amount, etc.)
+// are matched first, and any others in order. This way a row added on the same
+// day as another is reported as added, rather than shifting which of the rows
+// are matched.
+func DiffDeltas(oldDeltas []*TxDelta, newDeltas []*TxDelta) []*DeltaDiff {
+	keys := []deltaDiffKey{}
+	oldGroups := make(map[deltaDiffKey][]*TxDelta)
+	newGroups := make(map[deltaDiffKey][]*TxDelta)
+	for _, d := range newDeltas {
+		key := makeDeltaDiffKey(d)
+		if _, ok := newGroups[key]; !ok {
+			keys = append(keys, key)
+		}
+		newGroups[key] = append(newGroups[key], d)
+	}
+	for _, d := range oldDeltas {
+		key := makeDeltaDiffKey(d)
+		_, inNew := newGroups[key]
+		if _, ok := oldGroups[key]; !ok && !inNew {
+			keys = append(keys, key)
+		}
+		oldGroups[key] = append(oldGroups[key], d)
+	}
+
+	diffs := []*DeltaDiff{}
+	for _, key := range keys {
+		olds := oldGroups[key]
+		news := newGroups[key]
+		// The index in olds of the match of each of news, or -1.
+		matches := make([]int, len(news))
+		oldMatched := make([]bool, len(olds))
+		for i, n := range news {
+			matches[i] = -1
+			for j, o := range olds {
+				if !oldMatched[j] && sameDiffTxInputs(o.Tx, n.Tx) {
+					matches[i] = j
+					oldMatched[j] = true
+					break
+				}
+			}
+		}
+		for i, _ := range news {
+			if matches[i] != -1 {
+				continue
+			}
+			for j, _ := range olds {
+				if !oldMatched[j] {
+					matches[i] = j
+					oldMatched[j] = true
+					break
+				}
+			}
+		}
+
+		for i, n := range news {
+			if matches[i] == -1 {
+				diffs = append(diffs, &DeltaDiff{Kind: DIFF_ADDED, New: n})
+				continue
+			}
+			o := olds[matches[i]]
+			if changes := deltaChanges(o, n); len(changes) > 0 {
+				diffs = append(diffs, &DeltaDiff{Kind: DIFF_CHANGED, Old: o, New: n,
+					Changes: changes})
+			}
+		}
+		for j, o := range olds {
+			if !oldMatched[j] {
+				diffs = append(diffs, &DeltaDiff{Kind: DIFF_REMOVED, Old: o})
+			}
+		}
+	}
+
+	sort.SliceStable(diffs, func(i, j int) bool {
+		a, b := diffs[i].delta().Tx, diffs[j].delta().Tx
+		if a.Security != b.Security {
+			return a.Security < b.Security
+		}
+		return a.Date.Before(b.Date)
+	})
+	return diffs
+}
+
+func RenderDeltaDiffTable(diffs []*DeltaDiff, renderFullDollarValues bool) *RenderTable {
+	table := &RenderTable{}
+	table.Header = []string{"Change", "Security", "Date", "TX", "Shares", "Amt/Share",
+		"Old ACB", "New ACB", "Old Cap. Gain", "New Cap. Gain", "Changed Values"}
+
+	ph := _PrintHelper{PrintAllDecimals: renderFullDollarValues}
+	acbStr := func(d *TxDelta) string {
+		if d == nil {
+			return "-"
+		}
+		return "$" + ph.CurrStr(d.PostStatus.TotalAcb)
+	}
+	gainStr := func(d *TxDelta) string {
+		if d == nil {
+			return "-"
+		}
+		return strOrDash(d.Tx.Action == SELL || d.Tx.Action == GIFT || d.CoversShort(),
+			ph.PlusMinusDollar(d.CapitalGain, false))
+	}
+
+	counts := make(map[DeltaDiffKind]int)
+	for _, diff := range diffs {
+		tx := diff.delta().Tx
+		table.Rows = append(table.Rows, []string{
+			diff.Kind.String(),
+			tx.Security,
+			util.DateStr(tx.Date),
+			tx.Action.String(),
+			fmt.Sprintf("%d", tx.Shares),
+			ph.CurrWithFxStr(tx.AmountPerShare, tx.TxCurrency, tx.TxCurrToLocalExchangeRate),
+			acbStr(diff.Old),
+			acbStr(diff.New),
+			gainStr(diff.Old),
+			gainStr(diff.New),
+			strings.Join(diff.Changes, ", "),
+		})
+		table.RowSources = append(table.RowSources,
+			RenderRowSource{ReadIndex: tx.ReadIndex, Origin: tx.Origin})
+		counts[diff.Kind]++
+	}
+	table.Notes = append(table.Notes, fmt.Sprintf("%d added, %d removed, %d changed",
+		counts[DIFF_ADDED], counts[DIFF_REMOVED], counts[DIFF_CHANGED]))
+	return table
+}
@@ -0,0 +1,157 @@
+package portfolio
+
+import (
+	"fmt"
+	"math"
+	"strconv"
+	"strings"
+
+	"github.com/tsiemens/acb/util"
+)
+
+// Formats val with up to 6 decimals (and no trailing zeros), so that
+// explanations are deterministic.
+func explainNum(val float64) string {
+	return strconv.FormatFloat(math.Round(val*1e6)/1e6, 'f', -1, 64)
+}
+
+// Formats an amount in curr, and its conversion to localCurrency if curr is
+// not localCurrency. Eg. "60 USD x 1.5 (exchange rate) = 90 CAD"
+func explainLocalAmount(
+	amount float64, curr Currency, rateToLocal float64, localCurrency Currency) string {
+
+	if curr == DEFAULT_CURRENCY || curr == localCurrency {
+		return fmt.Sprintf("%s %s", explainNum(amount*rateToLocal), localCurrency)
+	}
+	return fmt.Sprintf("%s %s x %s (exchange rate) = %s %s",
+		explainNum(amount), curr, explainNum(rateToLocal),
+		explainNum(amount*rateToLocal), localCurrency)
+}
+
+// Returns the deduction of tx's fee from its distribution of gross (in local
+// currency), or "" if the fee is not netted.
+func explainDistributionFee(tx *Tx, gross float64, options DeltaOptions) string {
+	fee := distributionFee(tx, options)
+	if fee == 0.0 {
+		return ""
+	}
+	return fmt.Sprintf(", less fees of %s = %s %s",
+		explainNum(fee), explainNum(gross-fee), options.LocalCurrency)
+}
+
+func explainStatus(s *PortfolioSecurityStatus, localCurrency Currency) string {
+	if s.ShortBalance > 0 {
+		return fmt.Sprintf("%d shares short, total ACB %s %s",
+			s.ShortBalance, explainNum(s.TotalAcb), localCurrency)
+	}
+	return fmt.Sprintf("%d shares, total ACB %s %s",
+		s.ShareBalance, explainNum(s.TotalAcb), localCurrency)
+}
+
+// Returns a human-readable derivation of d, for debugging a surprising number.
+// It includes the status before the tx, the arithmetic for the proceeds (or
+// cost) and the disposed ACB, any superficial loss, and the status after the tx.
+// options must be those d was computed with.
+func ExplainTxDelta(d *TxDelta, options DeltaOptions) string {
+	tx := d.Tx
+	localCurrency := options.LocalCurrency
+	lines := []string{
+		fmt.Sprintf("Tx %s: %s of %s on %s", tx.RefStr(), tx.Action, tx.Security,
+			util.DateStr(tx.Date)),
+		"Before: " + explainStatus(d.PreStatus, localCurrency),
+	}
+	addLine := func(format string, args ...interface{}) {
+		lines = append(lines, fmt.Sprintf(format, args...))
+	}
+
+	shareAmount := float64(tx.Shares) * tx.AmountPerShare
+	switch tx.Action {
+	case BUY:
+		addLine("Cost: %d shares x %s/share = %s", tx.Shares, explainNum(tx.AmountPerShare),
+			explainLocalAmount(
+				shareAmount, tx.TxCurrency, tx.TxCurrToLocalExchangeRate, localCurrency))
+	case SELL:
+		addLine("Proceeds: %d shares x %s/share = %s", tx.Shares, explainNum(tx.AmountPerShare),
+			explainLocalAmount(
+				shareAmount, tx.TxCurrency, tx.TxCurrToLocalExchangeRate, localCurrency))
+	case GIFT:
+		addLine("Proceeds (fair market value): %d shares x %s/share = %s",
+			tx.Shares, explainNum(tx.AmountPerShare),
+			explainLocalAmount(
+				shareAmount, tx.TxCurrency, tx.TxCurrToLocalExchangeRate, localCurrency))
+	case ROC:
+		roc := tx.AmountPerShare * float64(d.PreStatus.ShareBalance)
+		addLine("Return of capital: %d shares x %s/share = %s%s",
+			d.PreStatus.ShareBalance, explainNum(tx.AmountPerShare),
+			explainLocalAmount(
+				roc, tx.TxCurrency, tx.TxCurrToLocalExchangeRate, localCurrency),
+			explainDistributionFee(tx, tx.TxCurrToLocal(roc), options))
+	case ADJUST:
+		addLine("ACB adjustment: %s", explainLocalAmount(
+			tx.TotalAmount, tx.TxCurrency, tx.TxCurrToLocalExchangeRate, localCurrency))
+	case DIV:
+		dividend := tx.DividendAmount(d.PreStatus.ShareBalance)
+		addLine("Dividend income: %s%s",
+			explainLocalAmount(
+				dividend, tx.TxCurrency, tx.TxCurrToLocalExchangeRate, localCurrency),
+			explainDistributionFee(tx, tx.TxCurrToLocal(dividend), options))
+	}
+	if tx.Commission != 0.0 {
+		addLine("Commission: %s", explainLocalAmount(
+			tx.Commission, tx.CommissionCurrency, tx.CommissionCurrToLocalExchangeRate,
+			localCurrency))
+	}
+	if tx.Outlays != 0.0 {
+		addLine("Outlays: %s", explainLocalAmount(
+			tx.Outlays, tx.CommissionCurrency, tx.CommissionCurrToLocalExchangeRate,
+			localCurrency))
+	}
+
+	disposes := (tx.Action == SELL || tx.Action == GIFT) &&
+		d.PostStatus.ShortBalance == d.PreStatus.ShortBalance
+	if disposes {
+		disposedAcb := d.DisposedAcb()
+		if d.PreStatus.Lots != nil || d.PostStatus.Lots != nil {
+			addLine("ACB disposed (FIFO lots): %s %s", explainNum(disposedAcb), localCurrency)
+		} else {
+			addLine("ACB disposed: %d shares x %s/share = %s %s", tx.Shares,
+				explainNum(d.PreStatus.PerShareAcb()), explainNum(disposedAcb), localCurrency)
+		}
+		proceeds := tx.TxCurrToLocal(shareAmount)
+		commission := tx.Commission * tx.CommissionCurrToLocalExchangeRate
+		outlays := tx.Outlays * tx.CommissionCurrToLocalExchangeRate
+		gain := proceeds - commission - outlays - disposedAcb
+		addLine("Capital gain: %s - %s - %s - %s = %s %s",
+			explainNum(proceeds), explainNum(commission), explainNum(outlays),
+			explainNum(disposedAcb), explainNum(gain), localCurrency)
+		if tx.Action == GIFT && tx.ListedSecurityDonation && gain > 0.0 {
+			addLine("The gain is exempt (donation of listed securities). Capital gain: 0 %s",
+				localCurrency)
+		}
+	} else if d.CoversShort() {
+		addLine("Capital gain (covering the short position): %s %s",
+			explainNum(d.CapitalGain), localCurrency)
+	}
+
+	if d.SuperficialLoss != 0.0 {
+		if calc := d.SuperficialLossCalc; calc != nil {
+			addLine("Superficial: min(%d sold, %d acquired in period, "+
+				"%d held at end of period) = %d, so %d/%d of the loss",
+				calc.SharesSold, calc.TotalAcquiredInPeriod, calc.SharesAtEndOfPeriod,
+				calc.SuperficialShares, calc.SuperficialShares, calc.SharesSold)
+		} else {
+			addLine("Superficial loss was specified")
+		}
+		addLine("Superficial loss: %s %s (added back to the ACB). Capital gain: %s %s",
+			explainNum(d.SuperficialLoss), localCurrency, explainNum(d.CapitalGain),
+			localCurrency)
+	}
+
+	addLine("ACB change: %s -> %s %s", explainNum(d.PreStatus.TotalAcb),
+		explainNum(d.PostStatus.TotalAcb), localCurrency)
+	for _, warning := range d.Warnings {
+		addLine("Warning: %s", warning)
+	}
+	lines = append(lines, "After: "+explainStatus(d.PostStatus, localCurrency))
+	return strings.Join(lines, "\n")
+}
@@ -0,0 +1,68 @@
+package portfolio
+
+import (
+	"fmt"
+	"strings"
+	"time"
+)
+
+type CostBasisMethod int
+
+const (
+	// Adjusted cost base (average cost), as required for Canadian taxes.
+	ACB_COST_BASIS CostBasisMethod = iota
+	// First-in first-out. Sells dispose of the oldest shares first.
+	// This is not valid for Canadian tax purposes.
+	FIFO_COST_BASIS
+)
+
+func ParseCostBasisMethod(name string) (CostBasisMethod, error) {
+	switch strings.TrimSpace(strings.ToLower(name)) {
+	case "", "acb":
+		return ACB_COST_BASIS, nil
+	case "fifo":
+		return FIFO_COST_BASIS, nil
+	default:
+		return ACB_COST_BASIS, fmt.Errorf(
+			"Invalid cost basis method '%s' (must be acb or fifo)", name)
+	}
+}
+
+// A group of shares acquired together, tracked for FIFO cost basis.
+type Lot struct {
+	Date   time.Time
+	Shares uint32
+	Acb    float64
+}
+
+// Returns a copy of the lots of status, oldest first.
+// A status with shares but without lots (eg. an initial status) is treated as
+// a single lot.
+func fifoLots(status *PortfolioSecurityStatus) []Lot {
+	if status.Lots == nil && status.ShareBalance > 0 {
+		return []Lot{{Shares: status.ShareBalance, Acb: status.TotalAcb}}
+	}
+	return append([]Lot{}, status.Lots...)
+}
+
+// Removes shares from the oldest lots.
+// Returns the remaining lots and the ACB of the removed shares.
+func consumeLots(lots []Lot, shares uint32) ([]Lot, float64) {
+	var acb float64 = 0.0
+	remaining := shares
+	for remaining > 0 && len(lots) > 0 {
+		lot := &lots[0]
+		if lot.Shares <= remaining {
+			acb += lot.Acb
+			remaining -= lot.Shares
+			lots = lots[1:]
+		} else {
+			partAcb := lot.Acb / float64(lot.Shares) * float64(remaining)
+			acb += partAcb
+			lot.Acb -= partAcb
+			lot.Shares -= remaining
+			remaining = 0
+		}
+	}
+	return lots, acb
+}
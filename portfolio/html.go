@@ -0,0 +1,101 @@
+package portfolio
+
+import (
+	"fmt"
+	"html"
+	"io"
+	"sort"
+	"strings"
+)
+
+// A table with a title, for a report.
+type TitledRenderTable struct {
+	Title string
+	Table *RenderTable
+}
+
+const htmlReportStyle = `body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; margin: 0.5em 0; }
+th, td { border: 1px solid #ccc; padding: 0.25em 0.5em; text-align: left; }
+thead, tfoot { background: #f0f0f0; }
+tfoot { font-weight: bold; }
+summary { font-size: 1.2em; font-weight: bold; cursor: pointer; margin: 0.5em 0; }
+.error { color: #b00; }`
+
+func htmlCell(tag string, text string) string {
+	text = strings.ReplaceAll(html.EscapeString(text), "\n", "<br>")
+	return fmt.Sprintf("<%s>%s</%s>", tag, text, tag)
+}
+
+func writeHtmlRow(writer io.Writer, tag string, row []string) {
+	cells := make([]string, 0, len(row))
+	for _, text := range row {
+		cells = append(cells, htmlCell(tag, text))
+	}
+	fmt.Fprintf(writer, "<tr>%s</tr>\n", strings.Join(cells, ""))
+}
+
+// Writes the table as an HTML table, followed by its notes.
+func writeHtmlTable(tableModel *RenderTable, writer io.Writer) {
+	fmt.Fprintln(writer, "<table>")
+	fmt.Fprintln(writer, "<thead>")
+	writeHtmlRow(writer, "th", tableModel.Header)
+	fmt.Fprintln(writer, "</thead>")
+	fmt.Fprintln(writer, "<tbody>")
+	for _, row := range tableModel.Rows {
+		writeHtmlRow(writer, "td", row)
+	}
+	fmt.Fprintln(writer, "</tbody>")
+	if len(tableModel.Footer) > 0 {
+		fmt.Fprintln(writer, "<tfoot>")
+		writeHtmlRow(writer, "td", tableModel.Footer)
+		fmt.Fprintln(writer, "</tfoot>")
+	}
+	fmt.Fprintln(writer, "</table>")
+	for _, note := range tableModel.Notes {
+		fmt.Fprintln(writer, htmlCell("p", strings.TrimSpace(note)))
+	}
+}
+
+// Writes a self-contained HTML page, with the summaries at the top, followed by
+// a collapsible section for each security (in alphabetical order) with its
+// table. The footer of a security's table has its total capital gain.
+func WriteHtmlReport(
+	summaries []*TitledRenderTable, securityTables map[string]*RenderTable,
+	writer io.Writer) {
+
+	fmt.Fprintln(writer, "<!DOCTYPE html>")
+	fmt.Fprintln(writer, "<html>")
+	fmt.Fprintln(writer, "<head>")
+	fmt.Fprintln(writer, `<meta charset="utf-8">`)
+	fmt.Fprintln(writer, "<title>ACB Report</title>")
+	fmt.Fprintf(writer, "<style>\n%s\n</style>\n", htmlReportStyle)
+	fmt.Fprintln(writer, "</head>")
+	fmt.Fprintln(writer, "<body>")
+
+	for _, summary := range summaries {
+		fmt.Fprintln(writer, htmlCell("h2", summary.Title))
+		writeHtmlTable(summary.Table, writer)
+	}
+
+	secs := make([]string, 0, len(securityTables))
+	for sec, _ := range securityTables {
+		secs = append(secs, sec)
+	}
+	sort.Strings(secs)
+	for _, sec := range secs {
+		table := securityTables[sec]
+		fmt.Fprintln(writer, "<details open>")
+		fmt.Fprintln(writer, htmlCell("summary", "Transactions for "+sec))
+		for _, err := range table.Errors {
+			fmt.Fprintf(writer, "<p class=\"error\">%s</p>\n",
+				html.EscapeString(fmt.Sprintf(
+					"[!] %v. Printing parsed information state:", err)))
+		}
+		writeHtmlTable(table, writer)
+		fmt.Fprintln(writer, "</details>")
+	}
+
+	fmt.Fprintln(writer, "</body>")
+	fmt.Fprintln(writer, "</html>")
+}
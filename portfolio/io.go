@@ -4,37 +4,194 @@ import (
 	"encoding/csv"
 	"fmt"
 	"io"
+	"math"
+	"sort"
 	"strconv"
 	"strings"
 	"time"
 
 	"github.com/tsiemens/acb/fx"
+	"github.com/tsiemens/acb/util"
 )
 
 const (
-	CsvDateFormatDefault string = "2006-01-02"
+	CsvDateFormatDefault     string = "2006-01-02"
+	CsvSettlementDaysDefault uint32 = 2
 )
 
-var CsvDateFormat string = CsvDateFormatDefault
+// Options for how txs are read from (and written to) csvs (see
+// NewTxCsvReader).
+type CsvOptions struct {
+	// The currency which exchange rates convert to. Txs in it have a rate of 1.
+	LocalCurrency Currency
+	// The layout of dates (see ParseDateFormat).
+	DateFormat string
+	// The field delimiter.
+	Delimiter rune
+	// If set, numbers use a decimal comma, and optionally periods to separate
+	// thousands (eg. 1.234,56).
+	DecimalComma bool
+	// If set, rows with a trade date but no (settlement) date are settled
+	// SettlementDays business days after the trade date.
+	InferSettlementDates bool
+	SettlementDays       uint32
+	// If set, rows with a trade date but no (settlement) date are settled on the
+	// trade date (taking precedence over InferSettlementDates). Superficial loss
+	// periods and sorting use the settlement date, so these can shift slightly
+	// from the true ones.
+	TradeDateAsSettlement bool
+	// How exchange rates are quoted. Rates are always stored in Tx as local per
+	// foreign.
+	ExchangeRateConvention ExchangeRateConvention
+	// Additional (lower-case) action names accepted in the action column.
+	ActionAliases map[string]TxAction
+	// The currency assumed for rows which do not specify one.
+	DefaultCurrency Currency
+	// If non-negative, amounts per share are rounded to this many decimal places
+	// when read (eg. for distributions reported with many more decimals than are
+	// meaningful). If negative, they are kept at full precision.
+	AmountPerSharePrecision int
+	// If set, rows with an empty security take the security of the previous row
+	// in the same csv (the first row must still have one).
+	FillDownSecurity bool
+	// If set, the commission column may be negative, for rebates paid by the
+	// broker. A rebate reduces the ACB of a Buy, and increases the proceeds of a
+	// Sell.
+	AllowNegativeCommission bool
+	// If set, missing exchange rates are looked up here by settlement date. The
+	// USD/CAD rates should also be used as the Overrides of the RateLoader, so
+	// that they take precedence over the automatic ones.
+	RateTable *fx.CsvRateTable
+}
+
+func NewCsvOptions() CsvOptions {
+	return CsvOptions{
+		LocalCurrency:           CAD,
+		DateFormat:              CsvDateFormatDefault,
+		Delimiter:               ',',
+		SettlementDays:          CsvSettlementDaysDefault,
+		ExchangeRateConvention:  FX_LOCAL_PER_FOREIGN,
+		ActionAliases:           map[string]TxAction{},
+		DefaultCurrency:         DEFAULT_CURRENCY,
+		AmountPerSharePrecision: -1,
+	}
+}
+
+// Named date formats, which may be used in place of a layout for
+// CsvOptions.DateFormat.
+var namedDateFormats = map[string]string{
+	"iso": "2006-01-02",
+	"ymd": "2006/01/02",
+	"mdy": "01/02/2006",
+	"dmy": "02/01/2006",
+}
+
+// Returns the layout for a date format, which is either one of the named
+// formats (iso, ymd, mdy or dmy), or a layout representing Jan 2, 2006.
+func ParseDateFormat(format string) (string, error) {
+	if layout, ok := namedDateFormats[strings.TrimSpace(strings.ToLower(format))]; ok {
+		return layout, nil
+	}
+	refDate := time.Date(2006, time.January, 2, 0, 0, 0, 0, time.UTC)
+	parsed, err := time.Parse(format, refDate.Format(format))
+	if err != nil || !parsed.Equal(refDate) {
+		return "", fmt.Errorf("Invalid date format '%s' (must be iso, ymd, mdy, dmy, "+
+			"or represent Jan 2, 2006)", format)
+	}
+	return format, nil
+}
+
+// Returns the delimiter named by str, which must be a single character or
+// "tab".
+func ParseCsvDelimiter(str string) (rune, error) {
+	if strings.ToLower(str) == "tab" {
+		return '\t', nil
+	}
+	runes := []rune(str)
+	if len(runes) != 1 || runes[0] == '"' || runes[0] == '\r' || runes[0] == '\n' {
+		return ',', fmt.Errorf("Invalid delimiter '%s' (must be a single character, "+
+			"such as ';', or tab)", str)
+	}
+	return runes[0], nil
+}
+
+// Returns an error if decimalComma is set with a comma delimiter, since the
+// fields of numbers would then be ambiguous.
+func CheckCsvDecimalComma(delimiter rune, decimalComma bool) error {
+	if decimalComma && delimiter == ',' {
+		return fmt.Errorf("A decimal comma requires a delimiter other than ',' (eg. ';')")
+	}
+	return nil
+}
 
-type ColParser func(string, *Tx) error
+// Parses a number, per options.DecimalComma.
+func parseCsvFloat(data string, options *CsvOptions) (float64, error) {
+	if options.DecimalComma {
+		data = strings.ReplaceAll(data, ".", "")
+		data = strings.ReplaceAll(data, ",", ".")
+	}
+	return strconv.ParseFloat(data, 64)
+}
+
+type ExchangeRateConvention int
+
+const (
+	// Units of local currency per unit of foreign currency (eg. CAD per USD)
+	FX_LOCAL_PER_FOREIGN ExchangeRateConvention = iota
+	// Units of foreign currency per unit of local currency (eg. USD per CAD)
+	FX_FOREIGN_PER_LOCAL
+)
+
+func ParseExchangeRateConvention(name string) (ExchangeRateConvention, error) {
+	switch strings.TrimSpace(strings.ToLower(name)) {
+	case "", "standard":
+		return FX_LOCAL_PER_FOREIGN, nil
+	case "inverted":
+		return FX_FOREIGN_PER_LOCAL, nil
+	default:
+		return FX_LOCAL_PER_FOREIGN, fmt.Errorf(
+			"Invalid exchange rate convention '%s' (must be standard or inverted)", name)
+	}
+}
+
+type ColParser func(string, *Tx, *CsvOptions) error
 
 var colParserMap = map[string]ColParser{
-	"security":                 parseSecurity,
-	"date":                     parseDate,
-	"action":                   parseAction,
-	"shares":                   parseShares,
-	"amount/share":             parseAmountPerShare,
-	"commission":               parseCommission,
+	"security":     parseSecurity,
+	"date":         parseDate,
+	"trade date":   parseTradeDate,
+	"action":       parseAction,
+	"shares":       parseShares,
+	"amount/share": parseAmountPerShare,
+	"commission":   parseCommission,
+	// Handled by TxCsvReader, since these depend on other columns.
+	"commission %":             parseNothing,
+	"commission included":      parseNothing,
 	"currency":                 parseTxCurr,
 	"exchange rate":            parseTxFx,
 	"commission currency":      parseCommissionCurr,
 	"commission exchange rate": parseCommissionFx,
+	"outlays":                  parseOutlays,
+	"total amount":             parseTotalAmount,
+	"superficial loss":         parseSuperficialLoss,
+	"withholding tax":          parseWithholdingTax,
+	"new security":             parseNewSecurity,
+	"ratio":                    parseRatio,
+	"acb allocation %":         parseAcbAllocationPercent,
+	"listed security donation": parseListedSecurityDonation,
 	"memo":                     parseMemo,
 }
 
 var ColNames []string
 
+// The columns written by WriteTxCsv, in order.
+var CanonicalColNames = []string{
+	"security", "date", "trade date", "action", "shares", "amount/share", "commission",
+	"currency", "exchange rate", "commission currency", "commission exchange rate",
+	"outlays", "total amount", "withholding tax", "superficial loss",
+	"new security", "ratio", "acb allocation %", "listed security donation", "memo",
+}
+
 func init() {
 	ColNames = make([]string, 0, len(colParserMap))
 	for name, _ := range colParserMap {
@@ -54,17 +211,87 @@ func DefaultTx() *Tx {
 func CheckTxSanity(tx *Tx) error {
 	if tx.Security == "" {
 		return fmt.Errorf("Transaction has no security")
+	} else if (tx.Date == time.Time{}) && (tx.TradeDate != time.Time{}) {
+		return fmt.Errorf("Transaction has a trade date but no (settlement) date")
 	} else if (tx.Date == time.Time{}) {
 		return fmt.Errorf("Transaction has no date")
 	} else if tx.Action == NO_ACTION {
-		return fmt.Errorf("Transaction has no action (Buy, Sell, RoC)")
+		return fmt.Errorf("Transaction has no action (Buy, Sell, RoC, Div, Acquire, Spinoff, Rename, Split, Gift, Open, Adjust, Note)")
+	} else if tx.SpecifiedSuperficialLoss != nil && tx.Action != SELL {
+		return fmt.Errorf("Superficial loss can only be specified on a Sell")
+	} else if tx.WithholdingTax != 0.0 && tx.Action != ROC && tx.Action != DIV {
+		return fmt.Errorf("Withholding tax can only be specified on a RoC or Div")
+	} else if tx.Outlays != 0.0 &&
+		tx.Action != SELL && tx.Action != GIFT && tx.Action != ROC && tx.Action != DIV {
+		return fmt.Errorf("Outlays can only be specified on a Sell, Gift, RoC or Div")
+	} else if tx.ListedSecurityDonation && tx.Action != GIFT {
+		return fmt.Errorf("Listed security donation can only be specified on a Gift")
+	} else if tx.Action == SPINOFF || tx.Action == RENAME {
+		if tx.NewSecurity == "" || tx.NewSecurity == tx.Security {
+			return fmt.Errorf("%s requires a new security, other than %s", tx.Action, tx.Security)
+		} else if tx.Action == SPINOFF && tx.Ratio <= 0.0 {
+			return fmt.Errorf("Spinoff requires a positive ratio of new shares per share held")
+		} else if tx.Action == RENAME && tx.AcbAllocationPercent != 0.0 {
+			return fmt.Errorf("ACB allocation cannot be specified on a Rename (all ACB is carried over)")
+		}
+	} else if tx.Action == SPLIT {
+		if tx.Ratio <= 0.0 {
+			return fmt.Errorf("Split requires a positive ratio of new shares per share held")
+		} else if tx.NewSecurity != "" || tx.AcbAllocationPercent != 0.0 {
+			return fmt.Errorf("New security and ACB allocation cannot be specified on a Split")
+		}
+	} else if tx.NewSecurity != "" || tx.Ratio != 0.0 || tx.AcbAllocationPercent != 0.0 {
+		return fmt.Errorf("New security, ratio and ACB allocation can only be specified " +
+			"on a Spinoff or Rename (or ratio on a Split)")
 	}
 	return nil
 }
 
-func fixupTxFx(tx *Tx, rl *fx.RateLoader) error {
+// Whether autoLocalExchangeRate supports curr.
+func hasAutoLocalExchangeRate(curr Currency, localCurrency Currency) bool {
+	return (localCurrency == CAD && curr == USD) || (localCurrency == USD && curr == CAD)
+}
+
+// Returns the rate to convert curr to localCurrency on date, from the
+// exchange rates which can be downloaded (USD/CAD).
+func autoLocalExchangeRate(
+	curr Currency, localCurrency Currency, date time.Time, rl *fx.RateLoader) (float64, error) {
+
+	if localCurrency == CAD && curr == USD {
+		rate, err := rl.GetUsdCadRate(date)
+		if err != nil {
+			return 0.0, err
+		}
+		return rate.ForeignToLocalRate, nil
+	} else if localCurrency == USD && curr == CAD {
+		rate, err := rl.GetUsdCadRate(date)
+		if err != nil {
+			return 0.0, err
+		}
+		return 1.0 / rate.ForeignToLocalRate, nil
+	}
+	return 0.0, fmt.Errorf("Unsupported auto-FX for %s to %s", curr, localCurrency)
+}
+
+// Returns the automatic rate for curr, or for other currencies, the rate in
+// options.RateTable.
+func lookupLocalExchangeRate(
+	curr Currency, tx *Tx, rl *fx.RateLoader, options *CsvOptions) (float64, error) {
+
+	if options.RateTable != nil && !hasAutoLocalExchangeRate(curr, options.LocalCurrency) {
+		if rate, ok := options.RateTable.GetRate(string(curr), tx.Date); ok {
+			return rate.ForeignToLocalRate, nil
+		}
+	}
+	return autoLocalExchangeRate(curr, options.LocalCurrency, tx.Date, rl)
+}
+
+func fixupTxFx(tx *Tx, rl *fx.RateLoader, options *CsvOptions) error {
+	if tx.TxCurrency == DEFAULT_CURRENCY {
+		tx.TxCurrency = options.DefaultCurrency
+	}
 	if tx.TxCurrency == DEFAULT_CURRENCY ||
-		tx.TxCurrency == CAD {
+		tx.TxCurrency == options.LocalCurrency {
 		tx.TxCurrToLocalExchangeRate = 1.0
 	}
 	if tx.CommissionCurrency == DEFAULT_CURRENCY {
@@ -72,53 +299,88 @@ func fixupTxFx(tx *Tx, rl *fx.RateLoader) error {
 	}
 
 	if tx.TxCurrToLocalExchangeRate == 0.0 {
-		if tx.TxCurrency != USD {
-			return fmt.Errorf("Unsupported auto-FX for %s", tx.TxCurrency)
-		}
-		rate, err := rl.GetUsdCadRate(tx.Date)
+		rate, err := lookupLocalExchangeRate(tx.TxCurrency, tx, rl, options)
 		if err != nil {
 			return err
 		}
-		tx.TxCurrToLocalExchangeRate = rate.ForeignToLocalRate
+		tx.TxCurrToLocalExchangeRate = rate
 	}
 
 	if tx.TxCurrency == tx.CommissionCurrency &&
 		tx.CommissionCurrToLocalExchangeRate == 0.0 {
 		// If this didn't get set, make it match the other.
 		tx.CommissionCurrToLocalExchangeRate = tx.TxCurrToLocalExchangeRate
+	} else if tx.CommissionCurrency == options.LocalCurrency {
+		tx.CommissionCurrToLocalExchangeRate = 1.0
 	} else if tx.CommissionCurrToLocalExchangeRate == 0.0 {
-		if tx.TxCurrency != USD {
-			return fmt.Errorf("Unsupported auto-FX for %s", tx.TxCurrency)
-		}
-		rate, err := rl.GetUsdCadRate(tx.Date)
+		rate, err := lookupLocalExchangeRate(tx.CommissionCurrency, tx, rl, options)
 		if err != nil {
 			return err
 		}
-		tx.CommissionCurrToLocalExchangeRate = rate.ForeignToLocalRate
+		tx.CommissionCurrToLocalExchangeRate = rate
 	}
 	return nil
 }
 
-func ParseTxCsv(reader io.Reader, initialGlobalReadIndex uint32,
-	csvDesc string, rateLoader *fx.RateLoader) ([]*Tx, error) {
-
-	globalRowIndex := initialGlobalReadIndex
-	csvR := csv.NewReader(reader)
-	records, err := csvR.ReadAll()
-	if err != nil {
-		return nil, fmt.Errorf("Failed to parse CSV %s: %v", csvDesc, err)
+// Identifies a (possibly partially parsed) tx, for error messages.
+func txContextStr(tx *Tx) string {
+	security := tx.Security
+	if security == "" {
+		security = "<no security>"
+	}
+	date := "<no date>"
+	if (tx.Date != time.Time{}) {
+		date = util.DateStr(tx.Date)
 	}
+	return fmt.Sprintf("tx #%d, %s, %s", tx.ReadIndex, security, date)
+}
+
+// Reads Txs from a csv one row at a time, so that the whole csv does not need
+// to be held in memory.
+type TxCsvReader struct {
+	csvR       *csv.Reader
+	csvDesc    string
+	rateLoader *fx.RateLoader
+	options    CsvOptions
+	colParsers []ColParser
+	// The index of the "commission %" column, or -1 if there is none.
+	commissionPctCol int
+	// The index of the "commission included" column, or -1 if there is none.
+	commissionIncludedCol int
+	globalRowIndex        uint32
+	line                  int
+	// The security of the previous row, for CsvOptions.FillDownSecurity.
+	prevSecurity string
+}
 
-	if len(records) == 0 {
+// Reads the header of the csv in reader. Subsequent rows are parsed by Next.
+func NewTxCsvReader(reader io.Reader, initialGlobalReadIndex uint32,
+	csvDesc string, rateLoader *fx.RateLoader, options CsvOptions) (*TxCsvReader, error) {
+
+	if err := CheckCsvDecimalComma(options.Delimiter, options.DecimalComma); err != nil {
+		return nil, err
+	}
+	csvR := csv.NewReader(util.SkipUtf8Bom(reader))
+	csvR.Comma = options.Delimiter
+	csvR.ReuseRecord = true
+	header, err := csvR.Read()
+	if err == io.EOF {
 		return nil, fmt.Errorf("No rows found in %s", csvDesc)
+	} else if err != nil {
+		return nil, fmt.Errorf("Failed to parse CSV %s: %v", csvDesc, err)
 	}
 
-	header := records[0]
-
 	colParsers := make([]ColParser, len(header))
+	commissionPctCol := -1
+	commissionIncludedCol := -1
 
 	for i, col := range header {
 		sanCol := strings.TrimSpace(strings.ToLower(col))
+		if sanCol == "commission %" {
+			commissionPctCol = i
+		} else if sanCol == "commission included" {
+			commissionIncludedCol = i
+		}
 		if parser, ok := colParserMap[sanCol]; ok {
 			colParsers[i] = parser
 		} else {
@@ -127,23 +389,178 @@ func ParseTxCsv(reader io.Reader, initialGlobalReadIndex uint32,
 		}
 	}
 
-	txs := make([]*Tx, 0, len(records)-1)
-	for i, record := range records[1:] {
-		tx := DefaultTx()
-		tx.ReadIndex = globalRowIndex
-		globalRowIndex++
-		for j, col := range record {
-			err = colParsers[j](col, tx)
-			if err != nil {
-				return nil, fmt.Errorf("Error parsing %s at line:col %d:%d: %v", csvDesc, i+1, j, err)
-			}
+	return &TxCsvReader{
+		csvR:                  csvR,
+		csvDesc:               csvDesc,
+		rateLoader:            rateLoader,
+		options:               options,
+		colParsers:            colParsers,
+		commissionPctCol:      commissionPctCol,
+		commissionIncludedCol: commissionIncludedCol,
+		globalRowIndex:        initialGlobalReadIndex,
+	}, nil
+}
+
+// Parses the next row of the csv. Returns nil, io.EOF when there are no more
+// rows.
+func (r *TxCsvReader) Next() (*Tx, error) {
+	record, err := r.csvR.Read()
+	if err == io.EOF {
+		return nil, io.EOF
+	} else if err != nil {
+		return nil, fmt.Errorf("Failed to parse CSV %s: %v", r.csvDesc, err)
+	}
+	r.line++
+
+	tx := DefaultTx()
+	tx.ReadIndex = r.globalRowIndex
+	tx.Source = r.csvDesc
+	r.globalRowIndex++
+	// Parse every column before reporting an error, so that the error can
+	// identify the tx as well as possible.
+	errCol := -1
+	var colErr error = nil
+	for j, col := range record {
+		err = r.colParsers[j](col, tx, &r.options)
+		if err != nil && colErr == nil {
+			errCol = j
+			colErr = err
 		}
-		err = CheckTxSanity(tx)
-		if err != nil {
-			return nil, fmt.Errorf("Error parsing %s at line %d: %v", csvDesc, i+1, err)
+	}
+	if colErr == nil && r.commissionPctCol >= 0 {
+		errCol = r.commissionPctCol
+		colErr = applyCommissionPercent(record[r.commissionPctCol], tx, &r.options)
+	}
+	if colErr == nil && r.commissionIncludedCol >= 0 {
+		errCol = r.commissionIncludedCol
+		colErr = applyCommissionIncluded(
+			record[r.commissionIncludedCol], tx, &r.options)
+	}
+	if colErr != nil {
+		return nil, fmt.Errorf("Error parsing %s at line:col %d:%d (%s): %v",
+			r.csvDesc, r.line, errCol, txContextStr(tx), colErr)
+	}
+	if r.options.FillDownSecurity {
+		if tx.Security == "" && r.prevSecurity == "" {
+			return nil, fmt.Errorf("Error parsing %s at line %d (%s): No security, "+
+				"and there is no previous row to take it from", r.csvDesc, r.line,
+				txContextStr(tx))
+		} else if tx.Security == "" {
+			tx.Security = r.prevSecurity
 		}
-		err = fixupTxFx(tx, rateLoader)
-		if err != nil {
+		r.prevSecurity = tx.Security
+	}
+	if (tx.Date == time.Time{}) && (tx.TradeDate != time.Time{}) {
+		if r.options.TradeDateAsSettlement {
+			tx.Date = tx.TradeDate
+		} else if r.options.InferSettlementDates {
+			tx.Date = util.AddBusinessDays(tx.TradeDate, r.options.SettlementDays)
+		}
+	}
+	err = CheckTxSanity(tx)
+	if err != nil {
+		return nil, fmt.Errorf("Error parsing %s at line %d (%s): %v",
+			r.csvDesc, r.line, txContextStr(tx), err)
+	}
+	err = fixupTxFx(tx, r.rateLoader, &r.options)
+	if err != nil {
+		return nil, fmt.Errorf("Error in %s at line %d (%s): %v",
+			r.csvDesc, r.line, txContextStr(tx), err)
+	}
+	return tx, nil
+}
+
+// An error in a single column of a csv row.
+type FieldError struct {
+	// The (lower-case) column name, or "" for errors in the row as a whole.
+	Column string
+	Err    error
+}
+
+func (e *FieldError) Error() string {
+	if e.Column == "" {
+		return e.Err.Error()
+	}
+	return fmt.Sprintf("%s: %v", e.Column, e.Err)
+}
+
+// Checks a single csv row (with the columns of header) as TxCsvReader would
+// parse it, but returns an error for every invalid column rather than only the
+// first, for feedback while a row is being entered. The checks of the row as a
+// whole (see CheckTxSanity) are only made if every column is valid.
+// Exchange rates are not looked up.
+// Returns nil if the row is valid.
+func ValidateCsvRow(header []string, record []string, options CsvOptions) []*FieldError {
+	var fieldErrs []*FieldError = nil
+	if len(record) != len(header) {
+		return append(fieldErrs, &FieldError{Err: fmt.Errorf(
+			"Row has %d columns, but the header has %d", len(record), len(header))})
+	}
+
+	tx := DefaultTx()
+	commissionPctCol := -1
+	commissionIncludedCol := -1
+	cols := make([]string, len(header))
+	for i, col := range header {
+		cols[i] = strings.TrimSpace(strings.ToLower(col))
+		parser, ok := colParserMap[cols[i]]
+		if !ok {
+			continue
+		}
+		if cols[i] == "commission %" {
+			commissionPctCol = i
+		} else if cols[i] == "commission included" {
+			commissionIncludedCol = i
+		}
+		if err := parser(record[i], tx, &options); err != nil {
+			fieldErrs = append(fieldErrs, &FieldError{Column: cols[i], Err: err})
+		}
+	}
+	if len(fieldErrs) > 0 {
+		return fieldErrs
+	}
+
+	if commissionPctCol >= 0 {
+		if err := applyCommissionPercent(record[commissionPctCol], tx, &options); err != nil {
+			fieldErrs = append(fieldErrs, &FieldError{Column: cols[commissionPctCol], Err: err})
+		}
+	}
+	if commissionIncludedCol >= 0 {
+		if err := applyCommissionIncluded(
+			record[commissionIncludedCol], tx, &options); err != nil {
+			fieldErrs = append(fieldErrs,
+				&FieldError{Column: cols[commissionIncludedCol], Err: err})
+		}
+	}
+	if len(fieldErrs) > 0 {
+		return fieldErrs
+	}
+	if (tx.Date == time.Time{}) && (tx.TradeDate != time.Time{}) &&
+		(options.TradeDateAsSettlement || options.InferSettlementDates) {
+		// The settlement date would be set from the trade date.
+		tx.Date = tx.TradeDate
+	}
+	if err := CheckTxSanity(tx); err != nil {
+		fieldErrs = append(fieldErrs, &FieldError{Err: err})
+	}
+	return fieldErrs
+}
+
+func ParseTxCsv(reader io.Reader, initialGlobalReadIndex uint32,
+	csvDesc string, rateLoader *fx.RateLoader, options CsvOptions) ([]*Tx, error) {
+
+	txReader, err := NewTxCsvReader(
+		reader, initialGlobalReadIndex, csvDesc, rateLoader, options)
+	if err != nil {
+		return nil, err
+	}
+
+	txs := make([]*Tx, 0, 20)
+	for {
+		tx, err := txReader.Next()
+		if err == io.EOF {
+			break
+		} else if err != nil {
 			return nil, err
 		}
 		txs = append(txs, tx)
@@ -151,17 +568,262 @@ func ParseTxCsv(reader io.Reader, initialGlobalReadIndex uint32,
 	return txs, nil
 }
 
-func parseNothing(data string, tx *Tx) error {
+func floatCsvStr(val float64) string {
+	if val == 0.0 {
+		return ""
+	}
+	// Use the minimal representation which reproduces val exactly when parsed.
+	return strconv.FormatFloat(val, 'f', -1, 64)
+}
+
+func boolCsvStr(val bool) string {
+	if val {
+		return "yes"
+	}
+	return ""
+}
+
+func optDateCsvStr(date time.Time, options *CsvOptions) string {
+	if (date == time.Time{}) {
+		return ""
+	}
+	return date.Format(options.DateFormat)
+}
+
+func optFloatCsvStr(val *float64) string {
+	if val == nil {
+		return ""
+	}
+	return strconv.FormatFloat(*val, 'f', -1, 64)
+}
+
+// Writes txs as a csv (with a header), using CanonicalColNames, with the dates
+// and exchange rates formatted per options.
+// The output can be read back with ParseTxCsv, with the same DateFormat and
+// ExchangeRateConvention.
+func WriteTxCsv(txs []*Tx, options CsvOptions, writer io.Writer) error {
+	csvW := csv.NewWriter(writer)
+	err := csvW.Write(CanonicalColNames)
+	if err != nil {
+		return err
+	}
+	for _, tx := range txs {
+		row := []string{
+			tx.Security,
+			tx.Date.Format(options.DateFormat),
+			optDateCsvStr(tx.TradeDate, &options),
+			tx.Action.String(),
+			fmt.Sprintf("%d", tx.Shares),
+			floatCsvStr(tx.AmountPerShare),
+			floatCsvStr(tx.Commission),
+			string(tx.TxCurrency),
+			exchangeRateCsvStr(tx.TxCurrToLocalExchangeRate, &options),
+			string(tx.CommissionCurrency),
+			exchangeRateCsvStr(tx.CommissionCurrToLocalExchangeRate, &options),
+			floatCsvStr(tx.Outlays),
+			floatCsvStr(tx.TotalAmount),
+			floatCsvStr(tx.WithholdingTax),
+			optFloatCsvStr(tx.SpecifiedSuperficialLoss),
+			tx.NewSecurity,
+			floatCsvStr(tx.Ratio),
+			floatCsvStr(tx.AcbAllocationPercent),
+			boolCsvStr(tx.ListedSecurityDonation),
+			tx.Memo,
+		}
+		err = csvW.Write(row)
+		if err != nil {
+			return err
+		}
+	}
+	csvW.Flush()
+	return csvW.Error()
+}
+
+// Creates one Acquire Tx per security in holdings, on date, which reproduces
+// the security's share balance and total ACB (in localCurrency) exactly.
+// Securities with no shares are omitted.
+func MakeBaselineTxs(
+	holdings map[string]*PortfolioSecurityStatus, date time.Time,
+	localCurrency Currency) []*Tx {
+
+	secs := make([]string, 0, len(holdings))
+	for sec, status := range holdings {
+		if status.ShareBalance > 0 {
+			secs = append(secs, sec)
+		}
+	}
+	sort.Strings(secs)
+
+	txs := make([]*Tx, 0, len(secs))
+	for _, sec := range secs {
+		status := holdings[sec]
+		tx := DefaultTx()
+		tx.Security = sec
+		tx.Date = date
+		tx.Action = ACQUIRE
+		tx.Shares = status.ShareBalance
+		tx.TotalAmount = status.TotalAcb
+		tx.TxCurrency = localCurrency
+		tx.TxCurrToLocalExchangeRate = 1.0
+		tx.CommissionCurrency = localCurrency
+		tx.CommissionCurrToLocalExchangeRate = 1.0
+		tx.Memo = "Baseline"
+		txs = append(txs, tx)
+	}
+	return txs
+}
+
+// Writes a baseline csv for holdings (see MakeBaselineTxs), which can be used in
+// place of the history which produced them.
+func WriteBaselineCsv(
+	holdings map[string]*PortfolioSecurityStatus, date time.Time,
+	options CsvOptions, writer io.Writer) error {
+	return WriteTxCsv(MakeBaselineTxs(holdings, date, options.LocalCurrency), options, writer)
+}
+
+// Returns the txs of deltas (which may be for any number of securities), such
+// that they reproduce the same deltas when computed again, regardless of the
+// superficial loss settings. Sells specify the superficial loss which was
+// applied to them (if any). Txs derived from others (eg. the Acquire of a
+// Spinoff) are omitted, since they are derived again.
+// Txs are sorted by date, and otherwise keep the order they were computed in.
+func MakeNormalizedTxs(deltas []*TxDelta) []*Tx {
+	derivedTxs := make(map[*Tx]bool)
+	for _, d := range deltas {
+		if d.DerivedTx != nil {
+			derivedTxs[d.DerivedTx] = true
+		}
+	}
+
+	txs := make([]*Tx, 0, len(deltas))
+	for _, d := range deltas {
+		if derivedTxs[d.Tx] {
+			continue
+		}
+		tx := *d.Tx
+		if tx.Action == SELL {
+			sfl := d.SuperficialLoss
+			tx.SpecifiedSuperficialLoss = &sfl
+		}
+		txs = append(txs, &tx)
+	}
+	sort.SliceStable(txs, func(i, j int) bool {
+		return txs[i].Date.Before(txs[j].Date)
+	})
+	return txs
+}
+
+// Creates an Acquire and a Sell Tx per year, which reproduce the yearly share
+// balance, total ACB and capital gains of deltas (which must all be for one
+// security). The Acquire contains all shares bought in the year, and the Sell
+// all shares sold, with the Acquire's ACB chosen so that the year-end ACB
+// matches. Either may be omitted if there was no such activity in a year.
+// Superficial losses are already accounted for, so the Sells specify none.
+// The txs are in localCurrency, which must be the one deltas were computed in.
+//
+// If the first delta has a non-empty PreStatus, it must be provided as the
+// initial status when the returned txs are computed.
+func MakeAnnualSummaryTxs(deltas []*TxDelta, localCurrency Currency) ([]*Tx, error) {
+	txs := make([]*Tx, 0, 8)
+	var readIndex uint32 = 0
+	for start := 0; start < len(deltas); {
+		year := deltas[start].Tx.Date.Year()
+		end := start
+		for end < len(deltas) && deltas[end].Tx.Date.Year() == year {
+			end++
+		}
+		yearDeltas := deltas[start:end]
+		start = end
+
+		preStatus := yearDeltas[0].PreStatus
+		postStatus := yearDeltas[len(yearDeltas)-1].PostStatus
+		lastTx := yearDeltas[len(yearDeltas)-1].Tx
+
+		var sharesAcquired uint32 = 0
+		var sharesSold uint32 = 0
+		var acquiredAcb float64 = 0.0
+		var capitalGain float64 = 0.0
+		for _, d := range yearDeltas {
+			switch d.Tx.Action {
+			case BUY, ACQUIRE, OPEN:
+				sharesAcquired += d.Tx.Shares
+				acquiredAcb += d.AcbDelta()
+			case SELL:
+				sharesSold += d.Tx.Shares
+				capitalGain += d.CapitalGain
+			}
+		}
+		if preStatus.ShareBalance+sharesAcquired-sharesSold != postStatus.ShareBalance {
+			return nil, fmt.Errorf("Cannot summarize %s in %d: the share balance changed "+
+				"other than by buys and sells", lastTx.Security, year)
+		}
+
+		// The ACB of all shares held after the Acquire.
+		acbBeforeSell := preStatus.TotalAcb + acquiredAcb
+		if postStatus.ShareBalance > 0 {
+			acbBeforeSell = postStatus.TotalAcb /
+				float64(postStatus.ShareBalance) * float64(preStatus.ShareBalance+sharesAcquired)
+		}
+		summaryAcquiredAcb := acbBeforeSell - preStatus.TotalAcb
+		if math.Abs(summaryAcquiredAcb) < 1e-9 {
+			// Only float error (common in years without any buys)
+			summaryAcquiredAcb = 0.0
+			acbBeforeSell = preStatus.TotalAcb
+		} else if summaryAcquiredAcb < 0.0 {
+			return nil, fmt.Errorf("Cannot summarize %s in %d: the ACB was reduced by more "+
+				"than the sells account for", lastTx.Security, year)
+		}
+
+		makeTx := func(action TxAction, shares uint32) *Tx {
+			tx := DefaultTx()
+			tx.Security = lastTx.Security
+			tx.Date = lastTx.Date
+			tx.Action = action
+			tx.Shares = shares
+			tx.TxCurrency = localCurrency
+			tx.TxCurrToLocalExchangeRate = 1.0
+			tx.CommissionCurrency = localCurrency
+			tx.CommissionCurrToLocalExchangeRate = 1.0
+			tx.Memo = fmt.Sprintf("%d summary", year)
+			tx.ReadIndex = readIndex
+			readIndex++
+			return tx
+		}
+
+		if sharesAcquired > 0 || summaryAcquiredAcb != 0.0 {
+			tx := makeTx(ACQUIRE, sharesAcquired)
+			tx.TotalAmount = summaryAcquiredAcb
+			txs = append(txs, tx)
+		}
+		if sharesSold > 0 {
+			disposedAcb := acbBeforeSell /
+				float64(preStatus.ShareBalance+sharesAcquired) * float64(sharesSold)
+			proceeds := capitalGain + disposedAcb
+			if proceeds < 0.0 {
+				return nil, fmt.Errorf("Cannot summarize %s in %d: the sells have "+
+					"negative proceeds", lastTx.Security, year)
+			}
+			tx := makeTx(SELL, sharesSold)
+			tx.AmountPerShare = proceeds / float64(sharesSold)
+			noSfl := 0.0
+			tx.SpecifiedSuperficialLoss = &noSfl
+			txs = append(txs, tx)
+		}
+	}
+	return txs, nil
+}
+
+func parseNothing(data string, tx *Tx, options *CsvOptions) error {
 	return nil
 }
 
-func parseSecurity(data string, tx *Tx) error {
+func parseSecurity(data string, tx *Tx, options *CsvOptions) error {
 	tx.Security = data
 	return nil
 }
 
-func parseDate(data string, tx *Tx) error {
-	t, err := time.Parse(CsvDateFormat, data)
+func parseDate(data string, tx *Tx, options *CsvOptions) error {
+	t, err := time.Parse(options.DateFormat, data)
 	if err != nil {
 		return err
 	}
@@ -169,23 +831,74 @@ func parseDate(data string, tx *Tx) error {
 	return nil
 }
 
-func parseAction(data string, tx *Tx) error {
+// Returns the action for a name as it would appear in the action column,
+// including any of aliases (see CsvOptions.ActionAliases), which may be nil.
+func ParseTxAction(name string, aliases map[string]TxAction) (TxAction, error) {
+	action, err := parseBuiltinTxAction(name)
+	if err != nil {
+		if alias, ok := aliases[strings.TrimSpace(strings.ToLower(name))]; ok {
+			return alias, nil
+		}
+		return NO_ACTION, err
+	}
+	return action, nil
+}
+
+func parseBuiltinTxAction(name string) (TxAction, error) {
 	var action TxAction = NO_ACTION
-	switch strings.TrimSpace(strings.ToLower(data)) {
+	switch strings.TrimSpace(strings.ToLower(name)) {
 	case "buy":
 		action = BUY
 	case "sell":
 		action = SELL
 	case "roc":
 		action = ROC
+	case "acquire":
+		action = ACQUIRE
+	case "note", "marker":
+		action = NOTE
+	case "div", "dividend":
+		action = DIV
+	case "spinoff", "spin-off":
+		action = SPINOFF
+	case "rename", "symbol change", "merger":
+		action = RENAME
+	case "split", "stock split", "reverse split", "consolidation":
+		action = SPLIT
+	case "gift", "donate", "donation":
+		action = GIFT
+	case "open", "opening balance":
+		action = OPEN
+	case "adjust", "adjustment", "acb adjust", "acb adjustment":
+		action = ADJUST
 	default:
-		return fmt.Errorf("Invalid action: '%s'", data)
+		return NO_ACTION, fmt.Errorf("Invalid action: '%s'", name)
+	}
+	return action, nil
+}
+
+func parseTradeDate(data string, tx *Tx, options *CsvOptions) error {
+	if data == "" {
+		return nil
+	}
+	t, err := time.Parse(options.DateFormat, data)
+	if err != nil {
+		return err
+	}
+	tx.TradeDate = t
+	return nil
+}
+
+func parseAction(data string, tx *Tx, options *CsvOptions) error {
+	action, err := ParseTxAction(data, options.ActionAliases)
+	if err != nil {
+		return err
 	}
 	tx.Action = action
 	return nil
 }
 
-func parseShares(data string, tx *Tx) error {
+func parseShares(data string, tx *Tx, options *CsvOptions) error {
 	shares, err := strconv.ParseUint(data, 10, 32)
 	if err != nil {
 		return fmt.Errorf("Error parsing # shares: %v", err)
@@ -194,65 +907,247 @@ func parseShares(data string, tx *Tx) error {
 	return nil
 }
 
-func parseAmountPerShare(data string, tx *Tx) error {
-	aps, err := strconv.ParseFloat(data, 64)
+func parseAmountPerShare(data string, tx *Tx, options *CsvOptions) error {
+	aps, err := parseCsvFloat(data, options)
 	if err != nil {
 		return fmt.Errorf("Error parsing price/share: %v", err)
 	}
+	if options.AmountPerSharePrecision >= 0 {
+		aps = util.RoundToPlaces(aps, options.AmountPerSharePrecision)
+	}
 	tx.AmountPerShare = aps
 	return nil
 }
 
-func parseCommission(data string, tx *Tx) error {
+func parseCommission(data string, tx *Tx, options *CsvOptions) error {
 	var c float64 = 0.0
 	var err error
 	if data != "" {
-		c, err = strconv.ParseFloat(data, 64)
+		c, err = parseCsvFloat(data, options)
 		if err != nil {
 			return fmt.Errorf("Error parsing commission: %v", err)
 		}
+		if c < 0.0 && !options.AllowNegativeCommission {
+			return fmt.Errorf("Commission must not be negative (got %s). Rebates can be "+
+				"allowed with --allow-commission-rebates", data)
+		}
 	}
 	tx.Commission = c
 	return nil
 }
 
-func parseTxCurr(data string, tx *Tx) error {
+// Sets the commission of tx (in its tx currency) to a percentage (eg. "0.5" or
+// "0.5%") of its total share price.
+func applyCommissionPercent(data string, tx *Tx, options *CsvOptions) error {
+	data = strings.TrimSpace(strings.TrimSuffix(strings.TrimSpace(data), "%"))
+	if data == "" {
+		return nil
+	}
+	pct, err := parseCsvFloat(data, options)
+	if err != nil {
+		return fmt.Errorf("Error parsing commission %%: %v", err)
+	} else if pct < 0.0 {
+		return fmt.Errorf("Commission %% cannot be negative")
+	} else if tx.Commission != 0.0 {
+		return fmt.Errorf("Commission and commission %% cannot both be specified")
+	} else if tx.CommissionCurrency != DEFAULT_CURRENCY && tx.CommissionCurrency != tx.TxCurrency {
+		return fmt.Errorf("Commission %% cannot be used with a commission currency " +
+			"other than the transaction currency")
+	}
+	tx.Commission = float64(tx.Shares) * tx.AmountPerShare * pct / 100.0
+	return nil
+}
+
+// For rows where amount/share is an all-in price, which already includes the
+// commission. The commission (if any) is then only informational, and is
+// dropped so that it is not counted twice.
+func applyCommissionIncluded(data string, tx *Tx, options *CsvOptions) error {
+	included, err := parseCsvBool(data)
+	if err != nil {
+		return fmt.Errorf("Invalid commission included value: %v", err)
+	} else if !included {
+		return nil
+	} else if tx.Action != BUY && tx.Action != SELL {
+		return fmt.Errorf("Commission included can only be specified on a Buy or Sell")
+	}
+	tx.Commission = 0.0
+	return nil
+}
+
+func parseTxCurr(data string, tx *Tx, options *CsvOptions) error {
 	tx.TxCurrency = Currency(strings.ToUpper(data))
 	return nil
 }
 
-func parseTxFx(data string, tx *Tx) error {
-	var fx float64 = 0.0
+func parseTxFx(data string, tx *Tx, options *CsvOptions) error {
+	fx, err := parseExchangeRate(data, "exchange rate", options)
+	if err != nil {
+		return err
+	}
+	tx.TxCurrToLocalExchangeRate = fx
+	return nil
+}
+
+// Parses a rate quoted per options.ExchangeRateConvention. Returns 0 if data
+// is empty, meaning the rate is to be determined automatically.
+func parseExchangeRate(data string, desc string, options *CsvOptions) (float64, error) {
+	if data == "" {
+		return 0.0, nil
+	}
+	fx, err := parseCsvFloat(data, options)
+	if err != nil {
+		return 0.0, fmt.Errorf("Error parsing %s: %v", desc, err)
+	}
+	if options.ExchangeRateConvention == FX_FOREIGN_PER_LOCAL {
+		if fx == 0.0 {
+			return 0.0, fmt.Errorf("Invalid %s: an inverted rate cannot be zero", desc)
+		}
+		fx = 1.0 / fx
+	}
+	return fx, nil
+}
+
+func exchangeRateCsvStr(fx float64, options *CsvOptions) string {
+	if options.ExchangeRateConvention == FX_FOREIGN_PER_LOCAL && fx != 0.0 {
+		fx = 1.0 / fx
+	}
+	return floatCsvStr(fx)
+}
+
+func parseCommissionCurr(data string, tx *Tx, options *CsvOptions) error {
+	tx.CommissionCurrency = Currency(strings.ToUpper(data))
+	return nil
+}
+
+func parseCommissionFx(data string, tx *Tx, options *CsvOptions) error {
+	fx, err := parseExchangeRate(data, "commission exchange rate", options)
+	if err != nil {
+		return err
+	}
+	tx.CommissionCurrToLocalExchangeRate = fx
+	return nil
+}
+
+func parseTotalAmount(data string, tx *Tx, options *CsvOptions) error {
+	var amount float64 = 0.0
 	var err error
 	if data != "" {
-		fx, err = strconv.ParseFloat(data, 64)
+		amount, err = parseCsvFloat(data, options)
 		if err != nil {
-			return fmt.Errorf("Error parsing exchange rate: %v", err)
+			return fmt.Errorf("Error parsing total amount: %v", err)
 		}
 	}
-	tx.TxCurrToLocalExchangeRate = fx
+	tx.TotalAmount = amount
 	return nil
 }
 
-func parseCommissionCurr(data string, tx *Tx) error {
-	tx.CommissionCurrency = Currency(strings.ToUpper(data))
+func parseWithholdingTax(data string, tx *Tx, options *CsvOptions) error {
+	var wht float64 = 0.0
+	var err error
+	if data != "" {
+		wht, err = parseCsvFloat(data, options)
+		if err != nil {
+			return fmt.Errorf("Error parsing withholding tax: %v", err)
+		}
+		if wht < 0.0 {
+			return fmt.Errorf("Withholding tax must not be negative (got %s)", data)
+		}
+	}
+	tx.WithholdingTax = wht
 	return nil
 }
 
-func parseCommissionFx(data string, tx *Tx) error {
-	var fx float64 = 0.0
+func parseOutlays(data string, tx *Tx, options *CsvOptions) error {
+	var outlays float64 = 0.0
 	var err error
 	if data != "" {
-		fx, err = strconv.ParseFloat(data, 64)
+		outlays, err = parseCsvFloat(data, options)
 		if err != nil {
-			return fmt.Errorf("Error parsing commission exchange rate: %v", err)
+			return fmt.Errorf("Error parsing outlays: %v", err)
+		}
+		if outlays < 0.0 {
+			return fmt.Errorf("Outlays must not be negative (got %s)", data)
 		}
 	}
-	tx.CommissionCurrToLocalExchangeRate = fx
+	tx.Outlays = outlays
+	return nil
+}
+
+// Parses a yes/no column. Empty is no.
+func parseCsvBool(data string) (bool, error) {
+	switch strings.TrimSpace(strings.ToLower(data)) {
+	case "", "no", "n", "false":
+		return false, nil
+	case "yes", "y", "true":
+		return true, nil
+	default:
+		return false, fmt.Errorf("'%s' (must be yes or no)", data)
+	}
+}
+
+func parseListedSecurityDonation(data string, tx *Tx, options *CsvOptions) error {
+	donation, err := parseCsvBool(data)
+	if err != nil {
+		return fmt.Errorf("Invalid listed security donation value: %v", err)
+	}
+	tx.ListedSecurityDonation = donation
+	return nil
+}
+
+func parseNewSecurity(data string, tx *Tx, options *CsvOptions) error {
+	tx.NewSecurity = strings.TrimSpace(data)
+	return nil
+}
+
+func parseRatio(data string, tx *Tx, options *CsvOptions) error {
+	var ratio float64 = 0.0
+	var err error
+	if data != "" {
+		ratio, err = parseCsvFloat(data, options)
+		if err != nil {
+			return fmt.Errorf("Error parsing ratio: %v", err)
+		}
+		if ratio < 0.0 {
+			return fmt.Errorf("Ratio must not be negative (got %s)", data)
+		}
+	}
+	tx.Ratio = ratio
+	return nil
+}
+
+func parseAcbAllocationPercent(data string, tx *Tx, options *CsvOptions) error {
+	var pct float64 = 0.0
+	var err error
+	if data != "" {
+		pct, err = parseCsvFloat(strings.TrimSuffix(strings.TrimSpace(data), "%"), options)
+		if err != nil {
+			return fmt.Errorf("Error parsing ACB allocation %%: %v", err)
+		}
+		if pct < 0.0 || pct > 100.0 {
+			return fmt.Errorf("ACB allocation %% must be between 0 and 100 (got %s)", data)
+		}
+	}
+	tx.AcbAllocationPercent = pct
+	return nil
+}
+
+func parseSuperficialLoss(data string, tx *Tx, options *CsvOptions) error {
+	if data == "" {
+		tx.SpecifiedSuperficialLoss = nil
+		return nil
+	}
+	sfl, err := parseCsvFloat(data, options)
+	if err != nil {
+		return fmt.Errorf("Error parsing superficial loss: %v", err)
+	}
+	if sfl > 0.0 {
+		return fmt.Errorf("Superficial loss must be negative or zero (got %s)", data)
+	}
+	tx.SpecifiedSuperficialLoss = &sfl
 	return nil
 }
 
-func parseMemo(data string, tx *Tx) error {
+func parseMemo(data string, tx *Tx, options *CsvOptions) error {
 	tx.Memo = data
 	return nil
 }
@@ -0,0 +1,299 @@
+package portfolio
+
+import (
+	"encoding/json"
+	"fmt"
+	"reflect"
+	"regexp"
+	"strconv"
+	"strings"
+	"time"
+
+	"github.com/tsiemens/acb/util"
+)
+
+// Incremented whenever the structure of JsonReport changes incompatibly.
+const JsonFormatVersion = 2
+
+// The number of decimal places a JsonDecimal is serialized with (before
+// trailing zeros are trimmed). Float error beyond this is rounded off.
+const jsonDecimalPlaces = 10
+
+const jsonDecimalPattern = `^-?[0-9]+(\.[0-9]+)?$`
+var jsonDecimalRegexp = regexp.MustCompile(jsonDecimalPattern)
+
+// A decimal amount, which is serialized as a string (eg. "10.9") rather than a
+// number, so that consumers (particularly javascript) are not required to
+// parse it as a float.
+type JsonDecimal float64
+
+func (d JsonDecimal) String() string {
+	str := strconv.FormatFloat(float64(d), 'f', jsonDecimalPlaces, 64)
+	str = strings.TrimSuffix(strings.TrimRight(str, "0"), ".")
+	if str == "-0" {
+		return "0"
+	}
+	return str
+}
+
+func (d JsonDecimal) MarshalJSON() ([]byte, error) {
+	return json.Marshal(d.String())
+}
+
+func (d *JsonDecimal) UnmarshalJSON(data []byte) error {
+	var str string
+	err := json.Unmarshal(data, &str)
+	if err != nil {
+		return fmt.Errorf("Decimal %s must be a string", string(data))
+	}
+	if !jsonDecimalRegexp.MatchString(str) {
+		return fmt.Errorf("Invalid decimal \"%s\"", str)
+	}
+	val, err := strconv.ParseFloat(str, 64)
+	if err != nil {
+		return fmt.Errorf("Invalid decimal \"%s\": %v", str, err)
+	}
+	*d = JsonDecimal(val)
+	return nil
+}
+
+func optJsonDecimal(val *float64) *JsonDecimal {
+	if val == nil {
+		return nil
+	}
+	d := JsonDecimal(*val)
+	return &d
+}
+
+type JsonPortfolioSecurityStatus struct {
+	Security     string      `json:"security"`
+	ShareBalance uint32      `json:"share_balance"`
+	ShortBalance uint32      `json:"short_balance,omitempty"`
+	TotalAcb     JsonDecimal `json:"total_acb"`
+}
+
+type JsonTx struct {
+	Security                          string       `json:"security"`
+	Date                              string       `json:"date"`
+	TradeDate                         string       `json:"trade_date"`
+	Action                            string       `json:"action"`
+	Shares                            uint32       `json:"shares"`
+	AmountPerShare                    JsonDecimal  `json:"amount_per_share"`
+	Commission                        JsonDecimal  `json:"commission"`
+	TxCurrency                        string       `json:"tx_currency"`
+	TxCurrToLocalExchangeRate         JsonDecimal  `json:"tx_curr_to_local_exchange_rate"`
+	CommissionCurrency                string       `json:"commission_currency"`
+	CommissionCurrToLocalExchangeRate JsonDecimal  `json:"commission_curr_to_local_exchange_rate"`
+	Outlays                           JsonDecimal  `json:"outlays"`
+	TotalAmount                       JsonDecimal  `json:"total_amount"`
+	WithholdingTax                    JsonDecimal  `json:"withholding_tax"`
+	SpecifiedSuperficialLoss          *JsonDecimal `json:"specified_superficial_loss"`
+	NewSecurity                       string       `json:"new_security"`
+	Ratio                             JsonDecimal  `json:"ratio"`
+	AcbAllocationPercent              JsonDecimal  `json:"acb_allocation_percent"`
+	ListedSecurityDonation            bool         `json:"listed_security_donation,omitempty"`
+	Memo                              string       `json:"memo"`
+	ReadIndex                         uint32       `json:"read_index"`
+	// csv_row, or the kind of tx computed from the tx at read_index
+	// (odd_lot_sale or derived).
+	Origin string `json:"origin"`
+}
+
+type JsonTxDelta struct {
+	Tx              *JsonTx                      `json:"tx"`
+	PreStatus       *JsonPortfolioSecurityStatus `json:"pre_status"`
+	PostStatus      *JsonPortfolioSecurityStatus `json:"post_status"`
+	CapitalGain     JsonDecimal                  `json:"capital_gain"`
+	SuperficialLoss JsonDecimal                  `json:"superficial_loss"`
+	DividendIncome  JsonDecimal                  `json:"dividend_income"`
+	WithholdingTax  JsonDecimal                  `json:"withholding_tax"`
+	// The ACB per share of PostStatus. null if it has no shares.
+	PerShareAcb *JsonDecimal `json:"per_share_acb"`
+}
+
+type JsonSecurityDeltas struct {
+	Deltas []*JsonTxDelta `json:"deltas"`
+	Errors []string       `json:"errors"`
+}
+
+// The serialized form of DeltaSummaryStats.
+type JsonDeltaSummaryStats struct {
+	TotalCapitalGains      JsonDecimal `json:"total_capital_gains"`
+	TotalCapitalLosses     JsonDecimal `json:"total_capital_losses"`
+	TotalSuperficialLosses JsonDecimal `json:"total_superficial_losses"`
+	TotalDividends         JsonDecimal `json:"total_dividends"`
+}
+
+// The serialized form of the deltas for all securities.
+type JsonReport struct {
+	Version    int                            `json:"version"`
+	Securities map[string]*JsonSecurityDeltas `json:"securities"`
+	// The totals over the deltas of all securities.
+	Summary JsonDeltaSummaryStats `json:"summary"`
+}
+
+func makeJsonStatus(s *PortfolioSecurityStatus) *JsonPortfolioSecurityStatus {
+	return &JsonPortfolioSecurityStatus{
+		Security:     s.Security,
+		ShareBalance: s.ShareBalance,
+		ShortBalance: s.ShortBalance,
+		TotalAcb:     JsonDecimal(s.TotalAcb),
+	}
+}
+
+func optDateStr(date time.Time) string {
+	if (date == time.Time{}) {
+		return ""
+	}
+	return util.DateStr(date)
+}
+
+func MakeJsonTx(tx *Tx) *JsonTx {
+	return &JsonTx{
+		Security:                          tx.Security,
+		Date:                              util.DateStr(tx.Date),
+		TradeDate:                         optDateStr(tx.TradeDate),
+		Action:                            tx.Action.String(),
+		Shares:                            tx.Shares,
+		AmountPerShare:                    JsonDecimal(tx.AmountPerShare),
+		Commission:                        JsonDecimal(tx.Commission),
+		TxCurrency:                        string(tx.TxCurrency),
+		TxCurrToLocalExchangeRate:         JsonDecimal(tx.TxCurrToLocalExchangeRate),
+		CommissionCurrency:                string(tx.CommissionCurrency),
+		CommissionCurrToLocalExchangeRate: JsonDecimal(tx.CommissionCurrToLocalExchangeRate),
+		Outlays:                           JsonDecimal(tx.Outlays),
+		TotalAmount:                       JsonDecimal(tx.TotalAmount),
+		WithholdingTax:                    JsonDecimal(tx.WithholdingTax),
+		SpecifiedSuperficialLoss:          optJsonDecimal(tx.SpecifiedSuperficialLoss),
+		NewSecurity:                       tx.NewSecurity,
+		Ratio:                             JsonDecimal(tx.Ratio),
+		AcbAllocationPercent:              JsonDecimal(tx.AcbAllocationPercent),
+		ListedSecurityDonation:            tx.ListedSecurityDonation,
+		Memo:                              tx.Memo,
+		ReadIndex:                         tx.ReadIndex,
+		Origin:                            tx.Origin.String(),
+	}
+}
+
+func jsonPerShareAcb(s *PortfolioSecurityStatus) *JsonDecimal {
+	if s.ShareBalance == 0 {
+		return nil
+	}
+	perShareAcb := JsonDecimal(s.PerShareAcb())
+	return &perShareAcb
+}
+
+func MakeJsonTxDelta(d *TxDelta) *JsonTxDelta {
+	return &JsonTxDelta{
+		Tx:              MakeJsonTx(d.Tx),
+		PreStatus:       makeJsonStatus(d.PreStatus),
+		PostStatus:      makeJsonStatus(d.PostStatus),
+		CapitalGain:     JsonDecimal(d.CapitalGain),
+		SuperficialLoss: JsonDecimal(d.SuperficialLoss),
+		DividendIncome:  JsonDecimal(d.DividendIncome),
+		WithholdingTax:  JsonDecimal(d.WithholdingTax),
+		PerShareAcb:     jsonPerShareAcb(d.PostStatus),
+	}
+}
+
+func MakeJsonSummaryStats(stats DeltaSummaryStats) JsonDeltaSummaryStats {
+	return JsonDeltaSummaryStats{
+		TotalCapitalGains:      JsonDecimal(stats.TotalCapitalGains),
+		TotalCapitalLosses:     JsonDecimal(stats.TotalCapitalLosses),
+		TotalSuperficialLosses: JsonDecimal(stats.TotalSuperficialLosses),
+		TotalDividends:         JsonDecimal(stats.TotalDividends),
+	}
+}
+
+func MakeJsonReport(deltaModels map[string]*SecurityDeltas) *JsonReport {
+	report := &JsonReport{
+		Version:    JsonFormatVersion,
+		Securities: make(map[string]*JsonSecurityDeltas),
+	}
+	allDeltas := []*TxDelta{}
+	for sec, deltaModel := range deltaModels {
+		jsonDeltas := &JsonSecurityDeltas{
+			Deltas: make([]*JsonTxDelta, 0, len(deltaModel.Deltas)),
+			Errors: make([]string, 0, len(deltaModel.Errors)),
+		}
+		for _, d := range deltaModel.Deltas {
+			jsonDeltas.Deltas = append(jsonDeltas.Deltas, MakeJsonTxDelta(d))
+		}
+		for _, err := range deltaModel.Errors {
+			jsonDeltas.Errors = append(jsonDeltas.Errors, err.Error())
+		}
+		report.Securities[sec] = jsonDeltas
+		allDeltas = append(allDeltas, deltaModel.Deltas...)
+	}
+	report.Summary = MakeJsonSummaryStats(SummarizeDeltas(allDeltas))
+	return report
+}
+
+// Generates a JSON Schema (draft-07) describing the serialized form of a value
+// of type t. Pointer types are nullable, and JsonDecimals are strings.
+func jsonSchemaForType(t reflect.Type) map[string]interface{} {
+	if t == reflect.TypeOf(JsonDecimal(0)) {
+		return map[string]interface{}{"type": "string", "pattern": jsonDecimalPattern}
+	}
+	switch t.Kind() {
+	case reflect.Ptr:
+		schema := jsonSchemaForType(t.Elem())
+		schema["type"] = []interface{}{schema["type"], "null"}
+		return schema
+	case reflect.Struct:
+		properties := make(map[string]interface{})
+		required := make([]interface{}, 0, t.NumField())
+		for i := 0; i < t.NumField(); i++ {
+			field := t.Field(i)
+			tagParts := strings.Split(field.Tag.Get("json"), ",")
+			name := tagParts[0]
+			if name == "-" {
+				continue
+			} else if name == "" {
+				name = field.Name
+			}
+			properties[name] = jsonSchemaForType(field.Type)
+			omitEmpty := len(tagParts) > 1 && tagParts[1] == "omitempty"
+			if !omitEmpty {
+				required = append(required, name)
+			}
+		}
+		return map[string]interface{}{
+			"type":                 "object",
+			"properties":           properties,
+			"required":             required,
+			"additionalProperties": false,
+		}
+	case reflect.Map:
+		return map[string]interface{}{
+			"type":                 "object",
+			"additionalProperties": jsonSchemaForType(t.Elem()),
+		}
+	case reflect.Slice, reflect.Array:
+		return map[string]interface{}{
+			"type":  "array",
+			"items": jsonSchemaForType(t.Elem()),
+		}
+	case reflect.String:
+		return map[string]interface{}{"type": "string"}
+	case reflect.Bool:
+		return map[string]interface{}{"type": "boolean"}
+	case reflect.Float32, reflect.Float64:
+		return map[string]interface{}{"type": "number"}
+	case reflect.Int, reflect.Int8, reflect.Int16, reflect.Int32, reflect.Int64,
+		reflect.Uint, reflect.Uint8, reflect.Uint16, reflect.Uint32, reflect.Uint64:
+		return map[string]interface{}{"type": "integer"}
+	default:
+		util.Assertf(false, "jsonSchemaForType: unsupported kind %v\n", t.Kind())
+		return nil
+	}
+}
+
+// Returns a JSON Schema describing JsonReport (as produced by MakeJsonReport).
+func JsonReportSchema() map[string]interface{} {
+	schema := jsonSchemaForType(reflect.TypeOf(JsonReport{}))
+	schema["$schema"] = "http://json-schema.org/draft-07/schema#"
+	schema["title"] = "acb delta report"
+	schema["version"] = JsonFormatVersion
+	return schema
+}
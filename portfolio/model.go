@@ -1,6 +1,7 @@
 package portfolio
 
 import (
+	"fmt"
 	"sort"
 	"time"
 )
@@ -19,7 +20,16 @@ const (
 	NO_ACTION TxAction = iota
 	BUY
 	SELL
-	ROC // Return of capital
+	ROC     // Return of capital
+	ACQUIRE // Non-purchase acquisition (eg. inherited or gifted shares)
+	NOTE    // Annotation only. Has no effect on the portfolio.
+	DIV     // Cash (non-reinvested) dividend. Has no effect on ACB.
+	SPINOFF // Distribution of shares of another security, carved out of the ACB.
+	RENAME  // Symbol change or merger. Carries all shares and ACB to another security.
+	SPLIT   // Stock split (or consolidation). Changes the share balance by Ratio.
+	GIFT    // Gift or donation of shares. A disposition at fair market value.
+	OPEN    // Opening balance. Sets the share balance and ACB of a new position.
+	ADJUST  // Manual ACB adjustment (eg. a reclassified distribution). Adds TotalAmount to the ACB.
 )
 
 func (a TxAction) String() string {
@@ -31,15 +41,70 @@ func (a TxAction) String() string {
 		str = "Sell"
 	case ROC:
 		str = "RoC"
+	case ACQUIRE:
+		str = "Acquire"
+	case NOTE:
+		str = "Note"
+	case DIV:
+		str = "Div"
+	case SPINOFF:
+		str = "Spinoff"
+	case RENAME:
+		str = "Rename"
+	case SPLIT:
+		str = "Split"
+	case GIFT:
+		str = "Gift"
+	case OPEN:
+		str = "Open"
+	case ADJUST:
+		str = "Adjust"
 	default:
 	}
 	return str
 }
 
+// Where a Tx came from.
+type TxOrigin int
+
+const (
+	// Read from a csv row (identified by ReadIndex).
+	ORIGIN_CSV_ROW TxOrigin = iota
+	// The sale of an odd lot before a Split (see DeltaOptions.AutoOddLotSales).
+	// ReadIndex is that of the Split.
+	ORIGIN_ODD_LOT_SALE
+	// The Acquire of the new security of a Spinoff or Rename. ReadIndex is that
+	// of the Spinoff or Rename.
+	ORIGIN_DERIVED
+)
+
+func (o TxOrigin) String() string {
+	switch o {
+	case ORIGIN_CSV_ROW:
+		return "csv_row"
+	case ORIGIN_ODD_LOT_SALE:
+		return "odd_lot_sale"
+	case ORIGIN_DERIVED:
+		return "derived"
+	default:
+		return "invalid"
+	}
+}
+
 type PortfolioSecurityStatus struct {
 	Security     string
 	ShareBalance uint32
 	TotalAcb     float64
+	// The number of shares sold short (only with DeltaOptions.AllowShortSales).
+	// While non-zero, ShareBalance is zero, and TotalAcb is the negated net
+	// proceeds of the open short sales.
+	ShortBalance uint32
+	// The lots making up ShareBalance, oldest first.
+	// Only tracked for FIFO_COST_BASIS.
+	Lots []Lot
+	// The full-precision ACB per share, from which TotalAcb was derived.
+	// Only tracked with DeltaOptions.PerShareAcbTracking (and 0 otherwise).
+	TrackedPerShareAcb float64
 }
 
 func NewEmptyPortfolioSecurityStatus(security string) *PortfolioSecurityStatus {
@@ -49,13 +114,19 @@ func NewEmptyPortfolioSecurityStatus(security string) *PortfolioSecurityStatus {
 func (s *PortfolioSecurityStatus) PerShareAcb() float64 {
 	if s.ShareBalance == 0 {
 		return 0
+	} else if s.TrackedPerShareAcb != 0.0 {
+		return s.TrackedPerShareAcb
 	}
 	return s.TotalAcb / float64(s.ShareBalance)
 }
 
 type Tx struct {
-	Security                          string
-	Date                              time.Time
+	Security string
+	// The settlement date, which determines the tax year and the superficial
+	// loss period.
+	Date time.Time
+	// The trade date, if known. Informational only.
+	TradeDate                         time.Time
 	Action                            TxAction
 	Shares                            uint32
 	AmountPerShare                    float64
@@ -64,10 +135,95 @@ type Tx struct {
 	TxCurrToLocalExchangeRate         float64
 	CommissionCurrency                Currency
 	CommissionCurrToLocalExchangeRate float64
-	Memo                              string
+	// For Acquire and Open, the total ACB of the acquired shares (in TxCurrency).
+	// If zero, AmountPerShare is used as the per-share ACB instead.
+	// For Div, the total dividend paid. If zero, AmountPerShare is used as the
+	// dividend per share held.
+	// For Adjust, the amount added to the ACB (negative to reduce it).
+	TotalAmount float64
+	// For RoC and Div, foreign tax withheld from the distribution (in TxCurrency).
+	// This has no effect on ACB.
+	WithholdingTax float64
+	// For Sell, outlays and expenses other than commission (eg. transfer fees),
+	// in CommissionCurrency. These reduce the proceeds just like commission, but
+	// are reported separately.
+	Outlays float64
+	// For Sell, a superficial loss (negative) to apply instead of the
+	// automatically computed one. nil if not specified.
+	SpecifiedSuperficialLoss *float64
+	// For Spinoff, the security whose shares are distributed. For Rename, the
+	// security which replaces this one.
+	NewSecurity string
+	// For Spinoff and Rename, the number of NewSecurity shares received per
+	// share held. For Rename, zero is treated as 1.
+	// For Split, the number of shares held after the split per share held
+	// before (eg. 2 for a 2-for-1 split, or 0.5 for a 1-for-2 consolidation).
+	Ratio float64
+	// For Spinoff, the percent (0 to 100) of the ACB allocated to NewSecurity.
+	AcbAllocationPercent float64
+	// For Gift, whether it is a donation of publicly listed securities to a
+	// qualified donee, for which the capital gain is not taxable. A loss is
+	// still allowed.
+	ListedSecurityDonation bool
+	Memo                   string
 	// The absolute order in which the Tx was read from file or entered.
 	// Used as a tiebreak in sorting.
 	ReadIndex uint32
+	// Whether the Tx was read from a csv row, or computed from the Tx at
+	// ReadIndex.
+	Origin TxOrigin
+	// The description (eg. the file name) of the csv the Tx was read from, if
+	// any. Used in messages.
+	Source string
+}
+
+// Returns the total dividend (in TxCurrency) of a Div tx, given the number of
+// shares held.
+func (tx *Tx) DividendAmount(shareBalance uint32) float64 {
+	if tx.TotalAmount != 0.0 {
+		return tx.TotalAmount
+	}
+	return tx.AmountPerShare * float64(shareBalance)
+}
+
+// Returns the number of tx (as used in messages), with its source if known.
+// Eg. "#3 in foo.csv"
+func (tx *Tx) RefStr() string {
+	if tx.Source == "" {
+		return fmt.Sprintf("#%d", tx.ReadIndex)
+	}
+	return fmt.Sprintf("#%d in %s", tx.ReadIndex, tx.Source)
+}
+
+// Returns a suffix for messages about tx, with its source (if known).
+func txSourceSuffix(tx *Tx) string {
+	if tx.Source == "" {
+		return ""
+	}
+	return fmt.Sprintf(" (read from %s)", tx.Source)
+}
+
+// Converts amount from tx's TxCurrency to local currency.
+func (tx *Tx) TxCurrToLocal(amount float64) float64 {
+	return amount * tx.TxCurrToLocalExchangeRate
+}
+
+// Converts amount from local currency (localCurrency) to tx's TxCurrency.
+// Returns an error if tx is in a foreign currency, but has no exchange rate.
+func (tx *Tx) LocalToTxCurr(amount float64, localCurrency Currency) (float64, error) {
+	if tx.TxCurrToLocalExchangeRate == 0.0 {
+		if tx.TxCurrency == DEFAULT_CURRENCY || tx.TxCurrency == localCurrency {
+			return amount, nil
+		}
+		return 0.0, fmt.Errorf("Tx on %v in %s has no exchange rate to %s",
+			tx.Date, tx.TxCurrency, localCurrency)
+	}
+	return amount / tx.TxCurrToLocalExchangeRate, nil
+}
+
+// Returns the commission and other outlays of tx, in local currency.
+func (tx *Tx) LocalCommissionAndOutlays() float64 {
+	return (tx.Commission + tx.Outlays) * tx.CommissionCurrToLocalExchangeRate
 }
 
 type TxDelta struct {
@@ -76,6 +232,17 @@ type TxDelta struct {
 	PostStatus      *PortfolioSecurityStatus
 	CapitalGain     float64
 	SuperficialLoss float64
+	// The inputs to SuperficialLoss, if it was computed automatically.
+	SuperficialLossCalc *SuperficialLossCalc
+	// Cash dividend received, in local currency. Informational only.
+	DividendIncome float64
+	// Foreign tax withheld, in local currency. Informational only.
+	WithholdingTax float64
+	// For txs which move shares and ACB into another security (eg. Spinoff),
+	// the Acquire to apply to that security.
+	DerivedTx *Tx
+	// Likely problems with the tx which do not prevent computing it.
+	Warnings []string
 }
 
 func (d *TxDelta) AcbDelta() float64 {
@@ -85,10 +252,63 @@ func (d *TxDelta) AcbDelta() float64 {
 	return d.PostStatus.TotalAcb - d.PreStatus.TotalAcb
 }
 
+// Whether the tx is a Buy which covers (part of) a short position, which
+// realizes a capital gain.
+func (d *TxDelta) CoversShort() bool {
+	return d.Tx.Action == BUY && d.PreStatus.ShortBalance > 0
+}
+
+// Whether the tx is a Sell which opens (or adds to) a short position. This is
+// not a disposition, since the gain is only realized by the covering Buys.
+func (d *TxDelta) OpensShort() bool {
+	return d.Tx.Action == SELL && d.PostStatus.ShortBalance > d.PreStatus.ShortBalance
+}
+
+// The net proceeds of the short sales covered by a Buy (see CoversShort).
+func (d *TxDelta) CoveredShortProceeds() float64 {
+	if !d.CoversShort() {
+		return 0.0
+	}
+	// TotalAcb is the negated proceeds of the open short sales.
+	return d.PostStatus.TotalAcb - d.PreStatus.TotalAcb
+}
+
+// The ACB of the shares disposed of by a Sell or Gift (before any superficial
+// loss).
+func (d *TxDelta) DisposedAcb() float64 {
+	if d.Tx.Action != SELL && d.Tx.Action != GIFT {
+		return 0.0
+	}
+	if d.PreStatus.Lots != nil || d.PostStatus.Lots != nil {
+		// FIFO cost basis, which does not allow superficial losses.
+		return d.PreStatus.TotalAcb - d.PostStatus.TotalAcb
+	}
+	return d.PreStatus.PerShareAcb() * float64(d.Tx.Shares)
+}
+
 func (d *TxDelta) SuperficialLossPercent() float64 {
 	return d.SuperficialLoss / (d.SuperficialLoss + d.CapitalGain)
 }
 
+// The computed deltas for a single security, along with any error which
+// halted their computation.
+type SecurityDeltas struct {
+	Deltas []*TxDelta
+	Errors []error
+}
+
+// Returns the final status of each security, from its last delta.
+// Securities without any deltas are omitted.
+func FinalStatuses(deltaModels map[string]*SecurityDeltas) map[string]*PortfolioSecurityStatus {
+	statuses := make(map[string]*PortfolioSecurityStatus)
+	for sec, deltaModel := range deltaModels {
+		if len(deltaModel.Deltas) > 0 {
+			statuses[sec] = deltaModel.Deltas[len(deltaModel.Deltas)-1].PostStatus
+		}
+	}
+	return statuses
+}
+
 type txSorter struct {
 	Txs []*Tx
 	// Settings
@@ -112,14 +332,19 @@ func (s *txSorter) Less(i, j int) bool {
 	}
 	if s.LegacySortBuysBeforeSells {
 		// Tie break on order type. Buys always first, so we don't go negative.
+		// Corporate actions come after the day's trades of the old shares.
 		actionSortVal := func(action TxAction) int {
 			switch action {
-			case BUY:
+			case OPEN:
 				return 0
-			case ROC:
+			case BUY, ACQUIRE:
 				return 1
-			case SELL:
+			case ROC, ADJUST, DIV, NOTE:
 				return 2
+			case SELL, GIFT:
+				return 3
+			case SPLIT, SPINOFF, RENAME:
+				return 4
 			default:
 				return -1
 			}
@@ -139,3 +364,28 @@ func SortTxs(txs []*Tx, legacySortBuysBeforeSells bool) []*Tx {
 	sort.Sort(&sorter)
 	return sorter.Txs
 }
+
+// Combines the txs of sources (eg. as read with ParseTxCsv, which may have
+// overlapping ReadIndexes) and sorts them as SortTxs would.
+// Each tx's ReadIndex is first reassigned to its position among all sources
+// (in the order of sources, then the order within each source), so ties on a
+// date are always ordered by source, then by position in the source.
+func MergeSourceTxs(sources []SourceTxs, legacySortBuysBeforeSells bool) []*Tx {
+	txs := make([]*Tx, 0)
+	var readIndex uint32 = 0
+	for _, source := range sources {
+		for _, tx := range source.Txs {
+			tx.ReadIndex = readIndex
+			readIndex++
+			txs = append(txs, tx)
+		}
+	}
+	sorter := txSorter{
+		Txs:                       txs,
+		LegacySortBuysBeforeSells: legacySortBuysBeforeSells,
+	}
+	// Stable, so that legacy sorting keeps the order of txs with the same
+	// date and action.
+	sort.Stable(&sorter)
+	return sorter.Txs
+}
@@ -0,0 +1,84 @@
+package portfolio
+
+import (
+	"fmt"
+	"strings"
+	"time"
+)
+
+type sameDayTradeKey struct {
+	Security                          string
+	Date                              time.Time
+	Action                            TxAction
+	TxCurrency                        Currency
+	TxCurrToLocalExchangeRate         float64
+	CommissionCurrency                Currency
+	CommissionCurrToLocalExchangeRate float64
+}
+
+// Combines the Buys of each security on each day into a single Buy, and the
+// Sells into a single Sell, at the weighted average amount/share, with the
+// summed commission and outlays. The combined tx takes the place of the first
+// tx it combines, so txs (sorted) should have all of a day's buys before its
+// sells (or all after) for the gains to be unchanged. The memos of the combined
+// txs are kept, followed by a note of how many were combined.
+//
+// Only txs with the same currencies and exchange rates are combined, so that
+// the total shares and cost are preserved. The amount/share is the summed
+// amount divided once by the total shares, so the total (shares x amount/share)
+// only differs from the summed amount by floating point rounding.
+// Sells with a specified superficial loss are not combined.
+func NetSameDayTrades(txs []*Tx) []*Tx {
+	netted := make([]*Tx, 0, len(txs))
+	nettedByKey := make(map[sameDayTradeKey]*Tx)
+	counts := make(map[*Tx]int)
+	totalAmounts := make(map[*Tx]float64)
+	memos := make(map[*Tx][]string)
+	for _, tx := range txs {
+		if (tx.Action != BUY && tx.Action != SELL) || tx.SpecifiedSuperficialLoss != nil {
+			netted = append(netted, tx)
+			continue
+		}
+		key := sameDayTradeKey{
+			Security:                          tx.Security,
+			Date:                              tx.Date,
+			Action:                            tx.Action,
+			TxCurrency:                        tx.TxCurrency,
+			TxCurrToLocalExchangeRate:         tx.TxCurrToLocalExchangeRate,
+			CommissionCurrency:                tx.CommissionCurrency,
+			CommissionCurrToLocalExchangeRate: tx.CommissionCurrToLocalExchangeRate,
+		}
+		combined, ok := nettedByKey[key]
+		if !ok {
+			txCopy := *tx
+			nettedByKey[key] = &txCopy
+			counts[&txCopy] = 1
+			totalAmounts[&txCopy] = float64(tx.Shares) * tx.AmountPerShare
+			if tx.Memo != "" {
+				memos[&txCopy] = []string{tx.Memo}
+			}
+			netted = append(netted, &txCopy)
+			continue
+		}
+		totalAmounts[combined] += float64(tx.Shares) * tx.AmountPerShare
+		combined.Shares += tx.Shares
+		combined.Commission += tx.Commission
+		combined.Outlays += tx.Outlays
+		if tx.Memo != "" {
+			memos[combined] = append(memos[combined], tx.Memo)
+		}
+		counts[combined]++
+	}
+
+	for combined, count := range counts {
+		if count == 1 {
+			continue
+		}
+		if combined.Shares > 0 {
+			combined.AmountPerShare = totalAmounts[combined] / float64(combined.Shares)
+		}
+		combined.Memo = strings.Join(append(memos[combined],
+			fmt.Sprintf("%d same-day %ss combined", count, combined.Action)), "; ")
+	}
+	return netted
+}
@@ -0,0 +1,149 @@
+package portfolio
+
+import (
+	"bytes"
+	"encoding/csv"
+	"fmt"
+	"io"
+	"math"
+	"strconv"
+	"strings"
+	"time"
+
+	"github.com/tsiemens/acb/log"
+	"github.com/tsiemens/acb/util"
+)
+
+// The formats of dates in Questrade activity exports. Dates may include a
+// (meaningless) time of day.
+var questradeDateFormats = []string{
+	"2006-01-02 3:04:05 PM",
+	"2006-01-02",
+}
+
+func parseQuestradeDate(data string) (time.Time, error) {
+	data = strings.TrimSpace(data)
+	for _, format := range questradeDateFormats {
+		t, err := time.Parse(format, data)
+		if err == nil {
+			return t, nil
+		}
+	}
+	return time.Time{}, fmt.Errorf("Invalid date '%s'", data)
+}
+
+func parseQuestradeFloat(data string) (float64, error) {
+	data = strings.ReplaceAll(strings.TrimSpace(data), ",", "")
+	if data == "" {
+		return 0.0, nil
+	}
+	return strconv.ParseFloat(data, 64)
+}
+
+// Converts a Questrade "Activity" export into the csv format read by
+// ParseTxCsv (with options).
+//
+// Quantities and commissions are negative in these exports (on sells and on
+// all trades, respectively), so their absolute values are used. Rows with
+// actions which do not affect ACB (eg. deposits) are skipped with a warning.
+func ConvertQuestradeCsv(
+	reader io.Reader, csvDesc string, options CsvOptions,
+	errPrinter log.ErrorPrinter) (io.Reader, error) {
+
+	csvR := csv.NewReader(util.SkipUtf8Bom(reader))
+	records, err := csvR.ReadAll()
+	if err != nil {
+		return nil, fmt.Errorf("Failed to parse Questrade CSV %s: %v", csvDesc, err)
+	}
+	if len(records) == 0 {
+		return nil, fmt.Errorf("No rows found in %s", csvDesc)
+	}
+
+	colIdx := make(map[string]int)
+	for i, col := range records[0] {
+		colIdx[strings.TrimSpace(strings.ToLower(col))] = i
+	}
+	requiredCols := []string{"settlement date", "action", "symbol", "quantity", "price",
+		"commission", "currency"}
+	for _, col := range requiredCols {
+		if _, ok := colIdx[col]; !ok {
+			return nil, fmt.Errorf("Questrade CSV %s has no '%s' column", csvDesc, col)
+		}
+	}
+	cell := func(record []string, col string) string {
+		i, ok := colIdx[col]
+		if !ok || i >= len(record) {
+			return ""
+		}
+		return strings.TrimSpace(record[i])
+	}
+
+	txs := make([]*Tx, 0, len(records)-1)
+	for i, record := range records[1:] {
+		lineErr := func(err error) error {
+			return fmt.Errorf("Error parsing Questrade CSV %s at line %d: %v", csvDesc, i+2, err)
+		}
+
+		action, err := ParseTxAction(cell(record, "action"), options.ActionAliases)
+		if err != nil || cell(record, "symbol") == "" {
+			errPrinter.F("Warning: Skipping %s line %d (action '%s', symbol '%s')\n",
+				csvDesc, i+2, cell(record, "action"), cell(record, "symbol"))
+			continue
+		}
+
+		tx := DefaultTx()
+		tx.Security = cell(record, "symbol")
+		tx.Action = action
+		tx.TxCurrency = Currency(strings.ToUpper(cell(record, "currency")))
+		tx.Memo = cell(record, "description")
+
+		tx.Date, err = parseQuestradeDate(cell(record, "settlement date"))
+		if err != nil {
+			return nil, lineErr(err)
+		}
+		if tradeDate := cell(record, "transaction date"); tradeDate != "" {
+			tx.TradeDate, err = parseQuestradeDate(tradeDate)
+			if err != nil {
+				return nil, lineErr(err)
+			}
+		}
+
+		quantity, err := parseQuestradeFloat(cell(record, "quantity"))
+		if err != nil {
+			return nil, lineErr(err)
+		}
+		quantity = math.Abs(quantity)
+		if quantity != math.Round(quantity) {
+			return nil, lineErr(fmt.Errorf("Fractional quantity %f is not supported", quantity))
+		}
+		price, err := parseQuestradeFloat(cell(record, "price"))
+		if err != nil {
+			return nil, lineErr(err)
+		}
+		commission, err := parseQuestradeFloat(cell(record, "commission"))
+		if err != nil {
+			return nil, lineErr(err)
+		}
+		tx.Commission = math.Abs(commission)
+
+		if action == DIV {
+			// The dividend is only given as the net amount of the row.
+			netAmount, err := parseQuestradeFloat(cell(record, "net amount"))
+			if err != nil {
+				return nil, lineErr(err)
+			}
+			tx.TotalAmount = math.Abs(netAmount)
+		} else {
+			tx.Shares = uint32(quantity)
+			tx.AmountPerShare = price
+		}
+		txs = append(txs, tx)
+	}
+
+	var buf bytes.Buffer
+	err = WriteTxCsv(txs, options, &buf)
+	if err != nil {
+		return nil, err
+	}
+	return &buf, nil
+}
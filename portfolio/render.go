@@ -3,6 +3,10 @@ package portfolio
 import (
 	"fmt"
 	"io"
+	"math"
+	"sort"
+	"strings"
+	"time"
 
 	tw "github.com/olekukonko/tablewriter"
 	"github.com/tsiemens/acb/util"
@@ -16,7 +20,8 @@ func (h _PrintHelper) CurrStr(val float64) string {
 	if h.PrintAllDecimals {
 		return fmt.Sprintf("%f", val)
 	}
-	return fmt.Sprintf("%.2f", val)
+	// %.2f would round the binary value half to even.
+	return fmt.Sprintf("%.2f", util.RoundToCents(val))
 }
 
 func (h _PrintHelper) CurrWithFxStr(val float64, curr Currency, rateToLocal float64) string {
@@ -44,20 +49,60 @@ func (h _PrintHelper) PlusMinusDollar(val float64, showPlus bool) string {
 	return fmt.Sprintf("%s$%s", plus, h.CurrStr(val))
 }
 
+// Short positions are shown as a negative balance.
+func shareBalanceStr(status *PortfolioSecurityStatus) string {
+	if status.ShortBalance > 0 {
+		return fmt.Sprintf("-%d", status.ShortBalance)
+	}
+	return fmt.Sprintf("%d", status.ShareBalance)
+}
+
+// Returns the portion of the loss of a partially superficial Sell which was
+// superficial, as a fraction of the shares sold in lowest terms
+// (eg. ", 1/4 of loss"). Returns "" if the whole loss was superficial, or if
+// the portion is not a whole number of shares (eg. for a specified loss).
+func superficialLossFractionStr(d *TxDelta) string {
+	if d.Tx.Action != SELL || d.Tx.Shares == 0 || d.CapitalGain == 0.0 {
+		return ""
+	}
+	shares := d.SuperficialLossPercent() * float64(d.Tx.Shares)
+	wholeShares := math.Round(shares)
+	if wholeShares <= 0.0 || wholeShares >= float64(d.Tx.Shares) ||
+		math.Abs(shares-wholeShares) > 1e-3 {
+		return ""
+	}
+	num, denom := util.ReduceFraction(uint32(wholeShares), d.Tx.Shares)
+	return fmt.Sprintf(", %d/%d of loss", num, denom)
+}
+
 type RenderTable struct {
 	Header []string
 	Rows   [][]string
 	Footer []string
 	Notes  []string
 	Errors []error
+	// For tx tables, the tx of each of Rows, so that a row can be linked back
+	// to the csv row it came from.
+	RowSources []RenderRowSource
+}
+
+type RenderRowSource struct {
+	ReadIndex uint32
+	Origin    TxOrigin
+}
+
+var TxTableHeader = []string{"Security", "Date", "TX", "Amount", "Shares", "Amt/Share", "ACB",
+	"Commission", "Cap. Gain", "Share Balance", "ACB +/-", "New ACB", "New ACB/Share",
+	"Memo",
 }
 
-func RenderTxTableModel(deltas []*TxDelta, renderFullDollarValues bool) *RenderTable {
+// rounding should be that which the deltas were computed with. It is also
+// applied to the ACB per share and the disposed ACB.
+func RenderTxTableModel(
+	deltas []*TxDelta, rounding RoundingPolicy, renderFullDollarValues bool) *RenderTable {
+
 	table := &RenderTable{}
-	table.Header = []string{"Security", "Date", "TX", "Amount", "Shares", "Amt/Share", "ACB",
-		"Commission", "Cap. Gain", "Share Balance", "ACB +/-", "New ACB", "New ACB/Share",
-		"Memo",
-	}
+	table.Header = append([]string{}, TxTableHeader...)
 
 	ph := _PrintHelper{PrintAllDecimals: renderFullDollarValues}
 
@@ -68,38 +113,63 @@ func RenderTxTableModel(deltas []*TxDelta, renderFullDollarValues bool) *RenderT
 		superficialLossAsterix := ""
 		superficialLossAddAsterix := ""
 		if d.SuperficialLoss != 0.0 {
-			superficialLossAsterix = fmt.Sprintf(" *\n(SFL %s)", ph.PlusMinusDollar(d.SuperficialLoss, false))
+			superficialLossAsterix = fmt.Sprintf(" *\n(SFL %s%s)",
+				ph.PlusMinusDollar(d.SuperficialLoss, false), superficialLossFractionStr(d))
 			superficialLossAddAsterix = fmt.Sprintf(" *\n(%s)", ph.PlusMinusDollar(-1*d.SuperficialLoss, true))
 			sawSuperficialLoss = true
 		}
 		tx := d.Tx
 
-		var preAcbPerShare float64 = 0.0
-		if tx.Action == SELL && d.PreStatus.ShareBalance > 0 {
-			preAcbPerShare = d.PreStatus.TotalAcb / float64(d.PreStatus.ShareBalance)
+		if tx.Action == NOTE {
+			row := []string{tx.Security, util.DateStr(tx.Date), tx.Action.String(),
+				"-", "-", "-", "-", "-", "-",
+				shareBalanceStr(d.PostStatus),
+				"-",
+				"$" + ph.CurrStr(d.PostStatus.TotalAcb),
+				strOrDash(d.PostStatus.ShareBalance > 0.0,
+					"$"+ph.CurrStr(rounding.Round(d.PostStatus.PerShareAcb()))),
+				tx.Memo,
+			}
+			table.Rows = append(table.Rows, row)
+			table.RowSources = append(table.RowSources,
+				RenderRowSource{ReadIndex: tx.ReadIndex, Origin: tx.Origin})
+			continue
+		}
+
+		amount := float64(tx.Shares) * tx.AmountPerShare
+		if (tx.Action == ACQUIRE || tx.Action == OPEN) && tx.TotalAmount != 0.0 {
+			amount = tx.TotalAmount
+		} else if tx.Action == ADJUST {
+			amount = tx.TotalAmount
+		} else if tx.Action == DIV {
+			amount = tx.DividendAmount(d.PreStatus.ShareBalance)
 		}
 
 		row := []string{d.Tx.Security, util.DateStr(tx.Date), tx.Action.String(),
 			// Amount
-			ph.CurrWithFxStr(float64(tx.Shares)*tx.AmountPerShare, tx.TxCurrency, tx.TxCurrToLocalExchangeRate),
+			ph.CurrWithFxStr(amount, tx.TxCurrency, tx.TxCurrToLocalExchangeRate),
 			fmt.Sprintf("%d", tx.Shares),
 			ph.CurrWithFxStr(tx.AmountPerShare, tx.TxCurrency, tx.TxCurrToLocalExchangeRate),
 			// ACB of sale
-			strOrDash(tx.Action == SELL, "$"+ph.CurrStr(preAcbPerShare*float64(tx.Shares))),
+			strOrDash(tx.Action == SELL || tx.Action == GIFT,
+				"$"+ph.CurrStr(rounding.Round(d.DisposedAcb()))),
 			// Commission
 			strOrDash(tx.Commission != 0.0,
 				ph.CurrWithFxStr(tx.Commission, tx.CommissionCurrency, tx.CommissionCurrToLocalExchangeRate)),
 			// Cap gains
-			strOrDash(tx.Action == SELL, ph.PlusMinusDollar(d.CapitalGain, false)+superficialLossAsterix),
-			fmt.Sprintf("%d", d.PostStatus.ShareBalance),
+			strOrDash(tx.Action == SELL || tx.Action == GIFT || d.CoversShort(),
+				ph.PlusMinusDollar(d.CapitalGain, false)+superficialLossAsterix),
+			shareBalanceStr(d.PostStatus),
 			ph.PlusMinusDollar(d.AcbDelta(), true) + superficialLossAddAsterix,
 			"$" + ph.CurrStr(d.PostStatus.TotalAcb) + superficialLossAddAsterix,
 			// Acb per share
 			strOrDash(d.PostStatus.ShareBalance > 0.0,
-				"$"+ph.CurrStr(d.PostStatus.TotalAcb/float64(d.PostStatus.ShareBalance))),
+				"$"+ph.CurrStr(rounding.Round(d.PostStatus.PerShareAcb()))),
 			tx.Memo,
 		}
 		table.Rows = append(table.Rows, row)
+		table.RowSources = append(table.RowSources,
+			RenderRowSource{ReadIndex: tx.ReadIndex, Origin: tx.Origin})
 
 		capGainsTotal += d.CapitalGain
 	}
@@ -113,6 +183,270 @@ func RenderTxTableModel(deltas []*TxDelta, renderFullDollarValues bool) *RenderT
 	return table
 }
 
+// Appends a note to table for each automatically computed superficial loss in
+// deltas, with the inputs to its calculation.
+func AddSuperficialLossCalcNotes(table *RenderTable, deltas []*TxDelta) {
+	for _, d := range deltas {
+		calc := d.SuperficialLossCalc
+		if calc == nil {
+			continue
+		}
+		table.Notes = append(table.Notes, fmt.Sprintf(
+			" SFL of %s sale on %s: min(%d sold, %d acquired in period, "+
+				"%d held at end of period) = %d, so %d/%d of the loss is superficial",
+			d.Tx.Security, util.DateStr(d.Tx.Date), calc.SharesSold,
+			calc.TotalAcquiredInPeriod, calc.SharesAtEndOfPeriod, calc.SuperficialShares,
+			calc.SuperficialShares, calc.SharesSold))
+	}
+}
+
+const TradeCurrencyGainHeader = "Trade Curr. Gain"
+
+// Appends a column to table (as rendered from deltas by RenderTxTableModel) with
+// the capital gain of each sale computed in the transaction currency.
+// If this cannot be computed (eg. the security has transactions in multiple
+// currencies), the column is filled with dashes and a note is added instead.
+func AddTradeCurrencyGainColumn(
+	table *RenderTable, deltas []*TxDelta, options DeltaOptions, renderFullDollarValues bool) {
+
+	ph := _PrintHelper{PrintAllDecimals: renderFullDollarValues}
+
+	tcDeltas, err := TradeCurrencyDeltas(deltas, options)
+	if err != nil {
+		table.Notes = append(table.Notes,
+			fmt.Sprintf(" Trade currency gains not shown: %v", err))
+	}
+
+	var total float64 = 0.0
+	var currency Currency = DEFAULT_CURRENCY
+	for i, d := range deltas {
+		cell := "-"
+		if tcDeltas != nil {
+			currency = tcDeltas[i].Currency
+			if d.Tx.Action == SELL || d.Tx.Action == GIFT {
+				cell = fmt.Sprintf("%s %s",
+					ph.PlusMinusDollar(tcDeltas[i].CapitalGain, false), currency)
+				total += tcDeltas[i].CapitalGain
+			}
+		}
+		table.Rows[i] = append(table.Rows[i], cell)
+	}
+
+	table.Header = append(table.Header, TradeCurrencyGainHeader)
+	footerTotal := ""
+	if len(tcDeltas) > 0 {
+		footerTotal = fmt.Sprintf("%s %s", ph.PlusMinusDollar(total, false), currency)
+		table.Notes = append(table.Notes,
+			" Trade currency gains are informational only, and exclude exchange rates "+
+				"and superficial losses")
+	}
+	table.Footer = append(table.Footer, footerTotal)
+}
+
+const TradeCurrencyAcbHeader = "Trade Curr. ACB"
+
+// Appends a column to table (as rendered from deltas by RenderTxTableModel) with
+// the total ACB after each delta in the transaction currency.
+// If this cannot be computed (eg. the security has transactions in multiple
+// currencies), the column is filled with dashes and the error is returned.
+func AddTradeCurrencyAcbColumn(
+	table *RenderTable, deltas []*TxDelta, options DeltaOptions, renderFullDollarValues bool) error {
+
+	ph := _PrintHelper{PrintAllDecimals: renderFullDollarValues}
+
+	tcDeltas, err := TradeCurrencyDeltas(deltas, options)
+	for i, _ := range deltas {
+		cell := "-"
+		if err == nil {
+			cell = fmt.Sprintf("$%s %s", ph.CurrStr(tcDeltas[i].TotalAcb), tcDeltas[i].Currency)
+		}
+		table.Rows[i] = append(table.Rows[i], cell)
+	}
+
+	table.Header = append(table.Header, TradeCurrencyAcbHeader)
+	table.Footer = append(table.Footer, "")
+	return err
+}
+
+var RunningTotalsHeader = []string{"Year Cap. Gain", "Year SFL"}
+
+// Appends columns to table (as rendered from deltas by RenderTxTableModel) with
+// the running total capital gain, and the running total superficial losses
+// denied, as of each delta. Both are reset at the start of each tax year.
+func AddRunningTotalsColumns(
+	table *RenderTable, deltas []*TxDelta, renderFullDollarValues bool) {
+
+	ph := _PrintHelper{PrintAllDecimals: renderFullDollarValues}
+
+	year := 0
+	var capGainsTotal, sflTotal float64
+	for i, d := range deltas {
+		if d.Tx.Date.Year() != year {
+			year = d.Tx.Date.Year()
+			capGainsTotal = 0.0
+			sflTotal = 0.0
+		}
+		capGainsTotal += d.CapitalGain
+		sflTotal += -d.SuperficialLoss
+		table.Rows[i] = append(table.Rows[i],
+			ph.PlusMinusDollar(capGainsTotal, false), "$"+ph.CurrStr(sflTotal))
+	}
+
+	table.Header = append(table.Header, RunningTotalsHeader...)
+	table.Footer = append(table.Footer, "", "")
+}
+
+func RenderCommissionTotalsTable(totalsByYear map[int]float64, renderFullDollarValues bool) *RenderTable {
+	table := &RenderTable{}
+	table.Header = []string{"Year", "Commission"}
+
+	ph := _PrintHelper{PrintAllDecimals: renderFullDollarValues}
+
+	years := make([]int, 0, len(totalsByYear))
+	for year, _ := range totalsByYear {
+		years = append(years, year)
+	}
+	sort.Ints(years)
+
+	var total float64 = 0.0
+	for _, year := range years {
+		table.Rows = append(table.Rows,
+			[]string{fmt.Sprintf("%d", year), "$" + ph.CurrStr(totalsByYear[year])})
+		total += totalsByYear[year]
+	}
+	table.Footer = []string{"Total", "$" + ph.CurrStr(total)}
+	return table
+}
+
+func RenderYearGainsTable(rows []*YearGainsRow, renderFullDollarValues bool) *RenderTable {
+	table := &RenderTable{}
+	table.Header = []string{"Year", "Proceeds", "Commission", "Other Outlays", "ACB",
+		"Cap. Gain", "SFL Denied", "Net Gain"}
+
+	ph := _PrintHelper{PrintAllDecimals: renderFullDollarValues}
+
+	for _, row := range rows {
+		table.Rows = append(table.Rows, []string{
+			fmt.Sprintf("%d", row.Year),
+			"$" + ph.CurrStr(row.Proceeds),
+			"$" + ph.CurrStr(row.Outlays),
+			"$" + ph.CurrStr(row.OtherOutlays),
+			"$" + ph.CurrStr(row.TotalAcbDisposed),
+			ph.PlusMinusDollar(row.CapitalGain, false),
+			"$" + ph.CurrStr(row.SuperficialLossDenied),
+			ph.PlusMinusDollar(row.NetGain, false),
+		})
+	}
+	return table
+}
+
+func RenderInclusionPeriodGainsTable(
+	rows []*InclusionPeriodGainsRow, splitDate time.Time, renderFullDollarValues bool) *RenderTable {
+
+	table := &RenderTable{}
+	dateStr := util.DateStr(splitDate)
+	table.Header = []string{"Year", "Net Gain Before " + dateStr, "Net Gain On/After " + dateStr}
+
+	ph := _PrintHelper{PrintAllDecimals: renderFullDollarValues}
+
+	for _, row := range rows {
+		table.Rows = append(table.Rows, []string{
+			fmt.Sprintf("%d", row.Year),
+			ph.PlusMinusDollar(row.NetGainBefore, false),
+			ph.PlusMinusDollar(row.NetGainOnOrAfter, false),
+		})
+	}
+	return table
+}
+
+func RenderHoldingsTable(
+	rows []*HoldingRow, rounding RoundingPolicy, renderFullDollarValues bool) *RenderTable {
+
+	table := &RenderTable{}
+	table.Header = []string{"Security", "Share Balance", "ACB", "ACB/Share", "Last TX"}
+
+	ph := _PrintHelper{PrintAllDecimals: renderFullDollarValues}
+
+	var totalAcb float64 = 0.0
+	for _, row := range rows {
+		balance := fmt.Sprintf("%d", row.ShareBalance)
+		if row.ShortBalance > 0 {
+			balance = fmt.Sprintf("-%d", row.ShortBalance)
+		}
+		table.Rows = append(table.Rows, []string{
+			row.Security,
+			balance,
+			"$" + ph.CurrStr(row.TotalAcb),
+			strOrDash(row.ShareBalance > 0, "$"+ph.CurrStr(rounding.Round(row.PerShareAcb))),
+			util.DateStr(row.LastTxDate),
+		})
+		totalAcb += row.TotalAcb
+	}
+	table.Footer = []string{"Total", "", "$" + ph.CurrStr(totalAcb), "", ""}
+	return table
+}
+
+func findColumn(header []string, column string) int {
+	for i, name := range header {
+		if strings.ToLower(name) == strings.TrimSpace(strings.ToLower(column)) {
+			return i
+		}
+	}
+	return -1
+}
+
+// Verifies that each of columns names a column in header (case-insensitive).
+func ValidateColumns(header []string, columns []string) error {
+	for _, col := range columns {
+		if findColumn(header, col) < 0 {
+			return fmt.Errorf("Invalid column '%s'. Valid columns are: %s",
+				col, strings.Join(header, ", "))
+		}
+	}
+	return nil
+}
+
+// Restricts table to the given columns, in the order provided.
+// Column names are matched case-insensitively against the table header.
+func SelectRenderTableColumns(table *RenderTable, columns []string) error {
+	if err := ValidateColumns(table.Header, columns); err != nil {
+		return err
+	}
+
+	colIndices := make([]int, 0, len(columns))
+	for _, col := range columns {
+		colIndices = append(colIndices, findColumn(table.Header, col))
+	}
+	selectCols := func(row []string) []string {
+		if row == nil {
+			return nil
+		}
+		newRow := make([]string, 0, len(colIndices))
+		for _, i := range colIndices {
+			newRow = append(newRow, row[i])
+		}
+		return newRow
+	}
+
+	table.Header = selectCols(table.Header)
+	for i, row := range table.Rows {
+		table.Rows[i] = selectCols(row)
+	}
+	table.Footer = selectCols(table.Footer)
+	return nil
+}
+
+// Reverses the order of the rows of table (eg. to show the newest first).
+// The header and footer are unchanged.
+func ReverseRenderTableRows(table *RenderTable) {
+	for i, j := 0, len(table.Rows)-1; i < j; i, j = i+1, j-1 {
+		table.Rows[i], table.Rows[j] = table.Rows[j], table.Rows[i]
+	}
+	for i, j := 0, len(table.RowSources)-1; i < j; i, j = i+1, j-1 {
+		table.RowSources[i], table.RowSources[j] = table.RowSources[j], table.RowSources[i]
+	}
+}
+
 func PrintRenderTable(tableModel *RenderTable, writer io.Writer) {
 	table := tw.NewWriter(writer)
 	table.SetHeader(tableModel.Header)
@@ -131,3 +465,41 @@ func PrintRenderTable(tableModel *RenderTable, writer io.Writer) {
 		fmt.Fprintln(writer, note)
 	}
 }
+
+func markdownCell(cell string) string {
+	cell = strings.ReplaceAll(cell, "|", "\\|")
+	return strings.ReplaceAll(cell, "\n", "<br>")
+}
+
+func writeMarkdownRow(writer io.Writer, row []string) {
+	cells := make([]string, 0, len(row))
+	for _, cell := range row {
+		cells = append(cells, markdownCell(cell))
+	}
+	fmt.Fprintf(writer, "| %s |\n", strings.Join(cells, " | "))
+}
+
+// Prints the table as a GitHub-flavoured Markdown table.
+// The footer (if any) is written as the last row.
+func PrintRenderTableMarkdown(tableModel *RenderTable, writer io.Writer) {
+	writeMarkdownRow(writer, tableModel.Header)
+	separator := make([]string, 0, len(tableModel.Header))
+	for range tableModel.Header {
+		separator = append(separator, "---")
+	}
+	writeMarkdownRow(writer, separator)
+
+	for _, row := range tableModel.Rows {
+		writeMarkdownRow(writer, row)
+	}
+	if len(tableModel.Footer) > 0 {
+		writeMarkdownRow(writer, tableModel.Footer)
+	}
+
+	if len(tableModel.Notes) > 0 {
+		fmt.Fprintln(writer, "")
+	}
+	for _, note := range tableModel.Notes {
+		fmt.Fprintln(writer, note)
+	}
+}
@@ -0,0 +1,44 @@
+package portfolio
+
+import (
+	"fmt"
+	"strings"
+
+	"github.com/tsiemens/acb/util"
+)
+
+type RoundingPolicy int
+
+const (
+	// Values are kept at full precision.
+	ROUND_NONE RoundingPolicy = iota
+	// Values are rounded to cents, with halves rounded away from zero.
+	ROUND_CENTS
+	// Values are rounded to cents, with halves rounded to the even cent.
+	ROUND_BANKERS_CENTS
+)
+
+func ParseRoundingPolicy(name string) (RoundingPolicy, error) {
+	switch strings.TrimSpace(strings.ToLower(name)) {
+	case "", "none":
+		return ROUND_NONE, nil
+	case "cents":
+		return ROUND_CENTS, nil
+	case "bankers-cents":
+		return ROUND_BANKERS_CENTS, nil
+	default:
+		return ROUND_NONE, fmt.Errorf(
+			"Invalid rounding '%s' (must be none, cents or bankers-cents)", name)
+	}
+}
+
+func (p RoundingPolicy) Round(val float64) float64 {
+	switch p {
+	case ROUND_CENTS:
+		return util.RoundToCents(val)
+	case ROUND_BANKERS_CENTS:
+		return util.RoundToCentsHalfEven(val)
+	default:
+		return val
+	}
+}
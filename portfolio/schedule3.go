@@ -0,0 +1,107 @@
+package portfolio
+
+import (
+	"encoding/csv"
+	"fmt"
+	"io"
+	"sort"
+)
+
+// A disposition of publicly traded shares, as reported on Schedule 3 (in local
+// currency).
+type Schedule3Row struct {
+	Security string
+	Shares   uint32
+	// Proceeds of disposition
+	Proceeds float64
+	// The ACB of the disposed shares.
+	Acb float64
+	// Commission and other outlays and expenses
+	Outlays float64
+	// The (positive) portion of the loss which was denied as superficial. This
+	// is excluded from Gain.
+	SuperficialLossDenied float64
+	// The reportable gain (or loss).
+	Gain float64
+}
+
+// The header written by WriteSchedule3Csv.
+var Schedule3CsvHeader = []string{"security", "shares", "proceeds of disposition",
+	"adjusted cost base", "outlays and expenses", "superficial loss denied",
+	"gain (or loss)"}
+
+// Returns a row for every Sell and Gift in deltas (which may be for any number
+// of securities) settled in year, sorted by settlement date and then security.
+// As in T5008Rows, short positions are disposed of by the Buys which cover them.
+func Schedule3Rows(deltas []*TxDelta, year int) []*Schedule3Row {
+	dispositions := make([]*TxDelta, 0, len(deltas))
+	for _, d := range deltas {
+		isDisposition := ((d.Tx.Action == SELL || d.Tx.Action == GIFT) && !d.OpensShort()) ||
+			d.CoversShort()
+		if isDisposition && d.Tx.Date.Year() == year {
+			dispositions = append(dispositions, d)
+		}
+	}
+	sort.SliceStable(dispositions, func(i, j int) bool {
+		if !dispositions[i].Tx.Date.Equal(dispositions[j].Tx.Date) {
+			return dispositions[i].Tx.Date.Before(dispositions[j].Tx.Date)
+		}
+		return dispositions[i].Tx.Security < dispositions[j].Tx.Security
+	})
+
+	rows := make([]*Schedule3Row, 0, len(dispositions))
+	for _, d := range dispositions {
+		tx := d.Tx
+		if d.CoversShort() {
+			rows = append(rows, &Schedule3Row{
+				Security: tx.Security,
+				Shares:   tx.Shares,
+				// The short sales received the proceeds (net of their commission),
+				// and the covering buy is the cost.
+				Proceeds: d.CoveredShortProceeds(),
+				Acb:      tx.TxCurrToLocal(float64(tx.Shares) * tx.AmountPerShare),
+				Outlays:  tx.LocalCommissionAndOutlays(),
+				Gain:     d.CapitalGain,
+			})
+			continue
+		}
+		rows = append(rows, &Schedule3Row{
+			Security: tx.Security,
+			Shares:   tx.Shares,
+			Proceeds: tx.TxCurrToLocal(float64(tx.Shares) * tx.AmountPerShare),
+			Acb:      d.DisposedAcb(),
+			Outlays:  tx.LocalCommissionAndOutlays(),
+			// SuperficialLoss is negative, and has already been removed from
+			// CapitalGain.
+			SuperficialLossDenied: -d.SuperficialLoss,
+			Gain:                  d.CapitalGain,
+		})
+	}
+	return rows
+}
+
+// Writes rows as a csv (with a header). Amounts are rounded to cents.
+func WriteSchedule3Csv(rows []*Schedule3Row, writer io.Writer) error {
+	csvW := csv.NewWriter(writer)
+	err := csvW.Write(Schedule3CsvHeader)
+	if err != nil {
+		return err
+	}
+	ph := _PrintHelper{PrintAllDecimals: false}
+	for _, row := range rows {
+		err = csvW.Write([]string{
+			row.Security,
+			fmt.Sprintf("%d", row.Shares),
+			ph.CurrStr(row.Proceeds),
+			ph.CurrStr(row.Acb),
+			ph.CurrStr(row.Outlays),
+			ph.CurrStr(row.SuperficialLossDenied),
+			ph.CurrStr(row.Gain),
+		})
+		if err != nil {
+			return err
+		}
+	}
+	csvW.Flush()
+	return csvW.Error()
+}
@@ -0,0 +1,103 @@
+package portfolio
+
+import (
+	"fmt"
+	"sort"
+
+	"github.com/tsiemens/acb/util"
+)
+
+// A single disposition, as it would be reported on a T5008 slip (in local
+// currency).
+type T5008Row struct {
+	Security       string
+	SettlementDate string
+	Quantity       uint32
+	// Gross proceeds of disposition
+	Proceeds float64
+	// The ACB of the disposed shares. Superficial loss adjustments are not
+	// included, as they are not part of the disposition.
+	Acb float64
+	// Commissions (outlays and expenses)
+	Outlays float64
+	// Outlays and expenses other than commission (eg. transfer fees)
+	OtherOutlays float64
+}
+
+// Returns a row for every Sell in deltas (which may be for any number of
+// securities), sorted by settlement date and then security.
+// A short position is disposed of when it is covered, so there is a row for
+// each Buy which covers one, rather than for the short sales.
+// If year is non-zero, only dispositions settled in that year are included.
+func T5008Rows(deltas []*TxDelta, year int) []*T5008Row {
+	sells := make([]*TxDelta, 0, len(deltas))
+	for _, d := range deltas {
+		if ((d.Tx.Action == SELL && !d.OpensShort()) || d.CoversShort()) &&
+			(year == 0 || d.Tx.Date.Year() == year) {
+			sells = append(sells, d)
+		}
+	}
+	sort.SliceStable(sells, func(i, j int) bool {
+		if !sells[i].Tx.Date.Equal(sells[j].Tx.Date) {
+			return sells[i].Tx.Date.Before(sells[j].Tx.Date)
+		}
+		return sells[i].Tx.Security < sells[j].Tx.Security
+	})
+
+	rows := make([]*T5008Row, 0, len(sells))
+	for _, d := range sells {
+		tx := d.Tx
+		if d.CoversShort() {
+			rows = append(rows, &T5008Row{
+				Security:       tx.Security,
+				SettlementDate: util.DateStr(tx.Date),
+				Quantity:       tx.Shares,
+				// The short sales received the proceeds (net of their commission),
+				// and the covering buy is the cost.
+				Proceeds: d.CoveredShortProceeds(),
+				Acb:      tx.TxCurrToLocal(float64(tx.Shares) * tx.AmountPerShare),
+				Outlays:  tx.Commission * tx.CommissionCurrToLocalExchangeRate,
+			})
+			continue
+		}
+		rows = append(rows, &T5008Row{
+			Security:       tx.Security,
+			SettlementDate: util.DateStr(tx.Date),
+			Quantity:       tx.Shares,
+			Proceeds:       tx.TxCurrToLocal(float64(tx.Shares) * tx.AmountPerShare),
+			Acb:            d.DisposedAcb(),
+			Outlays:        tx.Commission * tx.CommissionCurrToLocalExchangeRate,
+			OtherOutlays:   tx.Outlays * tx.CommissionCurrToLocalExchangeRate,
+		})
+	}
+	return rows
+}
+
+func RenderT5008Table(rows []*T5008Row, renderFullDollarValues bool) *RenderTable {
+	table := &RenderTable{}
+	table.Header = []string{"Security", "Settlement Date", "Quantity", "Proceeds",
+		"ACB", "Commission", "Other Outlays"}
+
+	ph := _PrintHelper{PrintAllDecimals: renderFullDollarValues}
+
+	var totalProceeds, totalAcb, totalOutlays, totalOtherOutlays float64
+	for _, row := range rows {
+		table.Rows = append(table.Rows, []string{
+			row.Security,
+			row.SettlementDate,
+			fmt.Sprintf("%d", row.Quantity),
+			"$" + ph.CurrStr(row.Proceeds),
+			"$" + ph.CurrStr(row.Acb),
+			"$" + ph.CurrStr(row.Outlays),
+			"$" + ph.CurrStr(row.OtherOutlays),
+		})
+		totalProceeds += row.Proceeds
+		totalAcb += row.Acb
+		totalOutlays += row.Outlays
+		totalOtherOutlays += row.OtherOutlays
+	}
+	table.Footer = []string{"", "", "Total", "$" + ph.CurrStr(totalProceeds),
+		"$" + ph.CurrStr(totalAcb), "$" + ph.CurrStr(totalOutlays),
+		"$" + ph.CurrStr(totalOtherOutlays)}
+	return table
+}
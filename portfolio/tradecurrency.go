@@ -0,0 +1,168 @@
+package portfolio
+
+import (
+	"fmt"
+)
+
+// A view of a TxDelta computed entirely in the transaction (trade) currency,
+// without any exchange rates applied. This is informational only (eg. for
+// reconciling against a foreign account's statements), and is not valid for
+// Canadian tax purposes.
+type TradeCurrencyDelta struct {
+	Currency    Currency
+	TotalAcb    float64
+	CapitalGain float64
+}
+
+func normalizedTxCurrency(tx *Tx, localCurrency Currency) Currency {
+	if tx.TxCurrency == DEFAULT_CURRENCY {
+		return localCurrency
+	}
+	return tx.TxCurrency
+}
+
+// Converts amount, in tx's commission currency, to its transaction currency.
+func commissionCurrAmountInTxCurrency(
+	tx *Tx, amount float64, localCurrency Currency) (float64, error) {
+
+	if tx.CommissionCurrency == tx.TxCurrency || amount == 0.0 {
+		return amount, nil
+	}
+	return tx.LocalToTxCurr(amount*tx.CommissionCurrToLocalExchangeRate, localCurrency)
+}
+
+// Tracks the ACB of a single security in its transaction currency.
+// Superficial losses are not applied.
+type tradeCurrencyTracker struct {
+	Options      DeltaOptions
+	Currency     Currency
+	ShareBalance uint32
+	TotalAcb     float64
+}
+
+// Applies tx, and returns the capital gain (if any) in the trade currency.
+func (t *tradeCurrencyTracker) apply(tx *Tx) (float64, error) {
+	localCurrency := t.Options.LocalCurrency
+	if t.Currency == DEFAULT_CURRENCY {
+		t.Currency = normalizedTxCurrency(tx, localCurrency)
+	} else if normalizedTxCurrency(tx, localCurrency) != t.Currency {
+		return 0.0, fmt.Errorf("Transactions for %s are in multiple currencies (%s and %s)",
+			tx.Security, t.Currency, normalizedTxCurrency(tx, localCurrency))
+	}
+	commission, err := commissionCurrAmountInTxCurrency(tx, tx.Commission, localCurrency)
+	if err != nil {
+		return 0.0, err
+	}
+	outlays, err := commissionCurrAmountInTxCurrency(tx, tx.Outlays, localCurrency)
+	if err != nil {
+		return 0.0, err
+	}
+
+	var capitalGain float64 = 0.0
+	switch tx.Action {
+	case BUY:
+		t.ShareBalance += tx.Shares
+		t.TotalAcb += float64(tx.Shares)*tx.AmountPerShare + commission
+	case SELL:
+		if tx.Shares > t.ShareBalance {
+			return 0.0, fmt.Errorf("Sell of %d shares of %s exceeds the known holdings (%d)",
+				tx.Shares, tx.Security, t.ShareBalance)
+		}
+		disposedAcb := t.TotalAcb / float64(t.ShareBalance) * float64(tx.Shares)
+		proceeds := float64(tx.Shares)*tx.AmountPerShare - commission - outlays
+		capitalGain = proceeds - disposedAcb
+		t.ShareBalance -= tx.Shares
+		t.TotalAcb -= disposedAcb
+	case GIFT:
+		if tx.Shares > t.ShareBalance {
+			return 0.0, fmt.Errorf("Gift of %d shares of %s exceeds the known holdings (%d)",
+				tx.Shares, tx.Security, t.ShareBalance)
+		}
+		disposedAcb := t.TotalAcb / float64(t.ShareBalance) * float64(tx.Shares)
+		proceeds := float64(tx.Shares)*tx.AmountPerShare - commission - outlays
+		capitalGain = proceeds - disposedAcb
+		if tx.ListedSecurityDonation && capitalGain > 0.0 {
+			capitalGain = 0.0
+		}
+		t.ShareBalance -= tx.Shares
+		t.TotalAcb -= disposedAcb
+	case ROC:
+		acbReduction := tx.AmountPerShare * float64(t.ShareBalance)
+		if t.Options.DistributionFeesNetted {
+			acbReduction -= commission + outlays
+		}
+		t.TotalAcb -= acbReduction
+	case ADJUST:
+		t.TotalAcb += tx.TotalAmount
+	case ACQUIRE, OPEN:
+		t.ShareBalance += tx.Shares
+		if tx.TotalAmount != 0.0 {
+			t.TotalAcb += tx.TotalAmount
+		} else {
+			t.TotalAcb += float64(tx.Shares) * tx.AmountPerShare
+		}
+	case SPINOFF:
+		t.TotalAcb -= t.TotalAcb * tx.AcbAllocationPercent / 100.0
+	case RENAME:
+		t.ShareBalance = 0
+		t.TotalAcb = 0.0
+	case SPLIT:
+		t.ShareBalance, _ = splitShares(t.ShareBalance, tx.Ratio)
+	default:
+		// No effect
+	}
+	return capitalGain, nil
+}
+
+// Re-computes deltas (which must all be for the same security) in their
+// transaction currency. Superficial losses are not applied.
+//
+// Returns an error if the transactions are not all in the same currency, or if
+// the security had a (local currency) initial status.
+func TradeCurrencyDeltas(deltas []*TxDelta, options DeltaOptions) ([]*TradeCurrencyDelta, error) {
+	tcDeltas := make([]*TradeCurrencyDelta, 0, len(deltas))
+	if len(deltas) == 0 {
+		return tcDeltas, nil
+	}
+	if deltas[0].PreStatus.ShareBalance != 0 {
+		return nil, fmt.Errorf(
+			"Initial status for %s is only known in local currency", deltas[0].Tx.Security)
+	}
+
+	tracker := tradeCurrencyTracker{Options: options}
+	for _, d := range deltas {
+		capitalGain, err := tracker.apply(d.Tx)
+		if err != nil {
+			return nil, err
+		}
+		tcDeltas = append(tcDeltas, &TradeCurrencyDelta{
+			Currency:    tracker.Currency,
+			TotalAcb:    tracker.TotalAcb,
+			CapitalGain: capitalGain,
+		})
+	}
+	return tcDeltas, nil
+}
+
+// Computes the capital gain of the tx at idx in its trade currency, by replaying
+// all txs up to it. ok is false if this cannot be determined (including when
+// the replayed share balance does not match preTxShareBalance, which happens if
+// there was an initial status).
+func tradeCurrencyGainAt(idx int, txs []*Tx, preTxShareBalance uint32, options DeltaOptions) (
+	gain float64, currency Currency, ok bool) {
+
+	tracker := tradeCurrencyTracker{Options: options}
+	for i := 0; i < idx; i++ {
+		if _, err := tracker.apply(txs[i]); err != nil {
+			return 0.0, DEFAULT_CURRENCY, false
+		}
+	}
+	if tracker.ShareBalance != preTxShareBalance {
+		return 0.0, DEFAULT_CURRENCY, false
+	}
+	gain, err := tracker.apply(txs[idx])
+	if err != nil {
+		return 0.0, DEFAULT_CURRENCY, false
+	}
+	return gain, tracker.Currency, true
+}
@@ -2,9 +2,13 @@ package test
 
 import (
 	"fmt"
+	"io"
 	"os"
 	"strings"
+	"sync"
 	"testing"
+	"testing/iotest"
+	"time"
 
 	"github.com/stretchr/testify/require"
 
@@ -12,6 +16,7 @@ import (
 	"github.com/tsiemens/acb/fx"
 	"github.com/tsiemens/acb/log"
 	ptf "github.com/tsiemens/acb/portfolio"
+	"github.com/tsiemens/acb/util"
 )
 
 const header = "security,date,action,shares,amount/share,currency,exchange rate,commission,memo\n"
@@ -40,6 +45,34 @@ func splitCsvRows(fileLens []uint32, rows ...string) []app.DescribedReader {
 	return csvReaders
 }
 
+type CapturingErrorPrinter struct {
+	Buf strings.Builder
+}
+
+func (p *CapturingErrorPrinter) Ln(v ...interface{}) {
+	fmt.Fprintln(&p.Buf, v...)
+}
+
+func (p *CapturingErrorPrinter) F(format string, v ...interface{}) {
+	fmt.Fprintf(&p.Buf, format, v...)
+}
+
+// Runs the app on csvReaders, with the default options.
+func runDeltaModels(csvReaders []app.DescribedReader, errPrinter log.ErrorPrinter) (
+	map[string]*ptf.SecurityDeltas, error) {
+
+	return runDeltaModelsWithOptions(csvReaders, app.NewAcbRunOptions(), errPrinter)
+}
+
+func runDeltaModelsWithOptions(
+	csvReaders []app.DescribedReader, options app.AcbRunOptions,
+	errPrinter log.ErrorPrinter) (map[string]*ptf.SecurityDeltas, error) {
+
+	return app.RunAcbAppToDeltaModels(
+		csvReaders, map[string]*ptf.PortfolioSecurityStatus{}, options,
+		fx.NewMemRatesCacheAccessor(), errPrinter)
+}
+
 func getTotalCapGain(tableModel *ptf.RenderTable) string {
 	return tableModel.Footer[8]
 }
@@ -122,3 +155,1571 @@ func TestNegativeStocks(t *testing.T) {
 	rq.Equal("$0.00", getTotalCapGain(renderTable))
 
 }
+
+func TestSourceDateOverlapWarning(t *testing.T) {
+	rq := require.New(t)
+
+	// Second file overlaps the first file's date range for FOO.
+	csvReaders := splitCsvRows([]uint32{3, 3},
+		"FOO,2016-01-05,Buy,20,1.5,CAD,,0,",
+		"FOO,2016-02-05,Sell,5,1.6,CAD,,0,",
+		"FOO,2016-03-05,Buy,5,1.7,CAD,,0,",
+		"FOO,2016-02-20,Buy,5,1.7,CAD,,0,",
+		"FOO,2016-04-05,Sell,5,1.7,CAD,,0,",
+		"BAR,2016-01-05,Buy,5,1.7,CAD,,0,",
+	)
+
+	errPrinter := &CapturingErrorPrinter{}
+	_, err := app.RunAcbAppToModel(
+		csvReaders, map[string]*ptf.PortfolioSecurityStatus{},
+		false, false,
+		app.LegacyOptions{},
+		fx.NewMemRatesCacheAccessor(),
+		errPrinter,
+	)
+
+	AssertNil(t, err)
+	rq.Equal(
+		"Warning: Transactions for FOO in foo0.csv and foo1.csv have overlapping "+
+			"dates (2016-02-20 to 2016-03-05)\n",
+		errPrinter.Buf.String())
+
+	// No overlap
+	csvReaders = splitCsvRows([]uint32{2, 2},
+		"FOO,2016-01-05,Buy,20,1.5,CAD,,0,",
+		"FOO,2016-02-05,Sell,5,1.6,CAD,,0,",
+		"FOO,2016-03-05,Buy,5,1.7,CAD,,0,",
+		"FOO,2016-04-05,Sell,5,1.7,CAD,,0,",
+	)
+
+	errPrinter = &CapturingErrorPrinter{}
+	_, err = app.RunAcbAppToModel(
+		csvReaders, map[string]*ptf.PortfolioSecurityStatus{},
+		false, false,
+		app.LegacyOptions{},
+		fx.NewMemRatesCacheAccessor(),
+		errPrinter,
+	)
+
+	AssertNil(t, err)
+	rq.Equal("", errPrinter.Buf.String())
+}
+
+func TestBaselineCsvReproducesAcb(t *testing.T) {
+	rq := require.New(t)
+
+	csvReaders := splitCsvRows([]uint32{5},
+		"FOO,2016-01-05,Buy,20,1.5,CAD,,1,",
+		"FOO,2016-02-05,Sell,5,1.6,CAD,,0,",
+		"FOO,2016-03-05,Buy,7,1.73,USD,1.3,2,",
+		"BAR,2016-01-05,Buy,3,10.1,CAD,,0,",
+		"BAR,2016-05-05,Sell,3,11,CAD,,0,",
+	)
+	deltaModels, err := runDeltaModels(
+		csvReaders, &log.StderrErrorPrinter{})
+	rq.Nil(err)
+	finalStatuses := ptf.FinalStatuses(deltaModels)
+
+	var baseline strings.Builder
+	err = ptf.WriteBaselineCsv(finalStatuses, mkDate(t, 1), ptf.NewCsvOptions(), &baseline)
+	rq.Nil(err)
+	// BAR has no shares remaining, so is omitted.
+	rq.Equal(2, len(strings.Split(strings.TrimSpace(baseline.String()), "\n")))
+
+	rateLoader := fx.NewRateLoader(false, fx.NewMemRatesCacheAccessor(), &log.StderrErrorPrinter{})
+	txs, err := ptf.ParseTxCsv(
+		strings.NewReader(baseline.String()), 0, "baseline", rateLoader, ptf.NewCsvOptions())
+	rq.Nil(err)
+	rq.Equal(1, len(txs))
+
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	rq.Equal(finalStatuses["FOO"], deltas[0].PostStatus)
+}
+
+func TestConfigAliasAndDefaultCurrency(t *testing.T) {
+	rq := require.New(t)
+
+	config, err := app.LoadConfig(strings.NewReader(`{
+		"date_format": "2006/01/02",
+		"default_currency": "usd",
+		"action_aliases": {"Reinvest": "Buy"}
+	}`))
+	rq.Nil(err)
+	options := app.NewAcbRunOptions()
+	rq.Nil(config.Apply(&options, true))
+
+	csvReaders := splitCsvRows([]uint32{2},
+		"FOO,2016/01/05,Reinvest,10,2,,1.25,0,",
+		"FOO,2016/01/06,Sell,5,3,CAD,,0,",
+	)
+	deltaModels, err := runDeltaModelsWithOptions(
+		csvReaders, options, &log.StderrErrorPrinter{})
+	rq.Nil(err)
+
+	deltas := deltaModels["FOO"].Deltas
+	rq.Equal(2, len(deltas))
+	rq.Equal(ptf.BUY, deltas[0].Tx.Action)
+	rq.Equal(ptf.USD, deltas[0].Tx.TxCurrency)
+	rq.Equal(25.0, deltas[0].PostStatus.TotalAcb)
+	rq.Equal(ptf.CAD, deltas[1].Tx.TxCurrency)
+	rq.Equal(2.5, deltas[1].CapitalGain)
+
+	// Aliases must refer to a built-in action
+	config, err = app.LoadConfig(strings.NewReader(`{"action_aliases": {"foo": "bar"}}`))
+	rq.Nil(err)
+	rq.NotNil(config.Apply(&options, true))
+
+	_, err = app.LoadConfig(strings.NewReader(`{"unknown_setting": 1}`))
+	rq.NotNil(err)
+}
+
+func TestInferSettlementDates(t *testing.T) {
+	rq := require.New(t)
+
+	const tradeDateHeader = "security,trade date,date,action,shares,amount/share,commission\n"
+	makeReaders := func() []app.DescribedReader {
+		return []app.DescribedReader{{"trades.csv", strings.NewReader(tradeDateHeader +
+			// A Thursday and a Friday trade, which settle over the weekend.
+			"FOO,2021-01-07,,Buy,10,1.0,0\n" +
+			"FOO,2021-01-08,,Sell,5,1.5,0\n" +
+			"FOO,2021-01-08,2021-01-11,Sell,1,1.5,0\n")}}
+	}
+
+	_, err := runDeltaModels(
+		makeReaders(), &log.StderrErrorPrinter{})
+	rq.NotNil(err)
+
+	options := app.NewAcbRunOptions()
+	options.CsvInferSettlementDates = true
+	deltaModels, err := runDeltaModelsWithOptions(
+		makeReaders(), options, &log.StderrErrorPrinter{})
+	rq.Nil(err)
+	deltas := deltaModels["FOO"].Deltas
+	rq.Equal(3, len(deltas))
+	rq.Equal("2021-01-11", util.DateStr(deltas[0].Tx.Date))
+	// An explicit settlement date is kept, and sorts before the inferred one.
+	rq.Equal("2021-01-11", util.DateStr(deltas[1].Tx.Date))
+	rq.Equal(uint32(1), deltas[1].Tx.Shares)
+	rq.Equal("2021-01-12", util.DateStr(deltas[2].Tx.Date))
+	rq.Equal("2021-01-08", util.DateStr(deltas[2].Tx.TradeDate))
+}
+
+func TestTradeDateAsSettlement(t *testing.T) {
+	rq := require.New(t)
+
+	const tradeDateHeader = "security,trade date,date,action,shares,amount/share,commission\n"
+	makeReaders := func() []app.DescribedReader {
+		return []app.DescribedReader{{"trades.csv", strings.NewReader(tradeDateHeader +
+			"FOO,2021-01-07,,Buy,10,1.0,0\n" +
+			"FOO,2021-01-08,,Sell,5,1.5,0\n" +
+			"FOO,2021-01-08,2021-01-12,Sell,1,1.5,0\n")}}
+	}
+
+	_, err := runDeltaModels(
+		makeReaders(), &log.StderrErrorPrinter{})
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "Transaction has a trade date but no (settlement) date")
+
+	config, err := app.LoadConfig(strings.NewReader(`{"use_trade_date_as_settlement": true}`))
+	rq.Nil(err)
+	options := app.NewAcbRunOptions()
+	rq.Nil(config.Apply(&options, false))
+	// Takes precedence over inferring the date.
+	options.CsvInferSettlementDates = true
+
+	deltaModels, err := runDeltaModelsWithOptions(
+		makeReaders(), options, &log.StderrErrorPrinter{})
+	rq.Nil(err)
+	deltas := deltaModels["FOO"].Deltas
+	rq.Equal(3, len(deltas))
+	rq.Equal("2021-01-07", util.DateStr(deltas[0].Tx.Date))
+	rq.Equal("2021-01-08", util.DateStr(deltas[1].Tx.Date))
+	// An explicit settlement date is kept.
+	rq.Equal("2021-01-12", util.DateStr(deltas[2].Tx.Date))
+}
+
+func TestCsvErrorsIdentifyTx(t *testing.T) {
+	rq := require.New(t)
+
+	parse := func(rows ...string) error {
+		_, err := runDeltaModels(
+			[]app.DescribedReader{makeCsvReader("foo.csv", rows...)},
+			&log.StderrErrorPrinter{})
+		return err
+	}
+
+	// Shares are parsed after the date, and the error includes both.
+	err := parse(
+		"FOO,2016-01-05,Buy,20,1.5,CAD,,0,",
+		"BAR,2016-02-05,Buy,-5,1.6,CAD,,0,",
+	)
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "line:col 2:3")
+	rq.Contains(err.Error(), "tx #1, BAR, 2016-02-05")
+
+	// Errors after all columns are parsed
+	err = parse("FOO,2016-01-05,Buy,20,1.5,EUR,,0,")
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "tx #0, FOO, 2016-01-05")
+	rq.Contains(err.Error(), "Unsupported auto-FX for EUR")
+}
+
+func TestUsdLocalCurrency(t *testing.T) {
+	rq := require.New(t)
+
+	ratesCache := fx.NewMemRatesCacheAccessor()
+	ratesCache.WriteRates(2016, []fx.DailyRate{
+		{fxDate(2016, time.February, 10), 1.25},
+	})
+	csvReaders := splitCsvRows([]uint32{4},
+		// USD is implicitly at a rate of 1
+		"FOO,2016-01-05,Buy,10,2.0,USD,1.3,0,",
+		// CAD converted with the inverted USD/CAD rate
+		"FOO,2016-02-10,Buy,10,2.5,CAD,,0,",
+		"FOO,2016-03-05,Sell,10,3.0,CAD,0.8,0,",
+		"FOO,2016-03-05,Sell,5,2.0,,,0,",
+	)
+	options := app.NewAcbRunOptions()
+	options.LocalCurrency = ptf.USD
+	deltaModels, err := app.RunAcbAppToDeltaModels(
+		csvReaders, map[string]*ptf.PortfolioSecurityStatus{}, options,
+		ratesCache, &log.StderrErrorPrinter{})
+	rq.Nil(err)
+
+	deltas := deltaModels["FOO"].Deltas
+	rq.Empty(deltaModels["FOO"].Errors)
+	rq.Equal(4, len(deltas))
+	rq.Equal(1.0, deltas[0].Tx.TxCurrToLocalExchangeRate)
+	rq.Equal(20.0, deltas[0].PostStatus.TotalAcb)
+	AlmostEqual(t, 0.8, deltas[1].Tx.TxCurrToLocalExchangeRate)
+	AlmostEqual(t, 40.0, deltas[1].PostStatus.TotalAcb)
+	// 24 USD of proceeds, for 20 USD of ACB
+	AlmostEqual(t, 4.0, deltas[2].CapitalGain)
+	// Rows without a currency are in the local currency
+	rq.Equal(1.0, deltas[3].Tx.TxCurrToLocalExchangeRate)
+	AlmostEqual(t, 0.0, deltas[3].CapitalGain)
+}
+
+func TestQuestradeImport(t *testing.T) {
+	rq := require.New(t)
+
+	fp, err := os.Open("questrade_activity.csv")
+	rq.Nil(err)
+	defer fp.Close()
+
+	errPrinter := &CapturingErrorPrinter{}
+	csvReaders, err := app.ConvertBrokerCsvs(
+		"Questrade", []app.DescribedReader{{"questrade_activity.csv", fp}},
+		app.NewAcbRunOptions(), errPrinter)
+	rq.Nil(err)
+	// The contribution is skipped
+	rq.Contains(errPrinter.Buf.String(), "Skipping questrade_activity.csv line 2 ")
+
+	ratesCache := fx.NewMemRatesCacheAccessor()
+	ratesCache.WriteRates(2021, []fx.DailyRate{{fxDate(2021, time.February, 3), 1.28}})
+	rateLoader := fx.NewRateLoader(false, ratesCache, errPrinter)
+	txs, err := ptf.ParseTxCsv(
+		csvReaders[0].Reader, 0, csvReaders[0].Desc, rateLoader, ptf.NewCsvOptions())
+	rq.Nil(err)
+
+	vfvBuy := &ptf.Tx{Security: "VFV.TO", Date: fxDate(2021, time.January, 7),
+		TradeDate: fxDate(2021, time.January, 5), Action: ptf.BUY,
+		Shares: 20, AmountPerShare: 90.5, Commission: 4.95,
+		TxCurrency: ptf.CAD, TxCurrToLocalExchangeRate: 1.0,
+		CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0,
+		Memo: "VANGUARD S&P 500 INDEX ETF", ReadIndex: 0,
+		Source: "questrade_activity.csv"}
+	aaplBuy := &ptf.Tx{Security: "AAPL", Date: fxDate(2021, time.February, 3),
+		TradeDate: fxDate(2021, time.February, 1), Action: ptf.BUY,
+		Shares: 10, AmountPerShare: 1350.25, Commission: 4.95,
+		TxCurrency: ptf.USD, TxCurrToLocalExchangeRate: 1.28,
+		CommissionCurrency: ptf.USD, CommissionCurrToLocalExchangeRate: 1.28,
+		Memo: "APPLE INC", ReadIndex: 1,
+		Source: "questrade_activity.csv"}
+	vfvDiv := &ptf.Tx{Security: "VFV.TO", Date: fxDate(2021, time.March, 31),
+		TradeDate: fxDate(2021, time.March, 31), Action: ptf.DIV,
+		Shares: 0, TotalAmount: 6.12,
+		TxCurrency: ptf.CAD, TxCurrToLocalExchangeRate: 1.0,
+		CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0,
+		Memo: "VANGUARD S&P 500 INDEX ETF", ReadIndex: 2,
+		Source: "questrade_activity.csv"}
+	// Negative quantity on the sell
+	vfvSell := &ptf.Tx{Security: "VFV.TO", Date: fxDate(2021, time.June, 3),
+		TradeDate: fxDate(2021, time.June, 1), Action: ptf.SELL,
+		Shares: 5, AmountPerShare: 100.0, Commission: 4.95,
+		TxCurrency: ptf.CAD, TxCurrToLocalExchangeRate: 1.0,
+		CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0,
+		Memo: "VANGUARD S&P 500 INDEX ETF", ReadIndex: 3,
+		Source: "questrade_activity.csv"}
+	rq.Equal([]*ptf.Tx{vfvBuy, aaplBuy, vfvDiv, vfvSell}, txs)
+
+	_, err = app.ConvertBrokerCsvs(
+		"foo", []app.DescribedReader{}, app.NewAcbRunOptions(), errPrinter)
+	rq.NotNil(err)
+}
+
+func TestDuplicateTxWarning(t *testing.T) {
+	rq := require.New(t)
+
+	csvReaders := splitCsvRows([]uint32{2},
+		"FOO,2016-01-05,Buy,20,1.5,CAD,,0,",
+		"FOO,2016-01-05,Buy,20,1.5,CAD,,0,",
+	)
+	errPrinter := &CapturingErrorPrinter{}
+	deltaModels, err := runDeltaModels(
+		csvReaders, errPrinter)
+	rq.Nil(err)
+	rq.Equal(
+		"Warning: Transactions #0 in foo0.csv and #1 in foo0.csv (Buy 20 shares of FOO "+
+			"on 2016-01-05) may be duplicates\n",
+		errPrinter.Buf.String())
+	// Processing is not affected.
+	rq.Equal(uint32(40), deltaModels["FOO"].Deltas[1].PostStatus.ShareBalance)
+}
+
+func TestInvertedExchangeRateConvention(t *testing.T) {
+	rq := require.New(t)
+
+	options := app.NewAcbRunOptions()
+	runWithRate := func(rate string) (*ptf.TxDelta, error) {
+		csvReaders := splitCsvRows([]uint32{1},
+			"FOO,2016-01-05,Buy,20,1.5,USD,"+rate+",0,",
+		)
+		deltaModels, err := runDeltaModelsWithOptions(
+			csvReaders, options, &log.StderrErrorPrinter{})
+		if err != nil {
+			return nil, err
+		}
+		return deltaModels["FOO"].Deltas[0], nil
+	}
+
+	standardDelta, err := runWithRate("0.75")
+	rq.Nil(err)
+
+	options.CsvExchangeRateConvention = ptf.FX_FOREIGN_PER_LOCAL
+	invertedDelta, err := runWithRate("1.3333333333333333")
+	rq.Nil(err)
+	AlmostEqual(t, 22.5, standardDelta.PostStatus.TotalAcb)
+	AlmostEqual(t, standardDelta.PostStatus.TotalAcb, invertedDelta.PostStatus.TotalAcb)
+
+	_, err = runWithRate("0")
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "cannot be zero")
+
+	_, err = ptf.ParseExchangeRateConvention("backwards")
+	rq.NotNil(err)
+}
+
+func TestTxCsvReaderMatchesParseTxCsv(t *testing.T) {
+	rq := require.New(t)
+
+	contents := header + strings.Join([]string{
+		"FOO,2016-01-05,Buy,20,1.5,CAD,,1,a memo",
+		"BAR,2016-01-06,Buy,3,10.1,USD,1.3,0,",
+		"FOO,2016-02-05,Sell,5,1.6,CAD,,0,",
+	}, "\n")
+
+	rateLoader := fx.NewRateLoader(false, fx.NewMemRatesCacheAccessor(), &log.StderrErrorPrinter{})
+	expTxs, err := ptf.ParseTxCsv(
+		strings.NewReader(contents), 5, "foo.csv", rateLoader, ptf.NewCsvOptions())
+	rq.Nil(err)
+	rq.Equal(3, len(expTxs))
+
+	// Read a byte at a time, to ensure nothing depends on the whole csv being
+	// available at once.
+	txReader, err := ptf.NewTxCsvReader(
+		iotest.OneByteReader(strings.NewReader(contents)), 5, "foo.csv", rateLoader,
+		ptf.NewCsvOptions())
+	rq.Nil(err)
+	txs := []*ptf.Tx{}
+	for {
+		tx, err := txReader.Next()
+		if err == io.EOF {
+			break
+		}
+		rq.Nil(err)
+		txs = append(txs, tx)
+	}
+	rq.Equal(expTxs, txs)
+	rq.Equal(uint32(7), txs[2].ReadIndex)
+
+	_, err = ptf.NewTxCsvReader(
+		strings.NewReader(""), 0, "empty.csv", rateLoader, ptf.NewCsvOptions())
+	rq.NotNil(err)
+}
+
+func TestRunAcbSingleSecurity(t *testing.T) {
+	rq := require.New(t)
+
+	makeReaders := func() []app.DescribedReader {
+		return splitCsvRows([]uint32{4},
+			"FOO,2016-01-05,Buy,20,1.5,CAD,,1,",
+			"BAR,2016-01-06,Buy,3,10.1,CAD,,0,",
+			"FOO,2016-02-05,Sell,5,1.6,CAD,,0,",
+			"BAR,2016-05-05,Sell,3,11,CAD,,0,",
+		)
+	}
+	run := func(security string) (bool, map[string]*ptf.RenderTable) {
+		reportOptions := app.NewReportOptions()
+		reportOptions.Security = security
+		var output strings.Builder
+		return app.RunAcbAppToWriter(
+			&output, makeReaders(), map[string]*ptf.PortfolioSecurityStatus{},
+			false, false, app.LegacyOptions{}, reportOptions,
+			fx.NewMemRatesCacheAccessor(), &CapturingErrorPrinter{})
+	}
+
+	ok, allTables := run("")
+	rq.True(ok)
+	rq.Equal(2, len(allTables))
+
+	ok, barTables := run("BAR")
+	rq.True(ok)
+	rq.Equal(1, len(barTables))
+	rq.Equal(allTables["BAR"], barTables["BAR"])
+
+	ok, _ = run("BAZ")
+	rq.False(ok)
+
+	// Securities which move shares into the requested one are kept.
+	spinoff := &ptf.Tx{Security: "FOO", Action: ptf.SPINOFF, NewSecurity: "BAR"}
+	txs := []*ptf.Tx{
+		{Security: "BAZ", Action: ptf.BUY},
+		spinoff,
+		{Security: "BAR", Action: ptf.BUY},
+	}
+	rq.Equal([]*ptf.Tx{spinoff, txs[2]}, ptf.FilterTxsForSecurity(txs, "BAR"))
+	rq.Equal([]*ptf.Tx{spinoff}, ptf.FilterTxsForSecurity(txs, "FOO"))
+}
+
+func TestAcbRunOptions(t *testing.T) {
+	rq := require.New(t)
+
+	options, err := app.ParseAcbRunOptionsJson([]byte("{}"))
+	rq.Nil(err)
+	rq.Equal(app.NewAcbRunOptions(), options)
+
+	options, err = app.ParseAcbRunOptionsJson([]byte(`{
+		"render_full_values": true,
+		"refresh_fx": true,
+		"local_currency": "usd",
+		"rounding": "cents",
+		"cost_basis": "fifo",
+		"sfl_mode": "manual",
+		"allow_short": true,
+		"auto_odd_lot_sales": true,
+		"per_share_acb": true,
+		"net_distribution_fees": true,
+		"net_same_day_trades": true,
+		"fx_sanity_range": "none",
+		"check_currency_codes": false,
+		"error_on_future_dates": true,
+		"legacy": {"sort_buys_before_sells": true},
+		"report": {"output_format": "json", "year_totals": true},
+		"csv": {
+			"date_format": "dmy",
+			"delimiter": ";",
+			"decimal_comma": true,
+			"infer_settlement_dates": true,
+			"settlement_days": 1,
+			"trade_date_as_settlement": true,
+			"fx_convention": "inverted",
+			"action_aliases": {"Reinvest": "Buy"},
+			"default_currency": "usd",
+			"amount_per_share_precision": 4,
+			"fill_down_security": true,
+			"allow_commission_rebates": true
+		}
+	}`))
+	rq.Nil(err)
+	expOptions := app.NewAcbRunOptions()
+	expOptions.RenderFullDollarValues = true
+	expOptions.RefreshStaleRates = true
+	expOptions.LocalCurrency = ptf.USD
+	expOptions.Rounding = ptf.ROUND_CENTS
+	expOptions.CostBasis = ptf.FIFO_COST_BASIS
+	expOptions.SuperficialLosses = ptf.SFL_MANUAL_ONLY
+	expOptions.AllowShortSales = true
+	expOptions.AutoOddLotSales = true
+	expOptions.PerShareAcbTracking = true
+	expOptions.DistributionFeesNetted = true
+	expOptions.SameDayTradeNetting = true
+	expOptions.PlausibleExchangeRates = ptf.ExchangeRateRange{}
+	expOptions.CheckCurrencyCodes = false
+	expOptions.FutureTxDatesAreErrors = true
+	expOptions.Legacy.SortBuysBeforeSells = true
+	expOptions.Report.OutputFormat = app.OutputFormatJson
+	expOptions.Report.YearTotals = true
+	expOptions.CsvDateFormat = "02/01/2006"
+	expOptions.CsvDelimiter = ';'
+	expOptions.CsvDecimalComma = true
+	expOptions.CsvInferSettlementDates = true
+	expOptions.CsvSettlementDays = 1
+	expOptions.CsvTradeDateAsSettlement = true
+	expOptions.CsvExchangeRateConvention = ptf.FX_FOREIGN_PER_LOCAL
+	expOptions.CsvActionAliases = map[string]ptf.TxAction{"reinvest": ptf.BUY}
+	expOptions.CsvDefaultCurrency = ptf.USD
+	expOptions.CsvAmountPerSharePrecision = 4
+	expOptions.CsvFillDownSecurity = true
+	expOptions.CsvAllowNegativeCommission = true
+	rq.Equal(expOptions, options)
+
+	// The rate table is read from the contents of a rates csv.
+	options, err = app.ParseAcbRunOptionsJson([]byte(`{
+		"csv": {"rates_csv": "date,currency,rate\n2016-01-05,EUR,1.5\n"}
+	}`))
+	rq.Nil(err)
+	rq.NotNil(options.CsvRateTable)
+	rate, ok := options.CsvRateTable.GetRate("EUR", fxDate(2016, time.January, 5))
+	rq.True(ok)
+	rq.Equal(1.5, rate.ForeignToLocalRate)
+
+	_, err = app.ParseAcbRunOptionsJson([]byte(`{"export_mode": true}`))
+	rq.NotNil(err)
+	_, err = app.ParseAcbRunOptionsJson([]byte(`{"rounding": "up"}`))
+	rq.NotNil(err)
+	_, err = app.ParseAcbRunOptionsJson([]byte(`{"local_currency": " "}`))
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "local_currency cannot be empty")
+	_, err = app.ParseAcbRunOptionsJson([]byte(`{"fx_sanity_range": "3:1"}`))
+	rq.NotNil(err)
+	// A decimal comma requires another delimiter.
+	_, err = app.ParseAcbRunOptionsJson([]byte(`{"csv": {"decimal_comma": true}}`))
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "csv: ")
+
+	// Runs with different options do not affect each other, even when
+	// concurrent.
+	runWithOptions := func(options app.AcbRunOptions, contents string) (string, error) {
+		result, err := app.RunAcbAppWithOptions(
+			&strings.Builder{}, []app.DescribedReader{{"foo.csv", strings.NewReader(contents)}},
+			map[string]*ptf.PortfolioSecurityStatus{}, options,
+			fx.NewMemRatesCacheAccessor(), &CapturingErrorPrinter{})
+		if err != nil {
+			return "", err
+		}
+		return getTotalCapGain(result.RenderTables["FOO"]), nil
+	}
+	roundingOptions := app.NewAcbRunOptions()
+	roundingOptions.Rounding = ptf.ROUND_CENTS
+	roundingCsv := header +
+		"FOO,2016-01-05,Buy,3,3.333,CAD,,0,\n" +
+		"FOO,2016-02-05,Sell,1,5,CAD,,0,\n"
+	shortOptions := app.NewAcbRunOptions()
+	shortOptions.AllowShortSales = true
+	shortOptions.CsvDecimalComma = true
+	shortOptions.CsvDelimiter = ';'
+	shortCsv := "security;date;action;shares;amount/share;currency\n" +
+		"FOO;2016-01-05;Sell;2;5,5;CAD\n" +
+		"FOO;2016-02-05;Buy;2;5;CAD\n"
+
+	const nRuns = 10
+	gains := make([]string, 2*nRuns)
+	errs := make([]error, 2*nRuns)
+	var wg sync.WaitGroup
+	for i := 0; i < nRuns; i++ {
+		wg.Add(2)
+		go func(i int) {
+			defer wg.Done()
+			gains[2*i], errs[2*i] = runWithOptions(roundingOptions, roundingCsv)
+		}(i)
+		go func(i int) {
+			defer wg.Done()
+			gains[2*i+1], errs[2*i+1] = runWithOptions(shortOptions, shortCsv)
+		}(i)
+	}
+	wg.Wait()
+	for i := 0; i < nRuns; i++ {
+		rq.Nil(errs[2*i])
+		rq.Equal("$1.67", gains[2*i])
+		rq.Nil(errs[2*i+1])
+		rq.Equal("$1.00", gains[2*i+1])
+	}
+
+	// Nor do they affect later runs.
+	_, err = runWithOptions(app.NewAcbRunOptions(), shortCsv)
+	rq.NotNil(err)
+}
+
+func TestErrorCategories(t *testing.T) {
+	rq := require.New(t)
+
+	run := func(rows ...string) (*app.AcbRunResult, string, error) {
+		errPrinter := &CapturingErrorPrinter{}
+		var output strings.Builder
+		result, err := app.RunAcbAppWithOptions(
+			&output, splitCsvRows([]uint32{uint32(len(rows))}, rows...),
+			map[string]*ptf.PortfolioSecurityStatus{}, app.NewAcbRunOptions(),
+			fx.NewMemRatesCacheAccessor(), errPrinter)
+		return result, errPrinter.Buf.String(), err
+	}
+
+	// Malformed csv
+	result, errStr, err := run("FOO,2016-01-05,Buy,ten,1.0,CAD,,0,")
+	rq.NotNil(err)
+	rq.Nil(result)
+	category, ok := app.ErrorCategoryOf(err)
+	rq.True(ok)
+	rq.Equal(app.ERR_PARSE, category)
+	rq.Equal(2, app.ExitCode(err))
+	rq.Contains(errStr, "Error: ")
+
+	// RoC which exceeds the ACB. The other securities are still computed.
+	result, _, err = run(
+		"FOO,2016-01-05,Buy,10,1.0,CAD,,0,",
+		"FOO,2016-02-05,RoC,0,2.0,CAD,,0,",
+		"BAR,2016-01-05,Buy,10,1.0,CAD,,0,",
+	)
+	rq.NotNil(err)
+	rq.NotNil(result)
+	rq.Contains(result.RenderTables, "BAR")
+	category, ok = app.ErrorCategoryOf(err)
+	rq.True(ok)
+	rq.Equal(app.ERR_COMPUTATION, category)
+	rq.Equal(4, app.ExitCode(err))
+	rq.Contains(err.Error(), "Error in FOO")
+	rq.Contains(err.Error(), "exceeds the current ACB")
+
+	// Invalid options
+	options := app.NewAcbRunOptions()
+	options.Report.OutputFormat = "pdf"
+	_, err = app.RunAcbAppWithOptions(
+		&strings.Builder{}, splitCsvRows([]uint32{1}, "FOO,2016-01-05,Buy,10,1.0,CAD,,0,"),
+		map[string]*ptf.PortfolioSecurityStatus{}, options,
+		fx.NewMemRatesCacheAccessor(), &CapturingErrorPrinter{})
+	category, ok = app.ErrorCategoryOf(err)
+	rq.True(ok)
+	rq.Equal(app.ERR_VALIDATION, category)
+	rq.Equal(3, app.ExitCode(err))
+
+	// Success
+	_, _, err = run("FOO,2016-01-05,Buy,10,1.0,CAD,,0,")
+	rq.Nil(err)
+	rq.Equal(0, app.ExitCode(err))
+	rq.Equal(1, app.ExitCode(fmt.Errorf("not categorized")))
+}
+
+func TestCommissionPercent(t *testing.T) {
+	rq := require.New(t)
+
+	run := func(rows ...string) (map[string]*ptf.SecurityDeltas, error) {
+		contents := "security,date,action,shares,amount/share,currency,commission,commission %\n" +
+			strings.Join(rows, "\n")
+		return runDeltaModels(
+			[]app.DescribedReader{{"pct.csv", strings.NewReader(contents)}},
+			&log.StderrErrorPrinter{})
+	}
+
+	deltaModels, err := run(
+		"FOO,2016-01-05,Buy,10,100,CAD,,0.5%",
+		"BAR,2016-01-05,Buy,10,100,CAD,5,",
+	)
+	rq.Nil(err)
+	fooDelta := deltaModels["FOO"].Deltas[0]
+	AlmostEqual(t, 5.0, fooDelta.Tx.Commission)
+	AlmostEqual(t, 1005.0, fooDelta.PostStatus.TotalAcb)
+	AlmostEqual(t, deltaModels["BAR"].Deltas[0].PostStatus.TotalAcb, fooDelta.PostStatus.TotalAcb)
+
+	_, err = run("FOO,2016-01-05,Buy,10,100,CAD,5,0.5")
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "cannot both be specified")
+}
+
+func TestCommissionIncluded(t *testing.T) {
+	rq := require.New(t)
+
+	run := func(rows ...string) (map[string]*ptf.SecurityDeltas, error) {
+		contents := "security,date,action,shares,amount/share,currency,commission," +
+			"commission included\n" + strings.Join(rows, "\n")
+		return runDeltaModels(
+			[]app.DescribedReader{{"allin.csv", strings.NewReader(contents)}},
+			&log.StderrErrorPrinter{})
+	}
+
+	deltaModels, err := run(
+		// All-in prices, with the commission for reference
+		"FOO,2016-01-05,Buy,10,100.5,CAD,5,yes",
+		"FOO,2016-02-05,Sell,4,109.5,CAD,2,yes",
+		// Explicit commission
+		"BAR,2016-01-05,Buy,10,100,CAD,5,no",
+		"BAR,2016-02-05,Sell,4,110,CAD,2,",
+	)
+	rq.Nil(err)
+	fooDeltas := deltaModels["FOO"].Deltas
+	barDeltas := deltaModels["BAR"].Deltas
+	rq.Equal(0.0, fooDeltas[0].Tx.Commission)
+	AlmostEqual(t, 1005.0, fooDeltas[0].PostStatus.TotalAcb)
+	for i, _ := range fooDeltas {
+		AlmostEqual(t, barDeltas[i].PostStatus.TotalAcb, fooDeltas[i].PostStatus.TotalAcb)
+		AlmostEqual(t, barDeltas[i].CapitalGain, fooDeltas[i].CapitalGain)
+	}
+
+	_, err = run("FOO,2016-01-05,RoC,0,1,CAD,,yes")
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "Commission included can only be specified on a Buy or Sell")
+	_, err = run("FOO,2016-01-05,Buy,10,100,CAD,,maybe")
+	rq.NotNil(err)
+}
+
+func TestConfigSettlementDays(t *testing.T) {
+	rq := require.New(t)
+
+	// The number of days alone does not enable inference
+	config, err := app.LoadConfig(strings.NewReader(`{"settlement_days": 1}`))
+	rq.Nil(err)
+	options := app.NewAcbRunOptions()
+	rq.Nil(config.Apply(&options, true))
+	rq.False(options.CsvInferSettlementDates)
+	rq.Equal(uint32(1), options.CsvSettlementDays)
+
+	config, err = app.LoadConfig(strings.NewReader(
+		`{"infer_settlement_dates": true, "settlement_days": 1}`))
+	rq.Nil(err)
+	options = app.NewAcbRunOptions()
+	rq.Nil(config.Apply(&options, true))
+	rq.True(options.CsvInferSettlementDates)
+
+	const tradeDateHeader = "security,trade date,action,shares,amount/share\n"
+	deltaModels, err := runDeltaModelsWithOptions(
+		[]app.DescribedReader{{"trades.csv", strings.NewReader(tradeDateHeader +
+			// Thursday and Friday trades
+			"FOO,2021-01-07,Buy,10,1.0\n" +
+			"FOO,2021-01-08,Sell,5,1.5\n")}},
+		options, &log.StderrErrorPrinter{})
+	rq.Nil(err)
+	deltas := deltaModels["FOO"].Deltas
+	rq.Equal("2021-01-08", util.DateStr(deltas[0].Tx.Date))
+	rq.Equal("2021-01-11", util.DateStr(deltas[1].Tx.Date))
+}
+
+func TestOutOfSequenceTxWarning(t *testing.T) {
+	rq := require.New(t)
+
+	csvReaders := splitCsvRows([]uint32{5},
+		"FOO,2021-01-05,Buy,10,1.5,CAD,,0,",
+		// Should be 2021
+		"FOO,2012-02-05,Buy,5,1.5,CAD,,0,",
+		"BAR,2015-02-05,Buy,5,1.5,CAD,,0,",
+		"FOO,2021-03-05,Sell,5,1.6,CAD,,0,",
+		"FOO,2021-04-05,Sell,5,1.6,CAD,,0,",
+	)
+	errPrinter := &CapturingErrorPrinter{}
+	_, err := runDeltaModels(
+		csvReaders, errPrinter)
+	rq.Nil(err)
+	rq.Equal(
+		"Warning: Transaction #1 in foo0.csv (Buy FOO on 2012-02-05) is dated over a "+
+			"year apart from the FOO transactions around it. Check that its date is correct\n",
+		errPrinter.Buf.String())
+}
+
+func TestWarningRecordingErrorPrinter(t *testing.T) {
+	rq := require.New(t)
+
+	run := func(rows ...string) *log.WarningRecordingErrorPrinter {
+		errPrinter := log.NewWarningRecordingErrorPrinter(&CapturingErrorPrinter{})
+		_, err := runDeltaModels(
+			splitCsvRows([]uint32{uint32(len(rows))}, rows...),
+			errPrinter)
+		rq.Nil(err)
+		return errPrinter
+	}
+
+	errPrinter := run(
+		"FOO,2016-01-05,Buy,20,1.5,CAD,,0,",
+		"FOO,2016-01-06,Buy,20,1.5,CAD,,0,",
+	)
+	rq.False(errPrinter.SawWarning)
+
+	// Possible duplicates
+	errPrinter = run(
+		"FOO,2016-01-05,Buy,20,1.5,CAD,,0,",
+		"FOO,2016-01-05,Buy,20,1.5,CAD,,0,",
+	)
+	rq.True(errPrinter.SawWarning)
+	rq.Contains(errPrinter.ErrorPrinter.(*CapturingErrorPrinter).Buf.String(),
+		"may be duplicates")
+
+	errPrinter = log.NewWarningRecordingErrorPrinter(&CapturingErrorPrinter{})
+	errPrinter.Ln("Error:", "foo")
+	rq.False(errPrinter.SawWarning)
+	errPrinter.Ln("Warning:", "foo")
+	rq.True(errPrinter.SawWarning)
+}
+
+func TestTxSourceAttribution(t *testing.T) {
+	rq := require.New(t)
+
+	run := func(bRows ...string) (map[string]*ptf.SecurityDeltas, error) {
+		return runDeltaModels(
+			[]app.DescribedReader{
+				makeCsvReader("a.csv", "FOO,2021-01-05,Buy,10,1.5,CAD,,0,"),
+				makeCsvReader("b.csv", bRows...),
+			},
+			&CapturingErrorPrinter{})
+	}
+
+	// A parse error
+	_, err := run("FOO,2021-02-05,Sell,x,1.5,CAD,,0,")
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "Error parsing b.csv at line:col 1:3")
+
+	// An error computing the deltas
+	deltaModels, err := run("FOO,2021-02-05,Sell,11,1.5,CAD,,0,")
+	rq.Nil(err)
+	rq.Equal(1, len(deltaModels["FOO"].Errors))
+	rq.Contains(deltaModels["FOO"].Errors[0].Error(),
+		"is more than the current holdings (10) (read from b.csv)")
+
+	deltaModels, err = run("FOO,2021-02-05,Sell,5,1.5,CAD,,0,")
+	rq.Nil(err)
+	deltas := deltaModels["FOO"].Deltas
+	rq.Equal("a.csv", deltas[0].Tx.Source)
+	rq.Equal("#0 in a.csv", deltas[0].Tx.RefStr())
+	rq.Equal("b.csv", deltas[1].Tx.Source)
+	rq.Equal("#1 in b.csv", deltas[1].Tx.RefStr())
+}
+
+func TestImplausibleExchangeRateWarning(t *testing.T) {
+	rq := require.New(t)
+
+	csvReaders := splitCsvRows([]uint32{3},
+		"FOO,2021-01-05,Buy,10,1.5,USD,1.33,0,",
+		// Should be 1.33
+		"FOO,2021-02-05,Buy,5,1.5,USD,13.3,0,",
+		"FOO,2021-03-05,Sell,5,1.6,USD,1.3,0,",
+	)
+	errPrinter := &CapturingErrorPrinter{}
+	_, err := runDeltaModels(
+		csvReaders, errPrinter)
+	rq.Nil(err)
+	rq.Equal(
+		"Warning: The exchange rate of transaction #1 in foo0.csv (Buy FOO on 2021-02-05), "+
+			"13.3, is outside of the plausible range (0.3 to 3). Check that it is correct\n",
+		errPrinter.Buf.String())
+
+	options := app.NewAcbRunOptions()
+	options.PlausibleExchangeRates, err = ptf.ParseExchangeRateRange("none")
+	rq.Nil(err)
+	csvReaders = splitCsvRows([]uint32{1}, "FOO,2021-02-05,Buy,5,1.5,USD,13.3,0,")
+	errPrinter = &CapturingErrorPrinter{}
+	_, err = runDeltaModelsWithOptions(
+		csvReaders, options, errPrinter)
+	rq.Nil(err)
+	rq.Equal("", errPrinter.Buf.String())
+
+	rng, err := ptf.ParseExchangeRateRange("0.5:2")
+	rq.Nil(err)
+	rq.Equal(ptf.ExchangeRateRange{Min: 0.5, Max: 2.0}, rng)
+	_, err = ptf.ParseExchangeRateRange("2:0.5")
+	rq.NotNil(err)
+}
+
+func TestUnknownCurrencyWarning(t *testing.T) {
+	rq := require.New(t)
+
+	rq.True(ptf.IsKnownCurrency(ptf.USD))
+	rq.True(ptf.IsKnownCurrency(ptf.Currency("EUR")))
+	rq.True(ptf.IsKnownCurrency(ptf.DEFAULT_CURRENCY))
+	rq.False(ptf.IsKnownCurrency(ptf.Currency("USE")))
+
+	csvReaders := splitCsvRows([]uint32{3},
+		"FOO,2021-01-05,Buy,10,1.5,USD,1.33,0,",
+		// Should be USD
+		"FOO,2021-02-05,Buy,5,1.5,use,1.33,0,",
+		"FOO,2021-03-05,Sell,5,1.6,EUR,1.5,0,",
+	)
+	errPrinter := &CapturingErrorPrinter{}
+	_, err := runDeltaModels(
+		csvReaders, errPrinter)
+	rq.Nil(err)
+	rq.Equal(
+		"Warning: The currency of transaction #1 in foo0.csv (Buy FOO on 2021-02-05), "+
+			"USE, is not a known currency code. Check that it is correct\n",
+		errPrinter.Buf.String())
+
+	options := app.NewAcbRunOptions()
+	options.CheckCurrencyCodes = false
+	csvReaders = splitCsvRows([]uint32{1}, "FOO,2021-02-05,Buy,5,1.5,USE,1.33,0,")
+	errPrinter = &CapturingErrorPrinter{}
+	_, err = runDeltaModelsWithOptions(
+		csvReaders, options, errPrinter)
+	rq.Nil(err)
+	rq.Equal("", errPrinter.Buf.String())
+}
+
+func TestFutureTxWarning(t *testing.T) {
+	rq := require.New(t)
+
+	csvReaders := splitCsvRows([]uint32{2},
+		"FOO,2021-01-05,Buy,10,1.5,CAD,,0,",
+		// Should be 2024
+		"FOO,2034-02-05,Sell,5,1.6,CAD,,0,",
+	)
+	errPrinter := &CapturingErrorPrinter{}
+	_, err := runDeltaModels(
+		csvReaders, errPrinter)
+	rq.Nil(err)
+	rq.Contains(errPrinter.Buf.String(),
+		"Warning: Transaction #1 in foo0.csv (Sell FOO) is dated 2034-02-05, which is after today")
+}
+
+func TestFutureTxWithTodaysDateForTest(t *testing.T) {
+	rq := require.New(t)
+
+	defer util.SetTodaysDateForTest(time.Time{})
+	util.SetTodaysDateForTest(time.Date(2022, 6, 1, 0, 0, 0, 0, time.UTC))
+
+	options := app.NewAcbRunOptions()
+	run := func() (string, error) {
+		csvReaders := splitCsvRows([]uint32{2},
+			"FOO,2022-06-01,Buy,10,1.5,CAD,,0,",
+			"FOO,2022-06-03,Sell,5,1.6,CAD,,0,",
+		)
+		errPrinter := &CapturingErrorPrinter{}
+		_, err := runDeltaModelsWithOptions(
+			csvReaders, options, errPrinter)
+		return errPrinter.Buf.String(), err
+	}
+
+	output, err := run()
+	rq.Nil(err)
+	rq.Equal("Warning: Transaction #1 in foo0.csv (Sell FOO) is dated 2022-06-03, "+
+		"which is after today (2022-06-01)\n", output)
+
+	options.FutureTxDatesAreErrors = true
+	_, err = run()
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "Transaction #1 in foo0.csv (Sell FOO) is dated 2022-06-03")
+
+	// Today's txs are fine
+	util.SetTodaysDateForTest(time.Date(2022, 6, 3, 0, 0, 0, 0, time.UTC))
+	output, err = run()
+	rq.Nil(err)
+	rq.Equal("", output)
+
+	// As are txs traded today, which settle later
+	errPrinter := &CapturingErrorPrinter{}
+	_, err = runDeltaModelsWithOptions(
+		[]app.DescribedReader{{"trade.csv", strings.NewReader(
+			"security,date,trade date,action,shares,amount/share,currency\n" +
+				"FOO,2022-06-01,2022-05-30,Buy,10,1.5,CAD\n" +
+				"FOO,2022-06-06,2022-06-03,Sell,5,1.6,CAD\n" +
+				"FOO,2022-06-07,2022-06-04,Sell,5,1.6,CAD\n")}},
+		options, errPrinter)
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "Transaction #2 in trade.csv (Sell FOO) is dated 2022-06-04")
+	rq.NotContains(err.Error(), "#1 in trade.csv")
+}
+
+func TestSchedule3(t *testing.T) {
+	rq := require.New(t)
+
+	csvReaders := func() []app.DescribedReader {
+		return splitCsvRows([]uint32{7},
+			"FOO,2021-12-05,Buy,20,10.0,CAD,,0,",
+			"FOO,2021-12-20,Sell,2,12.0,CAD,,0,",
+			"FOO,2022-03-05,Sell,5,12.0,CAD,,1,",
+			// Partially superficial (2 of 4 shares)
+			"FOO,2022-06-05,Sell,4,8.0,CAD,,0,",
+			"FOO,2022-06-10,Buy,2,8.0,CAD,,0,",
+			"BAR,2022-01-05,Buy,10,5.0,USD,1.25,0,",
+			"BAR,2022-03-05,Sell,10,4.0,USD,1.25,0,",
+		)
+	}
+	var output strings.Builder
+	err := app.RunSchedule3ToWriter(
+		&output, csvReaders(), map[string]*ptf.PortfolioSecurityStatus{},
+		app.NewAcbRunOptions(), 2022,
+		fx.NewMemRatesCacheAccessor(), &CapturingErrorPrinter{})
+	rq.Nil(err)
+
+	deltaModels, err := runDeltaModels(
+		csvReaders(), &CapturingErrorPrinter{})
+	rq.Nil(err)
+	fooDeltas := deltaModels["FOO"].Deltas
+	barDeltas := deltaModels["BAR"].Deltas
+
+	lines := strings.Split(strings.TrimSpace(output.String()), "\n")
+	rq.Equal([]string{
+		"security,shares,proceeds of disposition,adjusted cost base," +
+			"outlays and expenses,superficial loss denied,gain (or loss)",
+		"BAR,10,50.00,62.50,0.00,0.00,-12.50",
+		"FOO,5,60.00,50.00,1.00,0.00,9.00",
+		"FOO,4,32.00,40.00,0.00,4.00,-4.00",
+	}, lines)
+	// One row per disposition in 2022, with the gains of the deltas
+	rows := ptf.Schedule3Rows(append(append([]*ptf.TxDelta{}, fooDeltas...), barDeltas...), 2022)
+	rq.Equal(3, len(rows))
+	AlmostEqual(t, barDeltas[1].CapitalGain, rows[0].Gain)
+	AlmostEqual(t, fooDeltas[2].CapitalGain, rows[1].Gain)
+	AlmostEqual(t, fooDeltas[3].CapitalGain, rows[2].Gain)
+	AlmostEqual(t, -fooDeltas[3].SuperficialLoss, rows[2].SuperficialLossDenied)
+}
+
+func TestNormalizeRoundTrip(t *testing.T) {
+	rq := require.New(t)
+
+	contents := "security,date,action,shares,amount/share,currency,exchange rate," +
+		"new security,ratio,acb allocation %\n" +
+		"FOO,2021-01-05,Buy,10,2.0,USD,1.25,,,\n" +
+		// Partially superficial
+		"FOO,2021-02-05,Sell,8,1.0,USD,1.3,,,\n" +
+		"FOO,2021-02-10,Buy,2,1.0,USD,1.3,,,\n" +
+		"FOO,2021-06-05,Spinoff,0,0,USD,1.3,BAR,0.5,20\n" +
+		"BAR,2021-09-05,Sell,2,3.0,USD,1.3,,,\n"
+	computeDeltas := func(csv string, legacyOptions app.LegacyOptions) map[string]*ptf.SecurityDeltas {
+		options := app.NewAcbRunOptions()
+		options.Legacy = legacyOptions
+		deltaModels, err := app.RunAcbAppToDeltaModels(
+			[]app.DescribedReader{{"foo.csv", strings.NewReader(csv)}},
+			map[string]*ptf.PortfolioSecurityStatus{}, options,
+			fx.NewMemRatesCacheAccessor(), &CapturingErrorPrinter{})
+		rq.Nil(err)
+		for _, deltaModel := range deltaModels {
+			rq.Empty(deltaModel.Errors)
+		}
+		return deltaModels
+	}
+	expDeltaModels := computeDeltas(contents, app.LegacyOptions{})
+	rq.NotEqual(0.0, expDeltaModels["FOO"].Deltas[1].SuperficialLoss)
+	// The BAR sale is a gain, so it specifies an explicit superficial loss of 0.
+	barTxs := ptf.MakeNormalizedTxs(expDeltaModels["BAR"].Deltas)
+	rq.Equal(ptf.SELL, barTxs[len(barTxs)-1].Action)
+	rq.Equal(0.0, *barTxs[len(barTxs)-1].SpecifiedSuperficialLoss)
+
+	var output strings.Builder
+	err := app.RunNormalizeToWriter(
+		&output, []app.DescribedReader{{"foo.csv", strings.NewReader(contents)}},
+		map[string]*ptf.PortfolioSecurityStatus{}, app.NewAcbRunOptions(),
+		fx.NewMemRatesCacheAccessor(), &CapturingErrorPrinter{})
+	rq.Nil(err)
+	// The BAR Acquire is derived from the Spinoff again.
+	rq.Equal(6, len(strings.Split(strings.TrimSpace(output.String()), "\n")))
+
+	// The superficial losses are explicit, so do not depend on the settings.
+	for _, legacyOptions := range []app.LegacyOptions{
+		app.LegacyOptions{}, app.LegacyOptions{NoSuperficialLosses: true}} {
+		deltaModels := computeDeltas(output.String(), legacyOptions)
+		rq.Equal(len(expDeltaModels), len(deltaModels))
+		for sec, expDeltaModel := range expDeltaModels {
+			deltas := deltaModels[sec].Deltas
+			rq.Equal(len(expDeltaModel.Deltas), len(deltas))
+			for i, expDelta := range expDeltaModel.Deltas {
+				rq.Equal(expDelta.Tx.Action, deltas[i].Tx.Action)
+				rq.Equal(expDelta.Tx.Date, deltas[i].Tx.Date)
+				rq.Equal(expDelta.PostStatus.ShareBalance, deltas[i].PostStatus.ShareBalance)
+				AlmostEqual(t, expDelta.PostStatus.TotalAcb, deltas[i].PostStatus.TotalAcb)
+				AlmostEqual(t, expDelta.CapitalGain, deltas[i].CapitalGain)
+				AlmostEqual(t, expDelta.SuperficialLoss, deltas[i].SuperficialLoss)
+			}
+		}
+	}
+}
+
+func TestTaxYearFilter(t *testing.T) {
+	rq := require.New(t)
+
+	csvReaders := splitCsvRows([]uint32{4},
+		"FOO,2022-06-05,Buy,10,2.0,CAD,,0,",
+		"BAR,2022-06-05,Buy,10,2.0,CAD,,0,",
+		"FOO,2023-03-05,Sell,5,1.0,CAD,,0,",
+		// Makes the 2023 sale superficial
+		"FOO,2023-03-20,Buy,5,1.0,CAD,,0,",
+	)
+	reportOptions := app.NewReportOptions()
+	reportOptions.TaxYear = 2023
+	var output strings.Builder
+	ok, renderTables := app.RunAcbAppToWriter(
+		&output, csvReaders, map[string]*ptf.PortfolioSecurityStatus{},
+		false, false, app.LegacyOptions{}, reportOptions,
+		fx.NewMemRatesCacheAccessor(), &CapturingErrorPrinter{})
+	rq.True(ok)
+
+	// BAR has no 2023 transactions
+	rq.Equal(1, len(renderTables))
+	fooTable := renderTables["FOO"]
+	rq.Equal(2, len(fooTable.Rows))
+	rq.Equal("2023-03-05", fooTable.Rows[0][1])
+	// The ACB of the sale reflects the 2022 buy
+	rq.Equal("$10.00", fooTable.Rows[0][6])
+	rq.Equal("$0.00", getTotalCapGain(fooTable))
+	rq.Contains(fooTable.Rows[0][8], "SFL -$5.00")
+}
+
+func TestSinceFilter(t *testing.T) {
+	rq := require.New(t)
+
+	csvReaders := splitCsvRows([]uint32{4},
+		"FOO,2022-06-05,Buy,10,2.0,CAD,,0,",
+		"BAR,2022-06-05,Buy,10,2.0,CAD,,0,",
+		"FOO,2023-03-05,Sell,5,1.0,CAD,,0,",
+		// Makes the (hidden) sale superficial
+		"FOO,2023-03-20,Buy,5,1.0,CAD,,0,",
+	)
+	reportOptions := app.NewReportOptions()
+	reportOptions.Since = time.Date(2023, time.March, 10, 0, 0, 0, 0, time.UTC)
+	var output strings.Builder
+	ok, renderTables := app.RunAcbAppToWriter(
+		&output, csvReaders, map[string]*ptf.PortfolioSecurityStatus{},
+		false, false, app.LegacyOptions{}, reportOptions,
+		fx.NewMemRatesCacheAccessor(), &CapturingErrorPrinter{})
+	rq.True(ok)
+
+	// BAR has no transactions since the date
+	rq.Equal(1, len(renderTables))
+	fooTable := renderTables["FOO"]
+	rq.Equal(1, len(fooTable.Rows))
+	rq.Equal("2023-03-20", fooTable.Rows[0][1])
+	// The ACB before the buy reflects the earlier buy, and the superficial loss
+	// of the earlier sale ($10 + $5)
+	rq.Equal("+$5.00", fooTable.Rows[0][10])
+	rq.Equal("$20.00", fooTable.Rows[0][11])
+	rq.Equal("10", fooTable.Rows[0][9])
+}
+
+func TestMarkdownOutput(t *testing.T) {
+	rq := require.New(t)
+
+	reportOptions := app.NewReportOptions()
+	reportOptions.OutputFormat = app.OutputFormatMarkdown
+	reportOptions.Columns = []string{"Security", "Date", "TX", "Amount", "Memo"}
+
+	runMarkdown := func(renderFullValues bool) string {
+		csvReaders := splitCsvRows([]uint32{1},
+			"FOO,2022-06-05,Buy,10,2.0,CAD,,0,a|b",
+		)
+		var output strings.Builder
+		ok, _ := app.RunAcbAppToWriter(
+			&output, csvReaders, map[string]*ptf.PortfolioSecurityStatus{},
+			false, renderFullValues, app.LegacyOptions{}, reportOptions,
+			fx.NewMemRatesCacheAccessor(), &CapturingErrorPrinter{})
+		rq.True(ok)
+		return output.String()
+	}
+
+	lines := strings.Split(runMarkdown(false), "\n")
+	rq.Equal("Transactions for FOO", lines[0])
+	rq.Equal("| Security | Date | TX | Amount | Memo |", lines[1])
+	rq.Equal("| --- | --- | --- | --- | --- |", lines[2])
+	rq.Equal("| FOO | 2022-06-05 | Buy | $20.00 | a\\|b |", lines[3])
+
+	rq.Contains(runMarkdown(true), "| FOO | 2022-06-05 | Buy | $20.000000 | a\\|b |")
+}
+
+func TestHtmlOutput(t *testing.T) {
+	rq := require.New(t)
+
+	reportOptions := app.NewReportOptions()
+	reportOptions.OutputFormat = app.OutputFormatHtml
+	csvReaders := splitCsvRows([]uint32{5},
+		"FOO,2022-06-05,Buy,10,2.0,CAD,,0,<b>",
+		"BAR,2022-06-05,Buy,10,2.0,CAD,,0,",
+		"FOO,2022-07-05,Sell,5,3.0,CAD,,0,",
+		"FOO,2023-07-05,Sell,5,3.0,CAD,,0,",
+		"BAR,2023-07-05,Sell,5,1.0,CAD,,0,",
+	)
+	var output strings.Builder
+	ok, _ := app.RunAcbAppToWriter(
+		&output, csvReaders, map[string]*ptf.PortfolioSecurityStatus{},
+		false, false, app.LegacyOptions{}, reportOptions,
+		fx.NewMemRatesCacheAccessor(), &CapturingErrorPrinter{})
+	rq.True(ok)
+	page := output.String()
+	rq.True(strings.HasPrefix(page, "<!DOCTYPE html>"))
+
+	// The annual summary, then each security
+	tbodyRowCounts := []int{}
+	for _, part := range strings.Split(page, "<tbody>")[1:] {
+		tbody := strings.Split(part, "</tbody>")[0]
+		tbodyRowCounts = append(tbodyRowCounts, strings.Count(tbody, "<tr>"))
+	}
+	rq.Equal([]int{2, 2, 3}, tbodyRowCounts)
+	rq.Equal(2, strings.Count(page, "<details open>"))
+	rq.True(strings.Index(page, "Capital gains by year") <
+		strings.Index(page, "Transactions for BAR"))
+	rq.True(strings.Index(page, "Transactions for BAR") <
+		strings.Index(page, "Transactions for FOO"))
+	rq.Contains(page, "<td>&lt;b&gt;</td>")
+	// FOO's total gain
+	rq.Contains(page, "<td>Total</td><td>$10.00</td>")
+}
+
+func TestDescendingOrder(t *testing.T) {
+	rq := require.New(t)
+
+	run := func(order string) map[string]*ptf.RenderTable {
+		reportOptions := app.NewReportOptions()
+		reportOptions.Order = order
+		reportOptions.RunningTotals = true
+		csvReaders := splitCsvRows([]uint32{5},
+			"FOO,2022-06-05,Buy,10,2.0,CAD,,0,",
+			"FOO,2022-07-05,Sell,5,3.0,CAD,,0,",
+			"FOO,2022-08-05,Sell,2,1.0,CAD,,0,",
+			"FOO,2023-07-05,Sell,3,3.0,CAD,,0,",
+			"BAR,2022-06-05,Buy,10,2.0,CAD,,0,",
+		)
+		var output strings.Builder
+		ok, renderTables := app.RunAcbAppToWriter(
+			&output, csvReaders, map[string]*ptf.PortfolioSecurityStatus{},
+			false, false, app.LegacyOptions{}, reportOptions,
+			fx.NewMemRatesCacheAccessor(), &CapturingErrorPrinter{})
+		rq.True(ok)
+		return renderTables
+	}
+
+	ascTables := run(app.OrderAscending)
+	descTables := run(app.OrderDescending)
+	ascRows := ascTables["FOO"].Rows
+	descRows := descTables["FOO"].Rows
+	rq.Equal(4, len(descRows))
+	for i, row := range ascRows {
+		rq.Equal(row, descRows[len(descRows)-1-i])
+	}
+	rq.Equal(ascTables["FOO"].Footer, descTables["FOO"].Footer)
+	// Running totals still accumulate chronologically
+	rq.Equal("2023-07-05", descRows[0][1])
+	rq.Equal(ascRows[2][14], descRows[1][14])
+	rq.Equal(ascTables["BAR"].Rows, descTables["BAR"].Rows)
+
+	// Invalid order
+	reportOptions := app.NewReportOptions()
+	reportOptions.Order = "sideways"
+	errPrinter := &CapturingErrorPrinter{}
+	ok, _ := app.RunAcbAppToWriter(
+		&strings.Builder{}, splitCsvRows([]uint32{1}, "FOO,2022-06-05,Buy,10,2.0,CAD,,0,"),
+		map[string]*ptf.PortfolioSecurityStatus{},
+		false, false, app.LegacyOptions{}, reportOptions,
+		fx.NewMemRatesCacheAccessor(), errPrinter)
+	rq.False(ok)
+	rq.Contains(errPrinter.Buf.String(), "Invalid order 'sideways'")
+}
+
+func TestExplainTx(t *testing.T) {
+	rq := require.New(t)
+
+	reportOptions := app.NewReportOptions()
+	reportOptions.ExplainTxs = []int{1, 5}
+	csvReaders := splitCsvRows([]uint32{2},
+		"FOO,2022-06-05,Buy,10,10.0,USD,1.25,0,",
+		"FOO,2022-07-05,Sell,5,12.0,USD,1.5,2,",
+	)
+	var output strings.Builder
+	errPrinter := &CapturingErrorPrinter{}
+	ok, _ := app.RunAcbAppToWriter(
+		&output, csvReaders, map[string]*ptf.PortfolioSecurityStatus{},
+		false, false, app.LegacyOptions{}, reportOptions,
+		fx.NewMemRatesCacheAccessor(), errPrinter)
+	rq.True(ok)
+
+	explanation := output.String()[strings.Index(output.String(), "\nTx #1"):]
+	rq.Equal(strings.Join([]string{
+		"",
+		"Tx #1 in foo0.csv: Sell of FOO on 2022-07-05",
+		"Before: 10 shares, total ACB 125 CAD",
+		"Proceeds: 5 shares x 12/share = 60 USD x 1.5 (exchange rate) = 90 CAD",
+		"Commission: 2 USD x 1.5 (exchange rate) = 3 CAD",
+		"ACB disposed: 5 shares x 12.5/share = 62.5 CAD",
+		"Capital gain: 90 - 3 - 0 - 62.5 = 24.5 CAD",
+		"ACB change: 125 -> 62.5 CAD",
+		"After: 5 shares, total ACB 62.5 CAD",
+	}, "\n"), strings.TrimRight(explanation, "\n"))
+
+	rq.Contains(errPrinter.Buf.String(), "Warning: No computed transaction #5 to explain")
+}
+
+func TestDateFormats(t *testing.T) {
+	rq := require.New(t)
+
+	// Ambiguous dates are read as the format says, not guessed.
+	for format, dateStr := range map[string]string{
+		"iso":        "2023-04-03",
+		"ymd":        "2023/04/03",
+		"mdy":        "04/03/2023",
+		"DMY":        "03/04/2023",
+		"Jan 2 2006": "Apr 3 2023",
+	} {
+		options := app.NewAcbRunOptions()
+		var err error
+		options.CsvDateFormat, err = ptf.ParseDateFormat(format)
+		rq.Nil(err, format)
+
+		deltaModels, err := runDeltaModelsWithOptions(
+			splitCsvRows([]uint32{1}, "FOO,"+dateStr+",Buy,10,2,CAD,,0,"),
+			options, &log.StderrErrorPrinter{})
+		rq.Nil(err, format)
+		rq.Equal("2023-04-03", util.DateStr(deltaModels["FOO"].Deltas[0].Tx.Date), format)
+	}
+
+	for _, format := range []string{"", "yyyy-mm-dd", "01/02"} {
+		_, err := ptf.ParseDateFormat(format)
+		rq.NotNil(err, format)
+	}
+
+	config, err := app.LoadConfig(strings.NewReader(`{"date_format": "mdy"}`))
+	rq.Nil(err)
+	options := app.NewAcbRunOptions()
+	rq.Nil(config.Apply(&options, true))
+	rq.Equal("01/02/2006", options.CsvDateFormat)
+	// Unless it was overridden
+	options = app.NewAcbRunOptions()
+	rq.Nil(config.Apply(&options, false))
+	rq.Equal(ptf.CsvDateFormatDefault, options.CsvDateFormat)
+
+	config, err = app.LoadConfig(strings.NewReader(`{"date_format": "bad"}`))
+	rq.Nil(err)
+	rq.NotNil(config.Apply(&options, true))
+}
+
+func TestCsvBomAndQuotedFields(t *testing.T) {
+	rq := require.New(t)
+
+	contents := "\ufeff\"security\",date,action,shares,amount/share,currency,exchange rate,commission,memo\n" +
+		"\"FOO\",2016-01-05,Buy,10,2,CAD,,0,\"first, with \"\"quotes\"\"\"\n" +
+		"FOO,2016-01-06,Sell,5,3,CAD,,0,\"multi\nline\"\n"
+	errPrinter := &CapturingErrorPrinter{}
+	deltaModels, err := runDeltaModels(
+		[]app.DescribedReader{{"excel.csv", strings.NewReader(contents)}},
+		errPrinter)
+	rq.Nil(err)
+	rq.Equal("", errPrinter.Buf.String())
+
+	deltas := deltaModels["FOO"].Deltas
+	rq.Equal(2, len(deltas))
+	rq.Equal(`first, with "quotes"`, deltas[0].Tx.Memo)
+	rq.Equal("multi\nline", deltas[1].Tx.Memo)
+	AlmostEqual(t, 5.0, deltas[1].CapitalGain)
+
+	// A file with only a BOM has no rows
+	_, err = runDeltaModels(
+		[]app.DescribedReader{{"bom.csv", strings.NewReader("\ufeff")}},
+		errPrinter)
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "No rows found in bom.csv")
+}
+
+func TestAmountPerSharePrecision(t *testing.T) {
+	rq := require.New(t)
+
+	rateLoader := fx.NewRateLoader(false, fx.NewMemRatesCacheAccessor(), &log.StderrErrorPrinter{})
+	options := ptf.NewCsvOptions()
+	parse := func() []*ptf.Tx {
+		txs, err := ptf.ParseTxCsv(strings.NewReader(header+strings.Join([]string{
+			"FOO,2016-01-05,Buy,3,3.333333333333,CAD,,0,",
+			"FOO,2016-02-05,RoC,0,0.12345678,CAD,,0,",
+		}, "\n")), 0, "foo.csv", rateLoader, options)
+		rq.Nil(err)
+		return txs
+	}
+
+	// Full precision by default
+	txs := parse()
+	rq.Equal(3.333333333333, txs[0].AmountPerShare)
+	rq.Equal(0.12345678, txs[1].AmountPerShare)
+
+	options.AmountPerSharePrecision = 4
+	txs = parse()
+	rq.Equal(3.3333, txs[0].AmountPerShare)
+	rq.Equal(0.1235, txs[1].AmountPerShare)
+
+	options.AmountPerSharePrecision = 6
+	txs = parse()
+	rq.Equal(0.123457, txs[1].AmountPerShare)
+}
+
+func TestValidateCsvRow(t *testing.T) {
+	rq := require.New(t)
+
+	cols := strings.Split(strings.TrimSpace(header), ",")
+	validate := func(row string) []*ptf.FieldError {
+		return ptf.ValidateCsvRow(cols, strings.Split(row, ","), ptf.NewCsvOptions())
+	}
+
+	rq.Nil(validate("FOO,2016-01-05,Buy,10,1.0,USD,1.3,0,memo"))
+
+	// Every invalid column is reported
+	fieldErrs := validate("FOO,2016-13-05,Purchase,ten,1.0,USD,1.3,0,memo")
+	rq.Equal(3, len(fieldErrs))
+	rq.Equal("date", fieldErrs[0].Column)
+	rq.Equal("action", fieldErrs[1].Column)
+	rq.Contains(fieldErrs[1].Error(), "Invalid action: 'Purchase'")
+	rq.Equal("shares", fieldErrs[2].Column)
+
+	// Errors in the row as a whole
+	fieldErrs = validate(",2016-01-05,Buy,10,1.0,USD,1.3,0,memo")
+	rq.Equal(1, len(fieldErrs))
+	rq.Equal("", fieldErrs[0].Column)
+	rq.Contains(fieldErrs[0].Error(), "no security")
+
+	fieldErrs = validate("FOO,2016-01-05,Buy")
+	rq.Equal(1, len(fieldErrs))
+	rq.Contains(fieldErrs[0].Error(), "Row has 3 columns, but the header has 9")
+}
+
+func TestFillDownSecurity(t *testing.T) {
+	rq := require.New(t)
+
+	rateLoader := fx.NewRateLoader(false, fx.NewMemRatesCacheAccessor(), &log.StderrErrorPrinter{})
+	csvContents := header + strings.Join([]string{
+		"FOO,2016-01-05,Buy,10,1.0,CAD,,0,",
+		",2016-01-06,Buy,10,1.1,CAD,,0,",
+		",2016-01-07,Sell,5,1.2,CAD,,0,",
+		"BAR,2016-01-08,Buy,10,2.0,CAD,,0,",
+		",2016-01-09,Sell,10,2.1,CAD,,0,",
+	}, "\n")
+
+	// Empty securities are an error by default
+	_, err := ptf.ParseTxCsv(
+		strings.NewReader(csvContents), 0, "foo.csv", rateLoader, ptf.NewCsvOptions())
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "Transaction has no security")
+
+	options := ptf.NewCsvOptions()
+	options.FillDownSecurity = true
+	txs, err := ptf.ParseTxCsv(
+		strings.NewReader(csvContents), 0, "foo.csv", rateLoader, options)
+	rq.Nil(err)
+	securities := []string{}
+	for _, tx := range txs {
+		securities = append(securities, tx.Security)
+	}
+	rq.Equal([]string{"FOO", "FOO", "FOO", "BAR", "BAR"}, securities)
+
+	// The first row must have a security.
+	_, err = ptf.ParseTxCsv(strings.NewReader(header+strings.Join([]string{
+		",2016-01-05,Buy,10,1.0,CAD,,0,",
+		"FOO,2016-01-06,Buy,10,1.1,CAD,,0,",
+	}, "\n")), 0, "foo.csv", rateLoader, options)
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "no previous row")
+}
+
+func TestNegativeCommission(t *testing.T) {
+	rq := require.New(t)
+
+	rateLoader := fx.NewRateLoader(false, fx.NewMemRatesCacheAccessor(), &log.StderrErrorPrinter{})
+	csvContents := header + strings.Join([]string{
+		"FOO,2016-01-05,Buy,10,2.0,CAD,,-1,",
+		"FOO,2016-01-06,Sell,5,3.0,CAD,,-1,",
+	}, "\n")
+
+	// Negative commissions are an error by default
+	_, err := ptf.ParseTxCsv(
+		strings.NewReader(csvContents), 0, "foo.csv", rateLoader, ptf.NewCsvOptions())
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "Commission must not be negative")
+
+	options := ptf.NewCsvOptions()
+	options.AllowNegativeCommission = true
+	txs, err := ptf.ParseTxCsv(
+		strings.NewReader(csvContents), 0, "foo.csv", rateLoader, options)
+	rq.Nil(err)
+	rq.Equal(-1.0, txs[0].Commission)
+
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	// The rebate lowers the ACB of the buy by $1
+	AlmostEqual(t, 19.0, deltas[0].PostStatus.TotalAcb)
+	// and raises the proceeds of the sell by $1
+	AlmostEqual(t, 16.0-9.5, deltas[1].CapitalGain)
+}
+
+func TestCsvRateTable(t *testing.T) {
+	rq := require.New(t)
+
+	table, err := fx.ReadCsvRateTable(strings.NewReader(strings.Join([]string{
+		"date,currency,rate",
+		"2016-01-05,usd,1.4",
+		"2016-01-05,EUR,1.5",
+		"2016-01-06,USD,1.41",
+	}, "\n")), "rates.csv", "CAD")
+	rq.Nil(err)
+	options := ptf.NewCsvOptions()
+	options.RateTable = table
+
+	// The USD rates are used in place of the automatic ones.
+	rateLoader := fx.NewRateLoader(false, fx.NewMemRatesCacheAccessor(), &log.StderrErrorPrinter{})
+	rateLoader.Overrides = table
+	_, err = ptf.ParseTxCsv(strings.NewReader(header+strings.Join([]string{
+		"FOO,2016-01-05,Buy,10,1.0,USD,,0,",
+		"FOO,2016-01-06,Buy,10,1.0,EUR,,0,",
+		"FOO,2016-01-06,Buy,10,1.0,USD,1.2,0,",
+	}, "\n")), 0, "foo.csv", rateLoader, options)
+	// There is no EUR rate on 2016-01-06.
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "EUR")
+
+	txs, err := ptf.ParseTxCsv(strings.NewReader(header+strings.Join([]string{
+		"FOO,2016-01-05,Buy,10,1.0,USD,,0,",
+		"FOO,2016-01-05,Buy,10,1.0,EUR,,0,",
+		"FOO,2016-01-06,Buy,10,1.0,USD,1.2,0,",
+	}, "\n")), 0, "foo.csv", rateLoader, options)
+	rq.Nil(err)
+	rq.Equal(1.4, txs[0].TxCurrToLocalExchangeRate)
+	rq.Equal(1.4, txs[0].CommissionCurrToLocalExchangeRate)
+	rq.Equal(1.5, txs[1].TxCurrToLocalExchangeRate)
+	// Rates in the csv take precedence
+	rq.Equal(1.2, txs[2].TxCurrToLocalExchangeRate)
+
+	_, err = fx.ReadCsvRateTable(strings.NewReader("date,currency\n2016-01-05,USD\n"),
+		"rates.csv", "CAD")
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "no rate column")
+	_, err = fx.ReadCsvRateTable(
+		strings.NewReader("date,currency,rate\n2016-01-05,USD,1.4\n2016-01-06,USD,x\n"),
+		"rates.csv", "CAD")
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "rates.csv line 3")
+
+	// With USD as the local currency, the CAD rates are inverted.
+	table, err = fx.ReadCsvRateTable(
+		strings.NewReader("date,currency,rate\n2016-01-05,CAD,0.8\n2016-01-05,USD,9\n"),
+		"rates.csv", "USD")
+	rq.Nil(err)
+	rates, err := table.GetUsdCadRates(2016)
+	rq.Nil(err)
+	rq.Equal(1, len(rates))
+	AlmostEqual(t, 1.25, rates[0].ForeignToLocalRate)
+}
+
+func TestCsvDelimiterAndDecimalComma(t *testing.T) {
+	rq := require.New(t)
+
+	rateLoader := fx.NewRateLoader(false, fx.NewMemRatesCacheAccessor(), &log.StderrErrorPrinter{})
+	expTxs, err := ptf.ParseTxCsv(strings.NewReader(header+strings.Join([]string{
+		"FOO,2016-01-05,Buy,20,1234.5,CAD,,1.25,\"a, memo\"",
+		"FOO,2016-01-06,Buy,3,10.1,USD,1.3,0,",
+		"FOO,2016-02-05,Sell,5,1600,CAD,,0,",
+	}, "\n")), 0, "foo.csv", rateLoader, ptf.NewCsvOptions())
+	rq.Nil(err)
+
+	options := ptf.NewCsvOptions()
+	options.Delimiter, err = ptf.ParseCsvDelimiter(";")
+	rq.Nil(err)
+	options.DecimalComma = true
+	txs, err := ptf.ParseTxCsv(strings.NewReader(
+		"security;date;action;shares;amount/share;currency;exchange rate;commission;memo\n"+
+			strings.Join([]string{
+				"FOO;2016-01-05;Buy;20;1.234,5;CAD;;1,25;a, memo",
+				"FOO;2016-01-06;Buy;3;10,1;USD;1,3;0;",
+				"FOO;2016-02-05;Sell;5;1.600;CAD;;0;",
+			}, "\n")), 0, "foo.csv", rateLoader, options)
+	rq.Nil(err)
+	rq.Equal(expTxs, txs)
+
+	// A decimal comma cannot be used with a comma delimiter
+	options.Delimiter = ','
+	_, err = ptf.ParseTxCsv(strings.NewReader(header+"FOO,2016-01-05,Buy,20,1,CAD,,0,"),
+		0, "foo.csv", rateLoader, options)
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "requires a delimiter other than ','")
+
+	delimiter, err := ptf.ParseCsvDelimiter("tab")
+	rq.Nil(err)
+	rq.Equal('\t', delimiter)
+	for _, str := range []string{"", ";;", "\""} {
+		_, err = ptf.ParseCsvDelimiter(str)
+		rq.NotNil(err, str)
+	}
+}
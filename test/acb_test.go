@@ -2,6 +2,7 @@ package test
 
 import (
 	"fmt"
+	"math"
 	"testing"
 	"time"
 
@@ -36,10 +37,66 @@ func AlmostEqual(t *testing.T, exp float64, actual float64) {
 	t.FailNow()
 }
 
+// An option for mkTx, which sets a field of the Tx.
+type txOpt func(tx *ptf.Tx)
+
+// Sets the commission, which is in the commission currency.
+func txCommission(commission float64) txOpt {
+	return func(tx *ptf.Tx) { tx.Commission = commission }
+}
+
+// Sets the currency and exchange rate of both the amount and the commission.
+func txCurrency(curr ptf.Currency, rate float64) txOpt {
+	return func(tx *ptf.Tx) {
+		tx.TxCurrency, tx.TxCurrToLocalExchangeRate = curr, rate
+		tx.CommissionCurrency, tx.CommissionCurrToLocalExchangeRate = curr, rate
+	}
+}
+
+func txCommissionCurrency(curr ptf.Currency, rate float64) txOpt {
+	return func(tx *ptf.Tx) {
+		tx.CommissionCurrency, tx.CommissionCurrToLocalExchangeRate = curr, rate
+	}
+}
+
+func txOutlays(outlays float64) txOpt {
+	return func(tx *ptf.Tx) { tx.Outlays = outlays }
+}
+
+func txTotalAmount(amount float64) txOpt {
+	return func(tx *ptf.Tx) { tx.TotalAmount = amount }
+}
+
+func txRatio(ratio float64) txOpt {
+	return func(tx *ptf.Tx) { tx.Ratio = ratio }
+}
+
+func txReadIndex(readIndex uint32) txOpt {
+	return func(tx *ptf.Tx) { tx.ReadIndex = readIndex }
+}
+
+// Returns a Tx in CAD, with no commission, unless changed by opts.
+func mkTx(t *testing.T, sec string, day uint32, action ptf.TxAction, shares uint32,
+	amount float64, opts ...txOpt) *ptf.Tx {
+	tx := &ptf.Tx{Security: sec, Date: mkDate(t, day), Action: action,
+		Shares: shares, AmountPerShare: amount, Commission: 0.0,
+		TxCurrency: ptf.CAD, TxCurrToLocalExchangeRate: 1.0,
+		CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0}
+	for _, opt := range opts {
+		opt(tx)
+	}
+	return tx
+}
+
 func AddTxNoErr(t *testing.T, tx *ptf.Tx, preTxStatus *ptf.PortfolioSecurityStatus) *ptf.TxDelta {
+	return AddTxWithOptionsNoErr(t, tx, preTxStatus, ptf.NewDeltaOptions())
+}
+
+func AddTxWithOptionsNoErr(
+	t *testing.T, tx *ptf.Tx, preTxStatus *ptf.PortfolioSecurityStatus,
+	options ptf.DeltaOptions) *ptf.TxDelta {
 	txs := []*ptf.Tx{tx}
-	plo := ptf.NewLegacyOptions()
-	delta, err := ptf.AddTx(0, txs, preTxStatus, plo)
+	delta, err := ptf.AddTx(0, txs, preTxStatus, options)
 	require.Nil(t, err)
 	return delta
 }
@@ -95,8 +152,8 @@ func TestBasicSellAcbErrors(t *testing.T) {
 		CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0}
 	txs := []*ptf.Tx{tx}
 
-	plo := ptf.NewLegacyOptions()
-	delta, err := ptf.AddTx(0, txs, sptf, plo)
+	options := ptf.NewDeltaOptions()
+	delta, err := ptf.AddTx(0, txs, sptf, options)
 	rq.Nil(delta)
 	rq.NotNil(err)
 }
@@ -183,12 +240,13 @@ func doTestSuperficialLosses(t *testing.T, partialLosses bool) {
 		AlmostEqual(t, gain, deltas[i].CapitalGain)
 	}
 
-	plo := ptf.LegacyOptions{
+	options := ptf.NewDeltaOptions()
+	options.Legacy = ptf.LegacyOptions{
 		NoSuperficialLosses:        false,
 		NoPartialSuperficialLosses: !partialLosses,
 	}
 
-	deltas, err = ptf.TxsToDeltaList(txs, nil, plo)
+	deltas, err = ptf.TxsToDeltaList(txs, nil, options)
 	rq.Nil(err)
 	validate(0, 10, 12.0, 0)
 	validate(1, 5, 6.0, -5)
@@ -210,7 +268,7 @@ func doTestSuperficialLosses(t *testing.T, partialLosses bool) {
 	tx3 := makeTx(100, ptf.SELL, 1, 0.2)
 	txs = []*ptf.Tx{tx0, tx1, tx2, tx3}
 
-	deltas, err = ptf.TxsToDeltaList(txs, nil, plo)
+	deltas, err = ptf.TxsToDeltaList(txs, nil, options)
 	rq.Nil(err)
 	validate(0, 10, 12.0, 0)
 	if partialLosses {
@@ -235,7 +293,7 @@ func doTestSuperficialLosses(t *testing.T, partialLosses bool) {
 	tx2 = makeTx(51, ptf.BUY, 5, 0.2)
 	txs = []*ptf.Tx{tx0, tx1, tx2}
 
-	deltas, err = ptf.TxsToDeltaList(txs, nil, plo)
+	deltas, err = ptf.TxsToDeltaList(txs, nil, options)
 	rq.Nil(err)
 	validate(0, 10, 12.0, 0)
 	validate(1, 5, 11.0, 0)
@@ -253,7 +311,7 @@ func doTestSuperficialLosses(t *testing.T, partialLosses bool) {
 	tx2 = makeTx(51, ptf.SELL, 5, 0.2)
 	txs = []*ptf.Tx{tx0, tx1, tx2}
 
-	deltas, err = ptf.TxsToDeltaList(txs, nil, plo)
+	deltas, err = ptf.TxsToDeltaList(txs, nil, options)
 	rq.Nil(err)
 	validate(0, 10, 12.0, 0)
 	validate(1, 5, 6.0, -5.0)
@@ -271,7 +329,7 @@ func doTestSuperficialLosses(t *testing.T, partialLosses bool) {
 	tx2 = makeTx(51, ptf.BUY, 25, 2.2)
 	txs = []*ptf.Tx{tx0, tx1, tx2}
 
-	deltas, err = ptf.TxsToDeltaList(txs, nil, plo)
+	deltas, err = ptf.TxsToDeltaList(txs, nil, options)
 	rq.Nil(err)
 	validate(0, 100, 302.0, 0)
 	if partialLosses {
@@ -298,7 +356,7 @@ func doTestSuperficialLosses(t *testing.T, partialLosses bool) {
 	tx4 := makeTx(50, ptf.SELL, 3, 0.2)
 	txs = []*ptf.Tx{tx0, tx1, tx2, tx3, tx4}
 
-	deltas, err = ptf.TxsToDeltaList(txs, nil, plo)
+	deltas, err = ptf.TxsToDeltaList(txs, nil, options)
 	rq.Nil(err)
 	validate(0, 10, 12.0, 0)
 	if partialLosses {
@@ -322,7 +380,7 @@ func doTestSuperficialLosses(t *testing.T, partialLosses bool) {
 	tx1 = makeTx(2, ptf.SELL, 5, 2)
 	txs = []*ptf.Tx{tx0, tx1}
 
-	deltas, err = ptf.TxsToDeltaList(txs, nil, plo)
+	deltas, err = ptf.TxsToDeltaList(txs, nil, options)
 	rq.Nil(err)
 	validate(0, 10, 12.0, 0)
 	validate(1, 5, 6.0, 4.0)
@@ -347,8 +405,8 @@ func TestBasicRocAcbErrors(t *testing.T) {
 		CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0}
 	txs := []*ptf.Tx{tx}
 
-	plo := ptf.NewLegacyOptions()
-	delta, err := ptf.AddTx(0, txs, sptf, plo)
+	options := ptf.NewDeltaOptions()
+	delta, err := ptf.AddTx(0, txs, sptf, options)
 	rq.Nil(delta)
 	rq.NotNil(err)
 
@@ -360,9 +418,42 @@ func TestBasicRocAcbErrors(t *testing.T) {
 		CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0}
 	txs = []*ptf.Tx{tx}
 
-	delta, err = ptf.AddTx(0, txs, sptf, plo)
+	delta, err = ptf.AddTx(0, txs, sptf, options)
+	rq.Nil(delta)
+	rq.NotNil(err)
+}
+
+func TestRocRoundingTolerance(t *testing.T) {
+	rq := require.New(t)
+
+	options := ptf.NewDeltaOptions()
+	addRoc := func(amount float64) (*ptf.TxDelta, error) {
+		sptf := &ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 2, TotalAcb: 20.0}
+		tx := &ptf.Tx{Security: "FOO", Date: mkDate(t, 1), Action: ptf.ROC,
+			Shares: 0, AmountPerShare: amount, Commission: 0.0,
+			TxCurrency: ptf.CAD, TxCurrToLocalExchangeRate: 1.0,
+			CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0}
+		return ptf.AddTx(0, []*ptf.Tx{tx}, sptf, options)
+	}
+
+	// Exactly the ACB
+	delta, err := addRoc(10.0)
+	rq.Nil(err)
+	rq.Equal(0.0, delta.PostStatus.TotalAcb)
+	rq.Empty(delta.Warnings)
+
+	// Exceeds the ACB by 0.004, which is assumed to be rounding
+	delta, err = addRoc(10.002)
+	rq.Nil(err)
+	rq.Equal(0.0, delta.PostStatus.TotalAcb)
+	rq.Equal(1, len(delta.Warnings))
+	rq.Contains(delta.Warnings[0], "The ACB is reduced to 0")
+
+	// Exceeds the ACB by 0.006
+	delta, err = addRoc(10.003)
 	rq.Nil(delta)
 	rq.NotNil(err)
+	rq.Contains(err.Error(), "exceeds the current ACB")
 }
 
 func TestBasicRocAcb(t *testing.T) {
@@ -395,6 +486,67 @@ func TestBasicRocAcb(t *testing.T) {
 	rq.Equal(delta.CapitalGain, 0.0)
 }
 
+func TestDistributionFeesNetted(t *testing.T) {
+	rq := require.New(t)
+
+	newStatus := func() *ptf.PortfolioSecurityStatus {
+		return &ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 2, TotalAcb: 20.0}
+	}
+
+	// By default, the fee of a distribution is ignored
+	delta := AddTxNoErr(t, mkTx(t, "FOO", 1, ptf.ROC, 0, 3.0, txCommission(2.0)), newStatus())
+	AlmostEqual(t, 14.0, delta.PostStatus.TotalAcb)
+
+	options := ptf.NewDeltaOptions()
+	options.DistributionFeesNetted = true
+
+	// $6 RoC, with a $2 fee
+	delta = AddTxWithOptionsNoErr(
+		t, mkTx(t, "FOO", 1, ptf.ROC, 0, 3.0, txCommission(2.0)), newStatus(), options)
+	AlmostEqual(t, 16.0, delta.PostStatus.TotalAcb)
+	rq.Equal(0.0, delta.CapitalGain)
+
+	// The fee is converted at the commission exchange rate
+	tx := mkTx(t, "FOO", 1, ptf.ROC, 0, 3.0, txCommission(2.0))
+	tx.CommissionCurrency = ptf.USD
+	tx.CommissionCurrToLocalExchangeRate = 1.5
+	delta = AddTxWithOptionsNoErr(t, tx, newStatus(), options)
+	AlmostEqual(t, 17.0, delta.PostStatus.TotalAcb)
+
+	// Dividend income is also net of the fee
+	tx = mkTx(t, "FOO", 1, ptf.DIV, 0, 3.0, txCommission(2.0))
+	delta = AddTxWithOptionsNoErr(t, tx, newStatus(), options)
+	AlmostEqual(t, 4.0, delta.DividendIncome)
+	AlmostEqual(t, 20.0, delta.PostStatus.TotalAcb)
+	rq.Contains(ptf.ExplainTxDelta(delta, options),
+		"Dividend income: 6 CAD, less fees of 2 = 4 CAD")
+
+	// Outlays are a fee as well
+	tx = mkTx(t, "FOO", 1, ptf.DIV, 0, 3.0, txCommission(2.0), txOutlays(1.0))
+	delta = AddTxWithOptionsNoErr(t, tx, newStatus(), options)
+	AlmostEqual(t, 3.0, delta.DividendIncome)
+
+	// The trade currency ACB and explanation of a RoC are also net of the fee
+	deltas, err := ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 2, 10.0, txCurrency(ptf.USD, 1.2)),
+		mkTx(t, "FOO", 2, ptf.ROC, 0, 3.0, txCurrency(ptf.USD, 1.2), txCommission(2.0)),
+	}, nil, options)
+	rq.Nil(err)
+	tcDeltas, err := ptf.TradeCurrencyDeltas(deltas, options)
+	rq.Nil(err)
+	AlmostEqual(t, 16.0, tcDeltas[1].TotalAcb)
+	AlmostEqual(t, 19.2, deltas[1].PostStatus.TotalAcb)
+	rq.Contains(ptf.ExplainTxDelta(deltas[1], options),
+		"Return of capital: 2 shares x 3/share = 6 USD x 1.2 (exchange rate) = 7.2 CAD, "+
+			"less fees of 2.4 = 4.8 CAD")
+
+	// The fee cannot exceed the distribution
+	tx = mkTx(t, "FOO", 1, ptf.ROC, 0, 0.5, txCommission(2.0))
+	_, err = ptf.AddTx(0, []*ptf.Tx{tx}, newStatus(), options)
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "exceeds the RoC")
+}
+
 func TestTxSortLegacySortBuysBeforeSells(t *testing.T) {
 	txs := []*ptf.Tx{
 		&ptf.Tx{Security: "FOO2", Date: mkDate(t, 2), Action: ptf.SELL, ReadIndex: 0},
@@ -412,6 +564,19 @@ func TestTxSortLegacySortBuysBeforeSells(t *testing.T) {
 
 	ptf.SortTxs(txs, true)
 	require.Equal(t, txs, expTxs)
+
+	// A Rename comes after a same-day Buy of the old security.
+	txs = []*ptf.Tx{
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 2), Action: ptf.RENAME, ReadIndex: 0},
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 2), Action: ptf.SELL, ReadIndex: 1},
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 2), Action: ptf.DIV, ReadIndex: 2},
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 2), Action: ptf.BUY, ReadIndex: 3},
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 2), Action: ptf.OPEN, ReadIndex: 4},
+	}
+	ptf.SortTxs(txs, true)
+	for i, action := range []ptf.TxAction{ptf.OPEN, ptf.BUY, ptf.DIV, ptf.SELL, ptf.RENAME} {
+		require.Equal(t, action, txs[i].Action)
+	}
 }
 
 func TestTxSort(t *testing.T) {
@@ -432,3 +597,1824 @@ func TestTxSort(t *testing.T) {
 	ptf.SortTxs(txs, false)
 	require.Equal(t, txs, expTxs)
 }
+
+func TestAcquireAcb(t *testing.T) {
+	rq := require.New(t)
+
+	// Inherit 10 shares with a specified total ACB
+	sptf := ptf.NewEmptyPortfolioSecurityStatus("FOO")
+	tx := &ptf.Tx{Security: "FOO", Date: mkDate(t, 1), Action: ptf.ACQUIRE,
+		Shares: 10, AmountPerShare: 0.0, Commission: 0.0, TotalAmount: 500.0,
+		TxCurrency: ptf.CAD, TxCurrToLocalExchangeRate: 1.0,
+		CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0}
+
+	delta := AddTxNoErr(t, tx, sptf)
+	rq.Equal(delta.PostStatus,
+		&ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 10, TotalAcb: 500.0},
+	)
+	rq.Equal(delta.CapitalGain, 0.0)
+
+	// Sell some later, with the gain based on the specified ACB
+	tx = &ptf.Tx{Security: "FOO", Date: mkDate(t, 100), Action: ptf.SELL,
+		Shares: 5, AmountPerShare: 60.0, Commission: 0.0,
+		TxCurrency: ptf.CAD, TxCurrToLocalExchangeRate: 1.0,
+		CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0}
+
+	delta = AddTxNoErr(t, tx, delta.PostStatus)
+	rq.Equal(delta.PostStatus,
+		&ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 5, TotalAcb: 250.0},
+	)
+	rq.Equal(delta.CapitalGain, 50.0)
+
+	// Per-share ACB via amount/share, with exchange rate
+	tx = &ptf.Tx{Security: "FOO", Date: mkDate(t, 1), Action: ptf.ACQUIRE,
+		Shares: 10, AmountPerShare: 20.0, Commission: 1.0,
+		TxCurrency: ptf.USD, TxCurrToLocalExchangeRate: 1.5,
+		CommissionCurrency: ptf.USD, CommissionCurrToLocalExchangeRate: 1.5}
+
+	delta = AddTxNoErr(t, tx, sptf)
+	rq.Equal(delta.PostStatus,
+		&ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 10, TotalAcb: 300.0},
+	)
+}
+
+func TestCommissionTotalsByYear(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 1, 10.0, txCommission(1.0)),
+		mkTx(t, "FOO", 2, ptf.BUY, 1, 10.0, txCommission(2.0), txCommissionCurrency(ptf.USD, 1.25)),
+		mkTx(t, "FOO", 3, ptf.SELL, 1, 10.0, txCommission(4.0), txCommissionCurrency(ptf.USD, 1.5)),
+		// Next year
+		mkTx(t, "FOO", 400, ptf.SELL, 1, 10.0, txCommission(3.0)),
+	}
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+
+	totals := ptf.CommissionTotalsByYear(deltas)
+	rq.Equal(2, len(totals))
+	AlmostEqual(t, 1.0+2.5+6.0, totals[2017])
+	AlmostEqual(t, 3.0, totals[2018])
+}
+
+func TestAnnualGainsReport(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		mkTx(t, "BAR", 50, ptf.BUY, 10, 5.0, txCommission(1.0)),
+		// Loss, which is entirely superficial
+		mkTx(t, "FOO", 100, ptf.SELL, 5, 8.0),
+		mkTx(t, "FOO", 110, ptf.BUY, 5, 8.0),
+		// 2018
+		mkTx(t, "BAR", 400, ptf.SELL, 10, 7.0, txCommission(2.0)),
+		mkTx(t, "FOO", 420, ptf.SELL, 10, 9.0),
+	}
+	deltaModels := ptf.TxsToDeltaModels(txs, nil, ptf.NewDeltaOptions())
+	deltas := make([]*ptf.TxDelta, 0)
+	for _, deltaModel := range deltaModels {
+		rq.Empty(deltaModel.Errors)
+		deltas = append(deltas, deltaModel.Deltas...)
+	}
+
+	rows := ptf.AnnualGainsReport(deltas)
+	rq.Equal(2, len(rows))
+
+	rq.Equal(2017, rows[0].Year)
+	AlmostEqual(t, 40.0, rows[0].Proceeds)
+	AlmostEqual(t, 0.0, rows[0].Outlays)
+	AlmostEqual(t, 50.0, rows[0].TotalAcbDisposed)
+	AlmostEqual(t, -10.0, rows[0].CapitalGain)
+	AlmostEqual(t, 10.0, rows[0].SuperficialLossDenied)
+	AlmostEqual(t, 0.0, rows[0].NetGain)
+
+	rq.Equal(2018, rows[1].Year)
+	AlmostEqual(t, 160.0, rows[1].Proceeds)
+	AlmostEqual(t, 2.0, rows[1].Outlays)
+	AlmostEqual(t, 151.0, rows[1].TotalAcbDisposed)
+	AlmostEqual(t, 7.0, rows[1].CapitalGain)
+	AlmostEqual(t, 0.0, rows[1].SuperficialLossDenied)
+	AlmostEqual(t, 7.0, rows[1].NetGain)
+
+	table := ptf.RenderYearGainsTable(rows, false)
+	rq.Equal([]string{"2017", "$40.00", "$0.00", "$0.00", "$50.00", "-$10.00", "$10.00",
+		"$0.00"}, table.Rows[0])
+}
+
+func TestInclusionPeriodGainsReport(t *testing.T) {
+	rq := require.New(t)
+
+	deltas, err := ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 40, 10.0),
+		mkTx(t, "FOO", 100, ptf.SELL, 10, 12.0),
+		mkTx(t, "FOO", 150, ptf.SELL, 10, 11.0),
+		// On the split date
+		mkTx(t, "FOO", 200, ptf.SELL, 10, 9.0),
+		// 2018
+		mkTx(t, "FOO", 420, ptf.SELL, 10, 15.0),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+
+	splitDate := mkDate(t, 200)
+	rows := ptf.InclusionPeriodGainsReport(deltas, splitDate)
+	rq.Equal(2, len(rows))
+	rq.Equal(2017, rows[0].Year)
+	AlmostEqual(t, 30.0, rows[0].NetGainBefore)
+	AlmostEqual(t, -10.0, rows[0].NetGainOnOrAfter)
+	rq.Equal(2018, rows[1].Year)
+	AlmostEqual(t, 0.0, rows[1].NetGainBefore)
+	AlmostEqual(t, 50.0, rows[1].NetGainOnOrAfter)
+
+	table := ptf.RenderInclusionPeriodGainsTable(rows, splitDate, false)
+	rq.Equal([]string{"Year", "Net Gain Before 2017-07-20", "Net Gain On/After 2017-07-20"},
+		table.Header)
+	rq.Equal([]string{"2017", "$30.00", "-$10.00"}, table.Rows[0])
+
+	table = ptf.RenderInclusionPeriodGainsTable(rows, ptf.DefaultInclusionSplitDate, false)
+	rq.Equal("Net Gain Before 2024-06-25", table.Header[1])
+}
+
+func TestCurrentHoldings(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		mkTx(t, "BAR", 5, ptf.BUY, 20, 5.0),
+		mkTx(t, "FOO", 50, ptf.SELL, 4, 12.0),
+		mkTx(t, "BAZ", 60, ptf.BUY, 5, 2.0),
+		mkTx(t, "BAZ", 70, ptf.SELL, 5, 3.0),
+		mkTx(t, "BAR", 100, ptf.BUY, 10, 8.0),
+	}
+	deltaModels := ptf.TxsToDeltaModels(txs, nil, ptf.NewDeltaOptions())
+	deltas := make([]*ptf.TxDelta, 0)
+	for _, deltaModel := range deltaModels {
+		rq.Empty(deltaModel.Errors)
+		deltas = append(deltas, deltaModel.Deltas...)
+	}
+
+	// After the last txs. BAZ was sold entirely.
+	rows := ptf.CurrentHoldings(deltas, time.Time{})
+	rq.Equal(2, len(rows))
+	rq.Equal("BAR", rows[0].Security)
+	rq.Equal(uint32(30), rows[0].ShareBalance)
+	AlmostEqual(t, 180.0, rows[0].TotalAcb)
+	AlmostEqual(t, 6.0, rows[0].PerShareAcb)
+	rq.Equal(mkDate(t, 100), rows[0].LastTxDate)
+	rq.Equal("FOO", rows[1].Security)
+	rq.Equal(uint32(6), rows[1].ShareBalance)
+	AlmostEqual(t, 60.0, rows[1].TotalAcb)
+
+	// Before FOO's sale and BAR's second buy, while BAZ is held
+	rows = ptf.CurrentHoldings(deltas, mkDate(t, 65))
+	rq.Equal(3, len(rows))
+	rq.Equal("BAR", rows[0].Security)
+	rq.Equal(uint32(20), rows[0].ShareBalance)
+	AlmostEqual(t, 100.0, rows[0].TotalAcb)
+	rq.Equal("BAZ", rows[1].Security)
+	rq.Equal(uint32(5), rows[1].ShareBalance)
+	rq.Equal("FOO", rows[2].Security)
+	rq.Equal(uint32(6), rows[2].ShareBalance)
+	rq.Equal(mkDate(t, 50), rows[2].LastTxDate)
+
+	// Txs on the date are included
+	rows = ptf.CurrentHoldings(deltas, mkDate(t, 5))
+	rq.Equal(2, len(rows))
+
+	table := ptf.RenderHoldingsTable(
+		ptf.CurrentHoldings(deltas, mkDate(t, 40)), ptf.ROUND_NONE, false)
+	rq.Equal([][]string{
+		{"BAR", "20", "$100.00", "$5.00", "2017-01-06"},
+		{"FOO", "10", "$100.00", "$10.00", "2017-01-02"},
+	}, table.Rows)
+	rq.Equal("$200.00", table.Footer[2])
+}
+
+func TestSummarizeDeltas(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		mkTx(t, "BAR", 50, ptf.BUY, 10, 5.0, txCommission(1.0)),
+		// Loss, which is entirely superficial
+		mkTx(t, "FOO", 100, ptf.SELL, 5, 8.0),
+		mkTx(t, "FOO", 110, ptf.BUY, 5, 8.0),
+		// Loss of 4.40
+		mkTx(t, "BAR", 200, ptf.SELL, 4, 4.0),
+		// $0.50 per share on 6 shares
+		mkTx(t, "BAR", 300, ptf.DIV, 0, 0.5),
+		// Gain of 11.40
+		mkTx(t, "BAR", 400, ptf.SELL, 6, 7.0),
+		// Gain of 20
+		mkTx(t, "FOO", 420, ptf.SELL, 10, 12.0),
+	}
+	deltaModels := ptf.TxsToDeltaModels(txs, nil, ptf.NewDeltaOptions())
+	deltas := make([]*ptf.TxDelta, 0)
+	for _, deltaModel := range deltaModels {
+		rq.Empty(deltaModel.Errors)
+		deltas = append(deltas, deltaModel.Deltas...)
+	}
+
+	stats := ptf.SummarizeDeltas(deltas)
+	AlmostEqual(t, 31.4, stats.TotalCapitalGains)
+	AlmostEqual(t, 4.4, stats.TotalCapitalLosses)
+	AlmostEqual(t, 10.0, stats.TotalSuperficialLosses)
+	AlmostEqual(t, 3.0, stats.TotalDividends)
+
+	rq.Equal(ptf.DeltaSummaryStats{}, ptf.SummarizeDeltas([]*ptf.TxDelta{}))
+}
+
+func TestT5008Rows(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0, txCurrency(ptf.USD, 1.5)),
+		mkTx(t, "BAR", 10, ptf.BUY, 2, 5.0, txCurrency(ptf.USD, 1.5)),
+		mkTx(t, "FOO", 50, ptf.SELL, 3, 12.0, txCommission(1.0), txCurrency(ptf.USD, 1.5)),
+		mkTx(t, "BAR", 50, ptf.SELL, 2, 6.0, txCurrency(ptf.USD, 1.5)),
+		// Superficial loss
+		mkTx(t, "FOO", 100, ptf.SELL, 5, 8.0, txCurrency(ptf.USD, 1.5)),
+		mkTx(t, "FOO", 110, ptf.BUY, 5, 8.0, txCurrency(ptf.USD, 1.5)),
+	}
+	deltaModels := ptf.TxsToDeltaModels(txs, nil, ptf.NewDeltaOptions())
+	deltas := make([]*ptf.TxDelta, 0)
+	for _, deltaModel := range deltaModels {
+		rq.Empty(deltaModel.Errors)
+		deltas = append(deltas, deltaModel.Deltas...)
+	}
+
+	rows := ptf.T5008Rows(deltas, 0)
+	rq.Equal(3, len(rows))
+	rq.Equal(&ptf.T5008Row{Security: "BAR", SettlementDate: "2017-02-20", Quantity: 2,
+		Proceeds: 18.0, Acb: 15.0, Outlays: 0.0}, rows[0])
+	rq.Equal(&ptf.T5008Row{Security: "FOO", SettlementDate: "2017-02-20", Quantity: 3,
+		Proceeds: 54.0, Acb: 45.0, Outlays: 1.5}, rows[1])
+	// The ACB excludes the superficial loss.
+	rq.Equal("FOO", rows[2].Security)
+	rq.Equal(uint32(5), rows[2].Quantity)
+	AlmostEqual(t, 60.0, rows[2].Proceeds)
+	AlmostEqual(t, 75.0, rows[2].Acb)
+	AlmostEqual(t, -15.0, deltaModels["FOO"].Deltas[2].SuperficialLoss)
+
+	rq.Equal(0, len(ptf.T5008Rows(deltas, 2018)))
+	rq.Equal(3, len(ptf.T5008Rows(deltas, 2017)))
+}
+
+func TestOutlays(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0, txCurrency(ptf.USD, 1.5)),
+		mkTx(t, "BAR", 1, ptf.BUY, 10, 10.0, txCurrency(ptf.USD, 1.5)),
+		mkTx(t, "FOO", 50, ptf.SELL, 5, 12.0, txCommission(2.0), txCurrency(ptf.USD, 1.5)),
+		mkTx(t, "BAR", 50, ptf.SELL, 5, 12.0, txOutlays(2.0), txCurrency(ptf.USD, 1.5)),
+	}
+	deltaModels := ptf.TxsToDeltaModels(txs, nil, ptf.NewDeltaOptions())
+	deltas := make([]*ptf.TxDelta, 0)
+	for _, deltaModel := range deltaModels {
+		rq.Empty(deltaModel.Errors)
+		deltas = append(deltas, deltaModel.Deltas...)
+	}
+
+	// Outlays reduce the gain exactly like commission.
+	for _, d := range deltas {
+		if d.Tx.Action == ptf.SELL {
+			AlmostEqual(t, (5*12.0-2.0)*1.5-75.0, d.CapitalGain)
+		}
+	}
+
+	// But are reported separately
+	rows := ptf.T5008Rows(deltas, 0)
+	rq.Equal(2, len(rows))
+	rq.Equal(&ptf.T5008Row{Security: "BAR", SettlementDate: "2017-02-20", Quantity: 5,
+		Proceeds: 90.0, Acb: 75.0, Outlays: 0.0, OtherOutlays: 3.0}, rows[0])
+	rq.Equal(&ptf.T5008Row{Security: "FOO", SettlementDate: "2017-02-20", Quantity: 5,
+		Proceeds: 90.0, Acb: 75.0, Outlays: 3.0, OtherOutlays: 0.0}, rows[1])
+
+	gainsRows := ptf.AnnualGainsReport(deltas)
+	rq.Equal(1, len(gainsRows))
+	AlmostEqual(t, 3.0, gainsRows[0].Outlays)
+	AlmostEqual(t, 3.0, gainsRows[0].OtherOutlays)
+	AlmostEqual(t, 24.0, gainsRows[0].CapitalGain)
+
+	// Outlays are only valid on dispositions and distributions
+	err := ptf.CheckTxSanity(
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0, txOutlays(1.0), txCurrency(ptf.USD, 1.5)))
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "Outlays can only be specified on a Sell, Gift, RoC or Div")
+	for _, action := range []ptf.TxAction{ptf.GIFT, ptf.ROC, ptf.DIV} {
+		rq.Nil(ptf.CheckTxSanity(mkTx(t, "FOO", 1, action, 10, 10.0, txOutlays(1.0))))
+	}
+}
+
+func TestGift(t *testing.T) {
+	rq := require.New(t)
+
+	// Gift with a gain, at FMV
+	gift := mkTx(t, "FOO", 50, ptf.GIFT, 4, 15.0)
+	deltas, err := ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		gift,
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	rq.Equal(uint32(6), deltas[1].PostStatus.ShareBalance)
+	AlmostEqual(t, 60.0, deltas[1].PostStatus.TotalAcb)
+	AlmostEqual(t, 20.0, deltas[1].CapitalGain)
+	AlmostEqual(t, 40.0, deltas[1].DisposedAcb())
+
+	gainsRows := ptf.AnnualGainsReport(deltas)
+	rq.Equal(1, len(gainsRows))
+	AlmostEqual(t, 60.0, gainsRows[0].Proceeds)
+	AlmostEqual(t, 20.0, gainsRows[0].CapitalGain)
+
+	// Outlays (eg. a transfer fee) reduce the gain
+	deltas, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		mkTx(t, "FOO", 50, ptf.GIFT, 4, 15.0, txOutlays(5.0)),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, 15.0, deltas[1].CapitalGain)
+	tcDeltas, err := ptf.TradeCurrencyDeltas(deltas, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, 15.0, tcDeltas[1].CapitalGain)
+
+	// Donation of listed securities. The gain is exempt, but the shares and ACB
+	// are still removed.
+	gift.ListedSecurityDonation = true
+	deltas, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		gift,
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	rq.Equal(uint32(6), deltas[1].PostStatus.ShareBalance)
+	AlmostEqual(t, 60.0, deltas[1].PostStatus.TotalAcb)
+	rq.Equal(0.0, deltas[1].CapitalGain)
+
+	// A loss is still allowed, and is not superficial.
+	gift = mkTx(t, "FOO", 50, ptf.GIFT, 4, 5.0)
+	gift.ListedSecurityDonation = true
+	deltas, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		gift,
+		mkTx(t, "FOO", 60, ptf.BUY, 4, 5.0),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, -20.0, deltas[1].CapitalGain)
+	rq.Equal(0.0, deltas[1].SuperficialLoss)
+
+	// More than the holdings
+	_, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		mkTx(t, "FOO", 50, ptf.GIFT, 11, 15.0),
+	}, nil, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+
+	// Only valid on a Gift
+	tx := mkTx(t, "FOO", 1, ptf.SELL, 10, 10.0)
+	tx.ListedSecurityDonation = true
+	err = ptf.CheckTxSanity(tx)
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "Listed security donation can only be specified on a Gift")
+}
+
+func TestFindTaxLossCandidates(t *testing.T) {
+	rq := require.New(t)
+
+	holdings := map[string]*ptf.PortfolioSecurityStatus{
+		"FOO": &ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 10, TotalAcb: 100.0},
+		"BAR": &ptf.PortfolioSecurityStatus{Security: "BAR", ShareBalance: 5, TotalAcb: 50.0},
+		"BAZ": &ptf.PortfolioSecurityStatus{Security: "BAZ", ShareBalance: 2, TotalAcb: 40.0},
+		"QUX": &ptf.PortfolioSecurityStatus{Security: "QUX", ShareBalance: 0, TotalAcb: 0.0},
+	}
+	prices := map[string]float64{
+		"FOO": 8.0,  // Loss of 20
+		"BAR": 12.0, // Gain
+		"BAZ": 15.0, // Loss of 10
+		"QUX": 1.0,
+	}
+	txs := []*ptf.Tx{
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 1), Action: ptf.BUY, Shares: 10},
+		&ptf.Tx{Security: "BAZ", Date: mkDate(t, 90), Action: ptf.BUY, Shares: 2},
+	}
+
+	candidates, totalLoss := ptf.FindTaxLossCandidates(holdings, prices, txs, mkDate(t, 100))
+	rq.Equal(2, len(candidates))
+	rq.Equal("BAZ", candidates[0].Security)
+	AlmostEqual(t, -10.0, candidates[0].UnrealizedLoss)
+	rq.True(candidates[0].PotentiallySuperficial)
+	rq.Equal("FOO", candidates[1].Security)
+	AlmostEqual(t, -20.0, candidates[1].UnrealizedLoss)
+	AlmostEqual(t, 80.0, candidates[1].MarketValue)
+	rq.False(candidates[1].PotentiallySuperficial)
+	AlmostEqual(t, -30.0, totalLoss)
+}
+
+func TestBreakEvenExchangeRate(t *testing.T) {
+	rq := require.New(t)
+
+	// 10 shares bought at 20 USD when USD/CAD was 1.25 (CAD ACB of 250)
+	status := &ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 10, TotalAcb: 250.0}
+
+	// Selling 4 at 25 USD with 1 USD commission. 99 USD of proceeds must cover
+	// 100 CAD of ACB.
+	rate, err := ptf.BreakEvenExchangeRate(status, 4, 25.0, 1.0)
+	rq.Nil(err)
+	AlmostEqual(t, 100.0/99.0, rate)
+
+	// Confirm a sell at exactly this rate has no gain.
+	txs := []*ptf.Tx{&ptf.Tx{Security: "FOO", Date: mkDate(t, 1), Action: ptf.SELL,
+		Shares: 4, AmountPerShare: 25.0, Commission: 1.0,
+		TxCurrency: ptf.USD, TxCurrToLocalExchangeRate: rate,
+		CommissionCurrency: ptf.USD, CommissionCurrToLocalExchangeRate: rate}}
+	delta, err := ptf.AddTx(0, txs, status, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, 0.0, delta.CapitalGain)
+
+	_, err = ptf.BreakEvenExchangeRate(status, 11, 25.0, 1.0)
+	rq.NotNil(err)
+	_, err = ptf.BreakEvenExchangeRate(status, 1, 1.0, 2.0)
+	rq.NotNil(err)
+}
+
+func TestFindDuplicateTxs(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 1.5, txReadIndex(0)),
+		// Near duplicates
+		mkTx(t, "FOO", 2, ptf.BUY, 10, 1.5, txReadIndex(1)),
+		mkTx(t, "FOO", 1, ptf.BUY, 11, 1.5, txReadIndex(2)),
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 1.51, txReadIndex(3)),
+		mkTx(t, "FOO", 1, ptf.SELL, 10, 1.5, txReadIndex(4)),
+		mkTx(t, "BAR", 1, ptf.BUY, 10, 1.5, txReadIndex(5)),
+	}
+	rq.Equal(0, len(ptf.FindDuplicateTxs(txs)))
+
+	txs = append(txs, mkTx(t, "FOO", 1, ptf.BUY, 10, 1.5, txReadIndex(6)),
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 1.5, txReadIndex(7)))
+	dups := ptf.FindDuplicateTxs(txs)
+	rq.Equal(2, len(dups))
+	rq.Equal(uint32(0), dups[0].First.ReadIndex)
+	rq.Equal(uint32(6), dups[0].Second.ReadIndex)
+	rq.Equal(uint32(0), dups[1].First.ReadIndex)
+	rq.Equal(uint32(7), dups[1].Second.ReadIndex)
+	rq.Equal("Transactions #0 and #6 (Buy 10 shares of FOO on 2017-01-02) may be duplicates",
+		dups[0].String())
+}
+
+func TestSelectRenderTableColumns(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 1), Action: ptf.BUY,
+			Shares: 2, AmountPerShare: 10.0, Commission: 0.0,
+			TxCurrency: ptf.CAD, TxCurrToLocalExchangeRate: 1.0,
+			CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0,
+			Memo: "a memo"},
+	}
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+
+	table := ptf.RenderTxTableModel(deltas, ptf.ROUND_NONE, false)
+	err = ptf.SelectRenderTableColumns(table, []string{"memo", "Shares", "new acb", "date"})
+	rq.Nil(err)
+	rq.Equal([]string{"Memo", "Shares", "New ACB", "Date"}, table.Header)
+	rq.Equal([][]string{[]string{"a memo", "2", "$20.00", "2017-01-02"}}, table.Rows)
+	rq.Equal([]string{"", "", "", ""}, table.Footer)
+
+	table = ptf.RenderTxTableModel(deltas, ptf.ROUND_NONE, false)
+	err = ptf.SelectRenderTableColumns(table, []string{"memo", "price"})
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "Invalid column 'price'")
+}
+
+func TestNoteTx(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 1), Action: ptf.BUY,
+			Shares: 2, AmountPerShare: 10.0, Commission: 0.0,
+			TxCurrency: ptf.CAD, TxCurrToLocalExchangeRate: 1.0,
+			CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0},
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 2), Action: ptf.NOTE,
+			TxCurrency: ptf.CAD, TxCurrToLocalExchangeRate: 1.0,
+			CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0,
+			Memo: "Transferred broker"},
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 50), Action: ptf.SELL,
+			Shares: 1, AmountPerShare: 12.0, Commission: 0.0,
+			TxCurrency: ptf.CAD, TxCurrToLocalExchangeRate: 1.0,
+			CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0},
+	}
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	rq.Equal(3, len(deltas))
+	rq.Equal(deltas[0].PostStatus, deltas[1].PostStatus)
+	rq.Equal(0.0, deltas[1].CapitalGain)
+	rq.Equal(0.0, deltas[1].AcbDelta())
+	rq.Equal(2.0, deltas[2].CapitalGain)
+
+	table := ptf.RenderTxTableModel(deltas, ptf.ROUND_NONE, false)
+	rq.Equal(3, len(table.Rows))
+	rq.Equal("Note", table.Rows[1][2])
+	rq.Equal("Transferred broker", table.Rows[1][13])
+
+	// Notes cannot have shares
+	txs[1].Shares = 1
+	_, err = ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+}
+
+func TestTxCurrConversion(t *testing.T) {
+	rq := require.New(t)
+
+	tx := &ptf.Tx{Security: "FOO", Date: mkDate(t, 1), Action: ptf.BUY,
+		TxCurrency: ptf.USD, TxCurrToLocalExchangeRate: 1.25}
+	localToTxCurr := func(amount float64) float64 {
+		converted, err := tx.LocalToTxCurr(amount, ptf.CAD)
+		rq.Nil(err)
+		return converted
+	}
+	rq.Equal(12.5, tx.TxCurrToLocal(10.0))
+	rq.Equal(10.0, localToTxCurr(12.5))
+	for _, amount := range []float64{0.0, 0.01, 1.0, 123.45, 98765.4321} {
+		AlmostEqual(t, amount, localToTxCurr(tx.TxCurrToLocal(amount)))
+		AlmostEqual(t, amount, tx.TxCurrToLocal(localToTxCurr(amount)))
+	}
+
+	// A foreign tx without an exchange rate cannot be converted
+	tx.TxCurrToLocalExchangeRate = 0.0
+	_, err := tx.LocalToTxCurr(10.0, ptf.CAD)
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "has no exchange rate")
+
+	tx.TxCurrency = ptf.CAD
+	tx.TxCurrToLocalExchangeRate = 1.0
+	rq.Equal(10.0, tx.TxCurrToLocal(10.0))
+	rq.Equal(10.0, localToTxCurr(10.0))
+	// A local currency tx is converted at 1.0, even without an exchange rate
+	tx.TxCurrToLocalExchangeRate = 0.0
+	rq.Equal(10.0, localToTxCurr(10.0))
+}
+
+func TestOpeningBalance(t *testing.T) {
+	rq := require.New(t)
+
+	open := mkTx(t, "FOO", 1, ptf.OPEN, 100, 0.0, txTotalAmount(1500.0))
+
+	deltas, err := ptf.TxsToDeltaList([]*ptf.Tx{
+		open,
+		mkTx(t, "FOO", 20, ptf.SELL, 40, 20.0),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	rq.Equal(uint32(100), deltas[0].PostStatus.ShareBalance)
+	AlmostEqual(t, 1500.0, deltas[0].PostStatus.TotalAcb)
+	rq.Equal(0.0, deltas[0].CapitalGain)
+	rq.Equal(uint32(60), deltas[1].PostStatus.ShareBalance)
+	AlmostEqual(t, 900.0, deltas[1].PostStatus.TotalAcb)
+	AlmostEqual(t, 800.0-600.0, deltas[1].CapitalGain)
+
+	// The opening balance is not an acquisition for superficial losses.
+	deltas, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		open,
+		mkTx(t, "FOO", 20, ptf.SELL, 40, 10.0),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, 400.0-600.0, deltas[1].CapitalGain)
+	rq.Equal(0.0, deltas[1].SuperficialLoss)
+
+	// Must be the first tx
+	_, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 0, ptf.BUY, 10, 10.0),
+		open,
+	}, nil, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "must be the first transaction")
+	_, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		open,
+		mkTx(t, "FOO", 2, ptf.OPEN, 10, 10.0),
+	}, nil, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+	_, err = ptf.TxsToDeltaList([]*ptf.Tx{open}, &ptf.PortfolioSecurityStatus{
+		Security: "FOO", ShareBalance: 10, TotalAcb: 100.0}, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+}
+
+func TestMergeSourceTxs(t *testing.T) {
+	rq := require.New(t)
+
+	// Both read with ReadIndexes starting at 0
+	aTxs := []*ptf.Tx{mkTx(t, "FOO", 1, ptf.BUY, 1, 1.0, txReadIndex(0)),
+		mkTx(t, "FOO", 2, ptf.SELL, 2, 1.0, txReadIndex(1))}
+	bTxs := []*ptf.Tx{mkTx(t, "FOO", 1, ptf.SELL, 3, 1.0, txReadIndex(0)),
+		mkTx(t, "FOO", 1, ptf.BUY, 4, 1.0, txReadIndex(1))}
+	sources := []ptf.SourceTxs{{Desc: "a.csv", Txs: aTxs}, {Desc: "b.csv", Txs: bTxs}}
+
+	// Same-date txs are ordered by file, then by row.
+	txs := ptf.MergeSourceTxs(sources, false)
+	shares := []uint32{}
+	readIndexes := []uint32{}
+	for _, tx := range txs {
+		shares = append(shares, tx.Shares)
+		readIndexes = append(readIndexes, tx.ReadIndex)
+	}
+	rq.Equal([]uint32{1, 3, 4, 2}, shares)
+	rq.Equal([]uint32{0, 2, 3, 1}, readIndexes)
+
+	// Buys first, otherwise in the same order.
+	txs = ptf.MergeSourceTxs(sources, true)
+	shares = []uint32{}
+	for _, tx := range txs {
+		shares = append(shares, tx.Shares)
+	}
+	rq.Equal([]uint32{1, 4, 3, 2}, shares)
+}
+
+func TestNetSameDayTrades(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0, txCommission(1.0), txCurrency(ptf.USD, 1.3)),
+		mkTx(t, "FOO", 1, ptf.BUY, 5, 10.6, txCommission(1.0), txCurrency(ptf.USD, 1.3)),
+		mkTx(t, "FOO", 1, ptf.BUY, 7, 9.9, txCommission(0.5), txCurrency(ptf.USD, 1.3)),
+		mkTx(t, "FOO", 1, ptf.SELL, 12, 10.4, txCommission(1.0), txCurrency(ptf.USD, 1.3)),
+		mkTx(t, "FOO", 1, ptf.SELL, 3, 10.7, txCommission(1.0), txCurrency(ptf.USD, 1.3)),
+		mkTx(t, "FOO", 2, ptf.BUY, 4, 11.0, txCommission(1.0), txCurrency(ptf.USD, 1.3)),
+		mkTx(t, "FOO", 2, ptf.SELL, 6, 11.3, txCommission(1.0), txCurrency(ptf.USD, 1.3)),
+		mkTx(t, "FOO", 2, ptf.SELL, 5, 9.0, txCommission(1.0), txCurrency(ptf.USD, 1.3)),
+	}
+	netted := ptf.NetSameDayTrades(txs)
+	rq.Equal(4, len(netted))
+	rq.Equal(ptf.BUY, netted[0].Action)
+	rq.Equal(uint32(22), netted[0].Shares)
+	AlmostEqual(t, 2.5, netted[0].Commission)
+	rq.Equal("3 same-day Buys combined", netted[0].Memo)
+	AlmostEqual(t, 10*10.0+5*10.6+7*9.9, float64(netted[0].Shares)*netted[0].AmountPerShare)
+	rq.Equal(uint32(15), netted[1].Shares)
+	rq.Equal(uint32(4), netted[2].Shares)
+	rq.Equal("", netted[2].Memo)
+	rq.Equal(uint32(11), netted[3].Shares)
+	// The originals are unchanged
+	rq.Equal(uint32(10), txs[0].Shares)
+
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	nettedDeltas, err := ptf.TxsToDeltaList(netted, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+
+	finalStatus := deltas[len(deltas)-1].PostStatus
+	nettedFinalStatus := nettedDeltas[len(nettedDeltas)-1].PostStatus
+	rq.Equal(finalStatus.ShareBalance, nettedFinalStatus.ShareBalance)
+	AlmostEqual(t, finalStatus.TotalAcb, nettedFinalStatus.TotalAcb)
+	sumGains := func(deltas []*ptf.TxDelta) float64 {
+		var total float64 = 0.0
+		for _, d := range deltas {
+			total += d.CapitalGain
+		}
+		return total
+	}
+	AlmostEqual(t, sumGains(deltas), sumGains(nettedDeltas))
+
+	// Memos of the combined txs are kept
+	txs[0].Memo = "first"
+	txs[2].Memo = "third"
+	netted = ptf.NetSameDayTrades(txs)
+	rq.Equal("first; third; 3 same-day Buys combined", netted[0].Memo)
+	rq.Equal("", txs[1].Memo)
+
+	// Different exchange rates are not combined
+	txs[1].TxCurrToLocalExchangeRate = 1.31
+	rq.Equal(6, len(ptf.NetSameDayTrades(txs)))
+
+	// The total amount is kept to within rounding, even over many txs
+	txs = nil
+	for i := 0; i < 1000; i++ {
+		txs = append(txs, mkTx(t, "FOO", 1, ptf.BUY, 3, 0.1+0.07*float64(i%3)))
+	}
+	netted = ptf.NetSameDayTrades(txs)
+	rq.Equal(1, len(netted))
+	AlmostEqual(t, 334*3*0.1+333*3*0.17+333*3*0.24,
+		float64(netted[0].Shares)*netted[0].AmountPerShare)
+}
+
+func TestAcbAdjustment(t *testing.T) {
+	rq := require.New(t)
+
+	// Positive adjustment (eg. a reclassified distribution)
+	deltas, err := ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		mkTx(t, "FOO", 20, ptf.ADJUST, 0, 0.0, txTotalAmount(25.0)),
+		mkTx(t, "FOO", 40, ptf.SELL, 5, 15.0),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	rq.Equal(uint32(10), deltas[1].PostStatus.ShareBalance)
+	AlmostEqual(t, 125.0, deltas[1].PostStatus.TotalAcb)
+	AlmostEqual(t, 25.0, deltas[1].AcbDelta())
+	rq.Equal(0.0, deltas[1].CapitalGain)
+	AlmostEqual(t, 75.0-62.5, deltas[2].CapitalGain)
+
+	// Negative adjustment, in a foreign currency
+	deltas, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		mkTx(t, "FOO", 20, ptf.ADJUST, 0, 0.0, txTotalAmount(-20.0), txCurrency(ptf.USD, 1.5)),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, 70.0, deltas[1].PostStatus.TotalAcb)
+
+	// Cannot reduce the ACB below zero
+	_, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		mkTx(t, "FOO", 20, ptf.ADJUST, 0, 0.0, txTotalAmount(-100.01)),
+	}, nil, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "exceeds the current ACB")
+
+	// Shares are not allowed, and some must be held
+	_, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		mkTx(t, "FOO", 20, ptf.ADJUST, 5, 1.0),
+	}, nil, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+	_, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 20, ptf.ADJUST, 0, 0.0, txTotalAmount(10.0)),
+	}, nil, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "no shares of FOO are held")
+
+	action, err := ptf.ParseTxAction("ACB Adjustment", nil)
+	rq.Nil(err)
+	rq.Equal(ptf.ADJUST, action)
+}
+
+func TestTradeCurrencyGains(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 1), Action: ptf.BUY,
+			Shares: 10, AmountPerShare: 10.0, Commission: 1.0,
+			TxCurrency: ptf.USD, TxCurrToLocalExchangeRate: 1.2,
+			CommissionCurrency: ptf.USD, CommissionCurrToLocalExchangeRate: 1.2},
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 50), Action: ptf.SELL,
+			Shares: 5, AmountPerShare: 12.0, Commission: 0.0,
+			TxCurrency: ptf.USD, TxCurrToLocalExchangeRate: 1.3,
+			CommissionCurrency: ptf.USD, CommissionCurrToLocalExchangeRate: 1.3},
+	}
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	// CAD gain: (5 * 12 * 1.3) - (5 * 10.1 * 1.2)
+	AlmostEqual(t, 78.0-60.6, deltas[1].CapitalGain)
+
+	tcDeltas, err := ptf.TradeCurrencyDeltas(deltas, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	rq.Equal(ptf.USD, tcDeltas[1].Currency)
+	AlmostEqual(t, 60.0-50.5, tcDeltas[1].CapitalGain)
+	AlmostEqual(t, 50.5, tcDeltas[1].TotalAcb)
+
+	table := ptf.RenderTxTableModel(deltas, ptf.ROUND_NONE, false)
+	ptf.AddTradeCurrencyGainColumn(table, deltas, ptf.NewDeltaOptions(), false)
+	rq.Equal(ptf.TradeCurrencyGainHeader, table.Header[14])
+	rq.Equal("-", table.Rows[0][14])
+	rq.Equal("$9.50 USD", table.Rows[1][14])
+	rq.Equal("$17.40", table.Rows[1][8])
+
+	// Mixed currencies
+	txs[1].TxCurrency = ptf.CAD
+	txs[1].CommissionCurrency = ptf.CAD
+	deltas, err = ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	_, err = ptf.TradeCurrencyDeltas(deltas, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+	table = ptf.RenderTxTableModel(deltas, ptf.ROUND_NONE, false)
+	ptf.AddTradeCurrencyGainColumn(table, deltas, ptf.NewDeltaOptions(), false)
+	rq.Equal("-", table.Rows[1][14])
+}
+
+func TestTradeCurrencyAcb(t *testing.T) {
+	rq := require.New(t)
+
+	// Pure USD
+	deltas, err := ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0, txCurrency(ptf.USD, 1.2)),
+		mkTx(t, "FOO", 50, ptf.BUY, 10, 12.0, txCurrency(ptf.USD, 1.3)),
+		mkTx(t, "FOO", 100, ptf.SELL, 5, 15.0, txCurrency(ptf.USD, 1.25)),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+
+	table := ptf.RenderTxTableModel(deltas, ptf.ROUND_NONE, false)
+	err = ptf.AddTradeCurrencyAcbColumn(table, deltas, ptf.NewDeltaOptions(), false)
+	rq.Nil(err)
+	rq.Equal(ptf.TradeCurrencyAcbHeader, table.Header[14])
+	rq.Equal("$100.00 USD", table.Rows[0][14])
+	rq.Equal("$220.00 USD", table.Rows[1][14])
+	rq.Equal("$165.00 USD", table.Rows[2][14])
+	// The CAD ACB is still in the usual column
+	rq.Equal("$276.00", table.Rows[1][11])
+
+	// Mixed currencies
+	deltas, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0, txCurrency(ptf.USD, 1.2)),
+		mkTx(t, "FOO", 50, ptf.BUY, 10, 12.0, txCurrency(ptf.CAD, 1.0)),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+
+	table = ptf.RenderTxTableModel(deltas, ptf.ROUND_NONE, false)
+	err = ptf.AddTradeCurrencyAcbColumn(table, deltas, ptf.NewDeltaOptions(), false)
+	rq.NotNil(err)
+	rq.Equal(ptf.TradeCurrencyAcbHeader, table.Header[14])
+	rq.Equal("-", table.Rows[0][14])
+	rq.Equal("-", table.Rows[1][14])
+}
+
+func TestRunningTotals(t *testing.T) {
+	rq := require.New(t)
+
+	deltas, err := ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		// Gain of 6
+		mkTx(t, "FOO", 50, ptf.SELL, 2, 13.0),
+		// Loss of 6, of which 1/3 is superficial
+		mkTx(t, "FOO", 100, ptf.SELL, 3, 8.0),
+		mkTx(t, "FOO", 110, ptf.BUY, 1, 8.0),
+		// 2018. Gain of 10
+		mkTx(t, "FOO", 400, ptf.SELL, 1, 20.0),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+
+	table := ptf.RenderTxTableModel(deltas, ptf.ROUND_NONE, false)
+	ptf.AddRunningTotalsColumns(table, deltas, false)
+	rq.Equal(ptf.RunningTotalsHeader, table.Header[14:])
+	cumGains := []string{}
+	cumSfls := []string{}
+	for _, row := range table.Rows {
+		cumGains = append(cumGains, row[14])
+		cumSfls = append(cumSfls, row[15])
+	}
+	rq.Equal([]string{"$0.00", "$6.00", "$2.00", "$2.00", "$10.00"}, cumGains)
+	rq.Equal([]string{"$0.00", "$0.00", "$2.00", "$2.00", "$0.00"}, cumSfls)
+	rq.Equal(len(table.Header), len(table.Footer))
+}
+
+func TestSpecifiedSuperficialLoss(t *testing.T) {
+	rq := require.New(t)
+
+	sfl := -2.0
+	txs := []*ptf.Tx{
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 1), Action: ptf.BUY,
+			Shares: 10, AmountPerShare: 10.0, Commission: 0.0,
+			TxCurrency: ptf.USD, TxCurrToLocalExchangeRate: 1.0,
+			CommissionCurrency: ptf.USD, CommissionCurrToLocalExchangeRate: 1.0},
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 50), Action: ptf.SELL,
+			Shares: 5, AmountPerShare: 9.0, Commission: 0.0,
+			TxCurrency: ptf.USD, TxCurrToLocalExchangeRate: 1.0,
+			CommissionCurrency: ptf.USD, CommissionCurrToLocalExchangeRate: 1.0,
+			SpecifiedSuperficialLoss: &sfl},
+	}
+
+	// Loss of 5, of which 2 is specified as superficial.
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, -3.0, deltas[1].CapitalGain)
+	AlmostEqual(t, -2.0, deltas[1].SuperficialLoss)
+	AlmostEqual(t, 52.0, deltas[1].PostStatus.TotalAcb)
+
+	// Specified loss exceeds the actual loss
+	sfl = -6.0
+	_, err = ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "exceeds the capital loss")
+
+	// A loss in USD, but a gain in CAD due to the exchange rate.
+	sfl = -2.0
+	txs[1].TxCurrToLocalExchangeRate = 1.2
+	txs[1].CommissionCurrToLocalExchangeRate = 1.2
+	_, err = ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "is a capital gain of 4.000000 in local currency")
+	rq.Contains(err.Error(), "loss of -5.000000 USD in its trade currency")
+	rq.Contains(err.Error(), "change in exchange rate")
+
+	// No superficial loss may be specified on a gain
+	sfl = 0.0
+	deltas, err = ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, 4.0, deltas[1].CapitalGain)
+	rq.Equal(0.0, deltas[1].SuperficialLoss)
+}
+
+func TestDividendTx(t *testing.T) {
+	rq := require.New(t)
+
+	sptf := &ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 4, TotalAcb: 20.0}
+	// Per-share dividend
+	tx := &ptf.Tx{Security: "FOO", Date: mkDate(t, 1), Action: ptf.DIV,
+		Shares: 0, AmountPerShare: 0.5, Commission: 0.0,
+		TxCurrency: ptf.USD, TxCurrToLocalExchangeRate: 1.5,
+		CommissionCurrency: ptf.USD, CommissionCurrToLocalExchangeRate: 1.5}
+
+	delta := AddTxNoErr(t, tx, sptf)
+	rq.Equal(delta.PostStatus,
+		&ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 4, TotalAcb: 20.0},
+	)
+	rq.Equal(0.0, delta.CapitalGain)
+	rq.Equal(3.0, delta.DividendIncome)
+
+	// Total dividend
+	tx2 := &ptf.Tx{Security: "FOO", Date: mkDate(t, 400), Action: ptf.DIV,
+		Shares: 0, TotalAmount: 7.0,
+		TxCurrency: ptf.CAD, TxCurrToLocalExchangeRate: 1.0,
+		CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0}
+
+	delta2 := AddTxNoErr(t, tx2, sptf)
+	rq.Equal(sptf.TotalAcb, delta2.PostStatus.TotalAcb)
+	rq.Equal(7.0, delta2.DividendIncome)
+
+	totals := ptf.DividendTotalsByYear([]*ptf.TxDelta{delta, delta2})
+	rq.Equal(map[int]float64{2017: 3.0, 2018: 7.0}, totals)
+
+	// Dividends cannot have shares
+	tx.Shares = 1
+	delta, err := ptf.AddTx(0, []*ptf.Tx{tx}, sptf, ptf.NewDeltaOptions())
+	rq.Nil(delta)
+	rq.NotNil(err)
+}
+
+func TestWithholdingTax(t *testing.T) {
+	rq := require.New(t)
+
+	sptf := &ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 10, TotalAcb: 50.0}
+	tx := &ptf.Tx{Security: "FOO", Date: mkDate(t, 1), Action: ptf.DIV,
+		Shares: 0, TotalAmount: 10.0, WithholdingTax: 1.5,
+		TxCurrency: ptf.USD, TxCurrToLocalExchangeRate: 1.2,
+		CommissionCurrency: ptf.USD, CommissionCurrToLocalExchangeRate: 1.2}
+
+	delta := AddTxNoErr(t, tx, sptf)
+	rq.Equal(sptf.TotalAcb, delta.PostStatus.TotalAcb)
+	AlmostEqual(t, 12.0, delta.DividendIncome)
+	AlmostEqual(t, 1.8, delta.WithholdingTax)
+
+	// RoC reduces ACB by the gross amount, not the net of withholding.
+	tx2 := &ptf.Tx{Security: "FOO", Date: mkDate(t, 2), Action: ptf.ROC,
+		Shares: 0, AmountPerShare: 1.0, WithholdingTax: 2.0,
+		TxCurrency: ptf.CAD, TxCurrToLocalExchangeRate: 1.0,
+		CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0}
+	delta2 := AddTxNoErr(t, tx2, sptf)
+	rq.Equal(40.0, delta2.PostStatus.TotalAcb)
+	rq.Equal(2.0, delta2.WithholdingTax)
+
+	totals := ptf.WithholdingTaxTotalsByYear([]*ptf.TxDelta{delta, delta2})
+	rq.Equal(1, len(totals))
+	AlmostEqual(t, 3.8, totals[2017])
+
+	// Withholding is only valid on distributions
+	tx3 := &ptf.Tx{Security: "FOO", Date: mkDate(t, 3), Action: ptf.BUY,
+		Shares: 1, AmountPerShare: 1.0, WithholdingTax: 1.0,
+		TxCurrency: ptf.CAD, TxCurrToLocalExchangeRate: 1.0,
+		CommissionCurrency: ptf.CAD, CommissionCurrToLocalExchangeRate: 1.0}
+	rq.NotNil(ptf.CheckTxSanity(tx3))
+	tx3.Action = ptf.DIV
+	tx3.Shares = 0
+	rq.Nil(ptf.CheckTxSanity(tx3))
+
+	// Withholding cannot exceed the distribution
+	tx.WithholdingTax = 10.5
+	_, err := ptf.AddTx(0, []*ptf.Tx{tx}, sptf, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "the withholding tax (12.600000) exceeds the dividend")
+	tx2.WithholdingTax = 10.5
+	_, err = ptf.AddTx(0, []*ptf.Tx{tx2}, sptf, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "exceeds the RoC")
+}
+
+func TestSpinoff(t *testing.T) {
+	rq := require.New(t)
+
+	// Two shares of BAR are distributed for each FOO share, with 30% of the
+	// FOO ACB allocated to them.
+	spinoff := mkTx(t, "FOO", 10, ptf.SPINOFF, 0, 0.0)
+	spinoff.NewSecurity = "BAR"
+	spinoff.Ratio = 2.0
+	spinoff.AcbAllocationPercent = 30.0
+	rq.Nil(ptf.CheckTxSanity(spinoff))
+
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		mkTx(t, "BAR", 5, ptf.BUY, 5, 1.0),
+		spinoff,
+		mkTx(t, "BAR", 10, ptf.SELL, 5, 2.0),
+		mkTx(t, "FOO", 20, ptf.SELL, 5, 8.0),
+	}
+	deltaModels := ptf.TxsToDeltaModels(txs, nil, ptf.NewDeltaOptions())
+	rq.Equal(2, len(deltaModels))
+
+	fooDeltas := deltaModels["FOO"].Deltas
+	rq.Empty(deltaModels["FOO"].Errors)
+	rq.Equal(3, len(fooDeltas))
+	rq.Equal(&ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 10, TotalAcb: 70.0},
+		fooDeltas[1].PostStatus)
+	AlmostEqual(t, 5.0, fooDeltas[2].CapitalGain)
+
+	barDeltas := deltaModels["BAR"].Deltas
+	rq.Empty(deltaModels["BAR"].Errors)
+	rq.Equal(3, len(barDeltas))
+	// The derived Acquire comes before other BAR txs on the same day.
+	rq.Equal(ptf.ACQUIRE, barDeltas[1].Tx.Action)
+	rq.Equal(&ptf.PortfolioSecurityStatus{Security: "BAR", ShareBalance: 25, TotalAcb: 35.0},
+		barDeltas[1].PostStatus)
+	AlmostEqual(t, 3.0, barDeltas[2].CapitalGain)
+
+	// Total ACB is preserved across both securities.
+	AlmostEqual(t, 105.0, fooDeltas[1].PostStatus.TotalAcb+barDeltas[1].PostStatus.TotalAcb)
+
+	// A ratio which is not exact in floating point still yields whole shares.
+	spinoff.Ratio = 1.1
+	deltaModels = ptf.TxsToDeltaModels(txs, nil, ptf.NewDeltaOptions())
+	rq.Empty(deltaModels["FOO"].Errors)
+	rq.Equal(uint32(11), deltaModels["BAR"].Deltas[1].Tx.Shares)
+
+	// Fractional shares of the new security are not supported.
+	spinoff.Ratio = 0.25
+	deltaModels = ptf.TxsToDeltaModels(txs, nil, ptf.NewDeltaOptions())
+	rq.NotEmpty(deltaModels["FOO"].Errors)
+	rq.Equal(2, len(deltaModels["BAR"].Deltas))
+
+	// Spinoffs cannot be circular
+	spinoff.Ratio = 2.0
+	spinback := mkTx(t, "BAR", 15, ptf.SPINOFF, 0, 0.0)
+	spinback.NewSecurity = "FOO"
+	spinback.Ratio = 1.0
+	deltaModels = ptf.TxsToDeltaModels(
+		append([]*ptf.Tx{}, txs[0], txs[1], txs[2], txs[3], spinback, txs[4]),
+		nil, ptf.NewDeltaOptions())
+	rq.NotEmpty(deltaModels["FOO"].Errors)
+	rq.NotEmpty(deltaModels["BAR"].Errors)
+
+	spinoff.NewSecurity = "FOO"
+	rq.NotNil(ptf.CheckTxSanity(spinoff))
+}
+
+func TestRename(t *testing.T) {
+	rq := require.New(t)
+
+	rename := mkTx(t, "FB", 10, ptf.RENAME, 0, 0.0)
+	rename.NewSecurity = "META"
+	rq.Nil(ptf.CheckTxSanity(rename))
+
+	txs := []*ptf.Tx{
+		mkTx(t, "FB", 1, ptf.BUY, 10, 100.0),
+		mkTx(t, "FB", 2, ptf.SELL, 2, 110.0),
+		rename,
+		mkTx(t, "META", 20, ptf.BUY, 2, 130.0),
+		mkTx(t, "META", 40, ptf.SELL, 5, 150.0),
+	}
+	deltaModels := ptf.TxsToDeltaModels(txs, nil, ptf.NewDeltaOptions())
+
+	fbDeltas := deltaModels["FB"].Deltas
+	rq.Empty(deltaModels["FB"].Errors)
+	rq.Equal(3, len(fbDeltas))
+	rq.Equal(0.0, fbDeltas[2].CapitalGain)
+	rq.Equal(&ptf.PortfolioSecurityStatus{Security: "FB", ShareBalance: 0, TotalAcb: 0.0},
+		fbDeltas[2].PostStatus)
+
+	metaDeltas := deltaModels["META"].Deltas
+	rq.Empty(deltaModels["META"].Errors)
+	rq.Equal(3, len(metaDeltas))
+	rq.Equal(&ptf.PortfolioSecurityStatus{Security: "META", ShareBalance: 8, TotalAcb: 800.0},
+		metaDeltas[0].PostStatus)
+	rq.Equal(&ptf.PortfolioSecurityStatus{Security: "META", ShareBalance: 10, TotalAcb: 1060.0},
+		metaDeltas[1].PostStatus)
+	AlmostEqual(t, 750.0-530.0, metaDeltas[2].CapitalGain)
+
+	// A merger at 3 new shares per share held
+	rename.Ratio = 3.0
+	deltaModels = ptf.TxsToDeltaModels(txs, nil, ptf.NewDeltaOptions())
+	metaDeltas = deltaModels["META"].Deltas
+	rq.Equal(&ptf.PortfolioSecurityStatus{Security: "META", ShareBalance: 24, TotalAcb: 800.0},
+		metaDeltas[0].PostStatus)
+
+	// A ratio which is not exact in floating point still yields whole shares.
+	rename.Ratio = 1.1
+	deltaModels = ptf.TxsToDeltaModels(append([]*ptf.Tx{}, txs[0], rename),
+		nil, ptf.NewDeltaOptions())
+	rq.Empty(deltaModels["FB"].Errors)
+	rq.Equal(uint32(11), deltaModels["META"].Deltas[0].PostStatus.ShareBalance)
+	rename.Ratio = 3.0
+
+	// Nothing remains to be sold under the old name.
+	txs = append(txs, mkTx(t, "FB", 50, ptf.SELL, 1, 100.0))
+	deltaModels = ptf.TxsToDeltaModels(txs, nil, ptf.NewDeltaOptions())
+	rq.NotEmpty(deltaModels["FB"].Errors)
+
+	rename.AcbAllocationPercent = 50.0
+	rq.NotNil(ptf.CheckTxSanity(rename))
+
+	// Loss sales of the old name just before the rename, and of the new name
+	// just after, are not superficial, since no shares were bought.
+	rename = mkTx(t, "FB", 45, ptf.RENAME, 0, 0.0)
+	rename.NewSecurity = "META"
+	txs = []*ptf.Tx{
+		mkTx(t, "FB", 1, ptf.BUY, 10, 100.0),
+		mkTx(t, "FB", 40, ptf.SELL, 2, 90.0),
+		rename,
+		mkTx(t, "META", 60, ptf.SELL, 4, 80.0),
+	}
+	deltaModels = ptf.TxsToDeltaModels(txs, nil, ptf.NewDeltaOptions())
+	rq.Empty(deltaModels["FB"].Errors)
+	rq.Empty(deltaModels["META"].Errors)
+	fbDeltas = deltaModels["FB"].Deltas
+	rq.Equal(0.0, fbDeltas[1].SuperficialLoss)
+	AlmostEqual(t, -20.0, fbDeltas[1].CapitalGain)
+	metaDeltas = deltaModels["META"].Deltas
+	rq.Equal(ptf.ORIGIN_DERIVED, metaDeltas[0].Tx.Origin)
+	rq.Equal(0.0, metaDeltas[1].SuperficialLoss)
+	AlmostEqual(t, -80.0, metaDeltas[1].CapitalGain)
+}
+
+func TestSameDaySuperficialLossOrdering(t *testing.T) {
+	rq := require.New(t)
+
+	initStatus := &ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 10, TotalAcb: 100.0}
+
+	// Buy back at the existing per-share ACB, so the order does not change
+	// the per-share ACB of the sale.
+	sellFirst := []*ptf.Tx{mkTx(t, "FOO", 50, ptf.SELL, 5, 8.0),
+		mkTx(t, "FOO", 50, ptf.BUY, 5, 10.0)}
+	buyFirst := []*ptf.Tx{mkTx(t, "FOO", 50, ptf.BUY, 5, 10.0),
+		mkTx(t, "FOO", 50, ptf.SELL, 5, 8.0)}
+
+	deltasSellFirst, err := ptf.TxsToDeltaList(sellFirst, initStatus, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	deltasBuyFirst, err := ptf.TxsToDeltaList(buyFirst, initStatus, ptf.NewDeltaOptions())
+	rq.Nil(err)
+
+	sellDeltaA := deltasSellFirst[0]
+	sellDeltaB := deltasBuyFirst[1]
+	AlmostEqual(t, -10.0, sellDeltaA.SuperficialLoss)
+	AlmostEqual(t, sellDeltaA.SuperficialLoss, sellDeltaB.SuperficialLoss)
+	AlmostEqual(t, 0.0, sellDeltaA.CapitalGain)
+	AlmostEqual(t, sellDeltaA.CapitalGain, sellDeltaB.CapitalGain)
+
+	// Both orders end in the same place.
+	rq.Equal(deltasSellFirst[1].PostStatus, deltasBuyFirst[1].PostStatus)
+	AlmostEqual(t, 110.0, deltasBuyFirst[1].PostStatus.TotalAcb)
+}
+
+func TestFifoCostBasis(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 1.0),
+		mkTx(t, "FOO", 2, ptf.BUY, 10, 2.0),
+		mkTx(t, "FOO", 40, ptf.SELL, 15, 3.0),
+		mkTx(t, "FOO", 80, ptf.SELL, 5, 3.0),
+	}
+
+	acbDeltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, 22.5, acbDeltas[2].CapitalGain)
+	AlmostEqual(t, 7.5, acbDeltas[2].PostStatus.TotalAcb)
+	AlmostEqual(t, 7.5, acbDeltas[3].CapitalGain)
+	rq.Nil(acbDeltas[3].PostStatus.Lots)
+
+	options := ptf.NewDeltaOptions()
+	options.CostBasis = ptf.FIFO_COST_BASIS
+
+	fifoDeltas, err := ptf.TxsToDeltaList(txs, nil, options)
+	rq.Nil(err)
+	// All of the first lot, and half of the second.
+	AlmostEqual(t, 25.0, fifoDeltas[2].CapitalGain)
+	AlmostEqual(t, 10.0, fifoDeltas[2].PostStatus.TotalAcb)
+	rq.Equal([]ptf.Lot{{Date: mkDate(t, 2), Shares: 5, Acb: 10.0}},
+		fifoDeltas[2].PostStatus.Lots)
+	AlmostEqual(t, 5.0, fifoDeltas[3].CapitalGain)
+	// The total gain is the same either way.
+	AlmostEqual(t, acbDeltas[2].CapitalGain+acbDeltas[3].CapitalGain,
+		fifoDeltas[2].CapitalGain+fifoDeltas[3].CapitalGain)
+
+	// An initial status is treated as one lot, preceding all buys.
+	initStatus := &ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 10, TotalAcb: 50.0}
+	fifoDeltas, err = ptf.TxsToDeltaList(txs[1:3], initStatus, options)
+	rq.Nil(err)
+	AlmostEqual(t, 45.0-60.0, fifoDeltas[1].CapitalGain)
+
+	// Superficial losses are not supported
+	txs = []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 2.0),
+		mkTx(t, "FOO", 2, ptf.SELL, 5, 1.0),
+		mkTx(t, "FOO", 3, ptf.BUY, 5, 1.0),
+	}
+	_, err = ptf.TxsToDeltaList(txs, nil, options)
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "not supported with FIFO")
+
+	options.Legacy.NoSuperficialLosses = true
+	fifoDeltas, err = ptf.TxsToDeltaList(txs, nil, options)
+	rq.Nil(err)
+	AlmostEqual(t, -5.0, fifoDeltas[1].CapitalGain)
+}
+
+func TestRoundingPolicy(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 3, 3.333),
+		mkTx(t, "FOO", 40, ptf.SELL, 1, 5.0),
+	}
+
+	// The default retains full precision
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, 9.999, deltas[0].PostStatus.TotalAcb)
+	AlmostEqual(t, 5.0-3.333, deltas[1].CapitalGain)
+	AlmostEqual(t, 9.999-3.333, deltas[1].PostStatus.TotalAcb)
+
+	options := ptf.NewDeltaOptions()
+	options.Rounding = ptf.ROUND_CENTS
+
+	deltas, err = ptf.TxsToDeltaList(txs, nil, options)
+	rq.Nil(err)
+	rq.Equal(10.0, deltas[0].PostStatus.TotalAcb)
+	// 5 - 10/3
+	rq.Equal(1.67, deltas[1].CapitalGain)
+	rq.Equal(6.67, deltas[1].PostStatus.TotalAcb)
+
+	// Halves
+	txs = []*ptf.Tx{mkTx(t, "FOO", 1, ptf.BUY, 1, 0.125)}
+	deltas, err = ptf.TxsToDeltaList(txs, nil, options)
+	rq.Nil(err)
+	rq.Equal(0.13, deltas[0].PostStatus.TotalAcb)
+
+	options.Rounding = ptf.ROUND_BANKERS_CENTS
+	deltas, err = ptf.TxsToDeltaList(txs, nil, options)
+	rq.Nil(err)
+	rq.Equal(0.12, deltas[0].PostStatus.TotalAcb)
+}
+
+func TestPerShareAcbTracking(t *testing.T) {
+	rq := require.New(t)
+
+	// Returns the final ACB per share of txs, without and with per-share tracking.
+	finalPerShareAcbs := func(txs []*ptf.Tx) (float64, float64) {
+		perShareAcbs := []float64{}
+		for _, tracking := range []bool{false, true} {
+			options := ptf.NewDeltaOptions()
+			options.PerShareAcbTracking = tracking
+			deltas, err := ptf.TxsToDeltaList(txs, nil, options)
+			rq.Nil(err)
+			perShareAcbs = append(perShareAcbs, deltas[len(deltas)-1].PostStatus.PerShareAcb())
+		}
+		return perShareAcbs[0], perShareAcbs[1]
+	}
+
+	// Many small sells. The ACB per share should remain exactly 0.1.
+	txs := []*ptf.Tx{mkTx(t, "FOO", 0, ptf.BUY, 5000, 0.1)}
+	for i := 0; i < 1000; i++ {
+		txs = append(txs, mkTx(t, "FOO", 1, ptf.SELL, 3, 0.2))
+	}
+	totalModeAcb, perShareModeAcb := finalPerShareAcbs(txs)
+	rq.NotEqual(0.1, totalModeAcb)
+	AlmostEqual(t, 0.1, totalModeAcb)
+	rq.Equal(0.1, perShareModeAcb)
+
+	// 1000 small buys (each followed by a sell), all at 0.1/share
+	txs = []*ptf.Tx{}
+	for i := 0; i < 1000; i++ {
+		txs = append(txs, mkTx(t, "FOO", 1, ptf.BUY, 3, 0.1), mkTx(t, "FOO", 1, ptf.SELL, 2, 0.2))
+	}
+	totalModeAcb, perShareModeAcb = finalPerShareAcbs(txs)
+	rq.True(math.Abs(perShareModeAcb-0.1) < math.Abs(totalModeAcb-0.1),
+		"per-share drift %g, total drift %g", perShareModeAcb-0.1, totalModeAcb-0.1)
+
+	// The total ACB is derived from the ACB per share
+	options := ptf.NewDeltaOptions()
+	options.PerShareAcbTracking = true
+	deltas, err := ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 0, ptf.BUY, 3, 10.0),
+		mkTx(t, "FOO", 1, ptf.BUY, 3, 20.0),
+		mkTx(t, "FOO", 2, ptf.SELL, 4, 20.0),
+	}, nil, options)
+	rq.Nil(err)
+	rq.Equal(15.0, deltas[1].PostStatus.TrackedPerShareAcb)
+	rq.Equal(15.0, deltas[2].PostStatus.TrackedPerShareAcb)
+	AlmostEqual(t, 30.0, deltas[2].PostStatus.TotalAcb)
+	AlmostEqual(t, 20.0, deltas[2].CapitalGain)
+}
+
+func TestTxsToDeltaModelsProgress(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{mkTx(t, "FOO", 1, ptf.BUY, 1, 1.0), mkTx(t, "BAR", 2, ptf.BUY, 1, 1.0),
+		mkTx(t, "BAZ", 3, ptf.BUY, 1, 1.0)}
+
+	calls := [][]int{}
+	deltaModels := ptf.TxsToDeltaModelsWithProgress(txs, nil, ptf.NewDeltaOptions(),
+		func(done int, total int) {
+			calls = append(calls, []int{done, total})
+		})
+	rq.Equal(3, len(deltaModels))
+	rq.Equal([][]int{{1, 3}, {2, 3}, {3, 3}}, calls)
+}
+
+func TestAnnualSummaryTxs(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		// 2017
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		mkTx(t, "FOO", 100, ptf.BUY, 10, 20.0),
+		mkTx(t, "FOO", 200, ptf.SELL, 5, 25.0),
+		// 2018
+		mkTx(t, "FOO", 400, ptf.SELL, 5, 10.0),
+		mkTx(t, "FOO", 500, ptf.BUY, 5, 12.0),
+		// 2019
+		mkTx(t, "FOO", 800, ptf.BUY, 5, 30.0),
+		mkTx(t, "FOO", 900, ptf.SELL, 12, 20.0),
+	}
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, 50.0, deltas[2].CapitalGain)
+	AlmostEqual(t, -25.0, deltas[3].CapitalGain)
+	AlmostEqual(t, 24.0, deltas[6].CapitalGain)
+
+	summaryTxs, err := ptf.MakeAnnualSummaryTxs(deltas, ptf.CAD)
+	rq.Nil(err)
+	rq.Equal(6, len(summaryTxs))
+
+	expShares := []uint32{20, 5, 5, 5, 5, 12}
+	expActions := []ptf.TxAction{ptf.ACQUIRE, ptf.SELL, ptf.ACQUIRE, ptf.SELL, ptf.ACQUIRE, ptf.SELL}
+	for i, tx := range summaryTxs {
+		rq.Equal(expActions[i], tx.Action)
+		rq.Equal(expShares[i], tx.Shares)
+		rq.Nil(ptf.CheckTxSanity(tx))
+	}
+	rq.Equal(2017, summaryTxs[1].Date.Year())
+	rq.Equal(2019, summaryTxs[5].Date.Year())
+	// The sells specify no superficial loss, including those at a gain
+	for _, i := range []int{1, 3, 5} {
+		rq.Equal(0.0, *summaryTxs[i].SpecifiedSuperficialLoss)
+	}
+
+	summaryDeltas, err := ptf.TxsToDeltaList(summaryTxs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	// Year-end statuses and gains match
+	for i, deltaIdx := range []int{2, 4, 6} {
+		summarySell := summaryDeltas[i*2+1]
+		rq.Equal(deltas[deltaIdx].PostStatus.ShareBalance, summarySell.PostStatus.ShareBalance)
+		AlmostEqual(t, deltas[deltaIdx].PostStatus.TotalAcb, summarySell.PostStatus.TotalAcb)
+	}
+	AlmostEqual(t, 50.0, summaryDeltas[1].CapitalGain)
+	AlmostEqual(t, -25.0, summaryDeltas[3].CapitalGain)
+	AlmostEqual(t, 0.0, summaryDeltas[3].SuperficialLoss)
+	AlmostEqual(t, 24.0, summaryDeltas[5].CapitalGain)
+}
+
+func TestSuspectedSplitWarning(t *testing.T) {
+	rq := require.New(t)
+
+	// A 2-for-1 split happened between the buy and the sell, but was not entered.
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 100.0),
+		mkTx(t, "FOO", 20, ptf.SELL, 10, 50.0),
+		mkTx(t, "FOO", 25, ptf.BUY, 10, 50.0),
+	}
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, -500.0, deltas[1].SuperficialLoss)
+	rq.Equal(1, len(deltas[1].Warnings))
+	rq.Contains(deltas[1].Warnings[0], "stock split")
+	rq.Contains(deltas[1].Warnings[0], "2.00x")
+
+	// An ordinary price drop
+	txs[1].AmountPerShare = 80.0
+	txs[2].AmountPerShare = 80.0
+	deltas, err = ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, -200.0, deltas[1].SuperficialLoss)
+	rq.Empty(deltas[1].Warnings)
+}
+
+func TestOverAppliedSuperficialLossWarning(t *testing.T) {
+	rq := require.New(t)
+
+	// The 5 shares bought on day 45 make both sells (partially) superficial.
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 20, 10.0),
+		mkTx(t, "FOO", 40, ptf.SELL, 10, 5.0),
+		mkTx(t, "FOO", 45, ptf.BUY, 5, 5.0),
+		mkTx(t, "FOO", 50, ptf.SELL, 10, 5.0),
+	}
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, -25.0, deltas[1].SuperficialLoss)
+	rq.Empty(deltas[1].Warnings)
+	AlmostEqual(t, -25.0, deltas[3].SuperficialLoss)
+	rq.Equal(1, len(deltas[3].Warnings))
+	rq.Contains(deltas[3].Warnings[0],
+		"Sell of FOO on 2017-02-20 has a superficial loss on 5 shares, but Sells in the "+
+			"30 days before it already had superficial losses on 5 shares, and only 5 "+
+			"shares were acquired in its period")
+	rq.Contains(deltas[3].Warnings[0], "may be over-applied")
+
+	// When the first sell is more than 30 days before the second, its
+	// superficial shares are not counted against the second.
+	txs[3].Date = mkDate(t, 75)
+	deltas, err = ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, -25.0, deltas[3].SuperficialLoss)
+	rq.Empty(deltas[3].Warnings)
+}
+
+func TestManualOnlySuperficialLosses(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		mkTx(t, "FOO", 40, ptf.SELL, 10, 8.0),
+		mkTx(t, "FOO", 45, ptf.BUY, 10, 8.0),
+	}
+
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, -20.0, deltas[1].SuperficialLoss)
+	AlmostEqual(t, 0.0, deltas[1].CapitalGain)
+
+	options := ptf.NewDeltaOptions()
+	options.SuperficialLosses = ptf.SFL_MANUAL_ONLY
+
+	deltas, err = ptf.TxsToDeltaList(txs, nil, options)
+	rq.Nil(err)
+	AlmostEqual(t, 0.0, deltas[1].SuperficialLoss)
+	AlmostEqual(t, -20.0, deltas[1].CapitalGain)
+	AlmostEqual(t, 80.0, deltas[2].PostStatus.TotalAcb)
+
+	// Specified superficial losses are still applied
+	sfl := -15.0
+	txs[1].SpecifiedSuperficialLoss = &sfl
+	deltas, err = ptf.TxsToDeltaList(txs, nil, options)
+	rq.Nil(err)
+	AlmostEqual(t, -15.0, deltas[1].SuperficialLoss)
+	AlmostEqual(t, -5.0, deltas[1].CapitalGain)
+	AlmostEqual(t, 95.0, deltas[2].PostStatus.TotalAcb)
+}
+
+func TestShortSales(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		// Open
+		mkTx(t, "FOO", 1, ptf.SELL, 10, 50.0),
+		// Add to
+		mkTx(t, "FOO", 5, ptf.SELL, 10, 40.0),
+		// Cover
+		mkTx(t, "FOO", 10, ptf.BUY, 15, 30.0),
+		mkTx(t, "FOO", 20, ptf.BUY, 5, 60.0),
+	}
+
+	// Not allowed by default
+	_, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+
+	options := ptf.NewDeltaOptions()
+	options.AllowShortSales = true
+
+	deltas, err := ptf.TxsToDeltaList(txs, nil, options)
+	rq.Nil(err)
+	rq.Equal(&ptf.PortfolioSecurityStatus{Security: "FOO", ShortBalance: 10, TotalAcb: -500.0},
+		deltas[0].PostStatus)
+	AlmostEqual(t, 0.0, deltas[0].CapitalGain)
+	rq.Equal(&ptf.PortfolioSecurityStatus{Security: "FOO", ShortBalance: 20, TotalAcb: -900.0},
+		deltas[1].PostStatus)
+	// Covered at $30, with an average short price of $45
+	rq.Equal(uint32(5), deltas[2].PostStatus.ShortBalance)
+	AlmostEqual(t, -225.0, deltas[2].PostStatus.TotalAcb)
+	AlmostEqual(t, 225.0, deltas[2].CapitalGain)
+	rq.True(deltas[2].CoversShort())
+	rq.Equal(uint32(0), deltas[3].PostStatus.ShortBalance)
+	rq.Equal(uint32(0), deltas[3].PostStatus.ShareBalance)
+	AlmostEqual(t, 0.0, deltas[3].PostStatus.TotalAcb)
+	AlmostEqual(t, -75.0, deltas[3].CapitalGain)
+
+	yearGains := ptf.AnnualGainsReport(deltas)
+	rq.Equal(1, len(yearGains))
+	AlmostEqual(t, 900.0, yearGains[0].Proceeds)
+	AlmostEqual(t, 750.0, yearGains[0].TotalAcbDisposed)
+	AlmostEqual(t, 150.0, yearGains[0].NetGain)
+
+	// The covers are the dispositions, rather than the short sales.
+	t5008Rows := ptf.T5008Rows(deltas, 0)
+	rq.Equal(2, len(t5008Rows))
+	rq.Equal("2017-01-11", t5008Rows[0].SettlementDate)
+	rq.Equal(uint32(15), t5008Rows[0].Quantity)
+	AlmostEqual(t, 675.0, t5008Rows[0].Proceeds)
+	AlmostEqual(t, 450.0, t5008Rows[0].Acb)
+	AlmostEqual(t, 225.0, t5008Rows[1].Proceeds)
+	AlmostEqual(t, 300.0, t5008Rows[1].Acb)
+	schedule3Rows := ptf.Schedule3Rows(deltas, 2017)
+	rq.Equal(2, len(schedule3Rows))
+	rq.Equal(uint32(15), schedule3Rows[0].Shares)
+	AlmostEqual(t, 675.0, schedule3Rows[0].Proceeds)
+	AlmostEqual(t, 450.0, schedule3Rows[0].Acb)
+	AlmostEqual(t, 225.0, schedule3Rows[0].Gain)
+	AlmostEqual(t, -75.0, schedule3Rows[1].Gain)
+
+	// A buy cannot cover more than the short position
+	txs[3].Shares = 6
+	_, err = ptf.TxsToDeltaList(txs, nil, options)
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "short position")
+
+	// A loss is not superficial if the position is short at the end of the
+	// period, so no shares are held.
+	deltas, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		mkTx(t, "FOO", 10, ptf.SELL, 10, 5.0),
+		mkTx(t, "FOO", 12, ptf.BUY, 5, 5.0),
+		mkTx(t, "FOO", 14, ptf.SELL, 5, 5.0),
+		mkTx(t, "FOO", 16, ptf.SELL, 3, 5.0),
+	}, nil, options)
+	rq.Nil(err)
+	rq.Equal(0.0, deltas[1].SuperficialLoss)
+	AlmostEqual(t, -50.0, deltas[1].CapitalGain)
+	rq.Equal(uint32(3), deltas[4].PostStatus.ShortBalance)
+}
+
+func TestMalformedTxErrors(t *testing.T) {
+	rq := require.New(t)
+
+	// Mixed securities
+	deltas, err := ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 1.0),
+		mkTx(t, "BAR", 2, ptf.SELL, 5, 1.0),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Equal(1, len(deltas))
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "does not match the security of the preceding status (FOO)")
+
+	// Unset action
+	deltas, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 1.0),
+		mkTx(t, "FOO", 2, ptf.NO_ACTION, 5, 1.0),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Equal(1, len(deltas))
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "invalid action")
+
+	// A sell of no shares (with a commission) is not a superficial loss
+	deltas, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 1.0),
+		mkTx(t, "FOO", 2, ptf.SELL, 0, 1.0, txCommission(1.0)),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	rq.Equal(-1.0, deltas[1].CapitalGain)
+	rq.Equal(0.0, deltas[1].SuperficialLoss)
+}
+
+func TestPartialSuperficialLossFraction(t *testing.T) {
+	rq := require.New(t)
+
+	render := func(txs ...*ptf.Tx) string {
+		deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+		rq.Nil(err)
+		return ptf.RenderTxTableModel(deltas, ptf.ROUND_NONE, false).Rows[1][8]
+	}
+
+	// min(8, 10, 2) / 8 = 2/8 of the loss is superficial
+	rq.Equal("-$6.00 *\n(SFL -$2.00, 1/4 of loss)", render(
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 1.0),
+		mkTx(t, "FOO", 2, ptf.SELL, 8, 0.0)))
+
+	// min(9, 16, 7) / 9 cannot be reduced
+	rq.Equal("-$2.00 *\n(SFL -$7.00, 7/9 of loss)", render(
+		mkTx(t, "FOO", 1, ptf.BUY, 16, 1.0),
+		mkTx(t, "FOO", 2, ptf.SELL, 9, 0.0)))
+
+	// Entirely superficial
+	rq.Equal("$0.00 *\n(SFL -$5.00)", render(
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 1.0),
+		mkTx(t, "FOO", 50, ptf.SELL, 5, 0.0),
+		mkTx(t, "FOO", 51, ptf.BUY, 5, 1.0)))
+}
+
+func TestSplit(t *testing.T) {
+	rq := require.New(t)
+
+	split := mkTx(t, "FOO", 10, ptf.SPLIT, 0, 0.0, txRatio(2.0))
+	rq.Nil(ptf.CheckTxSanity(split))
+
+	deltas, err := ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		split,
+		mkTx(t, "FOO", 100, ptf.SELL, 5, 6.0),
+		// 1-for-3 consolidation
+		mkTx(t, "FOO", 200, ptf.SPLIT, 0, 0.0, txRatio(1.0/3.0)),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	rq.Equal(&ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 20, TotalAcb: 100.0},
+		deltas[1].PostStatus)
+	AlmostEqual(t, 30.0-25.0, deltas[2].CapitalGain)
+	rq.Equal(uint32(5), deltas[3].PostStatus.ShareBalance)
+	AlmostEqual(t, 75.0, deltas[3].PostStatus.TotalAcb)
+
+	// 15 shares cannot be split 1-for-2
+	_, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		split,
+		mkTx(t, "FOO", 100, ptf.SELL, 5, 6.0),
+		mkTx(t, "FOO", 200, ptf.SPLIT, 0, 0.0, txRatio(0.5)),
+	}, nil, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "fractional number of shares")
+
+	// Shares bought after a split count in pre-split shares towards a
+	// superficial loss. min(5, 4/2, 5 + 4/2) / 5 of the loss is superficial.
+	deltas, err = ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 10.0),
+		mkTx(t, "FOO", 50, ptf.SELL, 5, 5.0),
+		mkTx(t, "FOO", 52, ptf.SPLIT, 0, 0.0, txRatio(2.0)),
+		mkTx(t, "FOO", 55, ptf.BUY, 4, 2.5),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	AlmostEqual(t, -10.0, deltas[1].SuperficialLoss)
+	AlmostEqual(t, -15.0, deltas[1].CapitalGain)
+	rq.Empty(deltas[1].Warnings)
+
+	split.Ratio = 0.0
+	rq.NotNil(ptf.CheckTxSanity(split))
+	split.Ratio = 2.0
+	split.NewSecurity = "BAR"
+	rq.NotNil(ptf.CheckTxSanity(split))
+}
+
+func TestAutoOddLotSales(t *testing.T) {
+	rq := require.New(t)
+
+	// 1-for-2 consolidation, with odd lots sold at $3/share
+	split := mkTx(t, "FOO", 100, ptf.SPLIT, 0, 3.0, txRatio(0.5), txReadIndex(1))
+	txs := []*ptf.Tx{mkTx(t, "FOO", 1, ptf.BUY, 11, 10.0), split}
+
+	_, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.NotNil(err)
+
+	options := ptf.NewDeltaOptions()
+	options.AutoOddLotSales = true
+
+	deltas, err := ptf.TxsToDeltaList(txs, nil, options)
+	rq.Nil(err)
+	rq.Equal(2, len(txs))
+	rq.Equal(3, len(deltas))
+
+	sale := deltas[1]
+	rq.Equal(ptf.SELL, sale.Tx.Action)
+	rq.Equal(uint32(1), sale.Tx.Shares)
+	rq.Contains(sale.Tx.Memo, "Odd lot of 1 shares")
+	AlmostEqual(t, 3.0-10.0, sale.CapitalGain)
+	rq.Equal(&ptf.PortfolioSecurityStatus{Security: "FOO", ShareBalance: 5, TotalAcb: 100.0},
+		deltas[2].PostStatus)
+
+	// The sale is linked back to the split's row
+	rq.Equal(ptf.ORIGIN_CSV_ROW, deltas[0].Tx.Origin)
+	rq.Equal(ptf.ORIGIN_ODD_LOT_SALE, sale.Tx.Origin)
+	rq.Equal(split.ReadIndex, sale.Tx.ReadIndex)
+	table := ptf.RenderTxTableModel(deltas, ptf.ROUND_NONE, false)
+	rq.Equal(len(table.Rows), len(table.RowSources))
+	rq.Equal(ptf.RenderRowSource{ReadIndex: split.ReadIndex, Origin: ptf.ORIGIN_ODD_LOT_SALE},
+		table.RowSources[1])
+
+	// Nothing is sold if the split leaves whole shares
+	txs[0].Shares = 10
+	deltas, err = ptf.TxsToDeltaList(txs, nil, options)
+	rq.Nil(err)
+	rq.Equal(2, len(deltas))
+
+	// The odd lot cannot be sold without an amount/share
+	txs[0].Shares = 11
+	split.AmountPerShare = 0.0
+	_, err = ptf.TxsToDeltaList(txs, nil, options)
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "no amount/share at which to sell the odd lot of 1 shares")
+}
+
+func TestDeltaIterator(t *testing.T) {
+	rq := require.New(t)
+
+	split := mkTx(t, "FOO", 100, ptf.SPLIT, 0, 3.0, txRatio(0.5))
+	txs := []*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 11, 10.0),
+		mkTx(t, "FOO", 20, ptf.SELL, 2, 8.0),
+		mkTx(t, "FOO", 30, ptf.BUY, 2, 9.0),
+		// Injects the sale of an odd lot
+		split,
+		mkTx(t, "FOO", 120, ptf.SELL, 1, 25.0),
+	}
+	options := ptf.NewDeltaOptions()
+	options.AutoOddLotSales = true
+
+	expDeltas, err := ptf.TxsToDeltaList(txs, nil, options)
+	rq.Nil(err)
+	rq.Equal(6, len(expDeltas))
+
+	it := ptf.NewDeltaIterator(txs, nil, options)
+	deltas := []*ptf.TxDelta{}
+	for {
+		delta, err := it.Next()
+		rq.Nil(err)
+		if delta == nil {
+			break
+		}
+		deltas = append(deltas, delta)
+	}
+	rq.Equal(expDeltas, deltas)
+	rq.Equal(5, len(txs))
+
+	// Errors are sticky
+	txs = []*ptf.Tx{mkTx(t, "FOO", 1, ptf.SELL, 1, 10.0), mkTx(t, "FOO", 2, ptf.BUY, 1, 10.0)}
+	it = ptf.NewDeltaIterator(txs, nil, options)
+	delta, err := it.Next()
+	rq.Nil(delta)
+	rq.NotNil(err)
+	_, err2 := it.Next()
+	rq.Equal(err, err2)
+
+	// No txs
+	delta, err = ptf.NewDeltaIterator([]*ptf.Tx{}, nil, options).Next()
+	rq.Nil(delta)
+	rq.Nil(err)
+}
+
+func TestSuperficialLossCalc(t *testing.T) {
+	rq := require.New(t)
+
+	deltas, err := ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 1.0),
+		// min(8, 10 + 1, 2 + 1) / 8 is superficial
+		mkTx(t, "FOO", 2, ptf.SELL, 8, 0.5),
+		mkTx(t, "FOO", 20, ptf.BUY, 1, 0.5),
+		// Not superficial
+		mkTx(t, "FOO", 100, ptf.SELL, 3, 0.5),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+
+	rq.Equal(&ptf.SuperficialLossCalc{
+		SharesSold:            8,
+		TotalAcquiredInPeriod: 11,
+		SharesAtEndOfPeriod:   3,
+		SuperficialShares:     3,
+	}, deltas[1].SuperficialLossCalc)
+	rq.Nil(deltas[0].SuperficialLossCalc)
+	rq.Nil(deltas[3].SuperficialLossCalc)
+
+	table := ptf.RenderTxTableModel(deltas, ptf.ROUND_NONE, false)
+	ptf.AddSuperficialLossCalcNotes(table, deltas)
+	rq.Contains(table.Notes,
+		" SFL of FOO sale on 2017-01-03: min(8 sold, 11 acquired in period, "+
+			"3 held at end of period) = 3, so 3/8 of the loss is superficial")
+}
+
+func TestDiffDeltas(t *testing.T) {
+	rq := require.New(t)
+
+	oldDeltas, err := ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 2.0, txReadIndex(0)),
+		mkTx(t, "FOO", 10, ptf.SELL, 5, 3.0, txReadIndex(1)),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+	// A buy is added on the same day as the first, but before it, which shifts
+	// the ACB (and the read index) of everything after it.
+	newDeltas, err := ptf.TxsToDeltaList([]*ptf.Tx{
+		mkTx(t, "FOO", 1, ptf.BUY, 5, 1.0, txReadIndex(0)),
+		mkTx(t, "FOO", 1, ptf.BUY, 10, 2.0, txReadIndex(1)),
+		mkTx(t, "FOO", 10, ptf.SELL, 5, 3.0, txReadIndex(2)),
+	}, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+
+	rq.Empty(ptf.DiffDeltas(oldDeltas, oldDeltas))
+
+	diffs := ptf.DiffDeltas(oldDeltas, newDeltas)
+	rq.Equal(3, len(diffs))
+
+	rq.Equal(ptf.DIFF_ADDED, diffs[0].Kind)
+	rq.Nil(diffs[0].Old)
+	rq.Equal(newDeltas[0], diffs[0].New)
+
+	// The existing buy is matched to its own row, not the added one
+	rq.Equal(ptf.DIFF_CHANGED, diffs[1].Kind)
+	rq.Equal(oldDeltas[0], diffs[1].Old)
+	rq.Equal(newDeltas[1], diffs[1].New)
+	rq.Equal([]string{"Share Balance", "ACB"}, diffs[1].Changes)
+
+	rq.Equal(ptf.DIFF_CHANGED, diffs[2].Kind)
+	rq.Equal([]string{"Share Balance", "ACB", "Cap. Gain"}, diffs[2].Changes)
+	AlmostEqual(t, 5.0, diffs[2].Old.CapitalGain)
+	AlmostEqual(t, 15.0-25.0/3.0, diffs[2].New.CapitalGain)
+
+	table := ptf.RenderDeltaDiffTable(diffs, false)
+	rq.Equal(3, len(table.Rows))
+	rq.Equal([]string{"1 added, 0 removed, 2 changed"}, table.Notes)
+	rq.Equal("$20.00", table.Rows[1][6])
+	rq.Equal("$25.00", table.Rows[1][7])
+
+	// In reverse, the buy was removed
+	diffs = ptf.DiffDeltas(newDeltas, oldDeltas)
+	rq.Equal(3, len(diffs))
+	rq.Equal(ptf.DIFF_REMOVED, diffs[1].Kind)
+	rq.Equal(newDeltas[0], diffs[1].Old)
+	rq.Nil(diffs[1].New)
+}
@@ -0,0 +1,155 @@
+package test
+
+import (
+	"fmt"
+	"strings"
+	"testing"
+	"time"
+
+	"github.com/stretchr/testify/require"
+
+	"github.com/tsiemens/acb/fx"
+	"github.com/tsiemens/acb/log"
+)
+
+func fxDate(year int, month time.Month, day int) time.Time {
+	return time.Date(year, month, day, 0, 0, 0, 0, time.UTC)
+}
+
+func TestUsdCadRateFallsBackToPriorBusinessDay(t *testing.T) {
+	rq := require.New(t)
+
+	cache := fx.NewMemRatesCacheAccessor()
+	cache.WriteRates(2020, []fx.DailyRate{
+		{fxDate(2020, time.December, 30), 1.28},
+		{fxDate(2020, time.December, 31), 1.27},
+	})
+	cache.WriteRates(2021, []fx.DailyRate{
+		{fxDate(2021, time.January, 4), 1.275},
+		{fxDate(2021, time.January, 5), 1.272},
+	})
+	rateLoader := fx.NewRateLoader(false, cache, &log.StderrErrorPrinter{})
+	rateLoader.RemoteGetter = &fakeRatesGetter{}
+
+	rate, err := rateLoader.GetUsdCadRate(fxDate(2021, time.January, 5))
+	rq.Nil(err)
+	rq.Equal(1.272, rate.ForeignToLocalRate)
+
+	// Saturday, which falls back into the previous year.
+	rate, err = rateLoader.GetUsdCadRate(fxDate(2021, time.January, 2))
+	rq.Nil(err)
+	rq.Equal(fx.DailyRate{fxDate(2020, time.December, 31), 1.27}, rate)
+
+	// New Year's Day holiday (a Friday)
+	rate, err = rateLoader.GetUsdCadRate(fxDate(2021, time.January, 1))
+	rq.Nil(err)
+	rq.Equal(1.27, rate.ForeignToLocalRate)
+
+	// A weekday after the last published rate may simply not be published yet.
+	_, err = rateLoader.GetUsdCadRate(fxDate(2021, time.January, 6))
+	rq.NotNil(err)
+	rq.True(strings.Contains(err.Error(), "2021-01-06"), err.Error())
+}
+
+type fakeRatesGetter struct {
+	Rates []fx.DailyRate
+	Calls [][2]time.Time
+	Err   error
+}
+
+func (g *fakeRatesGetter) GetUsdCadRates(start time.Time, end time.Time) ([]fx.DailyRate, error) {
+	g.Calls = append(g.Calls, [2]time.Time{start, end})
+	if g.Err != nil {
+		return nil, g.Err
+	}
+	rates := make([]fx.DailyRate, 0)
+	for _, rate := range g.Rates {
+		if !rate.Date.Before(start) && !rate.Date.After(end) {
+			rates = append(rates, rate)
+		}
+	}
+	return rates, nil
+}
+
+func TestStaleUsdCadRatesCacheIsExtended(t *testing.T) {
+	rq := require.New(t)
+
+	cache := fx.NewMemRatesCacheAccessor()
+	cache.WriteRates(2021, []fx.DailyRate{
+		{fxDate(2021, time.January, 4), 1.275},
+	})
+	getter := &fakeRatesGetter{Rates: []fx.DailyRate{
+		{fxDate(2021, time.January, 4), 1.275},
+		{fxDate(2021, time.January, 5), 1.272},
+		{fxDate(2021, time.January, 6), 1.268},
+	}}
+	rateLoader := fx.NewRateLoader(false, cache, &log.StderrErrorPrinter{})
+	rateLoader.RemoteGetter = getter
+
+	rate, err := rateLoader.GetUsdCadRate(fxDate(2021, time.January, 4))
+	rq.Nil(err)
+	rq.Equal(1.275, rate.ForeignToLocalRate)
+	rq.Equal(0, len(getter.Calls))
+
+	// Only the dates after the cached rates are fetched.
+	rate, err = rateLoader.GetUsdCadRate(fxDate(2021, time.January, 6))
+	rq.Nil(err)
+	rq.Equal(1.268, rate.ForeignToLocalRate)
+	rq.Equal([][2]time.Time{{fxDate(2021, time.January, 5), fxDate(2021, time.December, 31)}},
+		getter.Calls)
+	rq.Equal(3, len(cache.RatesByYear[2021]))
+
+	// Rates are not re-fetched again in the same run.
+	_, err = rateLoader.GetUsdCadRate(fxDate(2021, time.January, 7))
+	rq.NotNil(err)
+	rq.Equal(1, len(getter.Calls))
+
+	// With RefreshStale, the newer rates are fetched even if the date is cached
+	cache.WriteRates(2021, []fx.DailyRate{
+		{fxDate(2021, time.January, 4), 1.275},
+	})
+	getter.Calls = nil
+	rateLoader = fx.NewRateLoader(false, cache, &log.StderrErrorPrinter{})
+	rateLoader.RemoteGetter = getter
+	rateLoader.RefreshStale = true
+	rate, err = rateLoader.GetUsdCadRate(fxDate(2021, time.January, 4))
+	rq.Nil(err)
+	rq.Equal(1.275, rate.ForeignToLocalRate)
+	rq.Equal([][2]time.Time{{fxDate(2021, time.January, 5), fxDate(2021, time.December, 31)}},
+		getter.Calls)
+	rq.Equal(3, len(cache.RatesByYear[2021]))
+}
+
+func TestUsdCadRateFallbackWhenRefreshFails(t *testing.T) {
+	rq := require.New(t)
+
+	cache := fx.NewMemRatesCacheAccessor()
+	cache.WriteRates(2021, []fx.DailyRate{
+		{fxDate(2021, time.January, 7), 1.27},
+		{fxDate(2021, time.January, 8), 1.268},
+	})
+	errPrinter := &CapturingErrorPrinter{}
+	rateLoader := fx.NewRateLoader(false, cache, errPrinter)
+	rateLoader.RemoteGetter = &fakeRatesGetter{Err: fmt.Errorf("network is unreachable")}
+
+	// Saturday, after the last cached rate.
+	rate, err := rateLoader.GetUsdCadRate(fxDate(2021, time.January, 9))
+	rq.Nil(err)
+	rq.Equal(fx.DailyRate{fxDate(2021, time.January, 8), 1.268}, rate)
+	rq.Contains(errPrinter.Buf.String(), "network is unreachable")
+
+	// A weekday still cannot be assumed to be a holiday.
+	_, err = rateLoader.GetUsdCadRate(fxDate(2021, time.January, 11))
+	rq.NotNil(err)
+	rq.Contains(err.Error(), "2021-01-11")
+
+	// The prior business day's rate may come from the overrides.
+	overrides := fx.NewMemRatesCacheAccessor()
+	overrides.WriteRates(2021, []fx.DailyRate{
+		{fxDate(2021, time.January, 15), 1.26},
+	})
+	rateLoader.Overrides = overrides
+	rate, err = rateLoader.GetUsdCadRate(fxDate(2021, time.January, 16))
+	rq.Nil(err)
+	rq.Equal(fx.DailyRate{fxDate(2021, time.January, 15), 1.26}, rate)
+}
@@ -0,0 +1,253 @@
+package test
+
+import (
+	"encoding/json"
+	"fmt"
+	"io/ioutil"
+	"strconv"
+	"strings"
+	"testing"
+
+	"github.com/stretchr/testify/require"
+
+	"github.com/tsiemens/acb/app"
+	"github.com/tsiemens/acb/fx"
+	"github.com/tsiemens/acb/log"
+	ptf "github.com/tsiemens/acb/portfolio"
+)
+
+// A minimal validator, supporting the subset of JSON Schema emitted by
+// JsonReportSchema.
+func validateJsonSchema(schema map[string]interface{}, val interface{}, path string) error {
+	types := []interface{}{}
+	switch typ := schema["type"].(type) {
+	case string:
+		types = append(types, typ)
+	case []interface{}:
+		types = typ
+	}
+
+	typeOk := false
+	for _, typ := range types {
+		switch typ {
+		case "null":
+			typeOk = typeOk || val == nil
+		case "string":
+			_, ok := val.(string)
+			typeOk = typeOk || ok
+		case "boolean":
+			_, ok := val.(bool)
+			typeOk = typeOk || ok
+		case "number":
+			_, ok := val.(float64)
+			typeOk = typeOk || ok
+		case "integer":
+			f, ok := val.(float64)
+			typeOk = typeOk || (ok && f == float64(int64(f)))
+		case "array":
+			arr, ok := val.([]interface{})
+			if ok {
+				typeOk = true
+				for i, item := range arr {
+					err := validateJsonSchema(schema["items"].(map[string]interface{}), item,
+						fmt.Sprintf("%s[%d]", path, i))
+					if err != nil {
+						return err
+					}
+				}
+			}
+		case "object":
+			obj, ok := val.(map[string]interface{})
+			if !ok {
+				continue
+			}
+			typeOk = true
+			props, _ := schema["properties"].(map[string]interface{})
+			if required, ok := schema["required"].([]interface{}); ok {
+				for _, name := range required {
+					if _, ok := obj[name.(string)]; !ok {
+						return fmt.Errorf("%s: missing required property %s", path, name)
+					}
+				}
+			}
+			for name, propVal := range obj {
+				propSchema, ok := props[name]
+				if !ok {
+					propSchema = schema["additionalProperties"]
+				}
+				propSchemaMap, ok := propSchema.(map[string]interface{})
+				if !ok {
+					return fmt.Errorf("%s: unexpected property %s", path, name)
+				}
+				err := validateJsonSchema(propSchemaMap, propVal, path+"."+name)
+				if err != nil {
+					return err
+				}
+			}
+		}
+	}
+	if !typeOk {
+		return fmt.Errorf("%s: %v does not match type %v", path, val, schema["type"])
+	}
+	return nil
+}
+
+func roundTripJson(t *testing.T, v interface{}) interface{} {
+	data, err := json.Marshal(v)
+	require.Nil(t, err)
+	var out interface{}
+	require.Nil(t, json.Unmarshal(data, &out))
+	return out
+}
+
+func TestJsonReportSchema(t *testing.T) {
+	rq := require.New(t)
+
+	sfl := -1.0
+	txs := []*ptf.Tx{
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 1), Action: ptf.BUY,
+			Shares: 10, AmountPerShare: 10.0, Commission: 1.0,
+			TxCurrency: ptf.USD, TxCurrToLocalExchangeRate: 1.2,
+			CommissionCurrency: ptf.USD, CommissionCurrToLocalExchangeRate: 1.2},
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 2), Action: ptf.SELL,
+			Shares: 5, AmountPerShare: 9.0, Commission: 0.0,
+			TxCurrency: ptf.USD, TxCurrToLocalExchangeRate: 1.2,
+			CommissionCurrency: ptf.USD, CommissionCurrToLocalExchangeRate: 1.2,
+			SpecifiedSuperficialLoss: &sfl},
+	}
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+
+	report := ptf.MakeJsonReport(map[string]*ptf.SecurityDeltas{
+		"FOO": &ptf.SecurityDeltas{Deltas: deltas, Errors: []error{fmt.Errorf("oops")}},
+	})
+
+	// Round trip the schema through JSON too, so it is validated as a consumer
+	// would see it.
+	schema := roundTripJson(t, ptf.JsonReportSchema()).(map[string]interface{})
+	rq.Equal(float64(ptf.JsonFormatVersion), schema["version"])
+
+	serialized := roundTripJson(t, report)
+	rq.Nil(validateJsonSchema(schema, serialized, "$"))
+
+	// Sanity check the validator
+	serializedMap := serialized.(map[string]interface{})
+	serializedMap["extra"] = 1
+	rq.NotNil(validateJsonSchema(schema, serializedMap, "$"))
+	delete(serializedMap, "extra")
+	delete(serializedMap, "version")
+	rq.NotNil(validateJsonSchema(schema, serializedMap, "$"))
+}
+
+func TestJsonPerShareAcb(t *testing.T) {
+	rq := require.New(t)
+
+	txs := []*ptf.Tx{
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 1), Action: ptf.BUY,
+			Shares: 3, AmountPerShare: 10.0, Commission: 1.0,
+			TxCurrency: ptf.USD, TxCurrToLocalExchangeRate: 1.2,
+			CommissionCurrency: ptf.USD, CommissionCurrToLocalExchangeRate: 1.2},
+		&ptf.Tx{Security: "FOO", Date: mkDate(t, 2), Action: ptf.SELL,
+			Shares: 3, AmountPerShare: 11.0, Commission: 0.0,
+			TxCurrency: ptf.USD, TxCurrToLocalExchangeRate: 1.2,
+			CommissionCurrency: ptf.USD, CommissionCurrToLocalExchangeRate: 1.2},
+	}
+	deltas, err := ptf.TxsToDeltaList(txs, nil, ptf.NewDeltaOptions())
+	rq.Nil(err)
+
+	serialized := roundTripJson(t, ptf.MakeJsonTxDelta(deltas[0])).(map[string]interface{})
+	perShareAcb, err := strconv.ParseFloat(serialized["per_share_acb"].(string), 64)
+	rq.Nil(err)
+	AlmostEqual(t, deltas[0].PostStatus.TotalAcb/3.0, perShareAcb)
+	AlmostEqual(t, 37.2/3.0, perShareAcb)
+
+	// No shares
+	serialized = roundTripJson(t, ptf.MakeJsonTxDelta(deltas[1])).(map[string]interface{})
+	perShareAcbVal, ok := serialized["per_share_acb"]
+	rq.True(ok)
+	rq.Nil(perShareAcbVal)
+}
+
+func TestJsonTxOutlays(t *testing.T) {
+	rq := require.New(t)
+
+	tx := mkTx(t, "FOO", 1, ptf.SELL, 3, 10.0, txCommission(1.0), txOutlays(2.5))
+	serialized := roundTripJson(t, ptf.MakeJsonTx(tx)).(map[string]interface{})
+	rq.Equal("2.5", serialized["outlays"])
+	rq.Equal("1", serialized["commission"])
+}
+
+func TestJsonOutputGolden(t *testing.T) {
+	rq := require.New(t)
+
+	csvReaders := splitCsvRows([]uint32{3},
+		"FOO,2016-01-05,Buy,20,1.5,CAD,,1,first",
+		"BAR,2016-01-10,Buy,3,10,CAD,,0,",
+		"FOO,2016-02-05,Sell,5,2,USD,1.25,0,",
+	)
+	reportOptions := app.NewReportOptions()
+	reportOptions.OutputFormat = app.OutputFormatJson
+
+	var out strings.Builder
+	ok, renderTables := app.RunAcbAppToWriter(
+		&out, csvReaders, map[string]*ptf.PortfolioSecurityStatus{},
+		false, false, app.NewLegacyOptions(), reportOptions,
+		fx.NewMemRatesCacheAccessor(), &log.StderrErrorPrinter{})
+	rq.True(ok)
+	rq.Nil(renderTables)
+
+	golden, err := ioutil.ReadFile("report_golden.json")
+	rq.Nil(err)
+	rq.Equal(string(golden), out.String())
+
+	reportOptions.OutputFormat = "xml"
+	ok, _ = app.RunAcbAppToWriter(
+		&out, csvReaders, map[string]*ptf.PortfolioSecurityStatus{},
+		false, false, app.NewLegacyOptions(), reportOptions,
+		fx.NewMemRatesCacheAccessor(), &log.StderrErrorPrinter{})
+	rq.False(ok)
+}
+
+func TestJsonDecimal(t *testing.T) {
+	rq := require.New(t)
+
+	marshal := func(val float64) string {
+		data, err := json.Marshal(ptf.JsonDecimal(val))
+		rq.Nil(err)
+		return string(data)
+	}
+	unmarshal := func(data string) (float64, error) {
+		var d ptf.JsonDecimal
+		err := json.Unmarshal([]byte(data), &d)
+		return float64(d), err
+	}
+
+	a, b := 0.1, 0.2
+	rq.NotEqual(0.3, a+b)
+	rq.Equal(`"0.3"`, marshal(a+b))
+	rq.Equal(`"10.9"`, marshal(10.9))
+	rq.Equal(`"-1.25"`, marshal(-1.25))
+	rq.Equal(`"100"`, marshal(100.0))
+	rq.Equal(`"0"`, marshal(-0.00000000001))
+
+	for _, val := range []float64{a + b, 10.9, -1.25, 100.0, 0.0, 1234.5678901234} {
+		parsed, err := unmarshal(marshal(val))
+		rq.Nil(err)
+		AlmostEqual(t, val, parsed)
+	}
+	val, err := unmarshal(`"10.90"`)
+	rq.Nil(err)
+	rq.Equal(10.9, val)
+
+	for _, data := range []string{`10.9`, `"abc"`, `"1e5"`, `"1."`, `""`, `null`} {
+		_, err = unmarshal(data)
+		rq.NotNil(err, data)
+	}
+
+	// The pointer fields round-trip as well
+	var tx ptf.JsonTx
+	rq.Nil(json.Unmarshal(
+		[]byte(`{"amount_per_share": "1.5", "specified_superficial_loss": "-2"}`), &tx))
+	rq.Equal(ptf.JsonDecimal(1.5), tx.AmountPerShare)
+	rq.Equal(ptf.JsonDecimal(-2.0), *tx.SpecifiedSuperficialLoss)
+}
@@ -1,6 +1,7 @@
 package test
 
 import (
+	"math"
 	"testing"
 
 	"github.com/stretchr/testify/require"
@@ -13,3 +14,45 @@ func TestMathMin(t *testing.T) {
 	require.Equal(t, util.MinUint32(40, 50, 60), uint32(40))
 	require.Equal(t, util.MinUint32(60, 50, 40), uint32(40))
 }
+
+func TestReduceFraction(t *testing.T) {
+	rq := require.New(t)
+
+	reduce := func(num uint32, denom uint32) []uint32 {
+		n, d := util.ReduceFraction(num, denom)
+		return []uint32{n, d}
+	}
+	rq.Equal([]uint32{7, 8}, reduce(14, 16))
+	rq.Equal([]uint32{1, 4}, reduce(25, 100))
+	rq.Equal([]uint32{3, 1}, reduce(9, 3))
+	rq.Equal([]uint32{7, 9}, reduce(7, 9))
+	rq.Equal([]uint32{1, 1}, reduce(5, 5))
+	rq.Equal([]uint32{0, 1}, reduce(0, 5))
+}
+
+func TestRoundToCents(t *testing.T) {
+	rq := require.New(t)
+
+	rq.Equal(0.0, util.RoundToCents(0.004))
+	rq.Equal(0.01, util.RoundToCents(0.005))
+	rq.Equal(0.02, util.RoundToCents(0.015))
+	rq.Equal(0.03, util.RoundToCents(0.025))
+	rq.Equal(-0.01, util.RoundToCents(-0.005))
+	rq.Equal(-0.02, util.RoundToCents(-0.015))
+	// 1.005 is slightly less than 1.005 as a float
+	rq.Equal(1.01, util.RoundToCents(1.005))
+	rq.Equal(1234.57, util.RoundToCents(1234.565))
+
+	rq.Equal(0.0, util.RoundToCentsHalfEven(0.005))
+	rq.Equal(0.02, util.RoundToCentsHalfEven(0.015))
+	rq.Equal(0.02, util.RoundToCentsHalfEven(0.025))
+	rq.Equal(-0.02, util.RoundToCentsHalfEven(-0.015))
+	rq.Equal(1.0, util.RoundToCentsHalfEven(1.005))
+	rq.Equal(1.02, util.RoundToCentsHalfEven(1.015))
+
+	rq.Equal(0.01, util.RoundGezToCents(0.005))
+	rq.Equal(0.02, util.RoundGezToCents(0.015))
+	rq.Equal(0.0, util.RoundGezToCents(-0.000001))
+	rq.Equal(0.0, util.RoundGezToCents(-0.015))
+	rq.False(math.Signbit(util.RoundGezToCents(-0.001)))
+}
@@ -0,0 +1,19 @@
+package util
+
+import (
+	"bufio"
+	"bytes"
+	"io"
+)
+
+var utf8Bom = []byte{0xEF, 0xBB, 0xBF}
+
+// Returns a reader of the contents of r, without any leading UTF-8 byte order
+// mark (as written by Excel, for example).
+func SkipUtf8Bom(r io.Reader) io.Reader {
+	bufR := bufio.NewReader(r)
+	if start, err := bufR.Peek(len(utf8Bom)); err == nil && bytes.Equal(start, utf8Bom) {
+		bufR.Discard(len(utf8Bom))
+	}
+	return bufR
+}
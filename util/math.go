@@ -1,5 +1,9 @@
 package util
 
+import (
+	"math"
+)
+
 func MinUint32(val0 uint32, vals ...uint32) uint32 {
 	min := val0
 	for _, v := range vals {
@@ -9,3 +13,55 @@ func MinUint32(val0 uint32, vals ...uint32) uint32 {
 	}
 	return min
 }
+
+// Returns val in cents, snapped to a millionth of a cent. Amounts like 1.005
+// are not exactly representable (1.005 * 100 is 100.49999999999999), so this
+// lets them round as the decimal amount they were written as.
+func effectiveCents(val float64) float64 {
+	return math.Round(val*100.0*1e6) / 1e6
+}
+
+// Rounds val to the nearest cent, with halves rounded away from zero
+// (eg. 0.005 to 0.01 and -0.005 to -0.01).
+func RoundToCents(val float64) float64 {
+	return math.Round(effectiveCents(val)) / 100.0
+}
+
+// Rounds val to the nearest cent, with halves rounded to the even cent
+// (banker's rounding, eg. 0.005 to 0.0 and 0.015 to 0.02).
+func RoundToCentsHalfEven(val float64) float64 {
+	return math.RoundToEven(effectiveCents(val)) / 100.0
+}
+
+// Like RoundToCents, but for amounts which cannot be negative (eg. an ACB),
+// where arithmetic error can leave a tiny negative remainder. Negative values
+// are rounded to 0.
+func RoundGezToCents(val float64) float64 {
+	rounded := RoundToCents(val)
+	if rounded <= 0.0 {
+		// Also avoids -0
+		return 0.0
+	}
+	return rounded
+}
+
+// Rounds val to the given number of decimal places, with halves rounded away
+// from zero.
+func RoundToPlaces(val float64, places int) float64 {
+	scale := math.Pow(10, float64(places))
+	return math.Round(val*scale) / scale
+}
+
+// Returns the greatest common divisor of a and b (which is a if b is 0).
+func GcdUint32(a uint32, b uint32) uint32 {
+	for b != 0 {
+		a, b = b, a%b
+	}
+	return a
+}
+
+// Reduces the fraction num/denom to lowest terms. denom must not be 0.
+func ReduceFraction(num uint32, denom uint32) (uint32, uint32) {
+	gcd := GcdUint32(num, denom)
+	return num / gcd, denom / gcd
+}
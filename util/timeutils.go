@@ -9,3 +9,32 @@ func DateStr(date time.Time) string {
 	year, month, day := date.Date()
 	return fmt.Sprintf("%d-%02d-%02d", year, month, day)
 }
+
+var todaysDateForTest time.Time
+
+// Returns today's date in the local time zone, at midnight UTC (the same as
+// dates parsed without a time zone).
+func Today() time.Time {
+	if (todaysDateForTest != time.Time{}) {
+		return todaysDateForTest
+	}
+	year, month, day := time.Now().Date()
+	return time.Date(year, month, day, 0, 0, 0, 0, time.UTC)
+}
+
+// Overrides the date returned by Today. A zero date removes the override.
+func SetTodaysDateForTest(date time.Time) {
+	todaysDateForTest = date
+}
+
+// Returns the date n business days after date, skipping weekends.
+// Holidays are not accounted for.
+func AddBusinessDays(date time.Time, n uint32) time.Time {
+	for n > 0 {
+		date = date.AddDate(0, 0, 1)
+		if date.Weekday() != time.Saturday && date.Weekday() != time.Sunday {
+			n--
+		}
+	}
+	return date
+}
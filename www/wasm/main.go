@@ -16,8 +16,11 @@ var globalRatesCache map[uint32][]fx.DailyRate = make(map[uint32][]fx.DailyRate)
 
 func main() {
 	fmt.Println("Go Web Assembly started")
-	js.Global().Set("runAcb", makeRunAcbWrapper())
+	js.Global().Set("runAcb", makeRunAcbWrapper(false))
+	js.Global().Set("runAcbSingle", makeRunAcbWrapper(true))
+	js.Global().Set("runAcbWithOptions", makeRunAcbWithOptionsWrapper())
 	js.Global().Set("getAcbVersion", makeGetVersionWrapper())
+	js.Global().Set("validateTxRow", makeValidateTxRowWrapper())
 	// Wait for calls
 	<-make(chan bool)
 }
@@ -29,6 +32,39 @@ func makeGetVersionWrapper() js.Func {
 	return wrapperFunc
 }
 
+/* Takes (header, row), both arrays of strings, and returns an array of
+ * {column, error} objects, with one for each invalid column of the row (see
+ * ptf.ValidateCsvRow, with the default csv options). The column is "" for
+ * errors in the row as a whole.
+ * The array is empty if the row is valid.
+ */
+func makeValidateTxRowWrapper() js.Func {
+	wrapperFunc := js.FuncOf(func(this js.Value, args []js.Value) interface{} {
+		err := validateFuncArgs(args, js.TypeObject, js.TypeObject)
+		if err != nil {
+			return makeRetVal(nil, err)
+		}
+		header, err := jsArrayToStringArray(args[0])
+		if err != nil {
+			return makeRetVal(nil, err)
+		}
+		row, err := jsArrayToStringArray(args[1])
+		if err != nil {
+			return makeRetVal(nil, err)
+		}
+
+		fieldErrs := make([]interface{}, 0)
+		for _, fieldErr := range ptf.ValidateCsvRow(header, row, ptf.NewCsvOptions()) {
+			fieldErrs = append(fieldErrs, map[string]interface{}{
+				"column": fieldErr.Column,
+				"error":  fieldErr.Err.Error(),
+			})
+		}
+		return makeRetVal(fieldErrs, nil)
+	})
+	return wrapperFunc
+}
+
 // The default ErrorPrinter
 type BufErrorPrinter struct {
 	Buf strings.Builder
@@ -83,6 +119,17 @@ func errorArrayToIntfArray(arr []error) []interface{} {
 	return outArr
 }
 
+func rowSourcesToIntfArray(sources []ptf.RenderRowSource) []interface{} {
+	outArr := make([]interface{}, 0, len(sources))
+	for _, source := range sources {
+		outArr = append(outArr, map[string]interface{}{
+			"readIndex": int(source.ReadIndex),
+			"origin":    source.Origin.String(),
+		})
+	}
+	return outArr
+}
+
 func renderTablesToJsObject(renderTables map[string]*ptf.RenderTable) js.Value {
 	if renderTables == nil {
 		return js.ValueOf(nil)
@@ -91,31 +138,46 @@ func renderTablesToJsObject(renderTables map[string]*ptf.RenderTable) js.Value {
 	tableObjMap := map[string]interface{}{}
 	for symbol, renderTable := range renderTables {
 		tableObjMap[symbol] = map[string]interface{}{
-			"header": stringArrayToIntfArray(renderTable.Header),
-			"rows":   stringArrayArrayToIntfArray(renderTable.Rows),
-			"footer": stringArrayToIntfArray(renderTable.Footer),
-			"notes":  stringArrayToIntfArray(renderTable.Notes),
-			"errors": errorArrayToIntfArray(renderTable.Errors),
+			"header":     stringArrayToIntfArray(renderTable.Header),
+			"rows":       stringArrayArrayToIntfArray(renderTable.Rows),
+			"footer":     stringArrayToIntfArray(renderTable.Footer),
+			"notes":      stringArrayToIntfArray(renderTable.Notes),
+			"errors":     errorArrayToIntfArray(renderTable.Errors),
+			"rowSources": rowSourcesToIntfArray(renderTable.RowSources),
 		}
 	}
 
 	return js.ValueOf(tableObjMap)
 }
 
+func summaryStatsToJsObject(stats ptf.DeltaSummaryStats) js.Value {
+	return js.ValueOf(map[string]interface{}{
+		"totalCapitalGains":      stats.TotalCapitalGains,
+		"totalCapitalLosses":     stats.TotalCapitalLosses,
+		"totalSuperficialLosses": stats.TotalSuperficialLosses,
+		"totalDividends":         stats.TotalDividends,
+	})
+}
+
 /* csvDescs: descriptions of each csv. usually just the name.
  * csvContents: The read contents of each csv file. Indexes must match csvDescs
  *	initialSymbolStates: list of symbol states formatted as  SYM:nShares:totalAcb.
  *                      Eg. GOOG:20:1000.00
+ * options: If options.Report.Security is non-empty, only that security is
+ *          computed (along with any securities it receives shares from) and
+ *          returned.
+ * progressCallback: If not undefined, a js function called with
+ *                   (securitiesDone, securitiesTotal) as each security is computed.
  *
- * Returns a js object representation of a map[string]ptf.RenderTable
+ * Returns a js object with the text output, a representation of a
+ * map[string]ptf.RenderTable (modelOutput), and the ptf.DeltaSummaryStats
+ * (summary).
  */
 func runAcb(
 	csvDescs []string, csvContents []string,
 	initialSymbolStates []string,
-	renderFullValues bool,
-	// Legacy options
-	noSuperficialLosses bool, noPartialSuperficialLosses bool,
-	sortBuysBeforeSells bool) (js.Value, error) {
+	options app.AcbRunOptions,
+	progressCallback js.Value) (js.Value, error) {
 
 	fmt.Println("runAcb")
 	csvReaders := make([]app.DescribedReader, 0, len(csvContents))
@@ -124,8 +186,6 @@ func runAcb(
 		csvReaders = append(csvReaders, app.DescribedReader{desc, strings.NewReader(contents)})
 	}
 
-	forceDownload := false
-
 	allInitStatus, err := app.ParseInitialStatus(initialSymbolStates)
 	if err != nil {
 		return js.ValueOf(nil), err
@@ -135,23 +195,32 @@ func runAcb(
 
 	var output strings.Builder
 
-	legacyOptions := app.NewLegacyOptions()
-	legacyOptions.NoSuperficialLosses = noSuperficialLosses
-	legacyOptions.NoPartialSuperficialLosses = noPartialSuperficialLosses
-	legacyOptions.SortBuysBeforeSells = sortBuysBeforeSells
+	if progressCallback.Type() == js.TypeFunction {
+		options.Report.Progress = func(done int, total int) {
+			progressCallback.Invoke(done, total)
+		}
+	}
 
-	_, renderTables := app.RunAcbAppToWriter(
+	result, _ := app.RunAcbAppWithOptions(
 		&output,
-		csvReaders, allInitStatus, forceDownload, renderFullValues,
-		legacyOptions, &fx.MemRatesCacheAccessor{RatesByYear: globalRatesCache},
+		csvReaders, allInitStatus, options,
+		&fx.MemRatesCacheAccessor{RatesByYear: globalRatesCache},
 		errPrinter,
 	)
 
 	outString := output.String()
 
+	var renderTables map[string]*ptf.RenderTable
+	summary := js.ValueOf(nil)
+	if result != nil {
+		renderTables = result.RenderTables
+		summary = summaryStatsToJsObject(result.Summary)
+	}
+
 	outObj := js.ValueOf(map[string]interface{}{
 		"textOutput":  outString,
 		"modelOutput": renderTablesToJsObject(renderTables),
+		"summary":     summary,
 	})
 
 	errString := errPrinter.Buf.String()
@@ -221,11 +290,24 @@ func jsArrayToStringArray(jsArr js.Value) ([]string, error) {
 	return arr, nil
 }
 
-func makeRunAcbWrapper() js.Func {
+/* If single is true, the wrapper (runAcbSingle) takes the security to compute
+ * as an additional first argument.
+ * A progress callback function may optionally be passed as the last argument.
+ */
+func makeRunAcbWrapper(single bool) js.Func {
 	wrapperFunc := js.FuncOf(func(this js.Value, args []js.Value) interface{} {
-		err := validateFuncArgs(
-			args, js.TypeObject, js.TypeObject, js.TypeObject, js.TypeBoolean,
-			js.TypeBoolean, js.TypeBoolean, js.TypeBoolean)
+		progressCallback := js.Undefined()
+		if len(args) > 0 && args[len(args)-1].Type() == js.TypeFunction {
+			progressCallback = args[len(args)-1]
+			args = args[:len(args)-1]
+		}
+
+		argTypes := []js.Type{js.TypeObject, js.TypeObject, js.TypeObject, js.TypeBoolean,
+			js.TypeBoolean, js.TypeBoolean, js.TypeBoolean}
+		if single {
+			argTypes = append([]js.Type{js.TypeString}, argTypes...)
+		}
+		err := validateFuncArgs(args, argTypes...)
 		if err != nil {
 			return makeErrorPromise(err)
 		}
@@ -237,6 +319,14 @@ func makeRunAcbWrapper() js.Func {
 			return args[i]
 		}
 
+		security := ""
+		if single {
+			security = popArg().String()
+			if security == "" {
+				return makeErrorPromise(fmt.Errorf("No security provided"))
+			}
+		}
+
 		descs, err := jsArrayToStringArray(popArg())
 		if err != nil {
 			return makeErrorPromise(err)
@@ -261,17 +351,18 @@ func makeRunAcbWrapper() js.Func {
 			return makeErrorPromise(err)
 		}
 
-		renderFullValues := popArg().Bool()
-		noSuperficialLosses := popArg().Bool()
-		noPartialSuperficialLosses := popArg().Bool()
-		sortBuysBeforeSells := popArg().Bool()
+		options := app.NewAcbRunOptions()
+		options.RenderFullDollarValues = popArg().Bool()
+		options.Legacy.NoSuperficialLosses = popArg().Bool()
+		options.Legacy.NoPartialSuperficialLosses = popArg().Bool()
+		options.Legacy.SortBuysBeforeSells = popArg().Bool()
+		options.Report.Security = security
 
 		promise := makeJsPromise(
 			func(resolveFunc js.Value, rejectFunc js.Value) {
 				go func() {
 					out, err := runAcb(
-						descs, contents, initialSymbolStates, renderFullValues,
-						noSuperficialLosses, noPartialSuperficialLosses, sortBuysBeforeSells)
+						descs, contents, initialSymbolStates, options, progressCallback)
 					resolveFunc.Invoke(makeRetVal(out, err))
 					// rejectFunc.Invoke("something error")
 				}()
@@ -280,3 +371,55 @@ func makeRunAcbWrapper() js.Func {
 	})
 	return wrapperFunc
 }
+
+/* Takes (csvDescs, csvContents, initialSymbolStates, options), where options is
+ * a js object in the form read by app.ParseAcbRunOptionsJson.
+ * A progress callback function may optionally be passed as the last argument.
+ */
+func makeRunAcbWithOptionsWrapper() js.Func {
+	wrapperFunc := js.FuncOf(func(this js.Value, args []js.Value) interface{} {
+		progressCallback := js.Undefined()
+		if len(args) > 0 && args[len(args)-1].Type() == js.TypeFunction {
+			progressCallback = args[len(args)-1]
+			args = args[:len(args)-1]
+		}
+
+		err := validateFuncArgs(
+			args, js.TypeObject, js.TypeObject, js.TypeObject, js.TypeObject)
+		if err != nil {
+			return makeErrorPromise(err)
+		}
+
+		descs, err := jsArrayToStringArray(args[0])
+		if err != nil {
+			return makeErrorPromise(err)
+		}
+		contents, err := jsArrayToStringArray(args[1])
+		if err != nil {
+			return makeErrorPromise(err)
+		}
+		for len(descs) < len(contents) {
+			descs = append(descs, "")
+		}
+		initialSymbolStates, err := jsArrayToStringArray(args[2])
+		if err != nil {
+			return makeErrorPromise(err)
+		}
+		optionsJson := js.Global().Get("JSON").Call("stringify", args[3]).String()
+		options, err := app.ParseAcbRunOptionsJson([]byte(optionsJson))
+		if err != nil {
+			return makeErrorPromise(err)
+		}
+
+		promise := makeJsPromise(
+			func(resolveFunc js.Value, rejectFunc js.Value) {
+				go func() {
+					out, err := runAcb(
+						descs, contents, initialSymbolStates, options, progressCallback)
+					resolveFunc.Invoke(makeRetVal(out, err))
+				}()
+			})
+		return promise
+	})
+	return wrapperFunc
+}